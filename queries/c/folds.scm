@@ -0,0 +1,11 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (function_definition)
+  (compound_statement)
+  (field_declaration_list)
+  (enumerator_list)
+  (initializer_list)
+  (preproc_if)
+  (preproc_ifdef)
+  (comment)
+] @fold
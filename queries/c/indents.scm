@@ -0,0 +1,25 @@
+; indentation for = and new lines; see src/indent.rs for the captures
+[
+  (compound_statement)
+  (field_declaration_list)
+  (enumerator_list)
+  (initializer_list)
+  (argument_list)
+  (parameter_list)
+  (if_statement)
+  (else_clause)
+  (for_statement)
+  (while_statement)
+  (do_statement)
+  (case_statement)
+] @indent
+
+(else_clause) @outdent
+
+[
+  "}"
+  ")"
+  "]"
+] @outdent
+
+(comment) @keep
@@ -0,0 +1,16 @@
+; text objects and motions: af/if ac/ic aa/ia a//i/ al/il, ]m [m ]] [[
+
+(function_definition body: (compound_statement) @function.inner) @function.outer
+
+(struct_specifier body: (field_declaration_list) @class.inner) @class.outer
+(union_specifier body: (field_declaration_list) @class.inner) @class.outer
+(enum_specifier body: (enumerator_list) @class.inner) @class.outer
+
+(parameter_list (_) @parameter.inner)
+(argument_list (_) @parameter.inner)
+
+(comment) @comment.outer
+
+(for_statement body: (_) @loop.inner) @loop.outer
+(while_statement body: (_) @loop.inner) @loop.outer
+(do_statement body: (_) @loop.inner) @loop.outer
@@ -0,0 +1,13 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+  (block)
+  (type_declaration)
+  (import_declaration)
+  (const_declaration)
+  (var_declaration)
+  (composite_literal)
+  (comment)
+] @fold
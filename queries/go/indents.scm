@@ -0,0 +1,37 @@
+; indentation for = and new lines; see src/indent.rs for the captures
+[
+  (block)
+  (literal_value)
+  (field_declaration_list)
+  (interface_type)
+  (argument_list)
+  (parameter_list)
+  (import_spec_list)
+  (const_declaration)
+  (var_declaration)
+  (expression_switch_statement)
+  (type_switch_statement)
+  (select_statement)
+  (expression_case)
+  (default_case)
+  (type_case)
+  (communication_case)
+] @indent
+
+[
+  (expression_case)
+  (default_case)
+  (type_case)
+  (communication_case)
+] @outdent
+
+[
+  "}"
+  ")"
+  "]"
+] @outdent
+
+[
+  (raw_string_literal)
+  (comment)
+] @keep
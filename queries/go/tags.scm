@@ -0,0 +1,9 @@
+; definitions listed by :Symbols and the outline; when one node matches
+; several patterns the last pattern wins (a struct type is a struct)
+
+(function_declaration name: (identifier) @name) @definition.function
+(method_declaration name: (field_identifier) @name) @definition.method
+(type_spec name: (type_identifier) @name) @definition.type
+(type_spec name: (type_identifier) @name type: (struct_type)) @definition.struct
+(type_spec name: (type_identifier) @name type: (interface_type)) @definition.interface
+(const_spec name: (identifier) @name) @definition.constant
@@ -0,0 +1,16 @@
+; text objects and motions: af/if ac/ic aa/ia a//i/ al/il, ]m [m ]] [[
+
+(function_declaration body: (block) @function.inner) @function.outer
+(method_declaration body: (block) @function.inner) @function.outer
+(func_literal body: (block) @function.inner) @function.outer
+
+(type_declaration (type_spec type: (struct_type (field_declaration_list) @class.inner))) @class.outer
+(type_declaration (type_spec type: (interface_type))) @class.outer
+
+(parameter_list (_) @parameter.inner)
+(argument_list (_) @parameter.inner)
+(type_arguments (_) @parameter.inner)
+
+(comment) @comment.outer
+
+(for_statement body: (block) @loop.inner) @loop.outer
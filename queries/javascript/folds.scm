@@ -0,0 +1,10 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (statement_block)
+  (class_body)
+  (object)
+  (array)
+  (arguments)
+  (template_string)
+  (comment)
+] @fold
@@ -0,0 +1,43 @@
+; indentation for = and new lines; see src/indent.rs for the captures
+[
+  (statement_block)
+  (class_body)
+  (object)
+  (array)
+  (arguments)
+  (formal_parameters)
+  (object_pattern)
+  (array_pattern)
+  (named_imports)
+  (export_clause)
+  (parenthesized_expression)
+  (switch_body)
+  (switch_case)
+  (switch_default)
+  (if_statement)
+  (else_clause)
+  (for_statement)
+  (for_in_statement)
+  (while_statement)
+  (do_statement)
+  (call_expression)
+  (member_expression)
+  (arrow_function)
+  (jsx_element)
+] @indent
+
+[
+  (else_clause)
+  (jsx_closing_element)
+] @outdent
+
+[
+  "}"
+  ")"
+  "]"
+] @outdent
+
+[
+  (template_string)
+  (comment)
+] @keep
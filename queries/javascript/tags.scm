@@ -0,0 +1,19 @@
+; definitions listed by :Symbols and the outline; when one node matches
+; several patterns the last pattern wins (a const arrow function is a function)
+
+(function_declaration name: (identifier) @name) @definition.function
+(generator_function_declaration name: (identifier) @name) @definition.function
+(class_declaration name: (identifier) @name) @definition.class
+(method_definition name: (_) @name) @definition.method
+
+(program
+  (lexical_declaration kind: "const"
+    (variable_declarator name: (identifier) @name) @definition.constant))
+(program
+  (export_statement
+    (lexical_declaration kind: "const"
+      (variable_declarator name: (identifier) @name) @definition.constant)))
+
+(variable_declarator
+  name: (identifier) @name
+  value: [(arrow_function) (function_expression)]) @definition.function
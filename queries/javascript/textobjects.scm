@@ -0,0 +1,21 @@
+; text objects and motions: af/if ac/ic aa/ia a//i/ al/il, ]m [m ]] [[
+
+(function_declaration body: (statement_block) @function.inner) @function.outer
+(generator_function_declaration body: (statement_block) @function.inner) @function.outer
+(function_expression body: (statement_block) @function.inner) @function.outer
+(generator_function body: (statement_block) @function.inner) @function.outer
+(arrow_function body: (_) @function.inner) @function.outer
+(method_definition body: (statement_block) @function.inner) @function.outer
+
+(class_declaration body: (class_body) @class.inner) @class.outer
+(class body: (class_body) @class.inner) @class.outer
+
+(formal_parameters (_) @parameter.inner)
+(arguments (_) @parameter.inner)
+
+(comment) @comment.outer
+
+(for_statement body: (_) @loop.inner) @loop.outer
+(for_in_statement body: (_) @loop.inner) @loop.outer
+(while_statement body: (_) @loop.inner) @loop.outer
+(do_statement body: (_) @loop.inner) @loop.outer
@@ -0,0 +1,5 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (object)
+  (array)
+] @fold
@@ -0,0 +1,7 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (section)
+  (fenced_code_block)
+  (list)
+  (block_quote)
+] @fold
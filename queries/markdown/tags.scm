@@ -0,0 +1,5 @@
+; headings for :Symbols and the outline; sections nest like the headings do
+
+(section
+  [(atx_heading heading_content: (_) @name)
+   (setext_heading heading_content: (_) @name)]) @definition.section
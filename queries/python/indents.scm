@@ -0,0 +1,64 @@
+; indentation for = and new lines; see src/indent.rs for the captures
+[
+  (function_definition)
+  (class_definition)
+  (if_statement)
+  (elif_clause)
+  (else_clause)
+  (for_statement)
+  (while_statement)
+  (with_statement)
+  (try_statement)
+  (except_clause)
+  (finally_clause)
+  (match_statement)
+  (case_clause)
+  (argument_list)
+  (parameters)
+  (list)
+  (dictionary)
+  (set)
+  (tuple)
+  (parenthesized_expression)
+  (list_comprehension)
+  (dictionary_comprehension)
+  (set_comprehension)
+  (generator_expression)
+] @indent
+
+; blocks end with their last statement, so a line below one may continue it
+[
+  (function_definition)
+  (class_definition)
+  (if_statement)
+  (elif_clause)
+  (else_clause)
+  (for_statement)
+  (while_statement)
+  (with_statement)
+  (try_statement)
+  (except_clause)
+  (finally_clause)
+  (match_statement)
+  (case_clause)
+] @open
+
+[
+  (elif_clause)
+  (else_clause)
+  (except_clause)
+  (finally_clause)
+  ")"
+  "]"
+  "}"
+] @outdent
+
+[
+  (return_statement)
+  (pass_statement)
+  (break_statement)
+  (continue_statement)
+  (raise_statement)
+] @dedent
+
+(string) @keep
@@ -0,0 +1,16 @@
+; definitions listed by :Symbols and the outline; when one node matches
+; several patterns the last pattern wins (a function in a class is a method)
+
+(class_definition name: (identifier) @name) @definition.class
+(function_definition name: (identifier) @name) @definition.function
+
+(module
+  (expression_statement
+    (assignment left: (identifier) @name) @definition.constant)
+  (#match? @name "^[A-Z][A-Z0-9_]*$"))
+
+(class_definition
+  body: (block (function_definition name: (identifier) @name) @definition.method))
+(class_definition
+  body: (block (decorated_definition
+    definition: (function_definition name: (identifier) @name) @definition.method)))
@@ -0,0 +1,16 @@
+; text objects and motions: af/if ac/ic aa/ia a//i/ al/il, ]m [m ]] [[
+
+(function_definition body: (block) @function.inner) @function.outer
+(decorated_definition definition: (function_definition)) @function.outer
+
+(class_definition body: (block) @class.inner) @class.outer
+(decorated_definition definition: (class_definition)) @class.outer
+
+(parameters (_) @parameter.inner)
+(lambda_parameters (_) @parameter.inner)
+(argument_list (_) @parameter.inner)
+
+(comment) @comment.outer
+
+(for_statement body: (block) @loop.inner) @loop.outer
+(while_statement body: (block) @loop.inner) @loop.outer
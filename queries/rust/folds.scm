@@ -0,0 +1,16 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (function_item)
+  (impl_item)
+  (trait_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (mod_item)
+  (match_expression)
+  (macro_definition)
+  (block_comment)
+  (use_declaration)
+] @fold
+
+(block) @fold
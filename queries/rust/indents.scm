@@ -0,0 +1,34 @@
+; indentation for = and new lines; see src/indent.rs for the captures
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (ordered_field_declaration_list)
+  (enum_variant_list)
+  (field_initializer_list)
+  (match_block)
+  (match_arm)
+  (use_list)
+  (arguments)
+  (parameters)
+  (array_expression)
+  (tuple_expression)
+  (token_tree)
+  (where_clause)
+  (call_expression)
+  (field_expression)
+  (binary_expression)
+  (let_declaration)
+] @indent
+
+[
+  "}"
+  ")"
+  "]"
+] @outdent
+
+[
+  (string_literal)
+  (raw_string_literal)
+  (block_comment)
+] @keep
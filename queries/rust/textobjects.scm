@@ -0,0 +1,24 @@
+; text objects and motions: af/if ac/ic aa/ia a//i/ al/il, ]m [m ]] [[
+
+(function_item body: (block) @function.inner) @function.outer
+(function_signature_item) @function.outer
+
+(impl_item body: (declaration_list) @class.inner) @class.outer
+(trait_item body: (declaration_list) @class.inner) @class.outer
+(struct_item body: (_) @class.inner) @class.outer
+(enum_item body: (enum_variant_list) @class.inner) @class.outer
+(union_item body: (field_declaration_list) @class.inner) @class.outer
+(mod_item body: (declaration_list) @class.inner) @class.outer
+
+(parameters (_) @parameter.inner)
+(closure_parameters (_) @parameter.inner)
+(arguments (_) @parameter.inner)
+(type_parameters (_) @parameter.inner)
+(type_arguments (_) @parameter.inner)
+
+(line_comment) @comment.outer
+(block_comment) @comment.outer
+
+(for_expression body: (block) @loop.inner) @loop.outer
+(while_expression body: (block) @loop.inner) @loop.outer
+(loop_expression body: (block) @loop.inner) @loop.outer
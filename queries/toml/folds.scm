@@ -0,0 +1,7 @@
+; zc/zo regions for foldmethod=syntax
+[
+  (table)
+  (table_array_element)
+  (array)
+  (inline_table)
+] @fold
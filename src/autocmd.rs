@@ -0,0 +1,182 @@
+// Autocommands: ex commands run when editor events fire (:autocmd)
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutocmdEvent {
+    BufReadPost,
+    BufEnter,
+    BufWritePre,
+    BufWritePost,
+    FileType,
+    InsertEnter,
+    InsertLeave,
+    VimEnter,
+    VimLeave,
+}
+
+impl AutocmdEvent {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bufread" | "bufreadpost" => Some(AutocmdEvent::BufReadPost),
+            "bufenter" => Some(AutocmdEvent::BufEnter),
+            "bufwrite" | "bufwritepre" => Some(AutocmdEvent::BufWritePre),
+            "bufwritepost" => Some(AutocmdEvent::BufWritePost),
+            "filetype" => Some(AutocmdEvent::FileType),
+            "insertenter" => Some(AutocmdEvent::InsertEnter),
+            "insertleave" => Some(AutocmdEvent::InsertLeave),
+            "vimenter" => Some(AutocmdEvent::VimEnter),
+            "vimleave" => Some(AutocmdEvent::VimLeave),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AutocmdEvent::BufReadPost => "BufReadPost",
+            AutocmdEvent::BufEnter => "BufEnter",
+            AutocmdEvent::BufWritePre => "BufWritePre",
+            AutocmdEvent::BufWritePost => "BufWritePost",
+            AutocmdEvent::FileType => "FileType",
+            AutocmdEvent::InsertEnter => "InsertEnter",
+            AutocmdEvent::InsertLeave => "InsertLeave",
+            AutocmdEvent::VimEnter => "VimEnter",
+            AutocmdEvent::VimLeave => "VimLeave",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autocmd {
+    pub event: AutocmdEvent,
+    pub pattern: String,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Autocmds {
+    list: Vec<Autocmd>,
+}
+
+impl Autocmds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// handle the text after `:autocmd[!]`: "Event[,Event] pattern command"
+    /// with `!` and no command the matching autocommands are removed
+    pub fn define(&mut self, bang: bool, args: &str) -> Result<()> {
+        let mut parts = args.trim().splitn(3, char::is_whitespace);
+        let events_str = parts.next().unwrap_or("");
+        let pattern = parts.next().map(str::trim).unwrap_or("");
+        let command = parts.next().map(str::trim).unwrap_or("");
+        if events_str.is_empty() {
+            if bang { self.list.clear(); }
+            return Ok(());
+        }
+        let mut events = Vec::new();
+        for name in events_str.split(',') {
+            match AutocmdEvent::parse(name) {
+                Some(e) => events.push(e),
+                None if name == "*" => events.extend([
+                    AutocmdEvent::BufReadPost, AutocmdEvent::BufEnter, AutocmdEvent::BufWritePre,
+                    AutocmdEvent::BufWritePost, AutocmdEvent::FileType, AutocmdEvent::InsertEnter,
+                    AutocmdEvent::InsertLeave, AutocmdEvent::VimEnter, AutocmdEvent::VimLeave,
+                ]),
+                None => return Err(Error::ParseError(format!("No such event: {}", name))),
+            }
+        }
+        if bang {
+            self.list.retain(|a| !(events.contains(&a.event) && (pattern.is_empty() || a.pattern == pattern)));
+        }
+        if command.is_empty() {
+            if !bang {
+                return Err(Error::ParseError("Usage: :autocmd Event pattern command".to_string()));
+            }
+            return Ok(());
+        }
+        for event in events {
+            self.list.push(Autocmd { event, pattern: pattern.to_string(), command: command.to_string() });
+        }
+        Ok(())
+    }
+
+    /// commands registered for `event` whose pattern matches `subject`
+    /// (a file path, or the filetype name for FileType)
+    pub fn matching(&self, event: AutocmdEvent, subject: &str) -> Vec<String> {
+        self.list.iter()
+            .filter(|a| a.event == event && pattern_matches(&a.pattern, subject))
+            .map(|a| a.command.clone())
+            .collect()
+    }
+
+    pub fn all(&self) -> &[Autocmd] {
+        &self.list
+    }
+}
+
+/// comma-separated glob patterns; a pattern without '/' matches the file name only
+pub fn pattern_matches(patterns: &str, subject: &str) -> bool {
+    patterns.split(',').any(|pat| {
+        let target = if pat.contains('/') {
+            subject
+        } else {
+            subject.rsplit('/').next().unwrap_or(subject)
+        };
+        glob_match(pat, target)
+    })
+}
+
+/// `*` matches any run of characters, `?` a single one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "main.rsx"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("te?t*", "test_file"));
+        assert!(pattern_matches("*.py,*.rs", "/home/u/src/lib.rs"));
+        assert!(pattern_matches("*/src/*.rs", "/home/u/src/lib.rs"));
+        assert!(!pattern_matches("*.md", "/home/u/src/lib.rs"));
+    }
+
+    #[test]
+    fn test_define_and_match() {
+        let mut au = Autocmds::new();
+        au.define(false, "BufWritePre,BufEnter *.rs echo hi").unwrap();
+        au.define(false, "FileType python set tab_width=4").unwrap();
+        assert_eq!(au.matching(AutocmdEvent::BufWritePre, "a/b.rs"), vec!["echo hi"]);
+        assert_eq!(au.matching(AutocmdEvent::FileType, "python"), vec!["set tab_width=4"]);
+        assert!(au.matching(AutocmdEvent::FileType, "rust").is_empty());
+        au.define(true, "BufEnter *.rs").unwrap();
+        assert!(au.matching(AutocmdEvent::BufEnter, "b.rs").is_empty());
+        assert_eq!(au.all().len(), 2);
+        assert!(au.define(false, "Nope * x").is_err());
+    }
+}
@@ -5,9 +5,20 @@ use ropey::Rope;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tree_sitter::{InputEdit, Point};
 
 use crate::error::{Error, Result};
-use crate::filetype::{detect_file_type, FileType};
+use crate::language::LanguageRegistry;
+use crate::syntax::SyntaxTree;
+
+static NEXT_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+// line edits kept for windows that have not caught up yet
+const MAX_LINE_EDITS: usize = 1024;
+
+fn next_buffer_id() -> u64 {
+    NEXT_BUFFER_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LineEnding {
@@ -46,6 +57,18 @@ impl Default for LineEnding {
     }
 }
 
+/// one edit as line-based state sees it: `delta` lines inserted before line
+/// `at`, or removed starting at `at`, after which lines `first..=last` hold
+/// changed text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEdit {
+    pub version: u64, // buffer version after the edit
+    pub at: usize,
+    pub delta: isize,
+    pub first: usize,
+    pub last: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Buffer {
     rope: Rope,
@@ -59,7 +82,20 @@ pub struct Buffer {
     line_ending: LineEnding,
     marks: HashMap<char, (usize, usize)>,
     encoding: Option<&'static Encoding>,
-    file_type: FileType,
+    language: Option<String>, // set by the editor from the language registry
+    id: u64,      // identifies the buffer to background parse jobs
+    version: u64, // bumped on every text change
+    syntax: Option<SyntaxTree>,
+    /// edits made since `syntax` was parsed, as (version after the edit, edit),
+    /// replayed onto trees that finish parsing an older version
+    syntax_edits: Vec<(u64, InputEdit)>,
+    /// a background parse is running; its tree needs the edits made meanwhile
+    /// even when there is no tree yet to apply them to
+    syntax_pending: bool,
+    /// recent edits, so line-based state like folds and marks can follow them
+    line_edits: Vec<LineEdit>,
+    /// the newest version whose edit was dropped from `line_edits`
+    line_edits_dropped: u64,
 }
 
 impl Buffer {
@@ -75,7 +111,14 @@ impl Buffer {
             line_ending: LineEnding::default(),
             marks: HashMap::new(),
             encoding: None,
-            file_type: FileType::Unknown,
+            language: None,
+            id: next_buffer_id(),
+            version: 0,
+            syntax: None,
+            syntax_edits: Vec::new(),
+            syntax_pending: false,
+            line_edits: Vec::new(),
+            line_edits_dropped: 0,
         }
     }
 
@@ -91,11 +134,24 @@ impl Buffer {
             line_ending: LineEnding::default(),
             marks: HashMap::new(),
             encoding: None,
-            file_type: FileType::Unknown,
+            language: None,
+            id: next_buffer_id(),
+            version: 0,
+            syntax: None,
+            syntax_edits: Vec::new(),
+            syntax_pending: false,
+            line_edits: Vec::new(),
+            line_edits_dropped: 0,
         }
     }
 
+    /// load a file, taking its language from the built-in definitions
     pub fn from_file<P: AsRef<Path>>(path: P, config: &crate::config::Config) -> Result<Self> {
+        Self::from_file_with_languages(path, config, LanguageRegistry::shared())
+    }
+
+    /// load a file, taking its language from `languages`
+    pub fn from_file_with_languages<P: AsRef<Path>>(path: P, config: &crate::config::Config, languages: &LanguageRegistry) -> Result<Self> {
         use chardetng::EncodingDetector;
         use std::io::{BufRead, BufReader};
 
@@ -119,8 +175,6 @@ impl Buffer {
 
         let (decoded_content, _, _) = encoding.decode(&bytes);
 
-        let file_type = detect_file_type(path.as_ref(), &decoded_content);
-
         // Detect line ending from file content
         let line_ending = LineEnding::detect(&decoded_content);
 
@@ -175,7 +229,7 @@ impl Buffer {
                 None
             };
 
-        Ok(Self {
+        let mut buffer = Self {
             rope,
             file_path: Some(path.as_ref().to_path_buf()),
             backup_path,
@@ -186,8 +240,17 @@ impl Buffer {
             line_ending,
             marks: HashMap::new(),
             encoding: Some(encoding),
-            file_type,
-        })
+            language: None,
+            id: next_buffer_id(),
+            version: 0,
+            syntax: None,
+            syntax_edits: Vec::new(),
+            syntax_pending: false,
+            line_edits: Vec::new(),
+            line_edits_dropped: 0,
+        };
+        buffer.detect_language(languages);
+        Ok(buffer)
     }
 
     pub fn get_mark(&self, mark: char) -> Option<(usize, usize)> {
@@ -276,7 +339,7 @@ impl Buffer {
         if line < self.line_count() {
             let line_start = self.rope.line_to_char(line);
             let insert_pos = line_start + col.min(self.line_len(line));
-            self.rope.insert_char(insert_pos, ch);
+            self.insert_text_at(insert_pos, ch);
             self.set_modified(true);
         }
     }
@@ -288,7 +351,7 @@ impl Buffer {
         if line < self.line_count() {
             let line_start = self.rope.line_to_char(line);
             let insert_pos = line_start + col.min(self.line_len(line));
-            self.rope.insert_char(insert_pos, '\n');
+            self.insert_text_at(insert_pos, '\n');
             self.set_modified(true);
         }
     }
@@ -300,7 +363,7 @@ impl Buffer {
         if line < self.line_count() && col < self.line_len(line) {
             let line_start = self.rope.line_to_char(line);
             let delete_pos = line_start + col;
-            self.rope.remove(delete_pos..delete_pos + 1);
+            self.remove_chars(delete_pos, delete_pos + 1);
             self.set_modified(true);
         }
     }
@@ -328,11 +391,136 @@ impl Buffer {
         };
 
         if start_char <= end_char && end_char <= self.rope.len_chars() {
-            self.rope.remove(start_char..end_char);
+            self.remove_chars(start_char, end_char);
             self.set_modified(true);
         }
     }
 
+    // all text changes go through these two so the syntax tree sees every edit
+
+    fn insert_text_at(&mut self, char_idx: usize, ch: char) {
+        let start_byte = self.rope.char_to_byte(char_idx);
+        let start_position = self.byte_point(start_byte);
+        self.rope.insert_char(char_idx, ch);
+        let new_end_byte = start_byte + ch.len_utf8();
+        self.text_changed(InputEdit {
+            start_byte,
+            old_end_byte: start_byte,
+            new_end_byte,
+            start_position,
+            old_end_position: start_position,
+            new_end_position: self.byte_point(new_end_byte),
+        });
+    }
+
+    fn remove_chars(&mut self, start_char: usize, end_char: usize) {
+        let start_byte = self.rope.char_to_byte(start_char);
+        let old_end_byte = self.rope.char_to_byte(end_char);
+        let start_position = self.byte_point(start_byte);
+        let old_end_position = self.byte_point(old_end_byte);
+        self.rope.remove(start_char..end_char);
+        self.text_changed(InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte: start_byte,
+            start_position,
+            old_end_position,
+            new_end_position: start_position,
+        });
+    }
+
+    /// tree-sitter position of a byte offset: row and byte column
+    fn byte_point(&self, byte: usize) -> Point {
+        let row = self.rope.byte_to_line(byte);
+        Point::new(row, byte - self.rope.line_to_byte(row))
+    }
+
+    fn text_changed(&mut self, edit: InputEdit) {
+        self.version += 1;
+        let delta = edit.new_end_position.row as isize - edit.old_end_position.row as isize;
+        // a change starting at column 0 moves its first line too
+        let whole_lines = edit.start_position.column == 0
+            && (delta > 0 || edit.old_end_position.column == 0);
+        if self.line_edits.len() >= MAX_LINE_EDITS {
+            self.line_edits_dropped = self.line_edits[MAX_LINE_EDITS / 2 - 1].version;
+            self.line_edits.drain(..MAX_LINE_EDITS / 2);
+        }
+        self.line_edits.push(LineEdit {
+            version: self.version,
+            at: edit.start_position.row + usize::from(!whole_lines),
+            delta,
+            first: edit.start_position.row,
+            last: edit.new_end_position.row,
+        });
+        if let Some(syntax) = &mut self.syntax {
+            syntax.edit(&edit);
+        }
+        if self.syntax.is_some() || self.syntax_pending {
+            self.syntax_edits.push((self.version, edit));
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    pub fn line_to_byte(&self, line: usize) -> usize {
+        self.rope.line_to_byte(line.min(self.rope.len_lines()))
+    }
+
+    /// edits made after `version`, oldest first; None when some of them are no
+    /// longer kept and state from `version` has to be rebuilt from the text
+    pub fn line_edits_since(&self, version: u64) -> Option<impl Iterator<Item = LineEdit> + '_> {
+        if version < self.line_edits_dropped {
+            return None;
+        }
+        Some(self.line_edits.iter().copied().filter(move |e| e.version > version))
+    }
+
+    pub fn syntax_tree(&self) -> Option<&SyntaxTree> {
+        self.syntax.as_ref()
+    }
+
+    pub fn syntax_tree_mut(&mut self) -> Option<&mut SyntaxTree> {
+        self.syntax.as_mut()
+    }
+
+    /// install a freshly parsed tree; edits made after the version it was parsed
+    /// from are replayed onto it so its byte ranges match the current text
+    pub fn set_syntax_tree(&mut self, syntax: Option<SyntaxTree>) {
+        self.syntax_pending = false;
+        let Some(mut syntax) = syntax else {
+            self.syntax = None;
+            self.syntax_edits.clear();
+            return;
+        };
+        self.syntax_edits.retain(|(v, _)| *v > syntax.version);
+        for (_, edit) in &self.syntax_edits {
+            syntax.tree.edit(edit);
+        }
+        if let Some(old) = self.syntax.take() {
+            syntax.adopt_injections(old);
+        }
+        self.syntax = Some(syntax);
+    }
+
+    /// note that a tree is being parsed from the current text in the background,
+    /// so edits from here on are kept for it
+    pub fn set_syntax_pending(&mut self, pending: bool) {
+        self.syntax_pending = pending;
+        if !pending && self.syntax.is_none() {
+            self.syntax_edits.clear();
+        }
+    }
+
     pub fn get_char_at(&self, line: usize, col: usize) -> Option<char> {
         if line < self.line_count() && col < self.line_len(line) {
             let line_start = self.rope.line_to_char(line);
@@ -411,6 +599,11 @@ impl Buffer {
         self.read_only
     }
 
+    /// refuse (or allow again) every change to the text
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
     pub fn is_large_file(&self) -> bool {
         self.is_large
     }
@@ -431,8 +624,27 @@ impl Buffer {
         Some(self.rope.to_string())
     }
 
-    pub fn file_type(&self) -> FileType {
-        self.file_type
+    /// name of the buffer's language, "unknown" when none was detected
+    pub fn file_type(&self) -> &str {
+        self.language.as_deref().unwrap_or("unknown")
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn set_language(&mut self, language: Option<String>) {
+        self.language = language;
+    }
+
+    /// set the language from the file name, `#!` line and modelines
+    pub fn detect_language(&mut self, languages: &LanguageRegistry) {
+        let line_count = self.line_count();
+        let lines = |range: std::ops::Range<usize>| -> String {
+            range.map(|l| self.rope.line(l).to_string()).collect()
+        };
+        let text = lines(0..line_count.min(5)) + &lines(line_count.saturating_sub(5).max(5)..line_count);
+        self.language = languages.detect(self.file_path.as_deref(), &text).map(|l| l.name.clone());
     }
 
     fn create_backup(&self) -> Result<()> {
@@ -808,4 +1020,24 @@ mod tests {
             assert_eq!(buffer.get_line(i), Some("a".to_string()));
         }
     }
+
+    #[test]
+    fn test_line_edits() {
+        let mut buffer = Buffer::from_string("a\nb\nc\nd\n");
+        let v = buffer.version();
+        buffer.insert_newline(1, 1); // split after "b": line 2 onwards moves
+        buffer.delete_range(0, 0, 2, 0); // remove lines 0 and 1 whole
+        buffer.insert_char(0, 0, 'x'); // no change in line count
+        let edits: Vec<(usize, isize, usize, usize)> = buffer.line_edits_since(v).unwrap()
+            .map(|e| (e.at, e.delta, e.first, e.last))
+            .collect();
+        assert_eq!(edits, vec![(2, 1, 1, 2), (0, -2, 0, 0), (0, 0, 0, 0)]);
+        assert_eq!(buffer.line_edits_since(buffer.version()).unwrap().count(), 0);
+        // a version whose edits were dropped can only start over
+        for _ in 0..MAX_LINE_EDITS {
+            buffer.insert_char(0, 0, 'y');
+        }
+        assert!(buffer.line_edits_since(v).is_none());
+        assert!(buffer.line_edits_since(buffer.version() - 1).is_some());
+    }
 }
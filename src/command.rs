@@ -1,6 +1,12 @@
 // Command mode implementation
 
 use crate::error::{Error, Result};
+use crate::mapping::MapMode;
+use crate::quickfix::{self, ListKind, VimgrepArgs};
+use crate::replace;
+use crate::search;
+use crate::substitute::{self, SubstituteSpec};
+use crate::user_command::AbbrevMode;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Range {
@@ -8,6 +14,25 @@ pub struct Range {
     pub end: usize,   // 1-indexed
 }
 
+/// what line addresses are resolved against, lines counted from 1; `'x` marks
+/// and `/pat/` `?pat?` searches go through the lookups
+pub struct LineContext<'a> {
+    pub current: usize,
+    pub last: usize,
+    pub mark: &'a dyn Fn(char) -> Option<usize>,
+    /// the line of the next match after (forward) or before a line
+    pub search: &'a dyn Fn(&str, usize, bool) -> Option<usize>,
+}
+
+impl LineContext<'static> {
+    /// just the current and last line, without marks or searches
+    pub fn lines(current: usize, last: usize) -> Self {
+        fn no_mark(_: char) -> Option<usize> { None }
+        fn no_match(_: &str, _: usize, _: bool) -> Option<usize> { None }
+        LineContext { current, last, mark: &no_mark, search: &no_match }
+    }
+}
+
 #[derive(Debug)]
 pub enum Command {
     Write(Option<String>),
@@ -17,9 +42,7 @@ pub enum Command {
     Edit(String),
     GoToLine(usize),
     Substitute {
-        pattern: String,
-        replacement: String,
-        global: bool,
+        spec: SubstituteSpec,
         range: Option<Range>,
     },
     Set {
@@ -40,17 +63,49 @@ pub enum Command {
     Registers,
     Marks,
     Goyo,
+    TSInfo,
+    Format,                                         // :Format with the language's formatter
+    Symbols,                                        // :Symbols finder over the buffer's definitions
+    Outline,                                        // :Outline toggles the symbol sidebar
     Files,
     Buffers,
-    Grep(String),
+    Grep { pattern: String, list: Option<ListKind> }, // :Rg into the picker, :grep / :lgrep into a list
+    Replace { pattern: String, replacement: String, ignore_case: Option<bool> }, // :Replace /pat/rep/ across the project
     Normal { keys: String, range: Option<Range> }, // :norm <keys>
+    Move { range: Option<Range>, dest: usize },     // :m {address}
+    Copy { range: Option<Range>, dest: usize },     // :t / :co {address}
+    Join { range: Option<Range>, spaces: bool },    // :j, :j! joins without spaces
+    Global { pattern: String, command: String, invert: bool, range: Option<Range> }, // :g, :v, :g!
+    ListOpen { list: ListKind, height: Option<usize> }, // :copen / :lopen [height]
+    ListClose(ListKind),                            // :cclose / :lclose
+    ListNext { list: ListKind, count: usize },      // :[count]cnext / :lnext
+    ListPrevious { list: ListKind, count: usize },  // :[count]cprevious / :lprevious
+    ListSelect { list: ListKind, entry: Option<usize> }, // :cc / :ll [N]; :cfirst is 1, :clast 0
+    ListFile { list: ListKind, file: Option<String> }, // :cfile / :lfile [errorfile]
+    ListExpr { list: ListKind, expr: String },      // :cexpr / :lexpr {expr}
+    Make { list: ListKind, args: String },          // :make / :lmake [args]
+    Vimgrep { list: ListKind, args: VimgrepArgs },  // :vimgrep / :lvimgrep /pat/[g][j] files
+    ListDo { list: ListKind, command: String, per_file: bool }, // :cdo / :ldo, :cfdo / :lfdo
+    Diagnostics,                                    // :Diagnostics into the window's location list
     Terminal,                                       // :terminal
+    NoHighlight,                                    // :noh until the next search
     Shell(String),                                  // :!cmd
     Filter { cmd: String, range: Option<Range> },   // :{range}!cmd
+    DefineCommand { bang: bool, definition: String }, // :command[!] [attrs] Name rep
+    DeleteCommand(String),                          // :delcommand Name
+    Abbreviate { mode: Option<AbbrevMode>, args: String }, // :ab / :iab / :cab
+    Unabbreviate { mode: Option<AbbrevMode>, lhs: String }, // :una / :iuna / :cuna
+    Map { modes: Vec<MapMode>, noremap: bool, args: String }, // :map, :nnoremap, ...
+    Unmap { modes: Vec<MapMode>, lhs: String },     // :unmap, :iunmap, ...
+    Autocmd { bang: bool, args: String },           // :autocmd[!] Event pattern cmd
+    Source(String),                                 // :source file
+    Colorscheme(Option<String>),                    // :colorscheme [name]
     Unknown(String),
 }
 
-pub fn parse_command(input: &str) -> Result<Command> {
+/// parse an ex command line; `lines` resolves its addresses (`.`, `$`, `+2`,
+/// `'a`, `/pat/`)
+pub fn parse_command(input: &str, lines: &LineContext) -> Result<Command> {
     let input = input.trim();
 
     if input.is_empty() {
@@ -61,7 +116,12 @@ pub fn parse_command(input: &str) -> Result<Command> {
     let input = input.strip_prefix(':').unwrap_or(input);
 
     // Parse range if present
-    let (range, command) = parse_range(input);
+    let (range, command) = parse_range(input, lines);
+    if command.is_empty() {
+        if let Some(r) = range {
+            return Ok(Command::GoToLine(r.end));
+        }
+    }
 
     match command {
         "w" | "write" => Ok(Command::Write(None)),
@@ -70,6 +130,7 @@ pub fn parse_command(input: &str) -> Result<Command> {
         "q!" => Ok(Command::ForceQuit),
         "d" | "delete" => Ok(Command::Delete { range }),
         "terminal" | "term" => Ok(Command::Terminal),
+        "noh" | "nohlsearch" => Ok(Command::NoHighlight),
         _ => {
             // :!cmd or :{range}!cmd
             if let Some(rest) = command.strip_prefix('!') {
@@ -79,9 +140,47 @@ pub fn parse_command(input: &str) -> Result<Command> {
                 return Ok(Command::Shell(rest.to_string()));
             }
 
-            // Try to parse substitute command
-            if command.starts_with("s/") {
-                return parse_substitute(command, range);
+            // user commands and abbreviations
+            let (word, rest) = match command.split_once(char::is_whitespace) {
+                Some((w, r)) => (w, r.trim()),
+                None => (command, ""),
+            };
+            let definition = rest.to_string();
+            match word {
+                "com" | "command" => return Ok(Command::DefineCommand { bang: false, definition }),
+                "com!" | "command!" => return Ok(Command::DefineCommand { bang: true, definition }),
+                "delc" | "delcommand" => return Ok(Command::DeleteCommand(definition)),
+                "ab" | "abbreviate" => return Ok(Command::Abbreviate { mode: None, args: definition }),
+                "iab" | "iabbrev" => return Ok(Command::Abbreviate { mode: Some(AbbrevMode::Insert), args: definition }),
+                "ca" | "cabbrev" => return Ok(Command::Abbreviate { mode: Some(AbbrevMode::Command), args: definition }),
+                "una" | "unabbreviate" => return Ok(Command::Unabbreviate { mode: None, lhs: definition }),
+                "iuna" | "iunabbrev" => return Ok(Command::Unabbreviate { mode: Some(AbbrevMode::Insert), lhs: definition }),
+                "cuna" | "cunabbrev" => return Ok(Command::Unabbreviate { mode: Some(AbbrevMode::Command), lhs: definition }),
+                "au" | "autocmd" => return Ok(Command::Autocmd { bang: false, args: definition }),
+                "au!" | "autocmd!" => return Ok(Command::Autocmd { bang: true, args: definition }),
+                "so" | "source" => return Ok(Command::Source(definition)),
+                "colo" | "colorscheme" => {
+                    let name = if definition.is_empty() { None } else { Some(definition) };
+                    return Ok(Command::Colorscheme(name));
+                }
+                _ => {}
+            }
+            if let Some((modes, noremap)) = map_command_modes(word) {
+                return Ok(Command::Map { modes, noremap, args: definition });
+            }
+            if let Some(modes) = word.strip_suffix("unmap").and_then(map_prefix_modes) {
+                return Ok(Command::Unmap { modes, lhs: definition });
+            }
+
+            // :s/pat/rep/, :s#pat#rep#, :&&, :~ ...
+            if let Some(spec) = substitute::parse(command) {
+                return spec.map(|spec| Command::Substitute { spec, range }).map_err(Error::ParseError);
+            }
+            if let Some(cmd) = parse_quickfix_command(command, range) {
+                return cmd;
+            }
+            if let Some(cmd) = parse_line_command(command, range, lines) {
+                return cmd;
             }
 
             // Try to parse as line number
@@ -138,20 +237,30 @@ pub fn parse_command(input: &str) -> Result<Command> {
                 Ok(Command::Registers)
             } else if command == "marks" {
                 Ok(Command::Marks)
+            } else if command == "TSInfo" {
+                Ok(Command::TSInfo)
+            } else if command == "Format" {
+                Ok(Command::Format)
+            } else if command == "Symbols" {
+                Ok(Command::Symbols)
+            } else if command == "Outline" {
+                Ok(Command::Outline)
             } else if command == "Goyo" || command == "Zen" {
                 Ok(Command::Goyo)
             } else if command == "Files" || command == "files" {
                 Ok(Command::Files)
             } else if command == "Buffers" || command == "buffers" || command == "buf" {
                 Ok(Command::Buffers)
+            } else if let Some(args) = command.strip_prefix("Replace") {
+                replace::parse(args)
+                    .map(|(pattern, replacement, ignore_case)| Command::Replace { pattern, replacement, ignore_case })
+                    .map_err(Error::ParseError)
             } else if let Some(pattern) = command.strip_prefix("Rg ") {
-                Ok(Command::Grep(pattern.trim().to_string()))
+                Ok(Command::Grep { pattern: pattern.trim().to_string(), list: None })
             } else if let Some(pattern) = command.strip_prefix("rg ") {
-                Ok(Command::Grep(pattern.trim().to_string()))
+                Ok(Command::Grep { pattern: pattern.trim().to_string(), list: None })
             } else if let Some(pattern) = command.strip_prefix("Grep ") {
-                Ok(Command::Grep(pattern.trim().to_string()))
-            } else if let Some(pattern) = command.strip_prefix("grep ") {
-                Ok(Command::Grep(pattern.trim().to_string()))
+                Ok(Command::Grep { pattern: pattern.trim().to_string(), list: None })
             } else {
                 Ok(Command::Unknown(command.to_string()))
             }
@@ -159,78 +268,174 @@ pub fn parse_command(input: &str) -> Result<Command> {
     }
 }
 
-fn parse_range(input: &str) -> (Option<Range>, &str) {
-    // Handle % (all lines)
-    if input.starts_with('%') {
-        return (
-            Some(Range {
-                start: 1,
-                end: usize::MAX,
-            }),
-            &input[1..],
-        );
+/// modes for a `[nvic]map` prefix ("" for plain :map, "!" suffix handled by the caller)
+fn map_prefix_modes(prefix: &str) -> Option<Vec<MapMode>> {
+    match prefix {
+        "" => Some(vec![MapMode::Normal, MapMode::Visual]),
+        "n" => Some(vec![MapMode::Normal]),
+        "v" | "x" => Some(vec![MapMode::Visual]),
+        "i" => Some(vec![MapMode::Insert]),
+        "c" => Some(vec![MapMode::Command]),
+        _ => None,
     }
+}
 
-    // Handle line,line format (e.g., 1,10)
-    if let Some(comma_pos) = input.find(',') {
-        let before_comma = &input[..comma_pos];
-        let after_comma = &input[comma_pos + 1..];
-
-        // Find where the command starts (after the range)
-        if let Some(cmd_start) = after_comma.find(|c: char| !c.is_ascii_digit()) {
-            let end_str = &after_comma[..cmd_start];
-            if let (Ok(start), Ok(end)) = (before_comma.parse::<usize>(), end_str.parse::<usize>())
-            {
-                return (Some(Range { start, end }), &after_comma[cmd_start..]);
-            }
-        }
+/// recognise :map-family command names, returning (modes, noremap)
+fn map_command_modes(word: &str) -> Option<(Vec<MapMode>, bool)> {
+    if let Some(base) = word.strip_suffix('!') {
+        // :map! / :noremap! apply to insert and command-line mode
+        return match base {
+            "map" => Some((vec![MapMode::Insert, MapMode::Command], false)),
+            "no" | "noremap" => Some((vec![MapMode::Insert, MapMode::Command], true)),
+            _ => None,
+        };
     }
+    let (prefix, noremap) = if let Some(p) = word.strip_suffix("noremap") {
+        (p, true)
+    } else if let Some(p) = word.strip_suffix("map") {
+        (p, false)
+    } else {
+        match word {
+            "nn" | "nno" => ("n", true),
+            "ino" => ("i", true),
+            "vn" | "vno" | "xn" | "xno" => (&word[..1], true),
+            "cno" => ("c", true),
+            "nm" | "vm" | "xm" | "im" | "cm" => (&word[..1], false),
+            _ => return None,
+        }
+    };
+    map_prefix_modes(prefix).map(|modes| (modes, noremap))
+}
 
-    // Handle single line number (e.g., 10d)
-    if let Some(first_non_digit) = input.find(|c: char| !c.is_ascii_digit()) {
-        let line_str = &input[..first_non_digit];
-        if let Ok(line) = line_str.parse::<usize>() {
-            return (
-                Some(Range {
-                    start: line,
-                    end: line,
-                }),
-                &input[first_non_digit..],
-            );
+/// one line address: a number, `.` (the current line), `$` (the last), `'x`
+/// (mark x) or `/pat/` / `?pat?` (the next line below / above the current one
+/// that matches), then any `+n` / `-n` offsets; offsets alone count from the
+/// current line. None with the input untouched when a mark is not set or a
+/// pattern not found
+fn parse_address<'a>(input: &'a str, current: usize, lines: &LineContext) -> (Option<usize>, &'a str) {
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (mut line, mut rest) = match input.chars().next() {
+        Some('.') => (Some(current), &input[1..]),
+        Some('$') => (Some(lines.last), &input[1..]),
+        Some(c) if c.is_ascii_digit() => {
+            let n = digits(input);
+            (input[..n].parse().ok(), &input[n..])
         }
+        Some('\'') => {
+            let Some(mark) = input[1..].chars().next() else { return (None, input); };
+            match (lines.mark)(mark) {
+                Some(line) => (Some(line), &input[1 + mark.len_utf8()..]),
+                None => return (None, input),
+            }
+        }
+        Some(delim @ ('/' | '?')) => {
+            let (pattern, rest) = search::split_at_delimiter(&input[1..], delim);
+            match (lines.search)(&pattern, current, delim == '/') {
+                Some(line) => (Some(line), rest.unwrap_or("")),
+                None => return (None, input),
+            }
+        }
+        _ => (None, input),
+    };
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        let n = digits(&rest[1..]);
+        let offset: usize = if n == 0 { 1 } else { rest[1..1 + n].parse().unwrap_or(0) };
+        let base = line.unwrap_or(current);
+        line = Some(if sign == '+' { base.saturating_add(offset) } else { base.saturating_sub(offset) });
+        rest = &rest[1 + n..];
     }
-
-    (None, input)
+    (line, rest)
 }
 
-fn parse_substitute(input: &str, range: Option<Range>) -> Result<Command> {
-    let input = input.strip_prefix("s/").unwrap_or(input);
-
-    // Parse s/pattern/replacement/flags
-    let parts: Vec<&str> = input.split('/').collect();
-    if parts.len() < 2 {
-        return Err(Error::ParseError(
-            "Substitute syntax: s/pattern/replacement/[g]".to_string(),
-        ));
+/// a leading range: `%`, an address, or two joined by `,` (or `;`, which makes
+/// the first the current line for the second); backwards ranges are swapped
+pub fn parse_range<'a>(input: &'a str, lines: &LineContext) -> (Option<Range>, &'a str) {
+    if let Some(rest) = input.strip_prefix('%') {
+        return (Some(Range { start: 1, end: lines.last }), rest);
     }
-
-    let pattern = parts[0].to_string();
-    if pattern.is_empty() {
-        return Err(Error::ParseError(
-            "Substitute pattern cannot be empty".to_string(),
-        ));
+    let (start, rest) = parse_address(input, lines.current, lines);
+    let (end, rest) = match rest.chars().next() {
+        Some(sep @ (',' | ';')) => {
+            let base = if sep == ';' { start.unwrap_or(lines.current) } else { lines.current };
+            let (end, rest) = parse_address(&rest[1..], base, lines);
+            (Some(end.unwrap_or(base)), rest)
+        }
+        _ => (start, rest),
+    };
+    match (start.or(end), end) {
+        (Some(start), Some(end)) => (Some(Range { start: start.min(end), end: start.max(end) }), rest),
+        _ => (None, input),
     }
+}
 
-    let replacement = parts[1].to_string();
-    let flags = if parts.len() > 2 { parts[2] } else { "" };
-
-    let global = flags.contains('g');
+/// :m, :t / :co, :j and :g / :v / :g!; None for other commands
+fn parse_line_command(command: &str, range: Option<Range>, lines: &LineContext) -> Option<Result<Command>> {
+    let name_end = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    let (name, rest) = command.split_at(name_end);
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let destination = |rest: &str| match parse_address(rest.trim(), lines.current, lines) {
+        (Some(dest), "") => Ok(dest),
+        _ => Err(Error::ParseError(format!("Invalid address: {}", rest.trim()))),
+    };
+    match name {
+        "m" | "move" if !bang => Some(destination(rest).map(|dest| Command::Move { range, dest })),
+        "t" | "co" | "copy" if !bang => Some(destination(rest).map(|dest| Command::Copy { range, dest })),
+        "j" | "join" if rest.trim().is_empty() => Some(Ok(Command::Join { range, spaces: !bang })),
+        "g" | "global" | "v" | "vglobal" => {
+            let delim = rest.chars().next().filter(|&c| substitute::is_delimiter(c))?;
+            let (pattern, command) = search::split_at_delimiter(&rest[delim.len_utf8()..], delim);
+            let invert = bang || name.starts_with('v');
+            Some(Ok(Command::Global { pattern, command: command.unwrap_or("").trim().to_string(), invert, range }))
+        }
+        _ => None,
+    }
+}
 
-    Ok(Command::Substitute {
-        pattern,
-        replacement,
-        global,
-        range,
+/// :copen, :cnext, :cc, :cfile, :cexpr, :make, :grep, :vimgrep, :cdo and the
+/// rest of the quickfix commands, and their :l forms for the window's location
+/// list; a range before :cnext / :cprevious is a count, before :cc the entry.
+/// None for other commands
+fn parse_quickfix_command(command: &str, range: Option<Range>) -> Option<Result<Command>> {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    let list = if name.starts_with('l') { ListKind::Location } else { ListKind::Quickfix };
+    let count = range.map_or(1, |r| r.end);
+    let number = |what: &str| match arg {
+        "" => Ok(range.map(|r| r.end)),
+        _ => arg.parse().map(Some).map_err(|_| Error::ParseError(format!("Invalid {}: {}", what, arg))),
+    };
+    let required = |arg: &str| match arg {
+        "" => Err(Error::ParseError("Argument required".to_string())),
+        _ => Ok(arg.to_string()),
+    };
+    Some(match name {
+        "cope" | "copen" | "lop" | "lopen" => number("height").map(|height| Command::ListOpen { list, height }),
+        "ccl" | "cclose" | "lcl" | "lclose" => Ok(Command::ListClose(list)),
+        "cn" | "cnext" | "lne" | "lnext" => Ok(Command::ListNext { list, count }),
+        "cp" | "cprevious" | "cN" | "cNext" | "lp" | "lprevious" | "lN" | "lNext" => {
+            Ok(Command::ListPrevious { list, count })
+        }
+        "cc" | "ll" => number("entry").map(|entry| Command::ListSelect { list, entry }),
+        "cfir" | "cfirst" | "cr" | "crewind" | "lfir" | "lfirst" | "lr" | "lrewind" => {
+            Ok(Command::ListSelect { list, entry: Some(1) })
+        }
+        "cla" | "clast" | "lla" | "llast" => Ok(Command::ListSelect { list, entry: Some(0) }),
+        "cf" | "cfile" | "lf" | "lfile" => Ok(Command::ListFile { list, file: (!arg.is_empty()).then(|| arg.to_string()) }),
+        "cex" | "cexpr" | "lex" | "lexpr" => required(arg).map(|expr| Command::ListExpr { list, expr }),
+        "mak" | "make" | "lmak" | "lmake" => Ok(Command::Make { list, args: arg.to_string() }),
+        "cdo" | "ldo" => required(arg).map(|command| Command::ListDo { list, command, per_file: false }),
+        "cfdo" | "lfdo" => required(arg).map(|command| Command::ListDo { list, command, per_file: true }),
+        "gr" | "grep" | "lgr" | "lgrep" => required(arg).map(|pattern| Command::Grep { pattern, list: Some(list) }),
+        "vim" | "vimgrep" | "lv" | "lvimgrep" => {
+            quickfix::parse_vimgrep(arg).map(|args| Command::Vimgrep { list, args }).map_err(Error::ParseError)
+        }
+        "Diagnostics" => Ok(Command::Diagnostics),
+        _ => return None,
     })
 }
 
@@ -248,3 +453,79 @@ fn parse_set(args: &str) -> Result<Command> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(input: &str, lines: &LineContext) -> Option<(usize, usize, String)> {
+        match parse_range(input, lines) {
+            (Some(r), rest) => Some((r.start, r.end, rest.to_string())),
+            (None, _) => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_range_lines_and_offsets() {
+        let lines = LineContext::lines(5, 20);
+        assert_eq!(range("%d", &lines), Some((1, 20, "d".to_string())));
+        assert_eq!(range(".d", &lines), Some((5, 5, "d".to_string())));
+        assert_eq!(range("$", &lines), Some((20, 20, String::new())));
+        assert_eq!(range("3,7s/a/b/", &lines), Some((3, 7, "s/a/b/".to_string())));
+        assert_eq!(range(".,+3j", &lines), Some((5, 8, "j".to_string())));
+        assert_eq!(range("-2,.d", &lines), Some((3, 5, "d".to_string())));
+        assert_eq!(range("+d", &lines), Some((6, 6, "d".to_string())));
+        assert_eq!(range("$-1,$", &lines), Some((19, 20, String::new())));
+        // backwards ranges are swapped; `;` counts the second from the first
+        assert_eq!(range("7,3d", &lines), Some((3, 7, "d".to_string())));
+        assert_eq!(range("10;+2d", &lines), Some((10, 12, "d".to_string())));
+        assert_eq!(range("d", &lines), None);
+    }
+
+    #[test]
+    fn test_parse_range_marks_and_patterns() {
+        let mark = |c: char| match c {
+            'a' => Some(4),
+            'b' => Some(9),
+            _ => None,
+        };
+        // "TODO" on lines 2 and 12 of 20
+        let search = |pattern: &str, from: usize, forward: bool| {
+            let hits = if pattern == "TODO" { vec![2, 12] } else { Vec::new() };
+            if forward {
+                hits.iter().find(|&&l| l > from).or(hits.first()).copied()
+            } else {
+                hits.iter().rev().find(|&&l| l < from).or(hits.last()).copied()
+            }
+        };
+        let lines = LineContext { current: 5, last: 20, mark: &mark, search: &search };
+        assert_eq!(range("'a,'bd", &lines), Some((4, 9, "d".to_string())));
+        assert_eq!(range("'a+1", &lines), Some((5, 5, String::new())));
+        assert_eq!(range("/TODO/d", &lines), Some((12, 12, "d".to_string())));
+        assert_eq!(range("?TODO?,.d", &lines), Some((2, 5, "d".to_string())));
+        assert_eq!(range("/TODO/-1,/TODO/+1", &lines), Some((11, 13, String::new())));
+        assert_eq!(range("12;/TODO/", &lines), Some((2, 12, String::new())));
+        // unknown marks and patterns leave the input alone
+        assert_eq!(range("'zd", &lines), None);
+        assert_eq!(range("/nothing/d", &lines), None);
+    }
+
+    #[test]
+    fn test_parse_line_commands_relative_to_current() {
+        let lines = LineContext::lines(5, 20);
+        match parse_command("m0", &lines) {
+            Ok(Command::Move { range: None, dest: 0 }) => {}
+            other => panic!("{:?}", other),
+        }
+        match parse_command(".,+2t$", &lines) {
+            Ok(Command::Copy { range: Some(r), dest: 20 }) => assert_eq!((r.start, r.end), (5, 7)),
+            other => panic!("{:?}", other),
+        }
+        match parse_command("g!/x/s/a/b/", &lines) {
+            Ok(Command::Global { pattern, command, invert: true, range: None }) => {
+                assert_eq!((pattern.as_str(), command.as_str()), ("x", "s/a/b/"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
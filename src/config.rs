@@ -2,7 +2,10 @@
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
+use std::path::PathBuf;
 use toml;
+use crate::fold::FoldMethod;
+use crate::quickfix::ErrorFormat;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum LineNumberMode {
@@ -12,7 +15,32 @@ pub enum LineNumberMode {
     RelativeAbsolute, // Hybrid: relative numbers with absolute for current line
 }
 
+/// a leader-key binding: either a bare action name or an action with a description
 #[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LeaderBinding {
+    Action(String),
+    Described { action: String, desc: Option<String> },
+}
+
+impl LeaderBinding {
+    pub fn action(&self) -> &str {
+        match self {
+            LeaderBinding::Action(a) => a,
+            LeaderBinding::Described { action, .. } => action,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            LeaderBinding::Action(a) => a,
+            LeaderBinding::Described { action, desc } => desc.as_deref().unwrap_or(action),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub line_numbers: LineNumberMode,
     pub show_current_line: bool,
@@ -20,6 +48,7 @@ pub struct Config {
     pub expand_tab: bool, // Use spaces instead of tabs
     pub auto_indent: bool,
     pub highlight_search: bool,
+    pub incremental_search: bool, // jump to the first match while a search is typed
     pub ignore_case: bool,
     pub smart_case: bool, // Override ignorecase when search has uppercase
     pub zen_mode: bool,
@@ -29,12 +58,35 @@ pub struct Config {
     pub large_file_preview_lines: usize,
     #[serde(default = "default_leader_key")]
     pub leader_key: Option<char>,
-    #[serde(default)]
-    pub leader_bindings: HashMap<char, String>, // leader key -> action name
+    pub leader_bindings: HashMap<String, LeaderBinding>, // key sequence after leader -> action
+    pub leader_groups: HashMap<String, String>, // key prefix -> group description
+    pub which_key_delay_ms: u64, // delay before the key hint popup appears
+    pub commands: HashMap<String, String>, // user command name -> "[-nargs=N] [-range] ex-command"
+    pub iabbrev: HashMap<String, String>,  // insert-mode abbreviations
+    pub cabbrev: HashMap<String, String>,  // command-line abbreviations
+    pub timeout_len_ms: u64, // how long to wait for the rest of an ambiguous mapping or leader sequence
+    pub colorscheme: Option<String>, // theme name, bundled or from the themes directory
+    pub termguicolors: Option<bool>, // force (true) or disable (false) 24-bit color; unset detects
+    pub grammar_extensions: HashMap<String, Vec<String>>, // grammar name -> extra file extensions
+    pub foldmethod: FoldMethod, // default for windows that have not set their own
+    pub context_lines: usize, // enclosing scopes pinned at the top of a window, 0 turns them off
+    pub makeprg: String, // the command :make runs
+    pub errorformat: Option<String>, // how :make output is read; unset picks a built-in by makeprg
+    pub grep_max_results: usize, // lines :Rg and :grep stop searching after
+    pub autowrite: bool, // write a changed file when :cdo / :cfdo move on to another
 }
 
 fn default_leader_key() -> Option<char> { Some(' ') }
 
+/// per-user config directory: $XDG_CONFIG_HOME/bitsy, falling back to ~/.config/bitsy
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("bitsy"))
+}
+
 impl Config {
     pub fn new() -> Self {
         Self {
@@ -44,6 +96,7 @@ impl Config {
             expand_tab: true,
             auto_indent: true,
             highlight_search: true,
+            incremental_search: true,
             ignore_case: false,
             smart_case: true,
             zen_mode: false,
@@ -53,6 +106,21 @@ impl Config {
             large_file_preview_lines: 1000,
             leader_key: Some(' '),
             leader_bindings: HashMap::new(),
+            leader_groups: HashMap::new(),
+            which_key_delay_ms: 500,
+            commands: HashMap::new(),
+            iabbrev: HashMap::new(),
+            cabbrev: HashMap::new(),
+            timeout_len_ms: 1000,
+            colorscheme: None,
+            termguicolors: None,
+            grammar_extensions: HashMap::new(),
+            foldmethod: FoldMethod::Manual,
+            context_lines: 3,
+            makeprg: "make".to_string(),
+            errorformat: None,
+            grep_max_results: 100,
+            autowrite: false,
         }
     }
 
@@ -116,6 +184,14 @@ impl Config {
                 self.highlight_search = false;
                 Ok(())
             }
+            "incsearch" | "is" => {
+                self.incremental_search = true;
+                Ok(())
+            }
+            "noincsearch" | "nois" => {
+                self.incremental_search = false;
+                Ok(())
+            }
             "ignorecase" | "ic" => {
                 self.ignore_case = true;
                 Ok(())
@@ -132,6 +208,22 @@ impl Config {
                 self.smart_case = false;
                 Ok(())
             }
+            "autowrite" | "aw" => {
+                self.autowrite = true;
+                Ok(())
+            }
+            "noautowrite" | "noaw" => {
+                self.autowrite = false;
+                Ok(())
+            }
+            "termguicolors" | "tgc" => {
+                self.termguicolors = Some(true);
+                Ok(())
+            }
+            "notermguicolors" | "notgc" => {
+                self.termguicolors = Some(false);
+                Ok(())
+            }
             "tabstop" | "ts" => {
                 if let Some(val) = value {
                     if let Ok(width) = val.parse::<usize>() {
@@ -144,6 +236,62 @@ impl Config {
                     Err("tabstop requires a value".to_string())
                 }
             }
+            "context" => {
+                match value.map(|v| v.parse::<usize>().map_err(|_| v)) {
+                    Some(Ok(lines)) => {
+                        self.context_lines = lines;
+                        Ok(())
+                    }
+                    Some(Err(val)) => Err(format!("Invalid value for context: {}", val)),
+                    None => Err("context requires a value".to_string()),
+                }
+            }
+            "nocontext" => {
+                self.context_lines = 0;
+                Ok(())
+            }
+            "foldmethod" | "fdm" => {
+                match value.map(|v| FoldMethod::parse(v).ok_or(v)) {
+                    Some(Ok(method)) => {
+                        self.foldmethod = method;
+                        Ok(())
+                    }
+                    Some(Err(val)) => Err(format!("Invalid value for foldmethod: {} (manual, indent, syntax)", val)),
+                    None => Err("foldmethod requires a value".to_string()),
+                }
+            }
+            "makeprg" | "mp" => match value {
+                // vim escapes the spaces in :set values
+                Some(val) if !val.is_empty() => {
+                    self.makeprg = val.replace("\\ ", " ");
+                    Ok(())
+                }
+                _ => Err("makeprg requires a value".to_string()),
+            },
+            "errorformat" | "efm" => match value {
+                Some("") => {
+                    self.errorformat = None;
+                    Ok(())
+                }
+                Some(val) => {
+                    let val = val.replace("\\ ", " ");
+                    ErrorFormat::new(&val)?;
+                    self.errorformat = Some(val);
+                    Ok(())
+                }
+                None => Err("errorformat requires a value".to_string()),
+            },
+            "grepmax" => {
+                match value.map(|v| v.parse::<usize>().map_err(|_| v)) {
+                    Some(Ok(max)) if max > 0 => {
+                        self.grep_max_results = max;
+                        Ok(())
+                    }
+                    Some(Ok(_)) => Err("grepmax must be at least 1".to_string()),
+                    Some(Err(val)) => Err(format!("Invalid value for grepmax: {}", val)),
+                    None => Err("grepmax requires a value".to_string()),
+                }
+            }
             _ => Err(format!("Unknown option: {}", option)),
         }
     }
@@ -190,6 +338,22 @@ mod tests {
         assert_eq!(config.line_number_width(999), 5); // " 999 "
     }
 
+    #[test]
+    fn test_leader_bindings_from_toml() {
+        let config: Config = toml::from_str(r#"
+            [leader_bindings]
+            w = "w"
+            fg = { action = "Rg", desc = "grep project" }
+            [leader_groups]
+            f = "find"
+        "#).unwrap();
+        assert_eq!(config.leader_bindings["w"].action(), "w");
+        assert_eq!(config.leader_bindings["fg"].action(), "Rg");
+        assert_eq!(config.leader_bindings["fg"].description(), "grep project");
+        assert_eq!(config.leader_groups["f"], "find");
+        assert_eq!(config.tab_width, 4); // unspecified keys keep defaults
+    }
+
     #[test]
     fn test_line_number_width_none() {
         let mut config = Config::new();
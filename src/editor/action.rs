@@ -9,16 +9,17 @@ use super::{Editor, PendingOperator};
 
 impl Editor {
     pub(super) fn execute_action(&mut self, action: Action) -> Result<()> {
+        let before = self.current_window().cursor;
         match action {
             // movement
             Action::MoveUp => {
                 let count = if self.count == 0 { 1 } else { self.count };
-                self.current_window_mut().cursor.move_up(count);
+                self.move_visible_lines(-(count as isize));
                 self.clamp_cursor();
             }
             Action::MoveDown => {
                 let count = if self.count == 0 { 1 } else { self.count };
-                self.current_window_mut().cursor.move_down(count);
+                self.move_visible_lines(count as isize);
                 self.clamp_cursor();
             }
             Action::MoveLeft => {
@@ -56,7 +57,8 @@ impl Editor {
             Action::MoveFileEnd => {
                 self.save_jump_position();
                 let last_line = self.current_buffer().line_count().saturating_sub(1);
-                self.current_window_mut().cursor.line = last_line;
+                let target_line = if self.count > 0 { (self.count - 1).min(last_line) } else { last_line };
+                self.current_window_mut().cursor.line = target_line;
                 self.current_window_mut().cursor.col = 0;
             }
             Action::MoveWordEnd => { self.move_word_end(); }
@@ -107,7 +109,9 @@ impl Editor {
             }
             Action::MoveToScreenTop => {
                 let offset_line = self.current_window().viewport.offset_line;
-                self.current_window_mut().cursor.line = offset_line;
+                let line_count = self.current_buffer().line_count();
+                let top_line = offset_line + self.count.saturating_sub(1);
+                self.current_window_mut().cursor.line = top_line.min(line_count.saturating_sub(1));
                 self.current_window_mut().cursor.col = 0;
                 self.clamp_cursor();
             }
@@ -123,7 +127,7 @@ impl Editor {
             Action::MoveToScreenBottom => {
                 let offset_line = self.current_window().viewport.offset_line;
                 let height = self.current_window().viewport.height;
-                let bottom_line = offset_line + height - 1;
+                let bottom_line = (offset_line + height).saturating_sub(self.count.max(1)).max(offset_line);
                 let line_count = self.current_buffer().line_count();
                 self.current_window_mut().cursor.line = bottom_line.min(line_count.saturating_sub(1));
                 self.current_window_mut().cursor.col = 0;
@@ -156,12 +160,12 @@ impl Editor {
                 self.move_to_matching_bracket();
             }
             Action::MovePageUp => {
-                let page_size = self.current_window().viewport.height;
+                let page_size = self.current_window().viewport.height * self.count.max(1);
                 self.current_window_mut().cursor.move_up(page_size);
                 self.clamp_cursor();
             }
             Action::MovePageDown => {
-                let page_size = self.current_window().viewport.height;
+                let page_size = self.current_window().viewport.height * self.count.max(1);
                 self.current_window_mut().cursor.move_down(page_size);
                 self.clamp_cursor();
             }
@@ -175,6 +179,17 @@ impl Editor {
                 self.current_window_mut().cursor.move_down(half_page);
                 self.clamp_cursor();
             }
+            Action::MoveFunctionNext | Action::MoveFunctionPrev
+            | Action::MoveSectionNext | Action::MoveSectionPrev => {
+                let count = if self.count == 0 { 1 } else { self.count };
+                let forward = matches!(action, Action::MoveFunctionNext | Action::MoveSectionNext);
+                let outermost = matches!(action, Action::MoveSectionNext | Action::MoveSectionPrev);
+                self.move_function_start(forward, outermost, count);
+            }
+            Action::MoveContextStart => {
+                let count = if self.count == 0 { 1 } else { self.count };
+                self.move_to_context_start(count);
+            }
             Action::MoveToPercent => {
                 self.save_jump_position();
                 if self.count > 0 {
@@ -220,6 +235,7 @@ impl Editor {
                 self.current_window_mut().cursor.line += 1;
                 self.current_window_mut().cursor.col = 0;
                 self.mode = Mode::Insert;
+                self.indent_new_line();
             }
             Action::EnterInsertModeNewLineAbove => {
                 self.showing_landing_page = false;
@@ -228,6 +244,7 @@ impl Editor {
                 self.rec_insert_newline(line, 0);
                 self.current_window_mut().cursor.col = 0;
                 self.mode = Mode::Insert;
+                self.indent_new_line();
             }
             Action::EnterReplaceMode => {
                 self.showing_landing_page = false;
@@ -263,6 +280,10 @@ impl Editor {
                     }
                 }
                 let was_insert = self.mode == Mode::Insert;
+                if was_insert {
+                    self.expand_insert_abbreviation();
+                    self.clear_unused_indent();
+                }
                 self.mode = Mode::Normal;
                 self.selection = None;
                 let line = self.current_window().cursor.line;
@@ -278,21 +299,28 @@ impl Editor {
             Action::InsertChar(c) => {
                 if self.mode == Mode::Insert {
                     self.save_undo_state();
+                    if !crate::user_command::is_keyword_char(c) {
+                        self.expand_insert_abbreviation();
+                    }
                     let line = self.current_window().cursor.line;
                     let col = self.current_window().cursor.col;
                     self.rec_insert_char(line, col, c);
                     self.current_window_mut().cursor.move_right(1);
+                    self.reindent_after_typing();
                     self.emit_event(crate::event::EditorEvent::InsertChar { ch: c });
                 }
             }
             Action::InsertNewline => {
                 if self.mode == Mode::Insert || self.mode == Mode::Replace {
                     self.save_undo_state();
+                    if self.mode == Mode::Insert { self.expand_insert_abbreviation(); }
+                    self.clear_unused_indent();
                     let line = self.current_window().cursor.line;
                     let col = self.current_window().cursor.col;
                     self.rec_insert_newline(line, col);
                     self.current_window_mut().cursor.line += 1;
                     self.current_window_mut().cursor.col = 0;
+                    self.indent_new_line();
                 }
             }
             Action::DeleteChar => {
@@ -348,11 +376,15 @@ impl Editor {
                 if matches!(self.mode, Mode::Visual | Mode::VisualLine | Mode::VisualBlock) {
                     if let Some(selection) = self.selection.clone() {
                         let (start, end) = selection.range();
+                        // a characterwise selection includes the character under its end
+                        let end_col = if self.mode == Mode::Visual { end.col + 1 } else { end.col };
                         self.pending_operator = PendingOperator::Delete;
-                        self.apply_operator_to_range(start.line, start.col, end.line, end.col)?;
+                        self.apply_operator_to_range(start.line, start.col, end.line, end_col)?;
                         self.pending_operator = PendingOperator::None;
                         self.mode = Mode::Normal;
                         self.selection = None;
+                        self.current_window_mut().cursor = start.into();
+                        self.clamp_cursor();
                     }
                 } else { self.pending_operator = PendingOperator::Delete; }
             }
@@ -403,6 +435,16 @@ impl Editor {
                         self.mode = Mode::Normal;
                         self.selection = None;
                     }
+                } else if self.mode == Mode::Visual {
+                    if let Some(selection) = self.selection.clone() {
+                        let (start, end) = selection.range();
+                        self.pending_operator = PendingOperator::Yank;
+                        self.apply_operator_to_range(start.line, start.col, end.line, end.col + 1)?;
+                        self.pending_operator = PendingOperator::None;
+                        self.mode = Mode::Normal;
+                        self.selection = None;
+                        self.current_window_mut().cursor = start.into();
+                    }
                 } else { self.pending_operator = PendingOperator::Yank; }
             }
             Action::YankLine => {
@@ -542,8 +584,21 @@ impl Editor {
             Action::ToggleCase => { self.pending_operator = PendingOperator::ToggleCase; }
             Action::Indent => { self.pending_operator = PendingOperator::Indent; }
             Action::Dedent => { self.pending_operator = PendingOperator::Dedent; }
-            Action::AutoIndent => { self.pending_operator = PendingOperator::AutoIndent; }
+            Action::AutoIndent => {
+                if let (Mode::Visual | Mode::VisualLine, Some(selection)) = (self.mode, self.selection.clone()) {
+                    let (start, end) = selection.range();
+                    self.save_undo_state();
+                    self.apply_auto_indent(start.line, end.line);
+                    self.mode = Mode::Normal;
+                    self.selection = None;
+                } else { self.pending_operator = PendingOperator::AutoIndent; }
+            }
+            Action::CreateFold | Action::OpenFold | Action::CloseFold | Action::ToggleFold
+            | Action::OpenAllFolds | Action::CloseAllFolds | Action::DeleteFold | Action::EliminateFolds
+            | Action::MoveFoldNext | Action::MoveFoldPrev => { self.execute_fold_action(action.clone()); }
 
+            Action::RepeatSubstitute => self.run_ex_command("s")?,
+            Action::RepeatSubstituteAll => self.run_ex_command("%s//~/&")?,
             Action::RepeatLastChange => {
                 if let Some((last_action, last_count)) = self.last_change.clone() {
                     let saved_count = self.count;
@@ -581,11 +636,13 @@ impl Editor {
                 self.mode = Mode::Search;
                 self.search_buffer.clear();
                 self.search_forward = true;
+                self.search_origin = Some((self.current_window().cursor, self.current_window().viewport.offset_line));
             }
             Action::SearchBackward => {
                 self.mode = Mode::Search;
                 self.search_buffer.clear();
                 self.search_forward = false;
+                self.search_origin = Some((self.current_window().cursor, self.current_window().viewport.offset_line));
             }
             Action::SearchNext => {
                 if self.search_pattern.is_some() { self.execute_search()?; }
@@ -597,40 +654,8 @@ impl Editor {
                     self.search_forward = !self.search_forward;
                 }
             }
-            Action::SearchWordForward => {
-                if let Some(line_text) = self.current_buffer().get_line(self.current_window().cursor.line) {
-                    let chars: Vec<char> = line_text.chars().collect();
-                    if self.current_window().cursor.col < chars.len() {
-                        let mut start = self.current_window().cursor.col;
-                        let mut end = self.current_window().cursor.col;
-                        while start > 0 && !chars[start - 1].is_whitespace() && chars[start - 1].is_alphanumeric() { start -= 1; }
-                        while end < chars.len() && !chars[end].is_whitespace() && chars[end].is_alphanumeric() { end += 1; }
-                        let word: String = chars[start..end].iter().collect();
-                        if !word.is_empty() {
-                            self.search_pattern = Some(word);
-                            self.search_forward = true;
-                            self.execute_search()?;
-                        }
-                    }
-                }
-            }
-            Action::SearchWordBackward => {
-                if let Some(line_text) = self.current_buffer().get_line(self.current_window().cursor.line) {
-                    let chars: Vec<char> = line_text.chars().collect();
-                    if self.current_window().cursor.col < chars.len() {
-                        let mut start = self.current_window().cursor.col;
-                        let mut end = self.current_window().cursor.col;
-                        while start > 0 && !chars[start - 1].is_whitespace() && chars[start - 1].is_alphanumeric() { start -= 1; }
-                        while end < chars.len() && !chars[end].is_whitespace() && chars[end].is_alphanumeric() { end += 1; }
-                        let word: String = chars[start..end].iter().collect();
-                        if !word.is_empty() {
-                            self.search_pattern = Some(word);
-                            self.search_forward = false;
-                            self.execute_search()?;
-                        }
-                    }
-                }
-            }
+            Action::SearchWordForward => self.search_word_under_cursor(true)?,
+            Action::SearchWordBackward => self.search_word_under_cursor(false)?,
 
             // change list navigation
             Action::JumpToChangeNext => {
@@ -683,7 +708,8 @@ impl Editor {
                 let buf_idx = self.current_window().buffer_index;
                 let (w, h) = self.terminal.size();
                 let vh = (h as usize).saturating_sub(2);
-                let new_win = crate::window::Window::new(buf_idx, w as usize, vh / 2);
+                let mut new_win = crate::window::Window::new(buf_idx, w as usize, vh / 2);
+                new_win.location_list = self.current_window().location_list.clone();
                 let new_idx = self.windows.len();
                 self.windows.push(new_win);
                 self.layout.split_vertical(self.active_window, new_idx);
@@ -693,7 +719,8 @@ impl Editor {
                 let buf_idx = self.current_window().buffer_index;
                 let (w, h) = self.terminal.size();
                 let vh = (h as usize).saturating_sub(2);
-                let new_win = crate::window::Window::new(buf_idx, w as usize / 2, vh);
+                let mut new_win = crate::window::Window::new(buf_idx, w as usize / 2, vh);
+                new_win.location_list = self.current_window().location_list.clone();
                 let new_idx = self.windows.len();
                 self.windows.push(new_win);
                 self.layout.split_horizontal(self.active_window, new_idx);
@@ -719,12 +746,19 @@ impl Editor {
                 }
             }
             Action::WindowEqualize => { self.recalculate_window_rects(); }
+            Action::LeaderAction(cmd) => {
+                // leader bindings name ex commands, with or without the leading ':'
+                let cmd = cmd.strip_prefix(':').unwrap_or(&cmd).to_string();
+                self.registers.update_last_command(cmd.clone());
+                self.run_ex_command(&cmd)?;
+            }
 
             _ => {}
         }
         self.clamp_cursor();
-        let cursor = self.current_window().cursor;
-        self.current_window_mut().viewport.ensure_cursor_visible(cursor.line, cursor.col);
+        self.settle_cursor_in_folds(before);
+        let window = self.current_window_mut();
+        window.viewport.ensure_cursor_visible(window.cursor.line, window.cursor.col, &window.folds);
         Ok(())
     }
 }
@@ -2,23 +2,42 @@
 
 use std::path::PathBuf;
 use crate::buffer::Buffer;
-use crate::command::{parse_command, Command};
+use crate::command::{parse_command, Command, LineContext};
 use crate::cursor::Cursor;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::fuzzy_finder::FuzzyFinder;
 use crate::mode::Mode;
-use super::Editor;
+use crate::search::{self, SearchOffset, SearchPattern};
+use super::{Editor, SearchCount};
 
 impl Editor {
     pub(super) fn execute_command(&mut self) -> Result<()> {
-        self.registers.update_last_command(self.command_buffer.clone());
-        let cmd_str = self.command_buffer.clone();
-        let cmd = parse_command(&self.command_buffer)?;
+        let input = self.command_buffer.clone();
+        self.registers.update_last_command(input.clone());
+        self.run_ex_command(&input)
+    }
+
+    /// run a single ex command line, reporting parse errors as a message
+    pub(super) fn run_ex_command(&mut self, input: &str) -> Result<()> {
+        match self.execute_command_str(input) {
+            Err(crate::error::Error::ParseError(msg)) | Err(crate::error::Error::EditorError(msg)) => {
+                self.message = Some(msg);
+                Ok(())
+            }
+            other => other,
+        }
+    }
+
+    pub(super) fn execute_command_str(&mut self, input: &str) -> Result<()> {
+        let cmd_str = input.to_string();
+        let cmd = self.with_line_context(|lines| parse_command(input, lines))?;
         match cmd {
             Command::Write(path) => {
-                if let Some(p) = path {
+                if path.is_none() && self.in_replace_preview() {
+                    self.apply_replace();
+                } else if let Some(p) = path {
                     let p_str = p.clone();
-                    if let Err(e) = self.current_buffer_mut().save_as(p) {
+                    if let Err(e) = self.write_current_buffer(Some(p)) {
                         self.message = Some(format!("Error: {}", e));
                     } else {
                         self.message = Some("File written".to_string());
@@ -27,7 +46,7 @@ impl Editor {
                 } else {
                     if self.current_buffer().file_path().is_some() {
                         let fpath = self.current_buffer().file_path().unwrap().to_string_lossy().to_string();
-                        if let Err(e) = self.current_buffer_mut().save() {
+                        if let Err(e) = self.write_current_buffer(None) {
                             self.message = Some(format!("Error: {}", e));
                         } else {
                             self.message = Some("File written".to_string());
@@ -38,13 +57,8 @@ impl Editor {
             }
             Command::Quit => {
                 if self.viewing_help {
-                    if let Some(return_buffer) = self.help_return_buffer.take() {
-                        self.buffers[0] = return_buffer;
-                        if let Some(return_cursor) = self.help_return_cursor.take() {
-                            self.windows[0].cursor = return_cursor;
-                        }
-                        self.showing_landing_page = self.was_showing_landing_page;
-                        self.viewing_help = false;
+                    if self.help_return_buffer.is_some() {
+                        self.close_scratch_buffer();
                         self.message = Some("Returned from help".to_string());
                     }
                 } else if self.current_buffer().is_modified() {
@@ -57,10 +71,10 @@ impl Editor {
             }
             Command::WriteQuit(path) => {
                 let save_result = if let Some(p) = path {
-                    self.current_buffer_mut().save_as(p)
+                    self.write_current_buffer(Some(p))
                 } else {
                     if self.current_buffer().file_path().is_some() {
-                        self.current_buffer_mut().save()
+                        self.write_current_buffer(None)
                     } else {
                         self.message = Some("No file name. Use :w <filename>".to_string());
                         return Ok(());
@@ -77,11 +91,12 @@ impl Editor {
                 if self.current_buffer().is_modified() && !was_landing_page {
                     self.message = Some("No write since last change".to_string());
                 } else {
-                    match Buffer::from_file(&filename, &self.config) {
+                    match Buffer::from_file_with_languages(&filename, &self.config, &self.languages) {
                         Ok(new_buffer) => {
                             self.buffers[self.windows[self.active_window].buffer_index] = new_buffer;
                             self.windows[self.active_window].cursor = Cursor::default();
                             self.message = Some(format!("Opened {}", filename));
+                            self.fire_buffer_loaded();
                         }
                         Err(e) => { self.message = Some(format!("Error: {}", e)); }
                     }
@@ -95,71 +110,43 @@ impl Editor {
                 self.current_window_mut().cursor.col = 0;
                 self.clamp_cursor();
             }
-            Command::Substitute { pattern, replacement, global, range } => {
-                self.save_undo_state();
-                let current_line = self.current_window().cursor.line;
-                let line_count = self.current_buffer().line_count();
-                let (start_line, end_line) = if let Some(r) = range {
-                    let start = r.start.saturating_sub(1);
-                    let end = if r.end == usize::MAX {
-                        line_count.saturating_sub(1)
-                    } else { r.end.saturating_sub(1).min(line_count.saturating_sub(1)) };
-                    (start, end)
-                } else if let Some((vs, ve)) = self.visual_cmd_range {
-                    (vs.min(line_count.saturating_sub(1)), ve.min(line_count.saturating_sub(1)))
-                } else { (current_line, current_line) };
-                self.visual_cmd_range = None;
-                let mut total_count = 0;
-                for line in start_line..=end_line {
-                    total_count += self.execute_substitute_line(line, &pattern, &replacement, global);
-                }
-                let range_count = end_line - start_line + 1;
-                if total_count > 0 {
-                    self.message = Some(format!(
-                        "{} substitution{} on {} line{}",
-                        total_count,
-                        if total_count == 1 { "" } else { "s" },
-                        range_count,
-                        if range_count != 1 { "s" } else { "" }
-                    ));
-                } else { self.message = Some("Pattern not found".to_string()); }
-            }
-            Command::Delete { range } => {
-                self.save_undo_state();
-                if let Some(r) = range {
-                    let line_count = self.current_buffer().line_count();
-                    let start = r.start.saturating_sub(1).min(line_count.saturating_sub(1));
-                    let end = if r.end == usize::MAX {
-                        line_count.saturating_sub(1)
-                    } else { r.end.saturating_sub(1).min(line_count.saturating_sub(1)) };
-                    for line in (start..=end).rev() {
-                        let line_len = self.current_buffer().line_len(line);
-                        self.current_buffer_mut().delete_range(line, 0, line, line_len);
-                        if line < self.current_buffer().line_count() {
-                            self.current_buffer_mut().delete_char(line, 0);
-                        }
+            Command::Substitute { spec, range } => self.execute_substitute(spec, range),
+            Command::NoHighlight => self.search_highlight_off = true,
+            Command::Delete { range } => self.execute_delete_lines(range),
+            Command::Move { range, dest } => self.execute_move_lines(range, dest)?,
+            Command::Copy { range, dest } => self.execute_copy_lines(range, dest),
+            Command::Join { range, spaces } => self.execute_join_lines(range, spaces),
+            Command::Global { pattern, command, invert, range } => self.execute_global(pattern, command, invert, range)?,
+            Command::Set { option, value } if matches!(option.as_str(), "filetype" | "ft") => {
+                // buffer-local: pick the language by name, or report the current one
+                match value {
+                    None => self.message = Some(format!("filetype={}", self.current_buffer().file_type())),
+                    Some(name) if self.languages.get(&name).is_some() || self.syntax.supports(&name) => {
+                        let buf_idx = self.windows[self.active_window].buffer_index;
+                        self.buffers[buf_idx].set_language(Some(name.clone()));
+                        self.fire_autocmd(crate::autocmd::AutocmdEvent::FileType, &name);
+                        self.message = Some(format!("filetype={}", name));
                     }
-                    let deleted_count = end - start + 1;
-                    self.message = Some(format!(
-                        "{} line{} deleted",
-                        deleted_count,
-                        if deleted_count != 1 { "s" } else { "" }
-                    ));
-                    self.clamp_cursor();
-                } else { self.message = Some("No range specified".to_string()); }
+                    Some(name) => return Err(crate::error::Error::ParseError(format!("Unknown filetype: {}", name))),
+                }
             }
             Command::Set { option, value } => match self.config.set(&option, value.as_deref()) {
-                Ok(()) => { self.message = Some(format!("{} set", option)); }
-                Err(e) => { self.message = Some(e); }
+                Ok(()) => {
+                    self.message = Some(if matches!(option.as_str(), "termguicolors" | "tgc" | "notermguicolors" | "notgc") {
+                        let support = self.detected_colors.with_termguicolors(self.config.termguicolors);
+                        self.screen.set_color_support(support);
+                        format!("{} set ({})", option, support.name())
+                    } else {
+                        format!("{} set", option)
+                    });
+                }
+                Err(e) => { return Err(crate::error::Error::ParseError(e)); }
             },
             Command::Help(topic) => {
                 if let Some(ref t) = topic {
                     let help_text = self.get_help_topic(t);
                     self.message = Some(help_text);
                 } else {
-                    self.help_return_buffer = Some(self.buffers[0].clone());
-                    self.help_return_cursor = Some(self.windows[0].cursor);
-                    self.was_showing_landing_page = self.showing_landing_page;
                     let help_text = r#"Bitsy Keybinds
 
 NORMAL MODE
@@ -170,6 +157,11 @@ NORMAL MODE
   %               Matching bracket
   Ctrl-b/f/u/d    Page/half-page up/down
   ( ) { }         Sentence/paragraph
+  ]m/[m ]]/[[     Next/previous function start (tree-sitter)
+  [c              Start of the scope pinned at the window top
+  zf{motion}      Create fold (also on a visual selection)
+  zo/zc/za        Open/close/toggle fold
+  zR/zM zj/zk     Open/close all folds, next/previous fold
   :               Command mode
   i/a/I/A/o/O     Insert/append
   v/V             Visual/visual line mode
@@ -179,7 +171,7 @@ NORMAL MODE
   u/ctrl-r        Undo/redo
   .               Repeat last change
   >/<             Indent/dedent
-  =               Auto-indent
+  ={motion} ==    Re-indent (tree-sitter indent queries)
   J               Join lines
   m{char}         Set mark
   '{char}/`{char}  Jump to mark
@@ -189,6 +181,7 @@ NORMAL MODE
 VISUAL MODE
   h/j/k/l         Move selection
   d/y             Delete/yank selection
+  a{obj}/i{obj}   Select a text object (iw, ap, af, ia, ...)
   ESC             Exit visual mode
 
 COMMANDS
@@ -198,21 +191,25 @@ COMMANDS
   :set <opt>      Set option
   :help           Show help
   :d <range>      Delete lines
-  :s/find/rep/g   Substitute
+  :m / :t <addr>  Move / copy lines below addr
+  :j              Join lines
+  :g/pat/cmd      Run cmd on matching lines (:v others)
+  :s/find/rep/g   Substitute (any delimiter: :s#a#b#)
+  :&& :~          Repeat last substitute
+  :make :grep     Fill the quickfix list (:copen, :cn, :cp, :cdo)
+  :lvimgrep       Fill the window's location list (:lopen, :lne, :Diagnostics)
 
 SEARCH
   /pattern        Search forward
   ?pattern        Search backward
   n/N             Next/prev match
+  :noh            Clear match highlighting
   * #             Search word under cursor
+  & g&            Repeat :s on line / all lines
 
 note: this is a help buffer - :q to return, or edit as you like!
 "#;
-                    self.buffers[0] = Buffer::from_string(help_text);
-                    self.windows[0].cursor = Cursor::default();
-                    self.viewing_help = true;
-                    self.showing_landing_page = false;
-                    self.message = Some(":q to return to previous buffer".to_string());
+                    self.show_scratch_buffer(help_text);
                 }
             }
             Command::BufferNext => { self.message = Some("Already at the last buffer".to_string()); }
@@ -230,7 +227,8 @@ note: this is a help buffer - :q to return, or edit as you like!
                 let buf_idx = self.current_window().buffer_index;
                 let (w, h) = self.terminal.size();
                 let vh = (h as usize).saturating_sub(2);
-                let new_win = crate::window::Window::new(buf_idx, w as usize, vh / 2);
+                let mut new_win = crate::window::Window::new(buf_idx, w as usize, vh / 2);
+                new_win.location_list = self.current_window().location_list.clone();
                 let new_idx = self.windows.len();
                 self.windows.push(new_win);
                 self.layout.split_vertical(self.active_window, new_idx);
@@ -241,7 +239,8 @@ note: this is a help buffer - :q to return, or edit as you like!
                 let buf_idx = self.current_window().buffer_index;
                 let (w, h) = self.terminal.size();
                 let vh = (h as usize).saturating_sub(2);
-                let new_win = crate::window::Window::new(buf_idx, w as usize / 2, vh);
+                let mut new_win = crate::window::Window::new(buf_idx, w as usize / 2, vh);
+                new_win.location_list = self.current_window().location_list.clone();
                 let new_idx = self.windows.len();
                 self.windows.push(new_win);
                 self.layout.split_horizontal(self.active_window, new_idx);
@@ -286,6 +285,13 @@ note: this is a help buffer - :q to return, or edit as you like!
                     self.message = Some(output);
                 }
             }
+            Command::TSInfo => {
+                self.update_current_syntax();
+                self.message = Some(self.syntax.info(&self.current_grammar(), self.current_buffer()));
+            }
+            Command::Format => { self.format_buffer(); }
+            Command::Symbols => { self.open_symbol_finder(); }
+            Command::Outline => { self.toggle_outline(); }
             Command::Goyo => {
                 self.zen_mode = !self.zen_mode;
                 if self.zen_mode {
@@ -294,7 +300,22 @@ note: this is a help buffer - :q to return, or edit as you like!
             }
             Command::Files => { self.open_file_finder(); }
             Command::Buffers => { self.open_buffer_finder(); }
-            Command::Grep(pattern) => { self.open_grep_finder(&pattern); }
+            Command::Grep { pattern, list: None } => self.open_grep_finder(&pattern)?,
+            Command::Grep { pattern, list: Some(list) } => self.execute_grep(list, &pattern)?,
+            Command::ListOpen { list, height } => self.open_list_window(list, height)?,
+            Command::ListClose(list) => self.close_list_window(list),
+            Command::ListNext { list, count } => self.list_step(list, count, true)?,
+            Command::ListPrevious { list, count } => self.list_step(list, count, false)?,
+            Command::ListSelect { list, entry } => self.list_select(list, entry)?,
+            Command::ListFile { list, file } => self.execute_cfile(list, file)?,
+            Command::ListExpr { list, expr } => self.execute_cexpr(list, &expr)?,
+            Command::Make { list, args } => self.execute_make(list, &args)?,
+            Command::Vimgrep { list, args } => self.execute_vimgrep(list, args)?,
+            Command::ListDo { list, command, per_file } => self.execute_cdo(list, &command, per_file)?,
+            Command::Diagnostics => self.execute_diagnostics()?,
+            Command::Replace { pattern, replacement, ignore_case } => {
+                self.execute_replace(pattern, replacement, ignore_case)?;
+            }
             Command::Terminal => {
                 self.message = Some("Terminal: use :!<cmd> to run commands".to_string());
             }
@@ -305,14 +326,7 @@ note: this is a help buffer - :q to return, or edit as you like!
                         if trimmed.lines().count() <= 1 {
                             self.message = Some(trimmed.to_string());
                         } else {
-                            self.help_return_buffer = Some(self.buffers[0].clone());
-                            self.help_return_cursor = Some(self.windows[0].cursor);
-                            self.was_showing_landing_page = self.showing_landing_page;
-                            self.buffers[0] = Buffer::from_string(trimmed);
-                            self.windows[0].cursor = Cursor::default();
-                            self.viewing_help = true;
-                            self.showing_landing_page = false;
-                            self.message = Some(":q to return to previous buffer".to_string());
+                            self.show_scratch_buffer(trimmed);
                         }
                     }
                     Err(e) => { self.message = Some(format!("Shell error: {}", e)); }
@@ -375,13 +389,14 @@ note: this is a help buffer - :q to return, or edit as you like!
                 self.clamp_cursor();
             }
             Command::Normal { keys, range } => {
-                let line_count = self.current_buffer().line_count();
-                let (start, end) = if let Some(r) = range {
-                    let s = r.start.saturating_sub(1).min(line_count.saturating_sub(1));
-                    let e = if r.end == usize::MAX { line_count.saturating_sub(1) }
-                    else { r.end.saturating_sub(1).min(line_count.saturating_sub(1)) };
-                    (s, e)
-                } else { (0, line_count.saturating_sub(1)) };
+                // the whole buffer without a range, the marked line under :g
+                let (start, end) = if range.is_some() || self.in_global {
+                    self.command_lines(range)
+                } else {
+                    (0, self.current_buffer().line_count().saturating_sub(1))
+                };
+                // the keys start in normal mode, not on the command line they came from
+                self.mode = Mode::Normal;
                 for line in start..=end {
                     self.current_window_mut().cursor.line = line;
                     self.current_window_mut().cursor.col = 0;
@@ -390,9 +405,40 @@ note: this is a help buffer - :q to return, or edit as you like!
                         let key = KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE);
                         self.handle_key(key)?;
                     }
+                    // an insert or visual mode left open ends with the keys
+                    if self.mode != Mode::Normal {
+                        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+                        self.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))?;
+                    }
+                }
+            }
+            Command::DefineCommand { bang, definition } => { self.define_user_command(bang, &definition)?; }
+            Command::DeleteCommand(name) => { self.delete_user_command(&name); }
+            Command::Abbreviate { mode, args } => { self.abbreviate(mode, &args)?; }
+            Command::Unabbreviate { mode, lhs } => { self.unabbreviate(mode, &lhs); }
+            Command::Map { modes, noremap, args } => { self.define_mapping(&modes, noremap, &args)?; }
+            Command::Unmap { modes, lhs } => { self.remove_mapping(&modes, &lhs)?; }
+            Command::Autocmd { bang, args } => {
+                if args.is_empty() && !bang {
+                    let lines: Vec<String> = self.autocmds.all().iter()
+                        .map(|a| format!("{} {} {}", a.event.name(), a.pattern, a.command))
+                        .collect();
+                    self.message = Some(if lines.is_empty() { "No autocommands".to_string() } else { lines.join(" | ") });
+                } else {
+                    self.autocmds.define(bang, &args)?;
+                }
+            }
+            Command::Source(file) => { self.source_command(&file)?; }
+            Command::Colorscheme(None) => { self.message = Some(self.theme.name.clone()); }
+            Command::Colorscheme(Some(name)) => {
+                self.theme = crate::theme::Theme::load(&name).map_err(crate::error::Error::ParseError)?;
+                self.screen.force_redraw();
+            }
+            Command::Unknown(cmd) => {
+                if !self.run_user_command(input)? {
+                    return Err(crate::error::Error::ParseError(format!("Unknown command: {}", cmd)));
                 }
             }
-            Command::Unknown(cmd) => { self.message = Some(format!("Unknown command: {}", cmd)); }
         }
         self.emit_event(crate::event::EditorEvent::CommandExecuted { command: cmd_str });
         Ok(())
@@ -400,144 +446,254 @@ note: this is a help buffer - :q to return, or edit as you like!
 
     pub(super) fn get_help_topic(&self, topic: &str) -> String {
         match topic {
-            "motions" | "movement" => "Motions: hjkl (left/down/up/right), w/b/e (word), gg/G (file start/end), %/0/$ (line), /? (search)".to_string(),
+            "motions" | "movement" => "Motions: hjkl (left/down/up/right), w/b/e (word), gg/G (file start/end), %/0/$ (line), /? (search), ]m/[m (function start), ]]/[[ (outermost function start)".to_string(),
             "operators" | "editing" => "Operators: d (delete), c (change), y (yank), p/P (paste), J (join), u (undo), . (repeat), >/< (indent), = (auto-indent)".to_string(),
             "modes" => "Modes: i/I/a/A/o/O (insert), ESC (normal), v/V (visual), : (command), R (replace)".to_string(),
             "commands" => ":Commands: :w (write), :q (quit), :e (edit), :s/find/replace/g (substitute), :set (options), :help, :d (delete)".to_string(),
-            "textobjects" | "objects" => "Text objects: aw/iw (word), ap/ip (paragraph), a\"/i\" (quotes), a(/i( (parens), a[/i[, a{/i{, a</i<".to_string(),
+            "textobjects" | "objects" => "Text objects: aw/iw (word), ap/ip (paragraph), a\"/i\" (quotes), a(/i( (parens), a[/i[, a{/i{, a</i<; from the syntax tree: af/if (function), ac/ic (class/impl), aa/ia (argument), a//i/ (comment), al/il (loop), queries in runtime/queries/<lang>/textobjects.scm".to_string(),
+            "indent" | "indentation" | "autoindent" => "Indentation: with :set autoindent, o/O/Enter indent the new line and typing }, ), ], else or end at a line start re-indents it. = {motion}, == and visual = re-indent lines. Levels come from runtime/queries/<lang>/indents.scm (@indent, @outdent, @open, @dedent, @keep); other languages follow the line above, indenting after { ( [ or a block keyword ending in ':'. Width is the language's indent from languages.toml, else tabstop, tabs unless expandtab".to_string(),
+            "folding" | "folds" | "foldmethod" => "Folding: :set foldmethod=manual|indent|syntax (syntax uses runtime/queries/<lang>/folds.scm). zf{motion} or visual zf (create), zo/zc/za (open/close/toggle), zR/zM (open/close all), zj/zk (next fold start/previous fold end), zd/zE (delete one/all manual folds). A closed fold moves and deletes as one line".to_string(),
             "marks" => "Marks: m{a-z} (set mark), '{a-z} (jump to mark line), `{a-z} (jump to exact position), g; (next change), g, (prev change)".to_string(),
-            "search" => "Search: /{pattern} (forward), ?{pattern} (backward), n (next), N (prev), * (word forward), # (word backward)".to_string(),
-            "ranges" => "Ranges: % (all lines), 1,10 (lines 1-10), . (current line). Use with :s, :d. Example: %s/old/new/g".to_string(),
+            "global" | "g" | "vglobal" | "v" => "Global: :[range]g/{pattern}/{cmd} runs the ex command on every line (whole file by default) matching pattern, :v/{pattern}/{cmd} or :g! on every line that does not. Lines are marked first; cmd runs with the cursor on each marked line still there, and its own range counts from it: :g/TODO/t$, :g/^$/d, :g/fn /.,+2j, :v/error/d, :g/x/normal A;, :g/^/m0 (reverse the file). One undo step undoes it all".to_string(),
+            "substitute" | "s" => "Substitute: :[range]s/{pattern}/{replacement}/[flags] [count], any delimiter (:s#/usr#/opt#). Replacement: & or \\0 (match), \\1..\\9 (groups), ~ (previous replacement), \\u \\l (next char upper/lower), \\U \\L ... \\E (case until \\E), \\r or \\n (line break), \\t. Flags: & (keep previous flags), g (all matches), c (confirm each: y yes, n no, a all, q quit, l last, ^E/^Y scroll), i/I (ignore/match case), n (count only), e (no error). :s or :& repeats without flags, :&& with them, :~ with the last search pattern; & and g& in normal mode".to_string(),
+            "replace" | "Replace" => "Replace: :Replace /{pattern}/{replacement}/[i|I] substitutes across the files under the working directory (hidden, ignored and binary files left out), with the same pattern and replacement syntax as :s. A preview lists each match before and after; Tab switches the match under the cursor off or on, a file's matches on its path and all of them on the first line. :w makes the rest -- in the buffer being edited as one undo step, other files rewritten through a temporary file, files changed since the preview skipped -- and :q drops them".to_string(),
+            "quickfix" | "make" | "copen" | "cdo" | "errorformat" | "efm" | "location-list" | "lopen" | "vimgrep" | "Diagnostics" => "Quickfix: :make [args] runs makeprg (:set makeprg=cargo\\ build) and reads its output with errorformat, vim patterns (%f file, %l line, %c column, %m message, %t type, %E/%W/%C/%Z multi-line, %-G to skip) or a built-in: rust, gcc, go, pytest, picked from makeprg when unset. :cfile [file] reads errors.err or a file, :cexpr @r, system('cmd') or 'text' an expression, :grep {pattern} the project's matching lines, skipping binary files, those .gitignore or .ignore exclude and, unless they re-include them, node_modules, target, dist, build, __pycache__ and vendor, up to :set grepmax=N (100); Ctrl-Q in the :Rg picker sends its matches. :copen [height] / :cclose show or hide the list, :cnext / :cprevious (with a count), :cc N, :cfirst / :clast jump; :cdo cmd runs an ex command at every entry (:cdo s/a/b/), :cfdo cmd once per file; going on to another file needs the changes written, or :set autowrite to write them. :vimgrep /pattern/[g][j] files searches with the editor's patterns (% the current file, **/*.rs any depth; g every match on a line, j no jump). Each window also has a location list: :lmake, :lgrep, :lvimgrep, :lfile, :lexpr fill it, :lopen / :lclose, :lnext / :lprevious, :ll, :ldo, :lfdo use it; a split copies it. :Diagnostics puts the language server's diagnostics for the file into it, and it follows them as the server publishes new ones".to_string(),
+            "search" => "Search: /{pattern} (forward), ?{pattern} (backward), n (next), N (prev), * (word forward), # (word backward). An offset after the closing / or ? moves the cursor from the match, and n/N keep it: /foo/e (last char), /foo/e+1, /foo/s-2 or b-2 (from the start), /foo/+3 (lines below); // or ?? reuse the last pattern with a new offset. At the prompt Up/Down recall earlier searches starting with what is typed, Ctrl-R Ctrl-W inserts the word under the cursor and Ctrl-R {register} a register. With :set incsearch the cursor jumps to the first match while typing and Esc goes back; matches are highlighted (:set hlsearch) until :noh, and the status line shows [3/17] for the match under the cursor. Patterns are vim regexes: \\< \\> word edges, \\( \\) \\| groups and branches, \\+ \\= \\{n,m} counts, \\d \\w \\s classes, \\n or \\_s to match across lines; \\v very magic, \\V literal, \\c/\\C ignore/match case over :set ignorecase and smartcase".to_string(),
+            "user-commands" | "command" => "User commands: :command[!] [-nargs=0|1|*|?|+] [-range[=%]] Name ex-command, with <args> <q-args> <line1> <line2> <bang>; :delcommand Name".to_string(),
+            "abbreviations" | "abbrev" => "Abbreviations: :iabbrev lhs rhs (insert mode), :cabbrev lhs rhs (command line), :abbreviate (both); expand on a non-keyword character".to_string(),
+            "mappings" | "map" => "Mappings: :map/:nmap/:vmap/:imap/:cmap lhs rhs (recursive), :noremap/:nnoremap/... (non-recursive), :unmap lhs; keys like <CR> <Esc> <C-w> <leader>".to_string(),
+            "autocmd" => "Autocommands: :autocmd Event pattern cmd; events BufRead BufEnter BufWritePre BufWritePost FileType InsertEnter InsertLeave VimEnter VimLeave; :autocmd! Event pattern removes".to_string(),
+            "source" | "init" => "Scripts: :source file runs each line as an ex command; ~/.config/bitsy/init.ex runs at startup. Errors report file:line and the rest of the file still runs".to_string(),
+            "colorscheme" | "colo" | "themes" => "Color schemes: :colorscheme name (Tab completes). Theme files live in ~/.config/bitsy/themes/name.toml: Theme color keys, a [palette], and [highlights] with fg, bg, bold, italic, underline, undercurl. Highlight keys are tree-sitter captures (\"function.macro\", \"type.builtin\"); an unstyled capture uses its parent group".to_string(),
+            "treesitter" | "TSInfo" | "grammars" => "Grammars: compiled in, or loaded from ~/.config/bitsy/runtime/parser/<lang>.so; queries in runtime/queries/<lang>/{highlights,injections,locals,textobjects,folds,indents,tags}.scm override the bundled ones. The `grammar` key in languages.toml picks a language's grammar; [grammar_extensions] in editor.toml maps a grammar to more file extensions. :TSInfo shows what is active".to_string(),
+            "languages" | "filetype" | "ft" | "Format" => "Languages: ~/.config/bitsy/languages.toml adds [[language]] entries or overrides built-in ones by name: extensions, filenames, globs, shebangs, comment, block_comment, indent = { width, tabs }, grammar, language_servers, formatter = { command, args }. :set filetype={name} picks one for the buffer; :Format pipes the buffer through the formatter".to_string(),
+            "context" | "nocontext" => "Context: the lines opening the functions, classes and loops around the top of a window stay pinned above the text, from runtime/queries/<lang>/textobjects.scm. :set context=N caps them (context_lines in editor.toml, default 3), :set nocontext turns them off; [c jumps to the innermost, {count}[c further out. Themes style them with the `context` group".to_string(),
+            "symbols" | "Symbols" | "outline" | "Outline" => "Symbols: :Symbols fuzzy-finds the definitions in the buffer, nested under their parents, with a preview; Enter jumps. :Outline toggles a sidebar listing them with the one around the cursor highlighted. The language server's document symbols are used when one is running, else the grammar's tags.scm query".to_string(),
+            "termguicolors" | "tgc" => format!("Colors: detected from COLORTERM, TERM and terminfo; now {}. :set termguicolors forces 24-bit color, :set notermguicolors uses the 256-color palette", self.screen.color_support().name()),
+            "ranges" => "Ranges: % (all lines), 1,10 (lines 1-10), . (current line), $ (last line), 'a (mark a), /pat/ ?pat? (next line below / above matching), +N / -N (offsets, from the current line when alone: .,+3d, -2,.j), a;b (b counted from a). Use with :s, :d, :m, :t, :j, :g. Example: %s/old/new/g".to_string(),
             _ => format!("No help for '{}'. Try :help motions, :help operators, :help commands", topic),
         }
     }
 
-    pub(super) fn execute_search(&mut self) -> Result<()> {
-        if let Some(pattern) = self.search_pattern.clone() {
-            let start_line = self.current_window().cursor.line;
-            let start_col = if self.search_forward {
-                self.current_window().cursor.col + 1
-            } else {
-                self.current_window().cursor.col.saturating_sub(1)
+    /// show `text` in a scratch buffer in place of the one being edited, which
+    /// :q brings back
+    pub(super) fn show_scratch_buffer(&mut self, text: &str) {
+        if !self.viewing_help {
+            self.help_return_buffer = Some(self.buffers[0].clone());
+            self.help_return_cursor = Some(self.windows[0].cursor);
+            self.was_showing_landing_page = self.showing_landing_page;
+        }
+        self.buffers[0] = Buffer::from_string(text);
+        self.windows[0].cursor = Cursor::default();
+        self.viewing_help = true;
+        self.showing_landing_page = false;
+        self.message = Some(":q to return to previous buffer".to_string());
+    }
+
+    /// put the buffer being edited back in place of the scratch buffer
+    pub(super) fn close_scratch_buffer(&mut self) {
+        if let Some(return_buffer) = self.help_return_buffer.take() {
+            self.buffers[0] = return_buffer;
+            if let Some(return_cursor) = self.help_return_cursor.take() {
+                self.windows[0].cursor = return_cursor;
+            }
+            self.showing_landing_page = self.was_showing_landing_page;
+            self.viewing_help = false;
+        }
+        self.replace_preview = None;
+    }
+
+    /// run `f` with ex addresses resolved against the cursor line, the marks
+    /// and the current buffer's text
+    pub(super) fn with_line_context<R>(&self, f: impl FnOnce(&LineContext) -> R) -> R {
+        let buffer = self.current_buffer();
+        let mark = |c: char| match c {
+            'A'..='Z' => self.global_marks.get(&c).copied(),
+            _ => buffer.get_mark(c),
+        }.map(|(line, _)| line + 1);
+        // from the line below (or above) `line`, wrapping around; an empty
+        // pattern is the last search
+        let search = |pattern: &str, line: usize, forward: bool| {
+            let pattern = match pattern {
+                "" => self.search_pattern.clone()?,
+                pattern => pattern.to_string(),
             };
-            let old_pos = (self.current_window().cursor.line, self.current_window().cursor.col);
-            if self.search_forward {
-                if self.search_forward_from(start_line, start_col, &pattern) {
-                    self.current_buffer_mut().set_mark('\'', old_pos);
-                    self.current_buffer_mut().set_mark('`', old_pos);
-                    return Ok(());
-                }
-                self.message = Some("Pattern not found".to_string());
+            let compiled = self.compile_search(&pattern).ok()?;
+            let rope = buffer.rope();
+            let found = if forward {
+                compiled.find_forward(rope, rope.line_to_byte(line.min(rope.len_lines())))
             } else {
-                if self.search_backward_from(start_line, start_col, &pattern) {
-                    self.current_buffer_mut().set_mark('\'', old_pos);
-                    self.current_buffer_mut().set_mark('`', old_pos);
-                    return Ok(());
-                }
-                self.message = Some("Pattern not found".to_string());
+                compiled.find_backward(rope, rope.line_to_byte(line.saturating_sub(1).min(rope.len_lines())))
+            };
+            found.map(|(m, _)| rope.byte_to_line(m.start) + 1)
+        };
+        f(&LineContext {
+            current: self.current_window().cursor.line + 1,
+            last: buffer.line_count(),
+            mark: &mark,
+            search: &search,
+        })
+    }
+
+    /// compile a search pattern with the ignorecase and smartcase options
+    pub(super) fn compile_search(&self, pattern: &str) -> std::result::Result<SearchPattern, String> {
+        SearchPattern::new(pattern, self.config.ignore_case, self.config.smart_case)
+    }
+
+    /// `compile_search` for drawing, which asks for the same pattern every frame:
+    /// the last one compiled, valid or not, is kept
+    pub(super) fn cached_search(&mut self, pattern: &str) -> Option<SearchPattern> {
+        let key = (pattern.to_string(), self.config.ignore_case, self.config.smart_case);
+        match &self.compiled_search {
+            Some((cached, compiled)) if *cached == key => compiled.clone(),
+            _ => {
+                let compiled = self.compile_search(pattern).ok();
+                self.compiled_search = Some((key, compiled.clone()));
+                compiled
             }
         }
-        Ok(())
     }
 
-    fn search_forward_from(&mut self, start_line: usize, start_col: usize, pattern: &str) -> bool {
-        let line_count = self.current_buffer().line_count();
-        if let Some(line_text) = self.current_buffer().get_line(start_line) {
-            if let Some(pos) = line_text[start_col.min(line_text.len())..].find(pattern) {
-                let window = self.current_window_mut();
-                window.cursor.line = start_line;
-                window.cursor.col = start_col + pos;
-                return true;
+    /// * and #: search for the keyword under or after the cursor as a whole word
+    pub(super) fn search_word_under_cursor(&mut self, forward: bool) -> Result<()> {
+        let cursor = self.current_window().cursor;
+        let word = self.current_buffer().get_line(cursor.line)
+            .and_then(|line| search::word_at(&line, cursor.col));
+        let Some(word) = word else { return Ok(()); };
+        self.search_pattern = Some(format!("\\<{}\\>", word));
+        self.search_offset = SearchOffset::None;
+        self.search_count = None;
+        self.search_forward = forward;
+        self.execute_search()
+    }
+
+    pub(super) fn execute_search(&mut self) -> Result<()> {
+        let Some(source) = self.search_pattern.clone() else { return Ok(()); };
+        let pattern = match self.compile_search(&source) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.message = Some(format!("Invalid pattern: {}", e));
+                return Ok(());
             }
+        };
+        let cursor = self.current_window().cursor;
+        let buffer = &self.buffers[self.windows[self.active_window].buffer_index];
+        let source_id = (buffer.id(), buffer.version());
+        // an offset left the cursor off its match: go on from the match itself
+        let from = match self.search_count {
+            Some(count) if count.at == cursor && count.source == source_id => count.found,
+            _ => cursor,
+        };
+        let rope = buffer.rope();
+        let found = if self.search_forward {
+            pattern.find_forward(rope, search::byte_at(rope, from.line, from.col + 1))
+        } else {
+            pattern.find_backward(rope, search::byte_at(rope, from.line, from.col))
+        };
+        let Some((found, wrapped)) = found else {
+            self.message = Some(format!("Pattern not found: {}", source));
+            return Ok(());
+        };
+        let (match_line, match_col) = search::position(rope, found.start);
+        let (line, col) = self.search_offset.land(rope, &found);
+        let (index, total) = self.search_matches.match_index(&pattern, rope, source_id, found.start);
+        self.search_count = Some(SearchCount {
+            at: Cursor { line, col },
+            found: Cursor { line: match_line, col: match_col },
+            source: source_id,
+            index,
+            total,
+        });
+        self.search_highlight_off = false;
+        let old_pos = (cursor.line, cursor.col);
+        self.current_buffer_mut().set_mark('\'', old_pos);
+        self.current_buffer_mut().set_mark('`', old_pos);
+        self.current_window_mut().cursor.line = line;
+        self.current_window_mut().cursor.col = col;
+        self.clamp_cursor();
+        let landed = self.current_window().cursor;
+        if let Some(count) = self.search_count.as_mut() {
+            count.at = landed;
         }
-        for line_idx in (start_line + 1)..line_count {
-            if let Some(line_text) = self.current_buffer().get_line(line_idx) {
-                if let Some(pos) = line_text.find(pattern) {
-                    let window = self.current_window_mut();
-                    window.cursor.line = line_idx;
-                    window.cursor.col = pos;
-                    return true;
-                }
-            }
+        if wrapped {
+            self.message = Some(if self.search_forward {
+                "search hit BOTTOM, continuing at TOP"
+            } else {
+                "search hit TOP, continuing at BOTTOM"
+            }.to_string());
         }
-        for line_idx in 0..start_line {
-            if let Some(line_text) = self.current_buffer().get_line(line_idx) {
-                if let Some(pos) = line_text.find(pattern) {
-                    let window = self.current_window_mut();
-                    window.cursor.line = line_idx;
-                    window.cursor.col = pos;
-                    self.message = Some("search hit BOTTOM, continuing at TOP".to_string());
-                    return true;
+        self.reveal_cursor_line();
+        Ok(())
+    }
+
+    /// run what was typed at the / or ? prompt, `pattern[/offset]`: an empty
+    /// pattern reuses the last one with the new offset, an empty prompt repeats
+    /// the last search as it was
+    pub(super) fn execute_typed_search(&mut self, typed: &str) -> Result<()> {
+        if !typed.is_empty() {
+            let delim = if self.search_forward { '/' } else { '?' };
+            let (pattern, offset) = match search::parse_search(typed, delim) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    self.message = Some(e);
+                    return Ok(());
                 }
+            };
+            if !pattern.is_empty() {
+                self.search_pattern = Some(pattern);
             }
+            self.search_offset = offset;
+            // a new search goes from the cursor, not from the last match
+            self.search_count = None;
+        }
+        if self.search_pattern.is_none() {
+            self.message = Some("No previous regular expression".to_string());
+            return Ok(());
         }
-        false
+        self.execute_search()
     }
 
-    fn search_backward_from(&mut self, start_line: usize, start_col: usize, pattern: &str) -> bool {
-        if let Some(line_text) = self.current_buffer().get_line(start_line) {
-            let search_text = &line_text[..start_col.min(line_text.len())];
-            if let Some(pos) = search_text.rfind(pattern) {
-                let window = self.current_window_mut();
-                window.cursor.line = start_line;
-                window.cursor.col = pos;
-                return true;
-            }
+    /// with incsearch, show where the search being typed would land: the first
+    /// match from where it started, or that position when nothing matches yet
+    pub(super) fn update_incremental_search(&mut self) {
+        let Some((origin, top)) = self.search_origin.filter(|_| self.config.incremental_search) else { return; };
+        let window = self.current_window_mut();
+        window.cursor = origin;
+        window.viewport.offset_line = top;
+        let delim = if self.search_forward { '/' } else { '?' };
+        let Ok((typed, _)) = search::parse_search(&self.search_buffer, delim) else { return; };
+        // `//e` is the last pattern with a new offset
+        let typed = if typed.is_empty() { self.search_pattern.clone() } else { Some(typed) };
+        let Some(Ok(pattern)) = typed.map(|p| self.compile_search(&p)) else { return; };
+        let rope = self.current_buffer().rope();
+        let found = if self.search_forward {
+            pattern.find_forward(rope, search::byte_at(rope, origin.line, origin.col + 1))
+        } else {
+            pattern.find_backward(rope, search::byte_at(rope, origin.line, origin.col))
+        };
+        if let Some((found, _)) = found {
+            let (line, col) = search::position(rope, found.start);
+            let window = self.current_window_mut();
+            window.cursor = Cursor { line, col };
+            window.viewport.ensure_cursor_visible(line, col, &window.folds);
         }
-        if start_line > 0 {
-            for line_idx in (0..start_line).rev() {
-                if let Some(line_text) = self.current_buffer().get_line(line_idx) {
-                    if let Some(pos) = line_text.rfind(pattern) {
-                        let window = self.current_window_mut();
-                        window.cursor.line = line_idx;
-                        window.cursor.col = pos;
-                        return true;
-                    }
-                }
-            }
-        }
-        let line_count = self.current_buffer().line_count();
-        for line_idx in (start_line + 1..line_count).rev() {
-            if let Some(line_text) = self.current_buffer().get_line(line_idx) {
-                if let Some(pos) = line_text.rfind(pattern) {
-                    let window = self.current_window_mut();
-                    window.cursor.line = line_idx;
-                    window.cursor.col = pos;
-                    self.message = Some("search hit TOP, continuing at BOTTOM".to_string());
-                    return true;
-                }
-            }
+    }
+
+    /// put the cursor and view back where the search being typed started
+    pub(super) fn restore_search_origin(&mut self) {
+        if let Some((origin, top)) = self.search_origin.take() {
+            let window = self.current_window_mut();
+            window.cursor = origin;
+            window.viewport.offset_line = top;
         }
-        false
     }
 
-    pub(super) fn execute_substitute_line(
-        &mut self,
-        line: usize,
-        pattern: &str,
-        replacement: &str,
-        global: bool,
-    ) -> usize {
-        if let Some(line_text) = self.current_buffer().get_line(line) {
-            let new_text = if global {
-                line_text.replace(pattern, replacement)
-            } else {
-                line_text.replacen(pattern, replacement, 1)
-            };
-            let count = if global {
-                line_text.matches(pattern).count()
-            } else {
-                if line_text.contains(pattern) { 1 } else { 0 }
-            };
-            if count > 0 {
-                let line_len = self.current_buffer().line_len(line);
-                self.current_buffer_mut().delete_range(line, 0, line, line_len);
-                for (i, ch) in new_text.chars().enumerate() {
-                    self.current_buffer_mut().insert_char(line, i, ch);
-                }
-            }
-            count
-        } else { 0 }
+    /// the [3/17] of the last search while the cursor is on its match
+    pub(super) fn search_count_text(&self) -> Option<String> {
+        let count = self.search_count?;
+        let buffer = self.current_buffer();
+        (count.at == self.current_window().cursor && count.source == (buffer.id(), buffer.version()))
+            .then(|| format!("[{}/{}]", count.index, count.total))
     }
 
     pub(super) fn open_file_finder(&mut self) {
@@ -552,9 +708,12 @@ note: this is a help buffer - :q to return, or edit as you like!
         self.mode = Mode::FuzzyFind;
     }
 
-    pub(super) fn open_grep_finder(&mut self, pattern: &str) {
+    pub(super) fn open_grep_finder(&mut self, pattern: &str) -> Result<()> {
         let base_path = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-        self.fuzzy_finder = Some(FuzzyFinder::grep(&base_path, pattern));
+        let finder = FuzzyFinder::grep(&base_path, pattern, self.config.grep_max_results)
+            .map_err(Error::EditorError)?;
+        self.fuzzy_finder = Some(finder);
         self.mode = Mode::FuzzyFind;
+        Ok(())
     }
 }
@@ -0,0 +1,111 @@
+// sticky scope context: the lines opening the functions, classes and loops
+// around the top of a window stay pinned over its first rows
+
+use crate::textobject;
+use crate::theme::Style;
+use crate::window::Rect;
+use super::Editor;
+
+impl Editor {
+    /// start lines of the scopes pinned at the top of a window, outermost first;
+    /// never so many that they would cover the cursor or half the window
+    pub(super) fn window_context(&mut self, win_idx: usize) -> Vec<usize> {
+        let buf_idx = self.windows[win_idx].buffer_index;
+        let grammar = self.buffer_grammar(buf_idx);
+        if self.config.context_lines == 0 || !self.syntax.supports(&grammar) {
+            return Vec::new();
+        }
+        self.syntax.update(&mut self.buffers[buf_idx], &grammar);
+        let window = &self.windows[win_idx];
+        let line_count = self.buffers[buf_idx].line_count();
+        let mut max = self.config.context_lines.min(window.viewport.height / 2);
+        // (first, last) buffer line of the rows the context could cover
+        let mut rows: Vec<(usize, usize)> = Vec::new();
+        let mut line = window.folds.display_range(window.viewport.offset_line).0;
+        while rows.len() <= max && line < line_count {
+            let range = window.folds.display_range(line);
+            rows.push(range);
+            line = range.1 + 1;
+        }
+        let cursor = window.cursor.line;
+        if let Some(cursor_row) = rows.iter().position(|&(first, last)| first <= cursor && cursor <= last) {
+            max = max.min(cursor_row);
+        }
+        let (Some(&(first, _)), Some(&(_, last))) = (rows.first(), rows.last()) else { return Vec::new(); };
+        let buffer = &self.buffers[buf_idx];
+        let Some((query, root)) = self.syntax.textobjects(buffer) else { return Vec::new(); };
+        let scopes = textobject::scopes(query, root, buffer.rope(), first..last + 1);
+        textobject::context_lines(&scopes, |row| rows.get(row).map(|r| r.0), max)
+    }
+
+    /// draw the pinned scope lines over the top rows of a window, the last one
+    /// underlined to set them apart from the text scrolling under them
+    pub(super) fn render_context_cells(&mut self, win_idx: usize, rect: Rect, gutter_col: usize, text_col: usize, text_width: usize) {
+        let pinned = self.window_context(win_idx);
+        if pinned.is_empty() {
+            return;
+        }
+        let buf_idx = self.windows[win_idx].buffer_index;
+        let cursor_line = self.windows[win_idx].cursor.line;
+        let offset_col = self.windows[win_idx].viewport.offset_col;
+        let line_num_width = self.config.line_number_width(self.buffers[buf_idx].line_count());
+        let styles: Vec<Style> = (0..crate::syntax::HIGHLIGHT_NAMES.len())
+            .map(|i| crate::syntax::highlight_style(i, &self.theme))
+            .collect();
+        let context = self.theme.context_style();
+        let bg = self.theme.bg;
+        for (row, &line) in pinned.iter().enumerate() {
+            let screen_row = rect.y + row;
+            for col in rect.x..(rect.x + rect.width).min(self.screen.width) {
+                self.screen.put_char(screen_row, col, ' ', self.theme.fg, bg);
+            }
+            self.render_line_number_cells_at(screen_row, gutter_col, line, line_num_width, cursor_line);
+            if text_col > gutter_col + line_num_width {
+                self.screen.put_char(screen_row, gutter_col + line_num_width, '\u{2502}', self.theme.gutter_separator, bg);
+            }
+            let buffer = &mut self.buffers[buf_idx];
+            let text = buffer.get_line(line).unwrap_or_default();
+            let bytes = buffer.line_to_byte(line)..buffer.line_to_byte(line + 1);
+            let spans = self.syntax.highlight(buffer, bytes);
+            let colors = super::render::line_colors(&self.buffers[buf_idx], &spans, &styles, line);
+            self.render_line_content_cells(screen_row, text_col, line, &text, offset_col, text_width, &[], &[], Some(&colors));
+            let last = row + 1 == pinned.len();
+            for col in rect.x..(rect.x + rect.width).min(self.screen.width) {
+                let cell = &mut self.screen.cells[screen_row][col];
+                if cell.bg == bg {
+                    cell.bg = context.bg.unwrap_or(bg);
+                }
+                if let Some(fg) = context.fg.filter(|_| col < text_col) {
+                    cell.fg = fg;
+                }
+                cell.bold |= context.bold;
+                cell.italic |= context.italic;
+                cell.underline |= context.underline || last;
+            }
+        }
+    }
+
+    /// [c: to the start of the innermost pinned scope, `count` levels out; with
+    /// nothing pinned, to the start of the scope around the cursor
+    pub(super) fn move_to_context_start(&mut self, count: usize) {
+        let mut starts = self.window_context(self.active_window);
+        if starts.is_empty() {
+            self.update_current_syntax();
+            let buffer = self.current_buffer();
+            let cursor = self.current_window().cursor.line;
+            let Some((query, root)) = self.syntax.textobjects(buffer) else {
+                self.message = Some("No scope queries for this file type".to_string());
+                return;
+            };
+            starts = textobject::scopes(query, root, buffer.rope(), cursor..cursor + 1).into_iter()
+                .filter(|&(start, end)| start < cursor && cursor <= end)
+                .map(|(start, _)| start)
+                .collect();
+        }
+        let Some(&line) = starts.len().checked_sub(count).and_then(|i| starts.get(i)).or(starts.first()) else { return; };
+        self.save_jump_position();
+        self.current_window_mut().cursor.line = line;
+        self.move_to_first_non_blank();
+        self.clamp_cursor();
+    }
+}
@@ -0,0 +1,147 @@
+// folding: per-window fold state kept in step with the buffer, z commands
+
+use crate::cursor::Cursor;
+use crate::fold::{self, FoldMethod};
+use crate::keymap::Action;
+use crate::mode::Mode;
+use super::{Editor, PendingOperator};
+
+impl Editor {
+    /// bring a window's folds up to date with its buffer: follow the edits made
+    /// since the last sync, then recompute indent or syntax folds
+    pub(super) fn sync_folds(&mut self, win_idx: usize) {
+        let buf_idx = self.windows[win_idx].buffer_index;
+        let (id, version) = (self.buffers[buf_idx].id(), self.buffers[buf_idx].version());
+        let method = self.windows[win_idx].folds.method.unwrap_or(self.config.foldmethod);
+        let (synced_id, synced_version, synced_method) = self.windows[win_idx].folds.synced();
+        if synced_id == id && synced_version == version && synced_method == method { return; }
+        let folds = &mut self.windows[win_idx].folds;
+        if synced_id != id {
+            folds.reset(id, version);
+        } else {
+            match self.buffers[buf_idx].line_edits_since(synced_version) {
+                Some(edits) => edits.for_each(|edit| folds.follow_edit(&edit)),
+                // too many edits to follow: manual folds are lost, others recomputed
+                None if method == FoldMethod::Manual => folds.reset(id, version),
+                None => folds.remeasure(),
+            }
+        }
+        let ranges = match method {
+            FoldMethod::Manual => {
+                self.windows[win_idx].folds.mark_synced(version, method);
+                return;
+            }
+            FoldMethod::Indent => {
+                let width = self.buffer_indent_style(buf_idx).width;
+                let buffer = &self.buffers[buf_idx];
+                let line_count = buffer.line_count();
+                self.windows[win_idx].folds.indent_folds(line_count, width, |line| {
+                    fold::indent_level(buffer.rope().line(line).chars(), width)
+                })
+            }
+            FoldMethod::Syntax => {
+                let grammar = self.buffer_grammar(buf_idx);
+                self.syntax.update(&mut self.buffers[buf_idx], &grammar);
+                let buffer = &self.buffers[buf_idx];
+                self.syntax.folds(buffer)
+                    .map(|(query, root)| fold::syntax_folds(query, root, buffer.rope()))
+                    .unwrap_or_default()
+            }
+        };
+        self.windows[win_idx].folds.replace(ranges, version, method);
+    }
+
+    /// a closed fold behaves as one line: the cursor rests on its first line, and
+    /// a motion that starts on the fold and moves down leaves it; typing opens it
+    pub(super) fn settle_cursor_in_folds(&mut self, before: Cursor) {
+        let win_idx = self.active_window;
+        self.sync_folds(win_idx);
+        let mode = self.mode;
+        let line_count = self.current_buffer().line_count();
+        let window = self.current_window_mut();
+        let line = window.cursor.line;
+        if matches!(mode, Mode::Insert | Mode::Replace) {
+            window.folds.reveal(line);
+            return;
+        }
+        let Some((start, end)) = window.folds.closed_at(line) else { return; };
+        if line == start { return; }
+        if before.line >= start && before.line <= end && line > before.line && end + 1 < line_count {
+            window.cursor = Cursor { line: end + 1, col: 0 };
+        } else {
+            window.cursor.line = start;
+        }
+        self.clamp_cursor();
+    }
+
+    /// open any fold hiding the cursor, e.g. after jumping to a search match
+    pub(super) fn reveal_cursor_line(&mut self) {
+        let window = self.current_window_mut();
+        window.folds.reveal(window.cursor.line);
+    }
+
+    /// j / k: move by screen lines, stepping over closed folds
+    pub(super) fn move_visible_lines(&mut self, count: isize) {
+        self.sync_folds(self.active_window);
+        let line_count = self.current_buffer().line_count();
+        let window = self.current_window_mut();
+        window.cursor.line = window.folds.move_visible(window.cursor.line, count, line_count);
+    }
+
+    /// lines `first..=last` that `count` line-wise commands (dd, yy, cc) cover
+    /// from the cursor, with a closed fold counting as one line
+    pub(super) fn fold_span(&mut self, count: usize) -> (usize, usize) {
+        self.sync_folds(self.active_window);
+        let line_count = self.current_buffer().line_count();
+        let window = self.current_window();
+        window.folds.span(window.cursor.line, count, line_count)
+    }
+
+    pub(super) fn execute_fold_action(&mut self, action: Action) {
+        self.sync_folds(self.active_window);
+        let count = if self.count == 0 { 1 } else { self.count };
+        let line = self.current_window().cursor.line;
+        let mode = self.mode;
+        let folds = &mut self.windows[self.active_window].folds;
+        let found = match action {
+            Action::CreateFold => {
+                if let (Mode::Visual | Mode::VisualLine, Some(selection)) = (mode, self.selection.as_ref()) {
+                    let (start, end) = selection.range();
+                    folds.create(start.line, end.line);
+                    self.mode = Mode::Normal;
+                    self.selection = None;
+                    self.current_window_mut().cursor.line = start.line;
+                } else {
+                    self.pending_operator = PendingOperator::Fold;
+                }
+                true
+            }
+            Action::OpenFold => (0..count).fold(false, |found, _| folds.open(line) || found),
+            Action::CloseFold => (0..count).fold(false, |found, _| folds.close(line) || found),
+            Action::ToggleFold => folds.toggle(line),
+            Action::OpenAllFolds => { folds.set_all(false); true }
+            Action::CloseAllFolds => { folds.set_all(true); true }
+            Action::DeleteFold => folds.delete(line),
+            Action::EliminateFolds => { folds.clear(); true }
+            Action::MoveFoldNext | Action::MoveFoldPrev => {
+                let mut target = None;
+                for _ in 0..count {
+                    let from = target.unwrap_or(line);
+                    let next = if action == Action::MoveFoldNext { folds.next_start(from) } else { folds.prev_end(from) };
+                    match next {
+                        Some(l) => target = Some(l),
+                        None => break,
+                    }
+                }
+                if let Some(l) = target {
+                    self.current_window_mut().cursor = Cursor { line: l, col: 0 };
+                }
+                target.is_some()
+            }
+            _ => true,
+        };
+        if !found {
+            self.message = Some("E490: No fold found".to_string());
+        }
+    }
+}
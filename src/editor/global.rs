@@ -0,0 +1,215 @@
+// :global / :vglobal and the line commands mostly run under them: :d, :m, :t, :j
+
+use crate::command::Range;
+use crate::error::{Error, Result};
+use crate::global;
+use crate::register::RegisterContent;
+use super::Editor;
+
+/// a command changed more lines than the buffer's edit history keeps, so lines
+/// remembered from before it can no longer be followed
+pub(super) fn edits_outrun() -> Error {
+    Error::EditorError("Too many changes to follow the remaining lines".to_string())
+}
+
+impl Editor {
+    /// 0-indexed lines an ex command covers: its range, else the visual lines it
+    /// was started from, else the cursor line
+    pub(super) fn command_lines(&mut self, range: Option<Range>) -> (usize, usize) {
+        let last_line = self.current_buffer().line_count().saturating_sub(1);
+        let visual = self.visual_cmd_range.take();
+        let (first, last) = match (range, visual) {
+            (Some(r), _) => (r.start.saturating_sub(1), r.end.saturating_sub(1)),
+            (None, Some(lines)) => lines,
+            (None, None) => {
+                let line = self.current_window().cursor.line;
+                (line, line)
+            }
+        };
+        (first.min(last_line), last.min(last_line))
+    }
+
+    /// remove lines `first..=last` whole, returning their text
+    fn delete_lines(&mut self, first: usize, last: usize) -> Vec<String> {
+        let buffer = self.current_buffer();
+        let lines: Vec<String> = (first..=last).filter_map(|l| buffer.get_line(l)).collect();
+        let line_count = buffer.line_count();
+        if last + 1 < line_count {
+            self.rec_delete_range(first, 0, last + 1, 0);
+        } else if first > 0 {
+            let end_len = self.current_buffer().line_len(last);
+            let prev_len = self.current_buffer().line_len(first - 1);
+            self.rec_delete_range(first - 1, prev_len, last, end_len);
+        } else {
+            let end_len = self.current_buffer().line_len(last);
+            self.rec_delete_range(0, 0, last, end_len);
+        }
+        lines
+    }
+
+    /// put `lines` below 1-indexed line `dest`, 0 for above the first line
+    fn insert_lines_below(&mut self, dest: usize, lines: &[String]) {
+        let text = lines.join("\n");
+        if dest == 0 {
+            self.rec_insert_text(0, 0, &(text + "\n"));
+        } else {
+            let line = dest - 1;
+            let len = self.current_buffer().line_len(line);
+            self.rec_insert_text(line, len, &format!("\n{}", text));
+        }
+    }
+
+    fn put_cursor_on_line(&mut self, line: usize) {
+        self.current_window_mut().cursor.line = line;
+        self.move_to_first_non_blank();
+        self.clamp_cursor();
+    }
+
+    /// :d -- delete lines into the unnamed register
+    pub(super) fn execute_delete_lines(&mut self, range: Option<Range>) {
+        let (first, last) = self.command_lines(range);
+        self.save_undo_state();
+        let lines = self.delete_lines(first, last);
+        self.end_undo_group();
+        let count = lines.len();
+        self.registers.set_delete(None, RegisterContent::Line(lines));
+        let line_count = self.current_buffer().line_count();
+        self.put_cursor_on_line(first.min(line_count.saturating_sub(1)));
+        self.message = Some(format!("{} line{} deleted", count, if count != 1 { "s" } else { "" }));
+    }
+
+    /// :m -- move lines below `dest`
+    pub(super) fn execute_move_lines(&mut self, range: Option<Range>, dest: usize) -> Result<()> {
+        let (first, last) = self.command_lines(range);
+        if dest > first && dest <= last {
+            return Err(Error::EditorError("Cannot move a range of lines into itself".to_string()));
+        }
+        let dest = dest.min(self.current_buffer().line_count());
+        if dest == first || dest == last + 1 {
+            // already there
+            self.put_cursor_on_line(last);
+            return Ok(());
+        }
+        self.save_undo_state();
+        let lines = self.delete_lines(first, last);
+        let count = lines.len();
+        let dest = if dest > last { dest - count } else { dest };
+        self.insert_lines_below(dest, &lines);
+        self.end_undo_group();
+        self.put_cursor_on_line(dest + count - 1);
+        Ok(())
+    }
+
+    /// :t / :co -- copy lines below `dest`
+    pub(super) fn execute_copy_lines(&mut self, range: Option<Range>, dest: usize) {
+        let (first, last) = self.command_lines(range);
+        let dest = dest.min(self.current_buffer().line_count());
+        let buffer = self.current_buffer();
+        let lines: Vec<String> = (first..=last).filter_map(|l| buffer.get_line(l)).collect();
+        self.save_undo_state();
+        self.insert_lines_below(dest, &lines);
+        self.end_undo_group();
+        self.put_cursor_on_line(dest + lines.len() - 1);
+    }
+
+    /// join `count` lines from `line` into one; with `spaces` the leading white
+    /// space of each joined line becomes a single space, as J does, without it
+    /// the lines are put together as they are, as gJ does
+    fn join_lines(&mut self, line: usize, count: usize, spaces: bool) {
+        for _ in 1..count.max(2) {
+            if line + 1 >= self.current_buffer().line_count() {
+                break;
+            }
+            let len = self.current_buffer().line_len(line);
+            let current = self.current_buffer().get_line(line).unwrap_or_default();
+            let next = self.current_buffer().get_line(line + 1).unwrap_or_default();
+            let lead = if spaces { next.chars().take_while(|c| c.is_whitespace()).count() } else { 0 };
+            self.rec_delete_range(line, len, line + 1, lead);
+            let rest = next.trim_start();
+            let ends_blank = current.ends_with(char::is_whitespace) || current.is_empty();
+            if spaces && !ends_blank && !rest.is_empty() && !rest.starts_with(')') {
+                self.rec_insert_text(line, len, " ");
+            }
+        }
+    }
+
+    /// :j -- join the range, or the line with the next one
+    pub(super) fn execute_join_lines(&mut self, range: Option<Range>, spaces: bool) {
+        let (first, last) = self.command_lines(range);
+        self.save_undo_state();
+        self.join_lines(first, last - first + 1, spaces);
+        self.end_undo_group();
+        self.put_cursor_on_line(first);
+    }
+
+    /// :g/pattern/cmd -- mark the lines of the range (all by default) that match,
+    /// or with `invert` those that do not, then run `cmd` with the cursor on each
+    /// marked line still there; everything is one undo step
+    pub(super) fn execute_global(&mut self, pattern: String, command: String, invert: bool, range: Option<Range>) -> Result<()> {
+        if self.in_global {
+            return Err(Error::EditorError("Cannot do :global recursively".to_string()));
+        }
+        let pattern = if pattern.is_empty() {
+            self.search_pattern.clone().ok_or_else(|| Error::EditorError("No previous regular expression".to_string()))?
+        } else { pattern };
+        let compiled = self.compile_search(&pattern)
+            .map_err(|e| Error::EditorError(format!("Invalid pattern: {}", e)))?;
+        let line_count = self.current_buffer().line_count();
+        let (first, last) = match range {
+            Some(_) => self.command_lines(range),
+            None => self.visual_cmd_range.take().unwrap_or((0, line_count.saturating_sub(1))),
+        };
+        let marks = global::matching_lines(self.current_buffer().rope(), &compiled, first, last, invert);
+        self.search_pattern = Some(pattern.clone());
+        self.search_highlight_off = false;
+        if marks.is_empty() {
+            self.message = Some(if invert {
+                format!("Pattern found in every line: {}", pattern)
+            } else {
+                format!("Pattern not found: {}", pattern)
+            });
+            return Ok(());
+        }
+        if command.is_empty() {
+            self.message = Some(format!("{} matching line{}", marks.len(), if marks.len() == 1 { "" } else { "s" }));
+            return Ok(());
+        }
+
+        let mut marks = global::Marks::new(marks);
+        let buf_idx = self.windows[self.active_window].buffer_index;
+        self.save_undo_state();
+        self.in_global = true;
+        let mut result = Ok(());
+        let mut version = self.buffers[buf_idx].version();
+        while let Some(line) = marks.pop_front() {
+            self.current_window_mut().cursor.line = line;
+            self.current_window_mut().cursor.col = 0;
+            if let Err(e) = self.execute_command_str(&command) {
+                result = Err(e);
+                break;
+            }
+            if self.windows[self.active_window].buffer_index != buf_idx {
+                break;
+            }
+            // keep the remaining marks on their lines
+            let Some(edits) = self.buffers[buf_idx].line_edits_since(version) else {
+                result = Err(edits_outrun());
+                break;
+            };
+            for edit in edits.filter(|edit| edit.delta != 0) {
+                marks.follow(edit.at, edit.delta);
+            }
+            version = self.buffers[buf_idx].version();
+        }
+        self.in_global = false;
+        self.end_undo_group();
+        self.clamp_cursor();
+
+        let now = self.buffers[buf_idx].line_count();
+        if now != line_count {
+            let (n, what) = if now < line_count { (line_count - now, "fewer") } else { (now - line_count, "more") };
+            self.message = Some(format!("{} {} line{}", n, what, if n == 1 { "" } else { "s" }));
+        }
+        result
+    }
+}
@@ -8,11 +8,15 @@ use super::Editor;
 
 impl Editor {
     pub(super) fn save_undo_state(&mut self) {
-        self.undo_manager.begin_group();
+        if !self.in_global {
+            self.undo_manager.begin_group();
+        }
     }
 
     pub(super) fn end_undo_group(&mut self) {
-        self.undo_manager.end_group();
+        if !self.in_global {
+            self.undo_manager.end_group();
+        }
     }
 
     // record+apply helpers -- use these instead of calling buffer methods directly
@@ -34,6 +38,12 @@ impl Editor {
         self.current_buffer_mut().insert_newline(line, col);
     }
 
+    pub(super) fn rec_insert_text(&mut self, line: usize, col: usize, text: &str) {
+        let op = UndoOp::InsertRange { line, col, text: text.to_string() };
+        op.apply(self.current_buffer_mut());
+        self.undo_manager.record(op);
+    }
+
     pub(super) fn rec_delete_range(&mut self, start_line: usize, start_col: usize, end_line: usize, end_col: usize) {
         let text = self.current_buffer().get_range_text(start_line, start_col, end_line, end_col);
         self.undo_manager.record(UndoOp::DeleteRange { line: start_line, col: start_col, text });
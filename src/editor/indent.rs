@@ -0,0 +1,94 @@
+// language-aware indentation: =, o/O/Enter and re-indenting on } / else / end
+
+use crate::indent::{self, IndentStyle};
+use super::Editor;
+
+impl Editor {
+    fn indent_style(&self) -> IndentStyle {
+        self.buffer_indent_style(self.windows[self.active_window].buffer_index)
+    }
+
+    /// the indentation `line` of the current buffer should have, from a syntax
+    /// tree brought up to date beforehand (see update_current_syntax)
+    fn computed_indent(&self, line: usize) -> Option<usize> {
+        let buffer = self.current_buffer();
+        indent::indent_for_line(self.syntax.indents(buffer), buffer.rope(), line, &self.indent_style())
+    }
+
+    /// replace the leading whitespace of `line` with `columns` of indentation;
+    /// returns the old and new whitespace lengths in chars
+    fn set_line_indent(&mut self, line: usize, columns: usize) -> (usize, usize) {
+        let text = self.current_buffer().get_line(line).unwrap_or_default();
+        let old = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        let new = self.indent_style().string(columns);
+        if text[..old] != new {
+            if old > 0 { self.rec_delete_range(line, 0, line, old); }
+            for (i, ch) in new.chars().enumerate() {
+                self.rec_insert_char(line, i, ch);
+            }
+        }
+        (old, new.chars().count())
+    }
+
+    /// =: re-indent lines from the syntax tree, or from the lines above for
+    /// languages without an indents query; blank lines lose their whitespace
+    pub(super) fn apply_auto_indent(&mut self, start_line: usize, end_line: usize) {
+        self.update_current_syntax();
+        let last = end_line.min(self.current_buffer().line_count().saturating_sub(1));
+        for line in start_line..=last {
+            let text = self.current_buffer().get_line(line).unwrap_or_default();
+            if text.trim().is_empty() {
+                if !text.is_empty() { self.rec_delete_range(line, 0, line, text.chars().count()); }
+                continue;
+            }
+            if let Some(columns) = self.computed_indent(line) {
+                self.set_line_indent(line, columns);
+            }
+        }
+        let lines = last + 1 - start_line;
+        self.message = Some(format!("{} line{} indented", lines, if lines == 1 { "" } else { "s" }));
+        let indent = self.current_buffer().get_line(start_line).unwrap_or_default()
+            .chars().take_while(|c| c.is_whitespace()).count();
+        self.current_window_mut().cursor = crate::cursor::Cursor { line: start_line, col: indent };
+    }
+
+    /// indent the line o, O or Enter just put the cursor on; the cursor goes to
+    /// the start of its text
+    pub(super) fn indent_new_line(&mut self) {
+        if !self.config.auto_indent { return; }
+        self.update_current_syntax();
+        let line = self.current_window().cursor.line;
+        let Some(columns) = self.computed_indent(line) else { return; };
+        let (_, new) = self.set_line_indent(line, columns);
+        self.current_window_mut().cursor.col = new;
+        let blank = self.current_buffer().get_line(line).is_some_and(|t| t.trim().is_empty());
+        self.auto_indented_line = if blank && new > 0 { Some(line) } else { None };
+    }
+
+    /// drop the indent o, O or Enter added when the line is left with nothing
+    /// typed on it
+    pub(super) fn clear_unused_indent(&mut self) {
+        let Some(line) = self.auto_indented_line.take() else { return; };
+        if self.current_window().cursor.line != line { return; }
+        let text = self.current_buffer().get_line(line).unwrap_or_default();
+        if !text.is_empty() && text.trim().is_empty() {
+            self.rec_delete_range(line, 0, line, text.chars().count());
+            self.current_window_mut().cursor.col = 0;
+        }
+    }
+
+    /// after a character typed in insert mode: re-indent the line when it now
+    /// starts with a closing bracket or a branch keyword such as `else` or `end`
+    pub(super) fn reindent_after_typing(&mut self) {
+        self.auto_indented_line = None;
+        if !self.config.auto_indent { return; }
+        let (line, col) = (self.current_window().cursor.line, self.current_window().cursor.col);
+        let text = self.current_buffer().get_line(line).unwrap_or_default();
+        let before: String = text.chars().take(col).collect();
+        if !indent::is_reindent_trigger(&before) { return; }
+        self.update_current_syntax();
+        let Some(columns) = self.computed_indent(line) else { return; };
+        let (old, new) = self.set_line_indent(line, columns);
+        self.current_window_mut().cursor.col = col + new - old;
+    }
+}
@@ -1,9 +1,9 @@
 // key input handling: normal, command, search, fuzzy find modes
 
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::fs;
 use crate::error::Result;
-use crate::keymap::{map_key, Action};
+use crate::keymap::{self, map_key, Action, KeyHint, LeaderMatch};
 use crate::mode::Mode;
 use crate::register::RegisterContent;
 use super::{Editor, MarkAction, PendingOperator, TextObjectModifier};
@@ -53,13 +53,24 @@ impl Editor {
             }
         }
 
-        if self.mode == Mode::Command {
+        if self.confirm_substitute.is_some() {
+            self.handle_confirm_substitute_key(key);
+        } else if self.mode == Mode::Command {
             self.handle_command_mode_key(key)?;
         } else if self.mode == Mode::Search {
             self.handle_search_mode_key(key)?;
         } else if self.mode == Mode::FuzzyFind {
             self.handle_fuzzy_find_key(key)?;
         } else {
+            if self.mode == Mode::Normal && self.handle_leader_key(key)? {
+                return Ok(());
+            }
+            if self.mode == Mode::Normal && self.pending_operator == PendingOperator::None
+                && self.handle_replace_preview_key(key)
+            {
+                return Ok(());
+            }
+
             // handle mark operations (m, ', `)
             if self.mode == Mode::Normal && self.waiting_for_mark.is_some() {
                 if let KeyCode::Char(c) = key.code {
@@ -212,15 +223,32 @@ impl Editor {
                 }
                 let sequence_action = self.map_key_sequence(prefix, key);
                 self.pending_key = None;
+                self.end_prefix();
                 sequence_action
             } else if key.code == KeyCode::Char('g') && self.mode == Mode::Normal {
                 self.pending_key = Some('g');
+                self.start_prefix();
+                return Ok(());
+            } else if key.code == KeyCode::Char('z')
+                && matches!(self.mode, Mode::Normal | Mode::Visual | Mode::VisualLine)
+                && self.pending_text_object.is_none()
+            {
+                self.pending_key = Some('z');
+                self.start_prefix();
+                return Ok(());
+            } else if matches!(key.code, KeyCode::Char('[') | KeyCode::Char(']'))
+                && matches!(self.mode, Mode::Normal | Mode::Visual | Mode::VisualLine)
+                && self.pending_text_object.is_none()
+            {
+                self.pending_key = if key.code == KeyCode::Char('[') { Some('[') } else { Some(']') };
+                self.start_prefix();
                 return Ok(());
             } else if key.code == KeyCode::Char('r') && self.mode == Mode::Normal {
                 self.pending_key = Some('r');
                 return Ok(());
             } else if key.code == KeyCode::Char('w') && key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) && self.mode == Mode::Normal {
                 self.pending_key = Some('\x17'); // Ctrl-W marker
+                self.start_prefix();
                 return Ok(());
             } else {
                 map_key(key, &self.mode)
@@ -251,6 +279,21 @@ impl Editor {
                 }
             }
 
+            // text objects replace the selection in visual mode
+            if matches!(self.mode, Mode::Visual | Mode::VisualLine) && self.pending_operator == PendingOperator::None {
+                if self.pending_text_object.is_some() {
+                    self.handle_text_object(key)?;
+                    return Ok(());
+                } else if matches!(key.code, KeyCode::Char('a') | KeyCode::Char('i')) {
+                    self.pending_text_object = if key.code == KeyCode::Char('a') {
+                        Some(TextObjectModifier::Around)
+                    } else {
+                        Some(TextObjectModifier::Inner)
+                    };
+                    return Ok(());
+                }
+            }
+
             // handle text object composition
             if self.pending_operator != PendingOperator::None {
                 if matches!(key.code, KeyCode::Char('a') | KeyCode::Char('i'))
@@ -283,41 +326,130 @@ impl Editor {
     }
 
     pub(super) fn map_key_sequence(&self, prefix: char, key: KeyEvent) -> Action {
-        if prefix == 'g' {
-            match key.code {
-                KeyCode::Char('g') => Action::MoveFileStart,
-                KeyCode::Char('e') => Action::MoveWordEndBack,
-                KeyCode::Char('E') => Action::MoveWordEndBackBig,
-                KeyCode::Char('_') => Action::MoveLineEndNonBlank,
-                KeyCode::Char('0') => Action::MoveLineStartDisplay,
-                KeyCode::Char('$') => Action::MoveLineEndDisplay,
-                KeyCode::Char('J') => Action::JoinNoSpace,
-                KeyCode::Char('u') => Action::MakeLowercase,
-                KeyCode::Char('U') => Action::MakeUppercase,
-                KeyCode::Char('~') => Action::ToggleCase,
-                KeyCode::Char(';') => Action::JumpToChangeNext,
-                KeyCode::Char(',') => Action::JumpToChangePrev,
-                _ => Action::None,
-            }
-        } else if prefix == 'r' {
-            match key.code {
+        if prefix == 'r' {
+            return match key.code {
                 KeyCode::Char(c) => Action::Replace(c),
                 _ => Action::None,
+            };
+        }
+        let (Some(table), KeyCode::Char(c)) = (keymap::prefix_table(prefix), key.code) else {
+            return Action::None;
+        };
+        table.iter()
+            .find(|(k, _, _)| *k == c)
+            .map(|(_, action, _)| action.clone())
+            .unwrap_or(Action::None)
+    }
+
+    /// feed a key to the leader sequence; returns true if the key was consumed
+    fn handle_leader_key(&mut self, key: KeyEvent) -> Result<bool> {
+        let Some(mut seq) = self.leader_sequence.take() else {
+            // only claim the leader key when something is bound to it
+            let is_leader = matches!(key.code, KeyCode::Char(c) if Some(c) == self.config.leader_key);
+            if is_leader
+                && !self.config.leader_bindings.is_empty()
+                && self.pending_operator == PendingOperator::None
+                && self.pending_key.is_none()
+                && self.surround_pending.is_none()
+                && self.waiting_for_mark.is_none()
+                && !self.waiting_for_register
+            {
+                self.leader_sequence = Some(String::new());
+                self.start_prefix();
+                return Ok(true);
+            }
+            return Ok(false);
+        };
+        let c = match key.code {
+            KeyCode::Char(c) => c,
+            KeyCode::Backspace if !seq.is_empty() => {
+                seq.pop();
+                self.leader_sequence = Some(seq);
+                self.leader_pending_since = None;
+                return Ok(true);
+            }
+            _ => {
+                self.end_prefix();
+                self.count = 0;
+                return Ok(true);
+            }
+        };
+        seq.push(c);
+        self.leader_pending_since = None;
+        match keymap::match_leader_sequence(&self.config.leader_bindings, &seq) {
+            LeaderMatch::Prefix => { self.leader_sequence = Some(seq); }
+            LeaderMatch::ActionOrPrefix(_) => {
+                self.leader_sequence = Some(seq);
+                self.leader_pending_since = Some(std::time::Instant::now());
+            }
+            LeaderMatch::Action(action) => {
+                self.end_prefix();
+                self.execute_action(Action::LeaderAction(action))?;
+                self.count = 0;
             }
-        } else if prefix == '\x17' { // Ctrl-W prefix
-            match key.code {
-                KeyCode::Char('s') => Action::WindowSplitH,
-                KeyCode::Char('v') => Action::WindowSplitV,
-                KeyCode::Char('c') => Action::WindowClose,
-                KeyCode::Char('h') => Action::WindowFocusLeft,
-                KeyCode::Char('j') => Action::WindowFocusDown,
-                KeyCode::Char('k') => Action::WindowFocusUp,
-                KeyCode::Char('l') => Action::WindowFocusRight,
-                KeyCode::Char('w') => Action::WindowCycle,
-                KeyCode::Char('=') => Action::WindowEqualize,
-                _ => Action::None,
+            LeaderMatch::NoMatch => {
+                self.end_prefix();
+                self.count = 0;
+                // a shorter sequence may have been bound before this key broke the
+                // longer one: run it, then the key as usual if still in normal mode
+                seq.pop();
+                if let LeaderMatch::ActionOrPrefix(action) = keymap::match_leader_sequence(&self.config.leader_bindings, &seq) {
+                    self.execute_action(Action::LeaderAction(action))?;
+                    return Ok(self.mode != Mode::Normal);
+                }
+                self.message = Some(format!("<leader>{}{} is not mapped", seq, c));
             }
-        } else { Action::None }
+        }
+        Ok(true)
+    }
+
+    /// true once a bound leader sequence has waited longer than timeout_len_ms for a longer one
+    pub(super) fn leader_timeout_due(&self) -> bool {
+        self.leader_pending_since
+            .is_some_and(|t| t.elapsed().as_millis() >= self.config.timeout_len_ms as u128)
+    }
+
+    /// stop waiting for a longer leader sequence and run the one typed
+    pub(super) fn resolve_pending_leader(&mut self) -> Result<()> {
+        self.leader_pending_since = None;
+        let Some(seq) = self.leader_sequence.clone() else { return Ok(()); };
+        self.end_prefix();
+        self.count = 0;
+        if let LeaderMatch::ActionOrPrefix(action) = keymap::match_leader_sequence(&self.config.leader_bindings, &seq) {
+            self.execute_action(Action::LeaderAction(action))?;
+        }
+        Ok(())
+    }
+
+    fn start_prefix(&mut self) {
+        self.prefix_started = Some(std::time::Instant::now());
+    }
+
+    fn end_prefix(&mut self) {
+        self.leader_sequence = None;
+        self.leader_pending_since = None;
+        self.prefix_started = None;
+        self.which_key_visible = false;
+    }
+
+    /// title and entries for the which-key popup for the pending prefix, if any
+    pub(super) fn which_key_hints(&self) -> Option<(String, Vec<KeyHint>)> {
+        if let Some(ref seq) = self.leader_sequence {
+            let hints = keymap::leader_hints(&self.config.leader_bindings, &self.config.leader_groups, seq);
+            return Some((format!("<leader>{}", seq), hints));
+        }
+        let prefix = self.pending_key?;
+        let title = if prefix == '\x17' { "<C-w>".to_string() } else { prefix.to_string() };
+        let hints = keymap::prefix_hints(prefix);
+        if hints.is_empty() { None } else { Some((title, hints)) }
+    }
+
+    /// true once a prefix has been pending for longer than the which-key delay
+    pub(super) fn which_key_due(&self) -> bool {
+        match self.prefix_started {
+            Some(t) => t.elapsed().as_millis() >= self.config.which_key_delay_ms as u128,
+            None => false,
+        }
     }
 
     pub(super) fn generate_completions(&mut self) {
@@ -338,15 +470,25 @@ impl Editor {
                     }
                 }
             }
+        } else if let Some((cmd, prefix)) = input.split_once(' ').filter(|(c, _)| *c == "colo" || *c == "colorscheme") {
+            for name in crate::theme::Theme::available() {
+                if name.starts_with(prefix.trim_start()) {
+                    self.completion_candidates.push(format!("{} {}", cmd, name));
+                }
+            }
         } else if !input.contains(' ') {
             let commands = vec![
                 "w", "write", "q", "quit", "wq", "x", "q!", "e", "edit",
                 "bn", "bnext", "bp", "bprevious", "bd", "bdelete",
                 "ls", "buffers", "sp", "split", "vsp", "vsplit", "close", "help", "set",
+                "colorscheme", "TSInfo",
             ];
             for cmd in commands {
                 if cmd.starts_with(input) { self.completion_candidates.push(cmd.to_string()); }
             }
+            for name in self.user_commands.keys() {
+                if name.starts_with(input) { self.completion_candidates.push(name.clone()); }
+            }
         }
         self.completion_candidates.sort();
     }
@@ -368,6 +510,7 @@ impl Editor {
                     }
                 }
                 self.clear_substitute_preview();
+                self.expand_command_abbreviation();
                 self.execute_command()?;
                 self.mode = Mode::Normal;
                 self.command_buffer.clear();
@@ -419,6 +562,9 @@ impl Editor {
                 }
             }
             KeyCode::Char(c) => {
+                if !crate::user_command::is_keyword_char(c) {
+                    self.expand_command_abbreviation();
+                }
                 self.command_buffer.push(c);
                 self.completion_candidates.clear();
                 self.completion_index = None;
@@ -441,31 +587,87 @@ impl Editor {
     }
 
     pub(super) fn handle_search_mode_key(&mut self, key: KeyEvent) -> Result<()> {
+        if std::mem::take(&mut self.search_insert_pending) {
+            self.insert_into_search(key);
+            return Ok(());
+        }
         match key.code {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
                 self.search_buffer.clear();
+                self.search_history.reset();
+                self.restore_search_origin();
             }
             KeyCode::Enter => {
-                if !self.search_buffer.is_empty() {
-                    self.search_pattern = Some(self.search_buffer.clone());
-                    self.execute_search()?;
-                }
+                // search from where it started, not from the incsearch match
+                self.restore_search_origin();
                 self.mode = Mode::Normal;
-                self.search_buffer.clear();
+                let typed = std::mem::take(&mut self.search_buffer);
+                self.search_history.add(&typed);
+                self.execute_typed_search(&typed)?;
+                let window = self.current_window_mut();
+                window.viewport.ensure_cursor_visible(window.cursor.line, window.cursor.col, &window.folds);
+            }
+            KeyCode::Up => {
+                if let Some(entry) = self.search_history.older(&self.search_buffer) {
+                    self.search_buffer = entry;
+                    self.update_incremental_search();
+                }
+            }
+            KeyCode::Down => {
+                if let Some(entry) = self.search_history.newer() {
+                    self.search_buffer = entry;
+                    self.update_incremental_search();
+                }
+            }
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.search_insert_pending = true;
+            }
+            KeyCode::Char(c) => {
+                self.search_buffer.push(c);
+                self.search_history.reset();
+                self.update_incremental_search();
             }
-            KeyCode::Char(c) => { self.search_buffer.push(c); }
             KeyCode::Backspace => {
                 self.search_buffer.pop();
-                if self.search_buffer.is_empty() { self.mode = Mode::Normal; }
+                self.search_history.reset();
+                if self.search_buffer.is_empty() {
+                    self.mode = Mode::Normal;
+                    self.restore_search_origin();
+                } else {
+                    self.update_incremental_search();
+                }
             }
             _ => {}
         }
         Ok(())
     }
 
+    /// Ctrl-R at the search prompt, then `key`: Ctrl-W puts in the word under
+    /// the cursor, a register name that register
+    fn insert_into_search(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let text = match key.code {
+            KeyCode::Char('w') if ctrl => {
+                let cursor = self.search_origin.map_or(self.current_window().cursor, |(origin, _)| origin);
+                self.current_buffer().get_line(cursor.line).and_then(|line| crate::search::word_at(&line, cursor.col))
+            }
+            KeyCode::Char(c) if !ctrl => self.registers.get(Some(c))
+                .map(|content| content.as_string().trim_end_matches('\n').replace('\n', "\\n")),
+            _ => None,
+        };
+        if let Some(text) = text {
+            self.search_buffer.push_str(&text);
+            self.search_history.reset();
+            self.update_incremental_search();
+        }
+    }
+
     pub(super) fn handle_fuzzy_find_key(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
+            KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.grep_picker_to_quickfix()?;
+            }
             KeyCode::Esc => {
                 self.fuzzy_finder = None;
                 self.mode = Mode::Normal;
@@ -475,6 +677,7 @@ impl Editor {
                     if let Some(selected) = finder.selected_item() {
                         let selected = selected.to_string();
                         let finder_type = finder.finder_type;
+                        let symbol = finder.selected_symbol().map(|s| (s.line, s.col));
                         self.fuzzy_finder = None;
                         self.mode = Mode::Normal;
                         match finder_type {
@@ -500,6 +703,11 @@ impl Editor {
                                     }
                                 }
                             }
+                            crate::fuzzy_finder::FinderType::Symbols => {
+                                if let Some((line, col)) = symbol {
+                                    self.jump_to_symbol(line, col);
+                                }
+                            }
                         }
                     }
                 }
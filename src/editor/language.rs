@@ -0,0 +1,122 @@
+// per-buffer language: detection through the registry, and the grammar, indent
+// style, language server and formatter that come with it
+
+use std::io::Write;
+use std::process::{Command as Process, Stdio};
+use crate::indent::IndentStyle;
+use crate::language::Language;
+use super::Editor;
+
+impl Editor {
+    /// set a buffer's language again from its path and first lines, e.g. once
+    /// it has a file name
+    pub(super) fn detect_language(&mut self, buf_idx: usize) {
+        self.buffers[buf_idx].detect_language(&self.languages);
+    }
+
+    pub(crate) fn buffer_language(&self, buf_idx: usize) -> Option<&Language> {
+        self.buffers[buf_idx].language().and_then(|name| self.languages.get(name))
+    }
+
+    /// tree-sitter grammar for a buffer, empty when it has no language
+    pub(crate) fn buffer_grammar(&self, buf_idx: usize) -> String {
+        match self.buffer_language(buf_idx) {
+            Some(language) => language.grammar().to_string(),
+            None => self.buffers[buf_idx].language().unwrap_or("").to_string(),
+        }
+    }
+
+    /// the language's indent settings, else tab_width / expand_tab; never
+    /// narrower than one column
+    pub(crate) fn buffer_indent_style(&self, buf_idx: usize) -> IndentStyle {
+        let style = self.buffer_language(buf_idx).and_then(|l| l.indent)
+            .unwrap_or(IndentStyle { width: self.config.tab_width, tabs: !self.config.expand_tab });
+        IndentStyle { width: style.width.max(1), ..style }
+    }
+
+    /// start the first of the current language's servers that launches, and
+    /// open the current file with it
+    pub(super) fn start_language_server(&mut self) {
+        let buf_idx = self.windows[self.active_window].buffer_index;
+        let Some(language) = self.buffer_language(buf_idx) else { return; };
+        let Some(path) = self.buffers[buf_idx].file_path() else { return; };
+        let (name, servers, uri) = (language.name.clone(), language.language_servers.clone(), format!("file://{}", path.display()));
+        let root = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
+        for server in servers {
+            let args: Vec<&str> = server.args.iter().map(|a| a.as_str()).collect();
+            match self.lsp_client.start(&server.command, &args, &root) {
+                Ok(()) => {
+                    let text = self.buffers[buf_idx].get_all_text().unwrap_or_default();
+                    let _ = self.lsp_client.did_open(&uri, &name, &text);
+                    return;
+                }
+                Err(e) => log::warn!("LSP start failed: {}", e),
+            }
+        }
+    }
+
+    /// :Format -- pipe the buffer through the language's formatter and replace the
+    /// lines that changed, as one undo step
+    pub(super) fn format_buffer(&mut self) {
+        let buf_idx = self.windows[self.active_window].buffer_index;
+        let Some(language) = self.buffer_language(buf_idx) else {
+            self.message = Some("No language detected for this buffer".to_string());
+            return;
+        };
+        let Some(formatter) = language.formatter.clone() else {
+            self.message = Some(format!("No formatter configured for {}", language.name));
+            return;
+        };
+        let text = self.current_buffer().get_all_text().unwrap_or_default();
+        let output = Process::new(&formatter.command)
+            .args(&formatter.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(text.as_bytes())?;
+                }
+                child.wait_with_output()
+            });
+        let formatted = match output {
+            Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).to_string(),
+            Ok(out) => {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                let first = stderr.lines().find(|l| !l.trim().is_empty()).unwrap_or("").to_string();
+                self.message = Some(format!("{} failed: {}", formatter.command, first));
+                return;
+            }
+            Err(e) => {
+                self.message = Some(format!("{}: {}", formatter.command, e));
+                return;
+            }
+        };
+        if formatted == text {
+            self.message = Some("Already formatted".to_string());
+            return;
+        }
+        let old: Vec<&str> = text.split('\n').collect();
+        let new: Vec<&str> = formatted.split('\n').collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b).count();
+        let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+        self.save_undo_state();
+        if suffix > 0 {
+            // whole lines, each with its newline
+            self.rec_delete_range(prefix, 0, old_end, 0);
+            let inserted: String = new[prefix..new_end].iter().map(|l| format!("{}\n", l)).collect();
+            self.rec_insert_text(prefix, 0, &inserted);
+        } else {
+            let last = old.len() - 1;
+            self.rec_delete_range(prefix, 0, last, old[last].chars().count());
+            self.rec_insert_text(prefix, 0, &new[prefix..].join("\n"));
+        }
+        self.end_undo_group();
+        self.clamp_cursor();
+        let changed = new_end - prefix;
+        self.message = Some(format!("Formatted with {}: {} line{} changed", formatter.command, changed, if changed == 1 { "" } else { "s" }));
+    }
+}
@@ -2,12 +2,25 @@
 
 mod action;
 mod command_exec;
+mod context;
+mod fold;
+mod global;
 mod history;
+mod indent;
 mod input;
+mod language;
 mod motion;
 mod operator;
+mod quickfix;
 mod render;
+mod replace;
+mod script;
+mod substitute;
 mod surround;
+mod symbols;
+#[cfg(test)]
+mod tests;
+mod user_command;
 
 use crossterm::event::{Event, KeyEvent};
 use notify::{RecommendedWatcher, Watcher};
@@ -19,7 +32,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tiny_http::{Response, Server};
 
 use crate::buffer::Buffer;
@@ -29,17 +42,24 @@ use crate::cursor::Cursor;
 use crate::error::{Error, Result};
 use crate::fuzzy_finder::FuzzyFinder;
 use crate::keymap::Action;
+use crate::language::LanguageRegistry;
 use crate::mode::Mode;
+use crate::quickfix::QuickfixList;
 use crate::register::RegisterManager;
 use crate::screen::Screen;
+use crate::search::{MatchCache, SearchHistory, SearchOffset, SearchPattern};
 use crate::selection::Selection;
 use crate::statusline::StatusLine;
 use crate::syntax::SyntaxHighlighter;
+use crate::term_color::ColorSupport;
 use crate::terminal::Terminal;
 use crate::theme::Theme;
+use crate::autocmd::{AutocmdEvent, Autocmds};
+use crate::mapping::KeyMappings;
 use crate::undo::UndoManager;
-use crate::event::{EditorApi, EditorEvent, EventBus, Plugin};
-use crate::lsp::LspClient;
+use crate::user_command::{Abbreviations, UserCommand};
+use crate::event::{CommentTogglePlugin, EditorApi, EditorEvent, EventBus, Plugin};
+use crate::lsp::{LspClient, LspEvent};
 use crate::window::Window;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -54,6 +74,7 @@ pub(crate) enum PendingOperator {
     Indent,
     Dedent,
     AutoIndent,
+    Fold,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -77,6 +98,17 @@ pub(crate) enum FindDirection {
     TillBack,
 }
 
+/// the [3/17] the status line shows after a search, for as long as the cursor
+/// stays on the match it landed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SearchCount {
+    at: Cursor,
+    found: Cursor, // start of the match, away from `at` after an offset
+    source: (u64, u64), // buffer id and version
+    index: usize,
+    total: usize,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum CaseChange {
     Lower,
@@ -87,6 +119,7 @@ pub(crate) enum CaseChange {
 pub struct Editor {
     terminal: Terminal,
     screen: Screen,
+    detected_colors: ColorSupport, // what the terminal reported at startup
     theme: Theme,
     buffers: Vec<Buffer>,
     pub(crate) undo_manager: UndoManager,
@@ -107,10 +140,22 @@ pub struct Editor {
     search_buffer: String,
     search_pattern: Option<String>,
     search_forward: bool,
+    search_offset: SearchOffset,
+    search_history: SearchHistory,
+    search_insert_pending: bool, // Ctrl-R typed at the search prompt
+    search_origin: Option<(Cursor, usize)>, // cursor and top line a typed search started from
+    search_highlight_off: bool,             // :noh, until the next search
+    search_count: Option<SearchCount>,
+    search_matches: MatchCache, // match starts behind the search count
+    compiled_search: Option<((String, bool, bool), Option<SearchPattern>)>, // pattern, ignorecase, smartcase
+    last_substitute: Option<crate::substitute::Substitute>,
+    confirm_substitute: Option<substitute::ConfirmSubstitute>,
+    in_global: bool, // :global runs its commands as one undo group
     substitute_preview_pattern: Option<String>,
     substitute_preview_range: Option<(usize, usize)>,
     visual_cmd_range: Option<(usize, usize)>,
     pending_text_object: Option<TextObjectModifier>,
+    auto_indented_line: Option<usize>, // blank line indented by o/O/Enter, cleared if left empty
     pending_register: Option<char>,
     waiting_for_register: bool,
     global_marks: HashMap<char, (usize, usize)>,
@@ -133,6 +178,7 @@ pub struct Editor {
     viewing_help: bool,
     help_return_buffer: Option<Buffer>,
     help_return_cursor: Option<Cursor>,
+    replace_preview: Option<replace::ReplacePreview>, // :Replace under review
     was_showing_landing_page: bool,
     markdown_preview_server: Option<JoinHandle<()>>,
     markdown_preview_url: Option<String>,
@@ -142,11 +188,27 @@ pub struct Editor {
     file_events: Option<Receiver<notify::Result<notify::Event>>>,
     file_changed_externally: bool,
     fuzzy_finder: Option<FuzzyFinder>,
+    outline: Option<symbols::Outline>,
+    quickfix: QuickfixList,
+    list_window: Option<quickfix::ListWindow>, // the list :copen / :lopen has open
     syntax: SyntaxHighlighter,
+    languages: LanguageRegistry,
     layout: crate::window::Layout,
     event_bus: EventBus,
     plugins: Vec<Box<dyn Plugin>>,
-    leader_pending: bool,           // waiting for key after leader
+    leader_sequence: Option<String>, // keys typed after leader, None when not in a leader sequence
+    prefix_started: Option<Instant>, // when the current multi-key prefix began (for which-key)
+    leader_pending_since: Option<Instant>, // a bound leader sequence waiting for a longer one
+    which_key_visible: bool,
+    user_commands: HashMap<String, UserCommand>,
+    abbreviations: Abbreviations,
+    user_command_depth: usize,
+    mappings: KeyMappings,
+    pending_map_keys: Vec<KeyEvent>, // typed keys that may be the start of a mapping
+    map_pending_since: Option<Instant>,
+    autocmds: Autocmds,
+    running_autocmd: bool,
+    source_depth: usize,
     surround_pending: Option<char>, // 'c' for cs, 'd' for ds, 'y' for ys
     surround_ys_pending: bool,      // waiting for text object after ys
     lsp_client: LspClient,
@@ -154,16 +216,39 @@ pub struct Editor {
 
 impl Editor {
     pub fn new() -> Result<Self> {
-        let terminal = Terminal::new()?;
+        let mut editor = Self::with_terminal(Terminal::new()?, Config::load_from_file("editor.toml"));
+        editor.source_init_script();
+        Ok(editor)
+    }
+
+    fn with_terminal(terminal: Terminal, config: Config) -> Self {
         let (width, height) = terminal.size();
         let viewport_height = (height as usize).saturating_sub(2);
         let window = Window::new(0, width as usize, viewport_height);
-        let config = Config::load_from_file("editor.toml");
-        let theme = Theme::webspinner();
-        let screen = Screen::new(width as usize, height as usize);
-        Ok(Self {
+        let (user_commands, abbreviations) = user_command::from_config(&config);
+        let (theme, theme_error) = match config.colorscheme.as_deref().map(Theme::load) {
+            Some(Ok(theme)) => (theme, None),
+            Some(Err(e)) => (Theme::webspinner(), Some(e)),
+            None => (Theme::webspinner(), None),
+        };
+        let (mut languages, mut load_errors) = LanguageRegistry::load(crate::config::config_dir().as_deref());
+        let runtime = crate::grammar::runtime_dir();
+        let runtime_grammars = runtime.as_deref().map(crate::grammar::runtime_parsers).unwrap_or_default();
+        languages.add_grammars(runtime_grammars.iter().map(|(name, _)| name.as_str()), &config.grammar_extensions);
+        let (syntax, grammar_errors) = SyntaxHighlighter::with_runtime(runtime.as_deref(), &languages);
+        load_errors.extend(grammar_errors);
+        for e in &load_errors {
+            log::warn!("{}", e);
+        }
+        let startup_message = theme_error.or_else(|| load_errors.first().cloned());
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(CommentTogglePlugin::new(languages.clone()))];
+        let mut screen = Screen::new(width as usize, height as usize);
+        let detected_colors = ColorSupport::detect();
+        screen.set_color_support(detected_colors.with_termguicolors(config.termguicolors));
+        Self {
             terminal,
             screen,
+            detected_colors,
             theme,
             buffers: vec![Buffer::new()],
             undo_manager: UndoManager::new(),
@@ -171,7 +256,7 @@ impl Editor {
             statusline: StatusLine::new(),
             command_bar: CommandBar::new(),
             command_buffer: String::new(),
-            message: None,
+            message: startup_message,
             should_quit: false,
             registers: RegisterManager::new(),
             pending_operator: PendingOperator::None,
@@ -184,10 +269,22 @@ impl Editor {
             search_buffer: String::new(),
             search_pattern: None,
             search_forward: true,
+            search_offset: SearchOffset::None,
+            search_history: SearchHistory::default(),
+            search_insert_pending: false,
+            search_origin: None,
+            search_highlight_off: false,
+            search_count: None,
+            search_matches: MatchCache::default(),
+            compiled_search: None,
+            last_substitute: None,
+            confirm_substitute: None,
+            in_global: false,
             substitute_preview_pattern: None,
             substitute_preview_range: None,
             visual_cmd_range: None,
             pending_text_object: None,
+            auto_indented_line: None,
             pending_register: None,
             waiting_for_register: false,
             global_marks: HashMap::new(),
@@ -210,6 +307,7 @@ impl Editor {
             viewing_help: false,
             help_return_buffer: None,
             help_return_cursor: None,
+            replace_preview: None,
             was_showing_landing_page: false,
             markdown_preview_server: None,
             markdown_preview_url: None,
@@ -219,15 +317,39 @@ impl Editor {
             file_events: None,
             file_changed_externally: false,
             fuzzy_finder: None,
-            syntax: SyntaxHighlighter::new(),
+            outline: None,
+            quickfix: QuickfixList::default(),
+            list_window: None,
+            syntax,
+            languages,
             layout: crate::window::Layout::new_leaf(0),
             event_bus: EventBus::new(),
-            plugins: Vec::new(),
-            leader_pending: false,
+            plugins,
+            leader_sequence: None,
+            prefix_started: None,
+            leader_pending_since: None,
+            which_key_visible: false,
+            user_commands,
+            abbreviations,
+            user_command_depth: 0,
+            mappings: KeyMappings::new(),
+            pending_map_keys: Vec::new(),
+            map_pending_since: None,
+            autocmds: Autocmds::new(),
+            running_autocmd: false,
+            source_depth: 0,
             surround_pending: None,
             surround_ys_pending: false,
             lsp_client: LspClient::new(),
-        })
+        }
+    }
+
+    /// an editor on a headless 80x24 terminal with the default config, editing `text`
+    #[cfg(test)]
+    pub(crate) fn for_test(text: &str) -> Self {
+        let mut editor = Self::with_terminal(Terminal::headless(80, 24), Config::new());
+        editor.buffers[0] = Buffer::from_string(text);
+        editor
     }
 
     pub(crate) fn current_buffer(&self) -> &Buffer {
@@ -239,6 +361,18 @@ impl Editor {
         &mut self.buffers[buffer_idx]
     }
 
+    /// tree-sitter grammar of the current buffer's language
+    pub(crate) fn current_grammar(&self) -> String {
+        self.buffer_grammar(self.windows[self.active_window].buffer_index)
+    }
+
+    /// bring the current buffer's syntax tree up to date before querying it
+    pub(crate) fn update_current_syntax(&mut self) {
+        let grammar = self.current_grammar();
+        let buf_idx = self.windows[self.active_window].buffer_index;
+        self.syntax.update(&mut self.buffers[buf_idx], &grammar);
+    }
+
     pub(crate) fn current_window(&self) -> &Window {
         &self.windows[self.active_window]
     }
@@ -249,11 +383,11 @@ impl Editor {
 
     pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
         let path = path.as_ref();
-        let buffer = Buffer::from_file(path, &self.config)?;
+        let buffer = Buffer::from_file_with_languages(path, &self.config, &self.languages)?;
         self.buffers[0] = buffer;
         self.windows[0].cursor = Cursor::default();
         self.registers.update_filename(path.to_string_lossy().to_string());
-        if path.extension().map_or(false, |ext| ext == "md" || ext == "markdown") {
+        if self.buffers[0].language() == Some("markdown") {
             self.start_markdown_preview(path.to_path_buf())?;
         } else {
             self.stop_markdown_preview();
@@ -265,20 +399,9 @@ impl Editor {
                 log::error!("Failed to start file watcher: {}", e);
             }
         }
-        // try to start LSP for this file type
-        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
-        if let Some((cmd, args)) = crate::lsp::server_for_extension(ext) {
-            let root = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
-            if let Err(e) = self.lsp_client.start(cmd, args, &root) {
-                log::warn!("LSP start failed: {}", e);
-            } else {
-                let uri = format!("file://{}", path.display());
-                let lang = ext.to_string();
-                let text = self.current_buffer().get_all_text().unwrap_or_default();
-                let _ = self.lsp_client.did_open(&uri, &lang, &text);
-            }
-        }
+        self.start_language_server();
         self.emit_event(EditorEvent::BufferOpen { path: path.to_string_lossy().to_string() });
+        self.fire_buffer_loaded();
         Ok(())
     }
 
@@ -335,16 +458,41 @@ impl Editor {
     }
 
     pub fn run(&mut self) -> Result<()> {
+        self.fire_buffer_autocmd(AutocmdEvent::VimEnter);
         loop {
+            if self.map_timeout_due() {
+                self.resolve_pending_mapping()?;
+                self.needs_render = true;
+            }
+            if self.leader_timeout_due() {
+                self.resolve_pending_leader()?;
+                self.needs_render = true;
+            }
+            if !self.which_key_visible && self.which_key_due() {
+                self.needs_render = true;
+            }
+            if self.syntax.poll_parses(&mut self.buffers) {
+                self.needs_render = true;
+            }
+            if self.fuzzy_finder.as_mut().is_some_and(|f| f.poll_grep()) {
+                self.needs_render = true;
+            }
             if self.needs_render {
                 self.render()?;
                 self.needs_render = false;
             }
             if self.should_quit {
+                self.fire_buffer_autocmd(AutocmdEvent::VimLeave);
                 self.stop_markdown_preview();
                 break;
             }
             self.lsp_client.poll_notifications();
+            for event in self.lsp_client.take_events() {
+                if let LspEvent::Diagnostics(uri) = event {
+                    self.diagnostics_arrived(&uri);
+                }
+                self.needs_render = true;
+            }
             self.check_for_file_changes();
             if let Some(event) = self.terminal.read_event()? {
                 self.handle_event(event)?;
@@ -367,7 +515,7 @@ impl Editor {
 
     fn handle_event(&mut self, event: Event) -> Result<()> {
         match event {
-            Event::Key(key) => self.handle_key(key)?,
+            Event::Key(key) => self.handle_typed_key(key)?,
             Event::Resize(width, height) => {
                 self.terminal.update_size()?;
                 let viewport_height = (height as usize).saturating_sub(2);
@@ -383,6 +531,7 @@ impl Editor {
 
     pub(crate) fn emit_event(&mut self, event: EditorEvent) {
         let mut api = EditorApi::new();
+        api.set_filetype(self.current_buffer().language());
         // run event bus handlers
         self.event_bus.emit(&event, &mut api);
         // run plugin handlers
@@ -410,10 +559,17 @@ impl Editor {
         self.plugins.push(plugin);
     }
 
-    pub(crate) fn recalculate_window_rects(&mut self) {
+    /// screen area the window layout fills: everything above the status and
+    /// command lines, less the outline sidebar and the quickfix list when open
+    pub(crate) fn windows_area(&self) -> crate::window::Rect {
         let (w, h) = self.terminal.size();
-        let viewport_height = (h as usize).saturating_sub(2);
-        let bounding = crate::window::Rect { x: 0, y: 0, width: w as usize, height: viewport_height };
+        let width = (w as usize).saturating_sub(self.outline_width(w as usize));
+        let height = (h as usize).saturating_sub(2);
+        crate::window::Rect { x: 0, y: 0, width, height: height.saturating_sub(self.list_window_rows(height)) }
+    }
+
+    pub(crate) fn recalculate_window_rects(&mut self) {
+        let bounding = self.windows_area();
         let rects = self.layout.calculate_rects(bounding);
         for (win_idx, rect) in rects {
             if let Some(win) = self.windows.get_mut(win_idx) {
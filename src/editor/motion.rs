@@ -1,4 +1,5 @@
-// vim motion implementations: word, line, sentence, paragraph, find, bracket
+// vim motion implementations: word, line, sentence, paragraph, find, bracket,
+// function starts
 
 use crate::mode::Mode;
 use super::{Editor, FindDirection};
@@ -363,6 +364,34 @@ impl Editor {
         }
     }
 
+    /// ]m [m ]] [[: jump `count` function starts away; `outermost` skips functions
+    /// nested in another one
+    pub(super) fn move_function_start(&mut self, forward: bool, outermost: bool, count: usize) {
+        self.update_current_syntax();
+        let buffer = self.current_buffer();
+        let Some((query, root)) = self.syntax.textobjects(buffer) else {
+            self.message = Some("No function queries for this file type".to_string());
+            return;
+        };
+        let rope = buffer.rope();
+        let starts = crate::textobject::function_starts(query, root, rope, outermost);
+        let cursor = self.current_window().cursor;
+        let line_start = rope.line_to_char(cursor.line);
+        let here = rope.char_to_byte((line_start + cursor.col).min(rope.len_chars()));
+        let target = if forward {
+            starts.iter().filter(|&&b| b > here).nth(count - 1)
+        } else {
+            starts.iter().rev().filter(|&&b| b < here).nth(count - 1)
+        };
+        let Some(&byte) = target else { return; };
+        let line = rope.byte_to_line(byte);
+        let col = rope.byte_to_char(byte) - rope.line_to_char(line);
+        if outermost { self.save_jump_position(); }
+        self.current_window_mut().cursor.line = line;
+        self.current_window_mut().cursor.col = col;
+        self.clamp_cursor();
+    }
+
     pub(super) fn move_paragraph_forward(&mut self) {
         let mut line = self.current_window().cursor.line + 1;
         let line_count = self.current_buffer().line_count();
@@ -5,8 +5,33 @@ use crate::error::Result;
 use crate::keymap::Action;
 use crate::mode::Mode;
 use crate::register::RegisterContent;
+use crate::selection::{Position, Selection};
+use crate::textobject::ObjectKind;
 use super::{CaseChange, Editor, PendingOperator, TextObjectModifier};
 
+/// motions that read a count as where to go (5gg, 50%, 3H) or as how far in
+/// pages, rather than as how many times to move
+fn takes_count_as_target(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::MoveFileStart | Action::MoveFileEnd | Action::MoveToPercent
+            | Action::MoveToScreenTop | Action::MoveToScreenBottom
+            | Action::MovePageUp | Action::MovePageDown
+    )
+}
+
+/// motions an operator applies to whole lines: dj, d5gg, dG, d50%, dH
+fn is_linewise_motion(action: &Action, count: usize) -> bool {
+    match action {
+        Action::MoveToPercent => count > 0,
+        _ => matches!(
+            action,
+            Action::MoveUp | Action::MoveDown | Action::MoveFileStart | Action::MoveFileEnd
+                | Action::MoveToScreenTop | Action::MoveToScreenMiddle | Action::MoveToScreenBottom
+        ),
+    }
+}
+
 impl Editor {
     pub(super) fn handle_text_object(&mut self, key: KeyEvent) -> Result<()> {
         let modifier = self.pending_text_object.unwrap();
@@ -22,6 +47,9 @@ impl Editor {
             KeyCode::Char('{') | KeyCode::Char('}') => { self.apply_text_object_bracket(modifier, '{', '}')?; }
             KeyCode::Char('<') | KeyCode::Char('>') => { self.apply_text_object_bracket(modifier, '<', '>')?; }
             KeyCode::Char('s') => { self.apply_text_object_sentence(modifier)?; }
+            KeyCode::Char(c @ ('f' | 'c' | 'a' | '/' | 'l')) => {
+                if let Some(kind) = ObjectKind::from_key(c) { self.apply_text_object_syntax(modifier, kind)?; }
+            }
             KeyCode::Esc => {
                 self.pending_operator = PendingOperator::None;
                 self.pending_text_object = None;
@@ -43,11 +71,16 @@ impl Editor {
             (PendingOperator::Delete, Action::Delete) => Some("delete_line"),
             (PendingOperator::Yank, Action::Yank) => Some("yank_line"),
             (PendingOperator::Change, Action::Change) => Some("change_line"),
+            (PendingOperator::AutoIndent, Action::AutoIndent) => Some("indent_line"),
             _ => None,
         };
         if let Some(op) = doubled {
             if op != "yank_line" { self.save_undo_state(); }
             let count = if self.count == 0 { 1 } else { self.count };
+            // a closed fold counts as a single line
+            let (first, last) = self.fold_span(count);
+            self.current_window_mut().cursor.line = first;
+            let count = last - first + 1;
             match op {
                 "delete_line" => {
                     let start_line = self.current_window().cursor.line;
@@ -124,9 +157,13 @@ impl Editor {
                         if lines.len() == 1 { "" } else { "s" }
                     ));
                 }
+                "indent_line" => {
+                    let line = self.current_window().cursor.line;
+                    self.apply_auto_indent(line, line + count - 1);
+                }
                 _ => {}
             }
-            if op == "delete_line" || op == "change_line" {
+            if op == "delete_line" || op == "change_line" || op == "indent_line" {
                 self.record_change(action.clone());
             }
             self.pending_operator = PendingOperator::None;
@@ -156,21 +193,38 @@ impl Editor {
             | Action::RepeatLastFind | Action::RepeatLastFindReverse
             | Action::MoveToScreenTop | Action::MoveToScreenMiddle | Action::MoveToScreenBottom
             | Action::MoveMatchingBracket | Action::MoveToPercent
+            | Action::MoveFunctionNext | Action::MoveFunctionPrev | Action::MoveSectionNext | Action::MoveSectionPrev
+            | Action::MoveContextStart
+            | Action::MoveFoldNext | Action::MoveFoldPrev
             | Action::MovePageUp | Action::MovePageDown | Action::MoveHalfPageUp | Action::MoveHalfPageDown => {
                 let old_cursor = self.current_window().cursor;
-                let count = if self.count == 0 { 1 } else { self.count };
-                for _ in 0..count {
+                if takes_count_as_target(&action) {
                     self.execute_action(action.clone())?;
+                } else {
+                    // relative motions move once per count, each a single step
+                    let count = if self.count == 0 { 1 } else { self.count };
+                    let saved_count = std::mem::take(&mut self.count);
+                    for _ in 0..count {
+                        self.execute_action(action.clone())?;
+                    }
+                    self.count = saved_count;
                 }
                 let end_line = self.current_window().cursor.line;
                 let end_col = self.current_window().cursor.col;
-                self.apply_operator_to_range(start_line, start_col, end_line, end_col)?;
-                if self.pending_operator != PendingOperator::Yank
-                    && self.pending_operator != PendingOperator::None
-                {
+                let linewise = is_linewise_motion(&action, self.count)
+                    && self.pending_operator != PendingOperator::Change;
+                if linewise {
+                    self.apply_operator_to_range(start_line.min(end_line), 0, start_line.max(end_line), usize::MAX)?;
+                } else {
+                    self.apply_operator_to_range(start_line, start_col, end_line, end_col)?;
+                }
+                if !matches!(self.pending_operator, PendingOperator::Yank | PendingOperator::Fold | PendingOperator::None) {
                     self.record_change(action.clone());
                 }
-                if self.pending_operator == PendingOperator::Delete
+                if linewise && self.pending_operator == PendingOperator::Delete {
+                    self.current_window_mut().cursor.line = start_line.min(end_line);
+                    self.move_to_first_non_blank();
+                } else if self.pending_operator == PendingOperator::Delete
                     || self.pending_operator == PendingOperator::Change
                 {
                     self.current_window_mut().cursor = old_cursor;
@@ -190,6 +244,10 @@ impl Editor {
         end_line: usize,
         end_col: usize,
     ) -> Result<()> {
+        if self.pending_operator == PendingOperator::None && matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            self.select_range(start_line, start_col, end_line, end_col);
+            return Ok(());
+        }
         self.save_undo_state();
         let (start_line, start_col, end_line, end_col) =
             if start_line > end_line || (start_line == end_line && start_col > end_col) {
@@ -260,11 +318,29 @@ impl Editor {
             PendingOperator::Indent => { self.apply_indent(start_line, end_line, true); }
             PendingOperator::Dedent => { self.apply_indent(start_line, end_line, false); }
             PendingOperator::AutoIndent => { self.apply_auto_indent(start_line, end_line); }
+            PendingOperator::Fold => { self.current_window_mut().folds.create(start_line, end_line); }
             PendingOperator::None => {}
         }
         Ok(())
     }
 
+    /// make a text object's range the visual selection; a linewise range
+    /// switches to linewise visual mode
+    fn select_range(&mut self, start_line: usize, start_col: usize, end_line: usize, end_col: usize) {
+        let (mode, last) = if start_col == 0 && end_col == usize::MAX {
+            (Mode::VisualLine, Position { line: end_line, col: 0 })
+        } else if end_col > 0 {
+            (Mode::Visual, Position { line: end_line, col: end_col - 1 })
+        } else {
+            // the range ends at a line start: its last character ends the line before
+            let line = end_line.saturating_sub(1).max(start_line);
+            (Mode::Visual, Position { line, col: self.current_buffer().line_len(line).saturating_sub(1) })
+        };
+        self.mode = mode;
+        self.selection = Some(Selection::new(Position { line: start_line, col: start_col }, last, mode));
+        self.current_window_mut().cursor = last.into();
+    }
+
     pub(super) fn get_range_text(
         &self,
         start_line: usize,
@@ -371,34 +447,6 @@ impl Editor {
         }
     }
 
-    pub(super) fn apply_auto_indent(&mut self, start_line: usize, end_line: usize) {
-        for line_idx in start_line..=end_line {
-            if line_idx >= self.current_buffer().line_count() { break; }
-            let mut indent_level = 0;
-            if line_idx > 0 {
-                for prev_line in (0..line_idx).rev() {
-                    if let Some(prev_text) = self.current_buffer().get_line(prev_line) {
-                        let trimmed = prev_text.trim_start();
-                        if !trimmed.is_empty() {
-                            indent_level = prev_text.len() - trimmed.len();
-                            break;
-                        }
-                    }
-                }
-            }
-            if let Some(line_text) = self.current_buffer().get_line(line_idx) {
-                let trimmed = line_text.trim_start();
-                let current_indent = line_text.len() - trimmed.len();
-                for _ in 0..current_indent {
-                    self.current_buffer_mut().delete_char(line_idx, 0);
-                }
-                for i in 0..indent_level {
-                    self.current_buffer_mut().insert_char(line_idx, i, ' ');
-                }
-            }
-        }
-    }
-
     // text object implementations
     pub(super) fn apply_text_object_word(&mut self, modifier: TextObjectModifier) -> Result<()> {
         if let Some(line_text) = self
@@ -449,6 +497,50 @@ impl Editor {
         Ok(())
     }
 
+    /// af/if, ac/ic, aa/ia, a//i/, al/il from the buffer's syntax tree
+    pub(super) fn apply_text_object_syntax(&mut self, modifier: TextObjectModifier, kind: ObjectKind) -> Result<()> {
+        self.update_current_syntax();
+        let buffer = self.current_buffer();
+        let Some((query, root)) = self.syntax.textobjects(buffer) else {
+            self.message = Some("No text object queries for this file type".to_string());
+            return Ok(());
+        };
+        let rope = buffer.rope();
+        let cursor = self.current_window().cursor;
+        let here = rope.char_to_byte((rope.line_to_char(cursor.line) + cursor.col).min(rope.len_chars()));
+        let inner = modifier == TextObjectModifier::Inner;
+        let Some(range) = crate::textobject::select(query, root, rope, kind, inner, here) else { return Ok(()); };
+        let position = |byte: usize| {
+            let line = rope.byte_to_line(byte);
+            (line, rope.byte_to_char(byte) - rope.line_to_char(line))
+        };
+        let (start_line, start_col) = position(range.start);
+        let selecting = self.pending_operator == PendingOperator::None;
+        let cursor_col = if range.linewise {
+            let end_line = rope.byte_to_line(range.end - 1);
+            if self.pending_operator == PendingOperator::Change {
+                // keep the lines' indentation and start typing in their place
+                let indent = self.current_buffer().get_line(start_line)
+                    .map_or(0, |t| t.chars().take_while(|c| c.is_whitespace()).count());
+                let end_col = self.current_buffer().line_len(end_line);
+                self.apply_operator_to_range(start_line, indent, end_line, end_col)?;
+                indent
+            } else {
+                self.apply_operator_to_range(start_line, 0, end_line, usize::MAX)?;
+                0
+            }
+        } else {
+            let (end_line, end_col) = position(range.end);
+            self.apply_operator_to_range(start_line, start_col, end_line, end_col)?;
+            start_col
+        };
+        if !selecting {
+            self.current_window_mut().cursor.line = start_line;
+            self.current_window_mut().cursor.col = cursor_col;
+        }
+        Ok(())
+    }
+
     pub(super) fn apply_text_object_paragraph(&mut self, modifier: TextObjectModifier) -> Result<()> {
         let line_count = self.current_buffer().line_count();
         let mut start_line = self.current_window().cursor.line;
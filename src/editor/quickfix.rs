@@ -0,0 +1,423 @@
+// the quickfix list and the windows' location lists: filled by :make, :grep,
+// :vimgrep, :cfile and :cexpr (:lmake, :lgrep, ... for a location list) or by
+// producers such as :Diagnostics, shown by :copen / :lopen in a list window
+// along the bottom of the screen, walked with :cnext, :cprevious and :cc, and
+// run over with :cdo and :cfdo
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::buffer::LineEdit;
+use crate::error::{Error, Result};
+use crate::fuzzy_finder::{self, FinderType, GrepMatch};
+use crate::global;
+use crate::mode::Mode;
+use crate::quickfix::{self, Entry, ErrorFormat, Expr, ListKind, QuickfixList, VimgrepArgs};
+use crate::window::Rect;
+use super::Editor;
+
+const LIST_HEIGHT: usize = 10;
+const ERROR_FILE: &str = "errors.err";
+const DIAGNOSTICS_TITLE: &str = ":Diagnostics";
+
+/// the list shown along the bottom of the screen; a location list is the
+/// current window's
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ListWindow {
+    kind: ListKind,
+    height: usize, // rows of entries
+}
+
+/// quickfix entries for lines a grep found
+pub(super) fn grep_entries(matches: &[&GrepMatch]) -> Vec<Entry> {
+    matches.iter()
+        .map(|m| Entry {
+            path: Some(m.path.clone()),
+            line: m.line,
+            col: m.col,
+            kind: None,
+            text: m.text.trim().to_string(),
+            valid: true,
+        })
+        .collect()
+}
+
+fn no_errors() -> Error {
+    Error::EditorError("No errors".to_string())
+}
+
+fn no_location_list() -> Error {
+    Error::EditorError("No location list".to_string())
+}
+
+impl Editor {
+    /// the errorformat option, or the built-in one for makeprg when it is unset
+    fn error_format(&self) -> Result<ErrorFormat> {
+        match &self.config.errorformat {
+            Some(spec) => ErrorFormat::new(spec).map_err(Error::EditorError),
+            None => Ok(ErrorFormat::for_program(&self.config.makeprg)),
+        }
+    }
+
+    /// whether `path` is the file in the current buffer
+    fn is_edited_file(&self, path: &Path) -> bool {
+        let Some(current) = self.current_buffer().file_path() else { return false; };
+        match (fs::canonicalize(current), fs::canonicalize(path)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => current == path,
+        }
+    }
+
+    /// the quickfix list, or the current window's location list
+    fn list(&self, kind: ListKind) -> Result<&QuickfixList> {
+        match kind {
+            ListKind::Quickfix => Ok(&self.quickfix),
+            ListKind::Location => self.current_window().location_list.as_ref().ok_or_else(no_location_list),
+        }
+    }
+
+    fn list_mut(&mut self, kind: ListKind) -> Result<&mut QuickfixList> {
+        match kind {
+            ListKind::Quickfix => Ok(&mut self.quickfix),
+            ListKind::Location => self.current_window_mut().location_list.as_mut().ok_or_else(no_location_list),
+        }
+    }
+
+    /// make `entries` the quickfix list or the current window's location list,
+    /// leaving the other windows' lists alone, and with `jump` go to the first
+    pub(super) fn set_list(&mut self, kind: ListKind, title: &str, entries: Vec<Entry>, jump: bool) -> Result<()> {
+        let list = QuickfixList::new(title, entries);
+        let valid = list.valid_count();
+        match kind {
+            ListKind::Quickfix => self.quickfix = list,
+            ListKind::Location => self.current_window_mut().location_list = Some(list),
+        }
+        if valid == 0 {
+            self.message = Some(format!("{}: no errors", title));
+            return Ok(());
+        }
+        if !jump {
+            self.message = Some(format!("{}: {} entries", title, valid));
+            return Ok(());
+        }
+        self.jump_to_list_entry(kind)
+    }
+
+    /// go to the list's current entry, opening its file in place of the one
+    /// being edited when that has no unwritten changes
+    fn jump_to_list_entry(&mut self, kind: ListKind) -> Result<()> {
+        let list = self.list(kind)?;
+        let entry = list.current().cloned().ok_or_else(no_errors)?;
+        let (n, total) = list.position();
+        if self.viewing_help {
+            self.close_scratch_buffer();
+        }
+        if let Some(path) = entry.path.as_deref().filter(|p| !self.is_edited_file(p)) {
+            if self.current_buffer().is_modified() {
+                return Err(Error::EditorError("No write since last change".to_string()));
+            }
+            self.open(path)?;
+        }
+        self.jump_to_symbol(entry.line.saturating_sub(1), entry.col.saturating_sub(1));
+        let kind = match entry.kind_name() {
+            "" => String::new(),
+            name => format!("{}: ", name),
+        };
+        self.message = Some(format!("({} of {}) {}{}", n, total, kind, entry.text));
+        Ok(())
+    }
+
+    /// :cnext / :cprevious -- `count` entries on or back
+    pub(super) fn list_step(&mut self, kind: ListKind, count: usize, forward: bool) -> Result<()> {
+        let list = self.list_mut(kind)?;
+        if list.valid_count() == 0 {
+            return Err(no_errors());
+        }
+        let moved = if forward { list.next(count) } else { list.prev(count) };
+        if moved.is_none() {
+            return Err(Error::EditorError("No more items".to_string()));
+        }
+        self.jump_to_list_entry(kind)
+    }
+
+    /// :cc [N] -- entry N, 0 for the last, or the current one again
+    pub(super) fn list_select(&mut self, kind: ListKind, n: Option<usize>) -> Result<()> {
+        if let Some(n) = n {
+            self.list_mut(kind)?.select(n).ok_or_else(no_errors)?;
+        }
+        self.jump_to_list_entry(kind)
+    }
+
+    /// :copen / :lopen [height] -- show the list window, as high as asked,
+    /// else as it was last, else 10 rows
+    pub(super) fn open_list_window(&mut self, kind: ListKind, height: Option<usize>) -> Result<()> {
+        self.list(kind)?;
+        let last = self.list_window.map(|w| w.height);
+        self.list_window = Some(ListWindow { kind, height: height.or(last).unwrap_or(LIST_HEIGHT) });
+        self.recalculate_window_rects();
+        Ok(())
+    }
+
+    /// :cclose / :lclose -- hide the list window when it shows that kind of list
+    pub(super) fn close_list_window(&mut self, kind: ListKind) {
+        if self.list_window.is_some_and(|w| w.kind == kind) {
+            self.list_window = None;
+            self.recalculate_window_rects();
+        }
+    }
+
+    /// rows the list window takes from the bottom of `height`, its title
+    /// included
+    pub(super) fn list_window_rows(&self, height: usize) -> usize {
+        match self.list_window {
+            Some(window) if !self.zen_mode => (window.height.max(1) + 1).min(height / 2),
+            _ => 0,
+        }
+    }
+
+    /// :make [args] -- run makeprg and read its output with errorformat
+    pub(super) fn execute_make(&mut self, kind: ListKind, args: &str) -> Result<()> {
+        let efm = self.error_format()?;
+        let command = match args {
+            "" => self.config.makeprg.clone(),
+            _ => format!("{} {}", self.config.makeprg, args),
+        };
+        let output = crate::term_pane::run_command(&format!("{} 2>&1", command))
+            .map_err(|e| Error::EditorError(format!("Shell error: {}", e)))?;
+        self.set_list(kind, &format!(":!{}", command), efm.parse(&output), true)
+    }
+
+    /// :cfile [file] -- read errors from a file, errors.err by default
+    pub(super) fn execute_cfile(&mut self, kind: ListKind, file: Option<String>) -> Result<()> {
+        let file = file.unwrap_or_else(|| ERROR_FILE.to_string());
+        let text = fs::read_to_string(&file)
+            .map_err(|e| Error::EditorError(format!("Can't open errorfile {}: {}", file, e)))?;
+        let entries = self.error_format()?.parse(&text);
+        self.set_list(kind, &format!(":cfile {}", file), entries, true)
+    }
+
+    /// :cexpr {expr} -- read errors from a register, a shell command or text
+    pub(super) fn execute_cexpr(&mut self, kind: ListKind, expr: &str) -> Result<()> {
+        let text = match quickfix::parse_expr(expr).map_err(Error::ParseError)? {
+            Expr::Register(r) => self.registers.get(Some(r))
+                .map(|content| content.as_string())
+                .ok_or_else(|| Error::EditorError(format!("Nothing in register {}", r)))?,
+            Expr::System(command) => crate::term_pane::run_command(&format!("{} 2>&1", command))
+                .map_err(|e| Error::EditorError(format!("Shell error: {}", e)))?,
+            Expr::Text(text) => text,
+        };
+        let entries = self.error_format()?.parse(&text);
+        self.set_list(kind, &format!(":cexpr {}", expr), entries, true)
+    }
+
+    /// :grep {pattern} -- the matching lines under the working directory, into
+    /// the list with its window open
+    pub(super) fn execute_grep(&mut self, kind: ListKind, pattern: &str) -> Result<()> {
+        let base_path = std::env::current_dir()?;
+        let matches = fuzzy_finder::grep_files(&base_path, pattern, self.config.grep_max_results)
+            .map_err(Error::EditorError)?;
+        if matches.is_empty() {
+            return Err(Error::EditorError(format!("No match: {}", pattern)));
+        }
+        let matches: Vec<&GrepMatch> = matches.iter().collect();
+        self.set_list(kind, &format!(":grep {}", pattern), grep_entries(&matches), true)?;
+        self.open_list_window(kind, None)
+    }
+
+    /// the files a :vimgrep argument names: % for the file being edited, a
+    /// path, or a pattern such as *.rs or **/*.rs matched under the working
+    /// directory
+    fn vimgrep_files(&self, arg: &str) -> Result<Vec<PathBuf>> {
+        if arg == "%" {
+            let path = self.current_buffer().file_path()
+                .ok_or_else(|| Error::EditorError("No file name".to_string()))?;
+            return Ok(vec![path.to_path_buf()]);
+        }
+        if Path::new(arg).is_file() {
+            return Ok(vec![PathBuf::from(arg)]);
+        }
+        let root = std::env::current_dir()?;
+        Ok(fuzzy_finder::project_files(&root).into_iter()
+            .filter_map(|p| p.strip_prefix(&root).ok().map(Path::to_path_buf))
+            .filter(|p| quickfix::file_matches(arg, &p.to_string_lossy()))
+            .collect())
+    }
+
+    /// :vimgrep /pattern/[g][j] file... -- search files with the editor's own
+    /// patterns; the file being edited is searched as it is in the buffer
+    pub(super) fn execute_vimgrep(&mut self, kind: ListKind, args: VimgrepArgs) -> Result<()> {
+        let pattern = match args.pattern.as_str() {
+            "" => self.search_pattern.clone()
+                .ok_or_else(|| Error::EditorError("No previous regular expression".to_string()))?,
+            pattern => pattern.to_string(),
+        };
+        let compiled = self.compile_search(&pattern)
+            .map_err(|e| Error::EditorError(format!("Invalid pattern: {}", e)))?;
+        let mut files = Vec::new();
+        for arg in &args.files {
+            for path in self.vimgrep_files(arg)? {
+                if !files.contains(&path) {
+                    files.push(path);
+                }
+            }
+        }
+        let mut entries = Vec::new();
+        for path in &files {
+            let text = if self.is_edited_file(path) {
+                self.current_buffer().rope().to_string()
+            } else if fuzzy_finder::is_likely_binary(path) {
+                continue;
+            } else {
+                match fs::read_to_string(path) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                }
+            };
+            entries.extend(quickfix::vimgrep_entries(path, &text, &compiled, args.every));
+        }
+        if entries.is_empty() {
+            return Err(Error::EditorError(format!("No match: {}", pattern)));
+        }
+        self.search_pattern = Some(pattern.clone());
+        self.search_highlight_off = false;
+        self.set_list(kind, &format!(":vimgrep /{}/", pattern), entries, args.jump)
+    }
+
+    /// :Diagnostics -- the language server's diagnostics for the file being
+    /// edited, into the current window's location list
+    pub(super) fn execute_diagnostics(&mut self) -> Result<()> {
+        let path = self.current_buffer().file_path()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| Error::EditorError("No file name".to_string()))?;
+        if !self.lsp_client.is_running() {
+            return Err(Error::EditorError("No language server running".to_string()));
+        }
+        self.lsp_client.poll_notifications();
+        let entries = self.current_diagnostic_entries(&path);
+        self.set_list(ListKind::Location, DIAGNOSTICS_TITLE, entries, false)
+    }
+
+    /// new diagnostics published for `uri`: a location list that :Diagnostics
+    /// filled, or none at all, follows them when the current buffer is that file
+    pub(super) fn diagnostics_arrived(&mut self, uri: &str) {
+        let Some(path) = self.current_buffer().file_path().map(Path::to_path_buf) else { return; };
+        if format!("file://{}", path.display()) != uri {
+            return;
+        }
+        let follows = self.current_window().location_list.as_ref()
+            .map_or(true, |list| list.title == DIAGNOSTICS_TITLE);
+        if follows {
+            let entries = self.current_diagnostic_entries(&path);
+            self.current_window_mut().location_list = Some(QuickfixList::new(DIAGNOSTICS_TITLE, entries));
+        }
+    }
+
+    fn current_diagnostic_entries(&self, path: &Path) -> Vec<Entry> {
+        let uri = format!("file://{}", path.display());
+        self.lsp_client.diagnostics.get(&uri)
+            .map(|diagnostics| quickfix::diagnostic_entries(path, diagnostics, self.current_buffer().rope()))
+            .unwrap_or_default()
+    }
+
+    /// Ctrl-Q in the :Rg picker: the lines it is showing, best first, into the
+    /// quickfix list with its window open
+    pub(super) fn grep_picker_to_quickfix(&mut self) -> Result<()> {
+        if self.fuzzy_finder.as_ref().map(|f| f.finder_type) != Some(FinderType::Grep) {
+            return Ok(());
+        }
+        let Some(finder) = self.fuzzy_finder.take() else { return Ok(()); };
+        self.mode = Mode::Normal;
+        let entries = grep_entries(&finder.matched_grep_lines());
+        self.set_list(ListKind::Quickfix, format!(":Rg {}", finder.query).trim_end(), entries, true)?;
+        self.open_list_window(ListKind::Quickfix, None)
+    }
+
+    /// :cdo {cmd} -- run cmd at every valid entry, or with `per_file` (:cfdo)
+    /// once in each file. Going on to another file stops at unwritten changes
+    /// unless autowrite is set, and entries further down a file follow the
+    /// lines added or removed
+    pub(super) fn execute_cdo(&mut self, kind: ListKind, command: &str, per_file: bool) -> Result<()> {
+        let targets = self.list(kind)?.targets(per_file);
+        if targets.is_empty() {
+            return Err(no_errors());
+        }
+        let mut gone = Vec::new(); // entries whose line was deleted
+        for (pos, &index) in targets.iter().enumerate() {
+            if gone.contains(&index) {
+                continue;
+            }
+            let path = self.list(kind)?.entries[index].path.clone();
+            let leaving = path.as_deref().is_some_and(|p| !self.is_edited_file(p));
+            if leaving && self.config.autowrite && self.current_buffer().is_modified() && self.current_buffer().file_path().is_some() {
+                self.write_current_buffer(None)?;
+            }
+            self.list_mut(kind)?.index = index;
+            self.jump_to_list_entry(kind)?;
+            let (id, version) = (self.current_buffer().id(), self.current_buffer().version());
+            self.execute_command_str(command)?;
+            if self.current_buffer().id() != id {
+                continue;
+            }
+            let edits: Vec<LineEdit> = self.current_buffer().line_edits_since(version)
+                .ok_or_else(super::global::edits_outrun)?
+                .collect();
+            let list = self.list_mut(kind)?;
+            for &later in &targets[pos + 1..] {
+                let entry = &mut list.entries[later];
+                if entry.path != path || entry.line == 0 {
+                    continue;
+                }
+                let mut line = Some(entry.line - 1);
+                for edit in &edits {
+                    line = line.and_then(|l| global::follow_line(l, edit.at, edit.delta));
+                }
+                match line {
+                    Some(line) => entry.line = line + 1,
+                    None => gone.push(later),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the list window in `area`: a title row, then one row per entry with the
+    /// current one highlighted and kept in view
+    pub(super) fn render_list_cells(&mut self, area: Rect) {
+        let Some(window) = self.list_window else { return; };
+        let (bg, fg) = (self.theme.bg, self.theme.fg);
+        let (status_bg, status_fg) = (self.theme.statusline_bg, self.theme.statusline_fg);
+        let (selected_bg, accent, comment) = (self.theme.finder_selected_bg, self.theme.accent, self.theme.comment);
+        let no_list = QuickfixList::new("No location list", Vec::new());
+        let list = match self.list(window.kind) {
+            Ok(list) => list.clone(),
+            Err(_) => no_list,
+        };
+        let (n, total) = list.position();
+        let mut title = format!(" [{}] {}", window.kind.name(), list.title);
+        if total > 0 {
+            title.push_str(&format!("  ({} of {})", n, total));
+        }
+        for col in area.x..area.x + area.width {
+            self.screen.put_char(area.y, col, ' ', status_fg, status_bg);
+        }
+        let title: String = title.chars().take(area.width).collect();
+        self.screen.put_str(area.y, area.x, &title, status_fg, status_bg);
+
+        let rows = area.height.saturating_sub(1);
+        let offset = list.index.saturating_sub(rows / 2).min(list.entries.len().saturating_sub(rows));
+        for (row, (i, entry)) in list.entries.iter().enumerate().skip(offset).take(rows).enumerate() {
+            let screen_row = area.y + 1 + row;
+            let row_bg = if i == list.index && entry.valid { selected_bg } else { bg };
+            for col in area.x..area.x + area.width {
+                self.screen.put_char(screen_row, col, ' ', fg, row_bg);
+            }
+            let shown = entry.display().replace('\t', " ");
+            let (location, text) = shown.split_at(shown.len() - entry.text.len().min(shown.len()));
+            let location_fg = if entry.valid { accent } else { comment };
+            let location: String = location.chars().take(area.width).collect();
+            let used = location.chars().count();
+            let text: String = text.chars().take(area.width - used).collect();
+            self.screen.put_str(screen_row, area.x, &location, location_fg, row_bg);
+            self.screen.put_str(screen_row, area.x + used, &text, fg, row_bg);
+        }
+    }
+}
@@ -5,6 +5,8 @@ use crossterm::style::Color;
 use crate::config::LineNumberMode;
 use crate::error::Result;
 use crate::mode::Mode;
+use crate::search;
+use crate::theme::Style;
 use super::Editor;
 
 impl Editor {
@@ -19,6 +21,9 @@ impl Editor {
         }
         self.render_status_line_cells();
         self.render_command_line_cells();
+        if self.which_key_due() {
+            self.render_which_key_cells();
+        }
         self.screen.flush().map_err(|e| crate::error::Error::EditorError(e.to_string()))?;
 
         // cursor style per mode
@@ -45,8 +50,8 @@ impl Editor {
             } else { (0, win_rect.width) };
             let line_num_width = self.config.line_number_width(self.current_buffer().line_count());
             let gutter_extra = if line_num_width > 0 { 1 } else { 0 };
-            let screen_row = win_rect.y + self.current_window().cursor.line
-                .saturating_sub(self.current_window().viewport.offset_line);
+            let window = self.current_window();
+            let screen_row = win_rect.y + window.folds.rows_between(window.viewport.offset_line, window.cursor.line);
             let screen_col = win_rect.x + padding + line_num_width + gutter_extra
                 + self.current_window().cursor.col
                     .saturating_sub(self.current_window().viewport.offset_col);
@@ -64,11 +69,22 @@ impl Editor {
         let fg = self.theme.fg;
         let prompt_fg = self.theme.finder_prompt_fg;
         let sel_bg = self.theme.finder_selected_bg;
+        if self.fuzzy_finder.as_ref().is_some_and(|f| f.finder_type == crate::fuzzy_finder::FinderType::Symbols) {
+            self.render_symbol_finder_cells();
+            return;
+        }
         if let Some(ref finder) = self.fuzzy_finder {
             // render prompt + query on row 0
             let prompt = finder.prompt();
             self.screen.put_str(0, 0, prompt, prompt_fg, bg);
             self.screen.put_str(0, prompt.len(), &finder.query, fg, bg);
+            // grep: lines found so far, right-aligned
+            if finder.finder_type == crate::fuzzy_finder::FinderType::Grep {
+                let searching = if finder.is_searching() { " searching..." } else { "" };
+                let status = format!("{} lines{} ", finder.grep_matches.len(), searching);
+                let col = width.saturating_sub(status.len()).max(prompt.len() + finder.query.len() + 1);
+                self.screen.put_str(0, col, &status, self.theme.comment, bg);
+            }
             // render matches
             let matches = finder.visible_matches();
             for (i, m) in matches.iter().take(height.saturating_sub(1)).enumerate() {
@@ -87,13 +103,22 @@ impl Editor {
 
     fn render_buffer_cells(&mut self) {
         let (term_width, term_height) = self.terminal.size();
-        let viewport_height = (term_height as usize).saturating_sub(2);
-        let bounding = crate::window::Rect { x: 0, y: 0, width: term_width as usize, height: viewport_height };
+        let bounding = self.windows_area();
         let rects = self.layout.calculate_rects(bounding);
         for (win_idx, rect) in rects.clone() {
             self.render_single_window(win_idx, rect);
         }
         self.draw_window_separators(&rects, bounding);
+        let outline_width = (term_width as usize).saturating_sub(bounding.width);
+        if outline_width > 0 {
+            let area = crate::window::Rect { x: bounding.width, y: 0, width: outline_width, height: bounding.height };
+            self.render_outline_cells(area);
+        }
+        let list_rows = (term_height as usize).saturating_sub(2 + bounding.height);
+        if list_rows > 0 {
+            let area = crate::window::Rect { x: 0, y: bounding.height, width: term_width as usize, height: list_rows };
+            self.render_list_cells(area);
+        }
     }
 
     fn render_single_window(&mut self, win_idx: usize, rect: crate::window::Rect) {
@@ -113,87 +138,109 @@ impl Editor {
         let gutter_extra = if line_num_width > 0 { 1 } else { 0 };
         let effective_text_width = text_width.saturating_sub(line_num_width + gutter_extra);
 
-        let search_pattern = self.search_pattern.clone();
-        let sub_pattern = self.substitute_preview_pattern.clone();
-        let sub_range = self.substitute_preview_range;
 
-        // syntax highlighting
-        let file_ext = self.buffers[buf_idx].file_path()
-            .and_then(|p| p.extension())
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_string();
-        let syntax_colors = if self.syntax.supports(&file_ext) {
-            let margin = 5;
-            let vis_start = offset_line.saturating_sub(margin);
-            let vis_end = (offset_line + rect.height + margin).min(self.buffers[buf_idx].line_count());
-            let mut visible_text = String::new();
-            let mut line_byte_offsets: Vec<usize> = Vec::new();
-            for l in vis_start..vis_end {
-                line_byte_offsets.push(visible_text.len());
-                if let Some(lt) = self.buffers[buf_idx].get_line(l) {
-                    visible_text.push_str(&lt);
-                }
-                visible_text.push('\n');
+        // buffer lines shown on each screen row: a closed fold takes a single row
+        self.sync_folds(win_idx);
+        let rows: Vec<(usize, usize)> = {
+            let folds = &self.windows[win_idx].folds;
+            let line_count = self.buffers[buf_idx].line_count();
+            let mut rows = Vec::with_capacity(rect.height);
+            let mut line = folds.display_range(offset_line).0;
+            while rows.len() < rect.height && line < line_count {
+                let range = folds.display_range(line);
+                rows.push(range);
+                line = range.1 + 1;
             }
-            let spans = self.syntax.highlight(&file_ext, visible_text.as_bytes());
-            let theme = &self.theme;
-            let mut color_map: Vec<Vec<Option<Color>>> = Vec::new();
-            let vis_chars: Vec<char> = visible_text.chars().collect();
-            let mut byte_to_char = vec![0usize; visible_text.len() + 1];
-            let mut byte_pos = 0;
-            for (ci, ch) in vis_chars.iter().enumerate() {
-                let len = ch.len_utf8();
-                for b in 0..len { byte_to_char[byte_pos + b] = ci; }
-                byte_pos += len;
+            rows
+        };
+
+        // runs of lines drawn one per row; the interiors of closed folds are skipped
+        let mut drawn: Vec<std::ops::Range<usize>> = Vec::new();
+        for &(line, _) in rows.iter().filter(|(line, last)| line == last) {
+            match drawn.last_mut() {
+                Some(run) if run.end == line => run.end += 1,
+                _ => drawn.push(line..line + 1),
             }
-            if byte_pos < byte_to_char.len() { byte_to_char[byte_pos] = vis_chars.len(); }
-            for l in vis_start..vis_end {
-                let rel = l - vis_start;
-                if let Some(lt) = self.buffers[buf_idx].get_line(l) {
-                    let char_count = lt.chars().count();
-                    let mut colors = vec![None; char_count];
-                    let line_byte_start = line_byte_offsets.get(rel).copied().unwrap_or(0);
-                    let line_byte_end = line_byte_offsets.get(rel + 1).copied()
-                        .unwrap_or(visible_text.len()).saturating_sub(1);
-                    for &(bs, be, hi) in &spans {
-                        if be <= line_byte_start || bs >= line_byte_end { continue; }
-                        let cs = byte_to_char.get(bs.max(line_byte_start)).copied().unwrap_or(0);
-                        let ce = byte_to_char.get(be.min(line_byte_end)).copied().unwrap_or(char_count);
-                        let line_char_start = byte_to_char.get(line_byte_start).copied().unwrap_or(0);
-                        let color = crate::syntax::highlight_color(hi, theme);
-                        for ci in cs..ce {
-                            let local = ci.saturating_sub(line_char_start);
-                            if local < char_count { colors[local] = Some(color); }
-                        }
-                    }
-                    color_map.push(colors);
-                } else {
-                    color_map.push(Vec::new());
-                }
+        }
+
+        // search and substitute matches on the drawn lines, one engine for both
+        let delim = if self.search_forward { '/' } else { '?' };
+        let typed = Some(&self.search_buffer)
+            .filter(|_| self.mode == Mode::Search && self.config.incremental_search)
+            .and_then(|input| crate::search::parse_search(input, delim).ok())
+            .map(|(pattern, _)| pattern)
+            .filter(|p| !p.is_empty());
+        let last = self.search_pattern.clone().filter(|_| self.config.highlight_search && !self.search_highlight_off);
+        let search_matches = match typed.or(last) {
+            Some(p) => self.cached_search(&p)
+                .map(|p| drawn.iter().flat_map(|lines| p.find_in_lines(self.buffers[buf_idx].rope(), lines.clone())).collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let confirming = self.confirm_substitute.as_ref()
+            .filter(|_| buf_idx == self.windows[self.active_window].buffer_index)
+            .and_then(|session| session.current_match());
+        let substitute_matches = match (&self.substitute_preview_pattern, self.substitute_preview_range) {
+            _ if confirming.is_some() => confirming.into_iter().map(|(start, end)| start..end).collect(),
+            (Some(p), Some((first, last))) => self.compile_search(p)
+                .map(|p| drawn.iter()
+                    .flat_map(|lines| p.find_in_lines(self.buffers[buf_idx].rope(), lines.start.max(first)..lines.end.min(last.saturating_add(1))))
+                    .collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        // syntax highlighting
+        let grammar = self.buffer_grammar(buf_idx);
+        let syntax_colors = if self.syntax.supports(&grammar) {
+            self.syntax.update(&mut self.buffers[buf_idx], &grammar);
+            let buffer = &mut self.buffers[buf_idx];
+            let mut spans = Vec::new();
+            for lines in &drawn {
+                let bytes = buffer.line_to_byte(lines.start)..buffer.line_to_byte(lines.end);
+                spans.extend(self.syntax.highlight(buffer, bytes));
             }
-            Some((vis_start, color_map))
+            let buffer = &self.buffers[buf_idx];
+            let styles: Vec<Style> = (0..crate::syntax::HIGHLIGHT_NAMES.len())
+                .map(|i| crate::syntax::highlight_style(i, &self.theme))
+                .collect();
+            let color_map: Vec<Vec<Option<Style>>> = rows.iter()
+                .map(|&(l, last)| if last > l { Vec::new() } else { line_colors(buffer, &spans, &styles, l) })
+                .collect();
+            Some(color_map)
         } else { None };
 
         for row in 0..rect.height {
             let screen_row = rect.y + row;
-            let file_line = offset_line + row;
-            if file_line < self.buffers[buf_idx].line_count() {
+            if let Some(&(file_line, fold_end)) = rows.get(row) {
                 self.render_line_number_cells_at(screen_row, rect.x + padding, file_line, line_num_width, cursor_line);
                 if gutter_extra > 0 {
                     let sep_col = rect.x + padding + line_num_width;
                     self.screen.put_char(screen_row, sep_col, '\u{2502}', self.theme.gutter_separator, bg);
                 }
+                let text_start_col = rect.x + padding + line_num_width + gutter_extra;
+                if fold_end > file_line {
+                    let depth = self.windows[win_idx].folds.folds().iter()
+                        .filter(|f| f.start <= file_line && fold_end <= f.end)
+                        .count();
+                    let first = self.buffers[buf_idx].get_line(file_line).unwrap_or_default();
+                    let text = crate::fold::summary(&first, fold_end - file_line + 1, depth);
+                    let style = self.theme.folded_style();
+                    let mut chars = text.chars().skip(offset_col);
+                    for col in text_start_col..(text_start_col + effective_text_width).min(self.screen.width) {
+                        let ch = chars.next().unwrap_or(' ');
+                        self.screen.put_char_styled(screen_row, col, ch, &style, self.theme.fg, bg);
+                    }
+                    continue;
+                }
                 if let Some(line) = self.buffers[buf_idx].get_line(file_line) {
-                    let text_start_col = rect.x + padding + line_num_width + gutter_extra;
-                    let line_colors = syntax_colors.as_ref().and_then(|(vis_start, cmap)| {
-                        let idx = file_line.checked_sub(*vis_start)?;
-                        cmap.get(idx)
-                    });
+                    let line_colors = syntax_colors.as_ref().and_then(|cmap| cmap.get(row));
+                    let rope = self.buffers[buf_idx].rope();
+                    let search_spans = search::line_spans(rope, &search_matches, file_line);
+                    let substitute_spans = search::line_spans(rope, &substitute_matches, file_line);
                     self.render_line_content_cells(
                         screen_row, text_start_col, file_line, &line, offset_col,
-                        effective_text_width, &search_pattern, &sub_pattern, sub_range,
-                        line_colors,
+                        effective_text_width, &search_spans, &substitute_spans, line_colors,
                     );
                     if file_line == cursor_line && self.config.show_current_line && win_idx == self.active_window {
                         let cur_bg = self.theme.current_line_bg;
@@ -213,6 +260,8 @@ impl Editor {
                 }
             }
         }
+        let text_col = rect.x + padding + line_num_width + gutter_extra;
+        self.render_context_cells(win_idx, rect, rect.x + padding, text_col, effective_text_width);
     }
 
     fn draw_window_separators(&mut self, rects: &[(usize, crate::window::Rect)], _bounding: crate::window::Rect) {
@@ -251,7 +300,7 @@ impl Editor {
         }
     }
 
-    fn render_line_number_cells_at(&mut self, row: usize, col_start: usize, line: usize, width: usize, cursor_line: usize) {
+    pub(super) fn render_line_number_cells_at(&mut self, row: usize, col_start: usize, line: usize, width: usize, cursor_line: usize) {
         if width == 0 { return; }
         let bg = self.theme.bg;
         let number = match self.config.line_numbers {
@@ -272,7 +321,7 @@ impl Editor {
         self.screen.put_char(row, col_start + width - 1, ' ', color, bg);
     }
 
-    fn render_line_content_cells(
+    pub(super) fn render_line_content_cells(
         &mut self,
         row: usize,
         start_screen_col: usize,
@@ -280,26 +329,14 @@ impl Editor {
         line_text: &str,
         offset_col: usize,
         available_width: usize,
-        search_pattern: &Option<String>,
-        sub_pattern: &Option<String>,
-        sub_range: Option<(usize, usize)>,
-        syntax_colors: Option<&Vec<Option<Color>>>,
+        search_matches: &[(usize, usize)],    // char column ranges on this line
+        substitute_matches: &[(usize, usize)],
+        syntax_colors: Option<&Vec<Option<Style>>>,
     ) {
         let bg = self.theme.bg;
         let fg = self.theme.fg;
         let chars: Vec<char> = line_text.chars().collect();
 
-        let search_matches: Vec<(usize, usize)> = if self.config.highlight_search {
-            search_pattern.as_ref().map_or(Vec::new(), |p| find_all_matches(&chars, p))
-        } else { Vec::new() };
-
-        let substitute_matches: Vec<(usize, usize)> = sub_pattern.as_ref().map_or(Vec::new(), |p| {
-            if let Some((sl, el)) = sub_range {
-                if file_line >= sl && file_line <= el { find_all_matches(&chars, p) }
-                else { Vec::new() }
-            } else { Vec::new() }
-        });
-
         let in_visual = matches!(self.mode, Mode::Visual | Mode::VisualLine | Mode::VisualBlock);
         let selection_ref = if in_visual { self.selection.as_ref() } else { None };
 
@@ -317,11 +354,12 @@ impl Editor {
             } else if is_search {
                 (self.theme.search_match_fg, self.theme.search_match_bg)
             } else {
-                // apply syntax color if available
-                let syn_fg = syntax_colors
-                    .and_then(|colors| colors.get(i).copied().flatten())
-                    .unwrap_or(fg);
-                (syn_fg, bg)
+                // apply syntax style if available
+                let syn_style = syntax_colors
+                    .and_then(|styles| styles.get(i).copied().flatten())
+                    .unwrap_or_default();
+                self.screen.put_char_styled(row, screen_col, ch, &syn_style, fg, bg);
+                continue;
             };
             self.screen.put_char(row, screen_col, ch, cell_fg, cell_bg);
         }
@@ -338,8 +376,8 @@ impl Editor {
         let cursor = self.current_window().cursor;
         let modified = self.current_buffer().is_modified();
         let read_only = self.current_buffer().is_read_only();
-        let file_type = self.current_buffer().file_type().as_str();
-        self.statusline.update(self.mode, &filename, file_type, cursor, modified, read_only, total_lines);
+        let file_type = self.current_buffer().file_type().to_string();
+        self.statusline.update(self.mode, &filename, &file_type, cursor, modified, read_only, total_lines);
 
         // mode segment
         let (mode_bg, mode_fg) = self.mode_colors();
@@ -385,7 +423,8 @@ impl Editor {
         let percentage = if total_lines > 0 {
             ((cursor.line + 1) * 100 / total_lines).min(100)
         } else { 0 };
-        let right_text = format!("{} // {}% // {} ", file_type, percentage, position);
+        let search_count = self.search_count_text().map(|c| format!("{} // ", c)).unwrap_or_default();
+        let right_text = format!("{}{} // {}% // {} ", search_count, file_type, percentage, position);
         let right_start = width.saturating_sub(right_text.len());
 
         // fill middle with statusline bg
@@ -427,6 +466,41 @@ impl Editor {
         }
     }
 
+    /// which-key popup: a bordered grid of "key → desc" entries above the statusline
+    fn render_which_key_cells(&mut self) {
+        let Some((title, hints)) = self.which_key_hints() else { return; };
+        if hints.is_empty() { return; }
+        let width = self.screen.width;
+        let avail_rows = self.screen.height.saturating_sub(3); // statusline, command line, border
+        let col_width = hints.iter()
+            .map(|h| h.key.chars().count() + h.desc.chars().count() + 5)
+            .max().unwrap_or(1)
+            .min(width.max(1));
+        let cols = (width / col_width).max(1);
+        let rows = ((hints.len() + cols - 1) / cols).min(avail_rows);
+        if rows == 0 { return; }
+        let top = self.screen.height.saturating_sub(2 + rows + 1);
+        let bg = self.theme.statusline_bg;
+        let border = format!("─ {} ", title);
+        for c in 0..width { self.screen.put_char(top, c, '─', self.theme.gutter_separator, bg); }
+        self.screen.put_str(top, 0, &border, self.theme.accent, bg);
+        for r in 0..rows {
+            for c in 0..width { self.screen.put_char(top + 1 + r, c, ' ', self.theme.fg, bg); }
+        }
+        for (i, hint) in hints.iter().enumerate() {
+            let (r, c) = (i % rows, i / rows);
+            if c >= cols { break; }
+            let row = top + 1 + r;
+            let col = c * col_width + 1;
+            self.screen.put_str_bold(row, col, &hint.key, self.theme.accent, bg);
+            let key_len = hint.key.chars().count();
+            self.screen.put_str(row, col + key_len + 1, "→", self.theme.comment, bg);
+            let desc_fg = if hint.is_group { self.theme.string } else { self.theme.fg };
+            self.screen.put_str(row, col + key_len + 3, &hint.desc, desc_fg, bg);
+        }
+        self.which_key_visible = true;
+    }
+
     fn mode_colors(&self) -> (Color, Color) {
         match self.mode {
             Mode::Normal => (self.theme.mode_normal_bg, self.theme.mode_normal_fg),
@@ -438,53 +512,26 @@ impl Editor {
         }
     }
 
-    pub(super) fn find_all_matches_in_line(&self, line_text: &str, pattern: &str) -> Vec<(usize, usize)> {
-        let chars: Vec<char> = line_text.chars().collect();
-        find_all_matches(&chars, pattern)
-    }
-
+    /// highlight what the :s being typed would match, over its range
     pub(super) fn update_substitute_preview(&mut self) {
         self.substitute_preview_pattern = None;
         self.substitute_preview_range = None;
         if self.mode != Mode::Command || self.command_buffer.is_empty() { return; }
-        let cmd = &self.command_buffer;
-        let (range, rest) = if cmd.starts_with('%') {
-            let line_count = self.current_buffer().line_count();
-            (Some((0, line_count.saturating_sub(1))), &cmd[1..])
-        } else if let Some(comma_pos) = cmd.find(',') {
-            let before_comma = &cmd[..comma_pos];
-            let after_comma = &cmd[comma_pos + 1..];
-            if let Some(cmd_start) = after_comma.find(|c: char| !c.is_ascii_digit()) {
-                let end_str = &after_comma[..cmd_start];
-                if let (Ok(start), Ok(end)) = (before_comma.parse::<usize>(), end_str.parse::<usize>()) {
-                    (Some((start.saturating_sub(1), end.saturating_sub(1))), &after_comma[cmd_start..])
-                } else { (None, cmd.as_str()) }
-            } else { (None, cmd.as_str()) }
-        } else if let Some(first_non_digit) = cmd.find(|c: char| !c.is_ascii_digit()) {
-            let line_str = &cmd[..first_non_digit];
-            if let Ok(line) = line_str.parse::<usize>() {
-                (Some((line.saturating_sub(1), line.saturating_sub(1))), &cmd[first_non_digit..])
-            } else { (None, cmd.as_str()) }
-        } else { (None, cmd.as_str()) };
-        if rest.starts_with("s/") {
-            let pattern_start = 2;
-            let default_range = self.visual_cmd_range.unwrap_or((
-                self.current_window().cursor.line, self.current_window().cursor.line,
-            ));
-            if let Some(pattern_end) = rest[pattern_start..].find('/') {
-                let pattern = &rest[pattern_start..pattern_start + pattern_end];
-                if !pattern.is_empty() {
-                    self.substitute_preview_pattern = Some(pattern.to_string());
-                    self.substitute_preview_range = range.or(Some(default_range));
-                }
-            } else if rest.len() > 2 {
-                let pattern = &rest[pattern_start..];
-                if !pattern.is_empty() {
-                    self.substitute_preview_pattern = Some(pattern.to_string());
-                    self.substitute_preview_range = range.or(Some(default_range));
-                }
-            }
+        let cursor_line = self.current_window().cursor.line;
+        let (range, rest) = self.with_line_context(|lines| crate::command::parse_range(&self.command_buffer, lines));
+        let Some(Ok(spec)) = crate::substitute::parse(rest) else { return; };
+        let Some(mut pattern) = spec.pattern.filter(|p| !p.is_empty()) else { return; };
+        match crate::substitute::Flags::default().apply(&spec.flags).ignore_case {
+            Some(true) => pattern.push_str("\\c"),
+            Some(false) => pattern.push_str("\\C"),
+            None => {}
         }
+        let lines = match range {
+            Some(r) => (r.start.saturating_sub(1), r.end.saturating_sub(1)),
+            None => self.visual_cmd_range.unwrap_or((cursor_line, cursor_line)),
+        };
+        self.substitute_preview_pattern = Some(pattern);
+        self.substitute_preview_range = Some(lines);
     }
 
     pub(super) fn clear_substitute_preview(&mut self) {
@@ -494,17 +541,17 @@ impl Editor {
     }
 }
 
-fn find_all_matches(chars: &[char], pattern: &str) -> Vec<(usize, usize)> {
-    let mut matches = Vec::new();
-    if pattern.is_empty() { return matches; }
-    let pattern_chars: Vec<char> = pattern.chars().collect();
-    let mut i = 0;
-    while i + pattern_chars.len() <= chars.len() {
-        let slice: String = chars[i..i + pattern_chars.len()].iter().collect();
-        if slice == pattern {
-            matches.push((i, i + pattern_chars.len()));
-            i += pattern_chars.len();
-        } else { i += 1; }
-    }
-    matches
+/// syntax style of each character of `line`, from sorted, disjoint spans
+pub(super) fn line_colors(buffer: &crate::buffer::Buffer, spans: &[(usize, usize, usize)], styles: &[Style], line: usize) -> Vec<Option<Style>> {
+    let line_start = buffer.line_to_byte(line);
+    let text = buffer.get_line(line).unwrap_or_default();
+    // walk the spans alongside the characters
+    let mut span = spans.partition_point(|&(_, end, _)| end <= line_start);
+    text.char_indices().map(|(bi, _)| {
+        let byte = line_start + bi;
+        while span < spans.len() && spans[span].1 <= byte { span += 1; }
+        spans.get(span)
+            .filter(|&&(start, _, _)| start <= byte)
+            .map(|&(_, _, hi)| styles[hi])
+    }).collect()
 }
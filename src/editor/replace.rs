@@ -0,0 +1,181 @@
+// :Replace -- plan a substitute over the project, review it in a preview buffer
+// where matches can be switched off, and make the rest on :w: in the buffer
+// being edited as one undo step, in other files by rewriting them whole
+
+use crossterm::event::{KeyCode, KeyEvent};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::buffer::Buffer;
+use crate::error::{Error, Result};
+use crate::fuzzy_finder;
+use crate::replace::{self, FileReplace, ProjectReplace};
+use crate::search;
+use crate::substitute;
+use super::Editor;
+
+/// the :Replace under review and the preview buffer showing it
+pub(super) struct ReplacePreview {
+    plan: ProjectReplace,
+    root: PathBuf, // the paths in the plan are relative to it
+    buffer_id: u64,
+}
+
+/// make the enabled replacements of `file` in the file at `path`, unless it
+/// changed since they were planned
+fn replace_in_file(path: &Path, file: &FileReplace) -> std::result::Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if !file.is_unchanged(&text) {
+        return Err("changed since the preview".to_string());
+    }
+    replace::write_atomically(path, &file.apply(&text)).map_err(|e| e.to_string())
+}
+
+impl Editor {
+    /// the buffer with the file being edited, put aside while a scratch buffer
+    /// is shown in its place
+    fn edited_buffer(&self) -> &Buffer {
+        match &self.help_return_buffer {
+            Some(buffer) if self.viewing_help => buffer,
+            _ => self.current_buffer(),
+        }
+    }
+
+    /// whether the current buffer is the :Replace preview
+    pub(super) fn in_replace_preview(&self) -> bool {
+        let id = self.current_buffer().id();
+        self.replace_preview.as_ref().is_some_and(|p| p.buffer_id == id)
+    }
+
+    /// :Replace /pattern/replacement/ -- find every match in the project's files,
+    /// the buffer being edited read as it is now, and show them for review
+    pub(super) fn execute_replace(&mut self, pattern: String, replacement: String, ignore_case: Option<bool>) -> Result<()> {
+        let pattern = if pattern.is_empty() {
+            self.search_pattern.clone().ok_or_else(|| Error::EditorError("No previous regular expression".to_string()))?
+        } else { pattern };
+        let source = match ignore_case {
+            Some(true) => format!("{}\\c", pattern),
+            Some(false) => format!("{}\\C", pattern),
+            None => pattern.clone(),
+        };
+        let compiled = self.compile_search(&source)
+            .map_err(|e| Error::EditorError(format!("Invalid pattern: {}", e)))?;
+        let previous = self.last_substitute.as_ref().map_or("", |s| s.replacement.as_str());
+        let replacement = substitute::expand_tilde(&replacement, previous);
+
+        let root = std::env::current_dir()?;
+        let open = self.edited_buffer();
+        let open_path = open.file_path().and_then(|p| fs::canonicalize(p).ok());
+        let open_text = open.rope().to_string();
+        let mut files = Vec::new();
+        for path in fuzzy_finder::project_files(&root) {
+            if fuzzy_finder::is_likely_binary(&path) {
+                continue;
+            }
+            let is_open = open_path.is_some() && fs::canonicalize(&path).ok() == open_path;
+            let text = if is_open {
+                open_text.clone()
+            } else {
+                match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue, // not text
+                }
+            };
+            let shown = path.strip_prefix(&root).map_or_else(|_| path.clone(), Path::to_path_buf);
+            files.extend(FileReplace::new(shown, &text, &compiled, &replacement));
+        }
+        self.search_pattern = Some(pattern.clone());
+        self.search_highlight_off = false;
+        if files.is_empty() {
+            self.message = Some(format!("Pattern not found: {}", pattern));
+            return Ok(());
+        }
+
+        let plan = ProjectReplace::new(pattern, replacement, files);
+        // rows map to matches by position, so the preview cannot be edited
+        self.show_scratch_buffer(&plan.preview());
+        self.current_buffer_mut().set_read_only(true);
+        let (matches, _) = plan.counts();
+        self.message = Some(format!("{} matches in {} files", matches, plan.files.len()));
+        self.replace_preview = Some(ReplacePreview { plan, root, buffer_id: self.current_buffer().id() });
+        Ok(())
+    }
+
+    /// Tab in the :Replace preview switches the match under the cursor, a
+    /// file's matches on its path, or all of them on the first line; false
+    /// when the key is not for the preview
+    pub(super) fn handle_replace_preview_key(&mut self, key: KeyEvent) -> bool {
+        if key.code != KeyCode::Tab || !self.in_replace_preview() {
+            return false;
+        }
+        let row = self.current_window().cursor.line;
+        let Some(preview) = self.replace_preview.as_mut() else { return false; };
+        if preview.plan.toggle(row) {
+            let mut buffer = Buffer::from_string(&preview.plan.preview());
+            buffer.set_read_only(true);
+            preview.buffer_id = buffer.id();
+            *self.current_buffer_mut() = buffer;
+            self.clamp_cursor();
+        }
+        true
+    }
+
+    /// the replacements of `file` in the buffer being edited, as one undo step
+    fn replace_in_buffer(&mut self, file: &FileReplace) -> std::result::Result<(), String> {
+        if self.current_buffer().is_read_only() {
+            return Err("read-only".to_string());
+        }
+        if !file.is_unchanged(&self.current_buffer().rope().to_string()) {
+            return Err("changed since the preview".to_string());
+        }
+        self.save_undo_state();
+        // back to front, so the edits still to make keep their offsets
+        for edit in file.enabled().rev() {
+            let rope = self.current_buffer().rope();
+            let (start_line, start_col) = search::position(rope, edit.start);
+            let (end_line, end_col) = search::position(rope, edit.end);
+            if edit.end > edit.start {
+                self.rec_delete_range(start_line, start_col, end_line, end_col);
+            }
+            if !edit.text.is_empty() {
+                self.rec_insert_text(start_line, start_col, &edit.text);
+            }
+        }
+        self.end_undo_group();
+        self.clamp_cursor();
+        Ok(())
+    }
+
+    /// :w in the preview: go back to the buffer being edited and make every
+    /// replacement still switched on, reporting the files left alone
+    pub(super) fn apply_replace(&mut self) {
+        let Some(preview) = self.replace_preview.take() else { return; };
+        self.close_scratch_buffer();
+        let open_path = self.current_buffer().file_path().and_then(|p| fs::canonicalize(p).ok());
+        let (mut replaced, mut changed_files) = (0, 0);
+        let mut skipped = Vec::new();
+        for file in &preview.plan.files {
+            let count = file.enabled().count();
+            if count == 0 {
+                continue;
+            }
+            let path = preview.root.join(&file.path);
+            let result = if open_path.is_some() && fs::canonicalize(&path).ok() == open_path {
+                self.replace_in_buffer(file)
+            } else {
+                replace_in_file(&path, file)
+            };
+            match result {
+                Ok(()) => {
+                    replaced += count;
+                    changed_files += 1;
+                }
+                Err(e) => skipped.push(format!("{} ({})", file.path.display(), e)),
+            }
+        }
+        let mut message = format!("Replaced {} matches in {} files", replaced, changed_files);
+        if !skipped.is_empty() {
+            message.push_str(&format!("; skipped {}", skipped.join(", ")));
+        }
+        self.message = Some(message);
+    }
+}
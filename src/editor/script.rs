@@ -0,0 +1,323 @@
+// ex scripts (:source, init.ex), key mappings and autocommands
+
+use crossterm::event::KeyEvent;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::autocmd::AutocmdEvent;
+use crate::error::{Error, Result};
+use crate::mapping::{normalize_key, MapMatch, MapMode, Mapping};
+use crate::mode::Mode;
+use super::Editor;
+
+const MAX_MAPPING_DEPTH: usize = 100;
+const MAX_SOURCE_DEPTH: usize = 16;
+
+/// message text for an ex command error, without the error-kind prefix
+fn error_text(e: &Error) -> String {
+    match e {
+        Error::ParseError(msg) | Error::EditorError(msg) => msg.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// expand a leading `~/` to the home directory
+pub(super) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// split a script into logical lines: (line number, text), joining `\` continuations
+/// and dropping blank lines and `"` comments
+fn script_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, raw) in content.lines().enumerate() {
+        let trimmed = raw.trim();
+        if let Some(cont) = trimmed.strip_prefix('\\') {
+            if let Some(last) = lines.last_mut() {
+                last.1.push_str(cont);
+                continue;
+            }
+        }
+        if trimmed.is_empty() || trimmed.starts_with('"') {
+            continue;
+        }
+        lines.push((i + 1, trimmed.to_string()));
+    }
+    lines
+}
+
+impl Editor {
+    /// run every line of `path` as an ex command, returning "file:line: error" for
+    /// each line that failed; a failing line does not stop the rest of the script
+    pub(super) fn source_file(&mut self, path: &Path) -> Result<Vec<String>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::EditorError(format!("Can't open file {}: {}", path.display(), e)))?;
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            return Err(Error::EditorError(format!("Too many nested :source: {}", path.display())));
+        }
+        self.source_depth += 1;
+        // feedback like "number set" is noise when it comes from a script
+        let saved_message = self.message.take();
+        let mut errors = Vec::new();
+        for (lnum, line) in script_lines(&content) {
+            if let Err(e) = self.execute_command_str(&line) {
+                let err = format!("{}:{}: {}", path.display(), lnum, error_text(&e));
+                log::warn!("{}", err);
+                errors.push(err);
+            }
+        }
+        self.source_depth -= 1;
+        self.message = saved_message;
+        Ok(errors)
+    }
+
+    /// `:source file`
+    pub(super) fn source_command(&mut self, file: &str) -> Result<()> {
+        if file.is_empty() {
+            return Err(Error::ParseError("Argument required: :source {file}".to_string()));
+        }
+        let path = expand_home(file);
+        let errors = self.source_file(&path)?;
+        if errors.is_empty() {
+            if self.source_depth == 0 {
+                self.message = Some(format!("Sourced {}", path.display()));
+            }
+            Ok(())
+        } else {
+            Err(Error::ParseError(errors.join(" | ")))
+        }
+    }
+
+    /// run init.ex from the config directory, if there is one
+    pub(super) fn source_init_script(&mut self) {
+        let Some(path) = crate::config::config_dir().map(|d| d.join("init.ex")) else { return; };
+        if !path.is_file() { return; }
+        match self.source_file(&path) {
+            Ok(errors) if !errors.is_empty() => {
+                let more = if errors.len() > 1 { format!(" (+{} more)", errors.len() - 1) } else { String::new() };
+                self.message = Some(format!("{}{}", errors[0], more));
+            }
+            Ok(_) => {}
+            Err(e) => { self.message = Some(error_text(&e)); }
+        }
+    }
+
+    // -- mappings --
+
+    pub(super) fn define_mapping(&mut self, modes: &[MapMode], noremap: bool, args: &str) -> Result<()> {
+        let mut args = args.trim_start();
+        // modifiers we accept for compatibility but have no effect here
+        for modifier in ["<silent>", "<buffer>", "<nowait>", "<unique>"] {
+            args = args.strip_prefix(modifier).unwrap_or(args).trim_start();
+        }
+        let Some((lhs, rhs)) = args.split_once(char::is_whitespace) else {
+            let entries: Vec<String> = self.mappings.list(modes).into_iter()
+                .filter(|(_, l, _, _)| args.is_empty() || l.starts_with(args))
+                .map(|(m, l, r, nore)| format!("{} {} {}{}", m, l, if nore { "*" } else { "" }, r))
+                .collect();
+            self.message = Some(if entries.is_empty() {
+                "No mapping found".to_string()
+            } else { entries.join(" | ") });
+            return Ok(());
+        };
+        let leader = self.config.leader_key;
+        for mode in modes {
+            self.mappings.add(*mode, lhs, rhs.trim(), noremap, leader)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn remove_mapping(&mut self, modes: &[MapMode], lhs: &str) -> Result<()> {
+        let leader = self.config.leader_key;
+        let mut removed = false;
+        for mode in modes {
+            removed |= self.mappings.remove(*mode, lhs.trim(), leader);
+        }
+        if removed { Ok(()) } else { Err(Error::ParseError(format!("No such mapping: {}", lhs))) }
+    }
+
+    /// mapping table that applies right now, or None when the next key is an
+    /// argument (r{char}, m{mark}, "{reg}, ...) that must not be remapped
+    fn map_mode(&self) -> Option<MapMode> {
+        match self.mode {
+            Mode::Normal => {
+                let awaiting_char = matches!(self.pending_key, Some('r' | 'q' | '@'))
+                    || self.waiting_for_mark.is_some()
+                    || self.waiting_for_register
+                    || self.surround_pending.is_some()
+                    || self.leader_sequence.is_some();
+                if awaiting_char { None } else { Some(MapMode::Normal) }
+            }
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => Some(MapMode::Visual),
+            Mode::Insert | Mode::Replace => Some(MapMode::Insert),
+            Mode::Command | Mode::Search => Some(MapMode::Command),
+            Mode::FuzzyFind => None,
+        }
+    }
+
+    /// entry point for keys typed by the user: applies mappings and fires
+    /// InsertEnter/InsertLeave autocommands
+    pub(super) fn handle_typed_key(&mut self, key: KeyEvent) -> Result<()> {
+        let was_insert = self.mode == Mode::Insert;
+        self.feed_key(key, 0)?;
+        let is_insert = self.mode == Mode::Insert;
+        if !was_insert && is_insert {
+            self.fire_buffer_autocmd(AutocmdEvent::InsertEnter);
+        } else if was_insert && !is_insert {
+            self.fire_buffer_autocmd(AutocmdEvent::InsertLeave);
+        }
+        Ok(())
+    }
+
+    fn feed_key(&mut self, key: KeyEvent, depth: usize) -> Result<()> {
+        let Some(mode) = self.map_mode() else { return self.handle_key(key); };
+        if self.pending_map_keys.is_empty() && self.mappings.is_empty(mode) {
+            return self.handle_key(key);
+        }
+        self.pending_map_keys.push(normalize_key(key));
+        match self.mappings.lookup(mode, &self.pending_map_keys) {
+            MapMatch::Prefix | MapMatch::ExactOrPrefix(_) => {
+                self.map_pending_since = Some(Instant::now());
+                Ok(())
+            }
+            MapMatch::Exact(mapping) => {
+                self.pending_map_keys.clear();
+                self.map_pending_since = None;
+                self.run_mapping(&mapping, depth)
+            }
+            MapMatch::None => {
+                let keys = std::mem::take(&mut self.pending_map_keys);
+                self.map_pending_since = None;
+                // a shorter mapping may have matched before this key broke the longer one
+                if keys.len() > 1 {
+                    if let MapMatch::ExactOrPrefix(mapping) = self.mappings.lookup(mode, &keys[..keys.len() - 1]) {
+                        self.run_mapping(&mapping, depth)?;
+                        return self.feed_key(keys[keys.len() - 1], depth);
+                    }
+                }
+                self.handle_key(keys[0])?;
+                for k in &keys[1..] {
+                    self.feed_key(*k, depth)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn run_mapping(&mut self, mapping: &Mapping, depth: usize) -> Result<()> {
+        if depth >= MAX_MAPPING_DEPTH {
+            self.message = Some(format!("Recursive mapping: {}", mapping.lhs));
+            return Ok(());
+        }
+        for key in &mapping.rhs {
+            if mapping.noremap {
+                self.handle_key(*key)?;
+            } else {
+                self.feed_key(*key, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// true once ambiguous mapping keys have waited longer than timeout_len_ms
+    pub(super) fn map_timeout_due(&self) -> bool {
+        self.map_pending_since
+            .map(|t| t.elapsed().as_millis() >= self.config.timeout_len_ms as u128)
+            .unwrap_or(false)
+    }
+
+    /// stop waiting for a longer mapping: use the exact match or replay the keys
+    pub(super) fn resolve_pending_mapping(&mut self) -> Result<()> {
+        self.map_pending_since = None;
+        let keys = std::mem::take(&mut self.pending_map_keys);
+        if keys.is_empty() { return Ok(()); }
+        let was_insert = self.mode == Mode::Insert;
+        if let Some(MapMatch::ExactOrPrefix(mapping)) = self.map_mode().map(|m| self.mappings.lookup(m, &keys)) {
+            self.run_mapping(&mapping, 0)?;
+        } else {
+            self.handle_key(keys[0])?;
+            for k in &keys[1..] {
+                self.feed_key(*k, 0)?;
+            }
+        }
+        if !was_insert && self.mode == Mode::Insert {
+            self.fire_buffer_autocmd(AutocmdEvent::InsertEnter);
+        } else if was_insert && self.mode != Mode::Insert {
+            self.fire_buffer_autocmd(AutocmdEvent::InsertLeave);
+        }
+        Ok(())
+    }
+
+    // -- autocommands --
+
+    /// run the autocommands for `event` matching `subject`; autocommands do not nest
+    pub(super) fn fire_autocmd(&mut self, event: AutocmdEvent, subject: &str) {
+        if self.running_autocmd { return; }
+        let commands = self.autocmds.matching(event, subject);
+        if commands.is_empty() { return; }
+        self.running_autocmd = true;
+        for cmd in commands {
+            if let Err(e) = self.execute_command_str(&cmd) {
+                self.message = Some(format!("{} autocommand: {}", event.name(), error_text(&e)));
+            }
+        }
+        self.running_autocmd = false;
+    }
+
+    /// fire a buffer event using the current buffer's path as the subject
+    pub(super) fn fire_buffer_autocmd(&mut self, event: AutocmdEvent) {
+        let path = self.current_buffer().file_path()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.fire_autocmd(event, &path);
+    }
+
+    /// autocommands for a buffer that was just loaded
+    pub(super) fn fire_buffer_loaded(&mut self) {
+        self.fire_buffer_autocmd(AutocmdEvent::BufReadPost);
+        let filetype = self.current_buffer().file_type().to_string();
+        self.fire_autocmd(AutocmdEvent::FileType, &filetype);
+        self.fire_buffer_autocmd(AutocmdEvent::BufEnter);
+    }
+
+    /// save the current buffer (optionally under a new name), running
+    /// BufWritePre/BufWritePost autocommands
+    pub(super) fn write_current_buffer(&mut self, path: Option<String>) -> Result<()> {
+        let subject = match path {
+            Some(ref p) => p.clone(),
+            None => self.current_buffer().file_path()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        self.fire_autocmd(AutocmdEvent::BufWritePre, &subject);
+        match path {
+            Some(p) => {
+                self.current_buffer_mut().save_as(p)?;
+                if self.current_buffer().language().is_none() {
+                    self.detect_language(self.windows[self.active_window].buffer_index);
+                }
+            }
+            None => self.current_buffer_mut().save()?,
+        }
+        self.fire_autocmd(AutocmdEvent::BufWritePost, &subject);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_lines() {
+        let script = "\" comment\nset number\n\n  map x\n  \\ y\n:w\n";
+        assert_eq!(script_lines(script), vec![
+            (2, "set number".to_string()),
+            (4, "map x y".to_string()),
+            (6, ":w".to_string()),
+        ]);
+    }
+}
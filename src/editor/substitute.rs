@@ -0,0 +1,228 @@
+// :substitute execution, and the y/n/a/q/l prompt its c flag asks at each match
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::command::Range;
+use crate::search::{self, SearchPattern};
+use crate::substitute::{self, Edit, Flags, Substitute, SubstituteSpec};
+use super::Editor;
+
+/// a :s///c in progress; the whole session is one open undo group
+pub(super) struct ConfirmSubstitute {
+    edits: Vec<Edit>,     // on the text as it was when the command ran
+    lines: Vec<usize>,    // line each edit starts on, in that text
+    next: usize,          // edit being asked about
+    shift: isize,         // bytes the accepted edits added before it
+    accepted: Vec<usize>, // edits made so far
+    replacement: String,  // as typed, for the prompt
+}
+
+impl ConfirmSubstitute {
+    /// bytes of the match being asked about, in the current text
+    pub(super) fn current_match(&self) -> Option<(usize, usize)> {
+        let edit = self.edits.get(self.next)?;
+        Some((edit.start.saturating_add_signed(self.shift), edit.end.saturating_add_signed(self.shift)))
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 { "" } else { "s" }
+}
+
+impl Editor {
+    /// :s and its repeat forms over `range` (the visual lines or the cursor line
+    /// when there is none), as one undo step
+    pub(super) fn execute_substitute(&mut self, spec: SubstituteSpec, range: Option<Range>) {
+        let last = self.last_substitute.clone();
+        let pattern = match spec.pattern.as_deref() {
+            None => last.as_ref().map(|s| s.pattern.clone()),
+            Some("") => self.search_pattern.clone().or_else(|| last.as_ref().map(|s| s.pattern.clone())),
+            Some(p) => Some(p.to_string()),
+        };
+        let Some(pattern) = pattern else {
+            self.message = Some("No previous substitute regular expression".to_string());
+            return;
+        };
+        let previous = last.as_ref().map_or("", |s| s.replacement.as_str());
+        let replacement = match &spec.replacement {
+            Some(r) => substitute::expand_tilde(r, previous),
+            None => previous.to_string(),
+        };
+        let base = match &last {
+            Some(s) if spec.keeps_flags() => s.flags,
+            _ => Flags::default(),
+        };
+        let flags = base.apply(&spec.flags);
+        if flags.confirm && self.in_global {
+            self.message = Some("The c flag cannot be used under :global".to_string());
+            return;
+        }
+        self.last_substitute = Some(Substitute { pattern: pattern.clone(), replacement: replacement.clone(), flags });
+        self.search_pattern = Some(pattern.clone());
+        self.search_highlight_off = false;
+
+        let (ignore_case, smart_case) = match flags.ignore_case {
+            Some(ic) => (ic, false),
+            None => (self.config.ignore_case, self.config.smart_case),
+        };
+        let compiled = match SearchPattern::new(&pattern, ignore_case, smart_case) {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                self.message = Some(format!("Invalid pattern: {}", e));
+                return;
+            }
+        };
+        let last_line = self.current_buffer().line_count().saturating_sub(1);
+        let (mut first, mut end) = if let Some(r) = range {
+            (r.start.saturating_sub(1), r.end.saturating_sub(1))
+        } else if let Some((vs, ve)) = self.visual_cmd_range {
+            (vs, ve)
+        } else {
+            let line = self.current_window().cursor.line;
+            (line, line)
+        };
+        self.visual_cmd_range = None;
+        if let Some(count) = spec.count {
+            first = end;
+            end = end.saturating_add(count - 1);
+        }
+        let (first, end) = (first.min(last_line), end.min(last_line));
+
+        let edits = substitute::plan(self.current_buffer().rope(), &compiled, &replacement, first, end, flags.global);
+        if edits.is_empty() {
+            if !flags.quiet {
+                self.message = Some(format!("Pattern not found: {}", pattern));
+            }
+            return;
+        }
+        let rope = self.current_buffer().rope();
+        let lines: Vec<usize> = edits.iter().map(|e| rope.byte_to_line(e.start)).collect();
+        if flags.count_only {
+            let mut changed = lines.clone();
+            changed.dedup();
+            self.message = Some(format!("{} match{} on {} line{}",
+                edits.len(), if edits.len() == 1 { "" } else { "es" }, changed.len(), plural(changed.len())));
+            return;
+        }
+
+        let mut session = ConfirmSubstitute {
+            edits, lines, next: 0, shift: 0, accepted: Vec::new(),
+            replacement: spec.replacement.unwrap_or(replacement),
+        };
+        self.save_undo_state();
+        if flags.confirm {
+            self.confirm_substitute = Some(session);
+            self.show_confirm_match();
+        } else {
+            while session.next < session.edits.len() {
+                self.accept_substitute_edit(&mut session);
+            }
+            self.finish_substitute(session);
+        }
+    }
+
+    /// make the edit the session is at and move on to the next
+    fn accept_substitute_edit(&mut self, session: &mut ConfirmSubstitute) {
+        let Some((start, end)) = session.current_match() else { return; };
+        let text = session.edits[session.next].text.clone();
+        let rope = self.current_buffer().rope();
+        let (start_line, start_col) = search::position(rope, start);
+        let (end_line, end_col) = search::position(rope, end);
+        if end > start {
+            self.rec_delete_range(start_line, start_col, end_line, end_col);
+        }
+        if !text.is_empty() {
+            self.rec_insert_text(start_line, start_col, &text);
+        }
+        session.shift += text.len() as isize - (end - start) as isize;
+        session.accepted.push(session.next);
+        session.next += 1;
+    }
+
+    /// close the undo group, leave the cursor on the last line changed and
+    /// report what was done
+    fn finish_substitute(&mut self, session: ConfirmSubstitute) {
+        self.end_undo_group();
+        let Some(&last) = session.accepted.last() else {
+            self.message = None;
+            return;
+        };
+        // edits before it moved its start by the shift up to it
+        let shift: isize = session.accepted.iter()
+            .filter(|&&i| i < last)
+            .map(|&i| {
+                let e = &session.edits[i];
+                e.text.len() as isize - (e.end - e.start) as isize
+            })
+            .sum();
+        let start = session.edits[last].start.saturating_add_signed(shift);
+        let rope = self.current_buffer().rope();
+        let line = rope.byte_to_line(start.min(rope.len_bytes()));
+        self.current_window_mut().cursor.line = line;
+        self.move_to_first_non_blank();
+        self.clamp_cursor();
+        let window = self.current_window_mut();
+        window.viewport.ensure_cursor_visible(window.cursor.line, window.cursor.col, &window.folds);
+        let mut lines: Vec<usize> = session.accepted.iter().map(|&i| session.lines[i]).collect();
+        lines.dedup();
+        let count = session.accepted.len();
+        self.message = Some(format!("{} substitution{} on {} line{}", count, plural(count), lines.len(), plural(lines.len())));
+    }
+
+    /// put the cursor on the match being asked about and ask
+    fn show_confirm_match(&mut self) {
+        let Some(session) = &self.confirm_substitute else { return; };
+        let Some((start, _)) = session.current_match() else {
+            if let Some(session) = self.confirm_substitute.take() {
+                self.finish_substitute(session);
+            }
+            return;
+        };
+        let prompt = format!("replace with {} (y/n/a/q/l/^E/^Y)?", session.replacement);
+        let (line, col) = search::position(self.current_buffer().rope(), start);
+        let window = self.current_window_mut();
+        window.cursor.line = line;
+        window.cursor.col = col;
+        window.viewport.ensure_cursor_visible(line, col, &window.folds);
+        self.message = Some(prompt);
+    }
+
+    /// y: replace, n: skip, a: replace this and the rest, q/Esc: stop, l: replace
+    /// this one and stop, Ctrl-E/Ctrl-Y: scroll
+    pub(super) fn handle_confirm_substitute_key(&mut self, key: KeyEvent) {
+        let Some(mut session) = self.confirm_substitute.take() else { return; };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let mut done = false;
+        match key.code {
+            KeyCode::Char('e') if ctrl => {
+                let last_line = self.current_buffer().line_count().saturating_sub(1);
+                let viewport = &mut self.current_window_mut().viewport;
+                if viewport.offset_line < last_line {
+                    viewport.scroll_down(1);
+                }
+            }
+            KeyCode::Char('y') if ctrl => self.current_window_mut().viewport.scroll_up(1),
+            KeyCode::Char('y') => self.accept_substitute_edit(&mut session),
+            KeyCode::Char('n') => session.next += 1,
+            KeyCode::Char('a') => {
+                while session.next < session.edits.len() {
+                    self.accept_substitute_edit(&mut session);
+                }
+            }
+            KeyCode::Char('l') => {
+                self.accept_substitute_edit(&mut session);
+                done = true;
+            }
+            KeyCode::Char('q') | KeyCode::Esc => done = true,
+            _ => {}
+        }
+        if done || session.next >= session.edits.len() {
+            self.finish_substitute(session);
+            return;
+        }
+        let scrolled = ctrl && matches!(key.code, KeyCode::Char('e' | 'y'));
+        self.confirm_substitute = Some(session);
+        if !scrolled {
+            self.show_confirm_match();
+        }
+    }
+}
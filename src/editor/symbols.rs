@@ -0,0 +1,242 @@
+// :Symbols finder and the :Outline sidebar, fed by the language server when one
+// is running and by the grammar's tags query otherwise
+
+use crossterm::style::Color;
+use crate::cursor::Cursor;
+use crate::fuzzy_finder::FuzzyFinder;
+use crate::mode::Mode;
+use crate::symbol::{self, Symbol};
+use crate::window::Rect;
+use super::Editor;
+
+const OUTLINE_WIDTH: usize = 32;
+
+/// the sidebar's symbols and the buffer state they were taken from
+pub(super) struct Outline {
+    source: (u64, u64), // buffer id and version
+    symbols: Vec<Symbol>,
+}
+
+impl Editor {
+    /// the current buffer's uri when a language server can be asked about it
+    fn symbols_uri(&self) -> Option<String> {
+        if !self.lsp_client.is_running() { return None; }
+        self.current_buffer().file_path().map(|path| format!("file://{}", path.display()))
+    }
+
+    /// the server's symbols for the current buffer if it answered for this version
+    fn cached_lsp_symbols(&self, uri: &str) -> Option<Vec<Symbol>> {
+        let buffer = self.current_buffer();
+        match self.lsp_client.document_symbols.get(uri) {
+            Some((version, items)) if *version == buffer.version() => Some(symbol::lsp_symbols(items, buffer.rope())),
+            _ => None,
+        }
+    }
+
+    /// definitions in the current buffer from the tags query
+    fn tag_symbols(&mut self) -> Vec<Symbol> {
+        self.update_current_syntax();
+        let buffer = self.current_buffer();
+        self.syntax.tags(buffer)
+            .map(|(query, root)| symbol::tag_symbols(query, root, buffer.rope()))
+            .unwrap_or_default()
+    }
+
+    /// definitions in the current buffer, nested; waits for the server
+    fn current_symbols(&mut self) -> Vec<Symbol> {
+        if let Some(uri) = self.symbols_uri() {
+            let symbols = match self.cached_lsp_symbols(&uri) {
+                Some(symbols) => symbols,
+                None => match self.lsp_client.document_symbols(&uri) {
+                    Ok(items) => {
+                        let version = self.current_buffer().version();
+                        let symbols = symbol::lsp_symbols(&items, self.current_buffer().rope());
+                        self.lsp_client.document_symbols.insert(uri, (version, items));
+                        symbols
+                    }
+                    Err(e) => {
+                        log::warn!("documentSymbol failed: {}", e);
+                        Vec::new()
+                    }
+                },
+            };
+            if !symbols.is_empty() {
+                return symbols;
+            }
+        }
+        self.tag_symbols()
+    }
+
+    pub(super) fn open_symbol_finder(&mut self) {
+        let symbols = self.current_symbols();
+        if symbols.is_empty() {
+            self.message = Some(format!("No symbols found ({})", self.current_buffer().file_type()));
+            return;
+        }
+        let cursor_line = self.current_window().cursor.line;
+        let mut finder = FuzzyFinder::symbols(symbols);
+        if let Some(i) = symbol::symbol_at(&finder.symbols, cursor_line) {
+            finder.selected_index = i;
+        }
+        self.fuzzy_finder = Some(finder);
+        self.mode = Mode::FuzzyFind;
+    }
+
+    pub(super) fn jump_to_symbol(&mut self, line: usize, col: usize) {
+        self.save_jump_position();
+        self.current_window_mut().cursor = Cursor { line, col };
+        self.clamp_cursor();
+        self.reveal_cursor_line();
+        let line = self.current_window().cursor.line;
+        let half_height = self.current_window().viewport.height / 2;
+        self.current_window_mut().viewport.offset_line = line.saturating_sub(half_height);
+    }
+
+    pub(super) fn toggle_outline(&mut self) {
+        self.outline = match self.outline.take() {
+            Some(_) => None,
+            None => Some(Outline { source: (0, 0), symbols: Vec::new() }),
+        };
+        self.recalculate_window_rects();
+    }
+
+    /// columns the outline takes from the right of the screen, separator included
+    pub(super) fn outline_width(&self, screen_width: usize) -> usize {
+        match self.outline {
+            Some(_) if !self.zen_mode => OUTLINE_WIDTH.min(screen_width / 3),
+            _ => 0,
+        }
+    }
+
+    /// retake the outline's symbols when the current buffer changed; edits made
+    /// in insert mode are picked up when it ends. the server is asked without
+    /// waiting and the last outline stays up until it answers
+    fn refresh_outline(&mut self) {
+        let buffer = self.current_buffer();
+        let source = (buffer.id(), buffer.version());
+        let stale = match &self.outline {
+            Some(outline) => outline.source != source
+                && (outline.source.0 != source.0 || !matches!(self.mode, Mode::Insert | Mode::Replace)),
+            None => false,
+        };
+        if stale {
+            let mut symbols = Vec::new();
+            if let Some(uri) = self.symbols_uri() {
+                match self.cached_lsp_symbols(&uri) {
+                    Some(cached) => symbols = cached,
+                    None => match self.lsp_client.request_document_symbols(&uri, source.1) {
+                        Ok(()) => return,
+                        Err(e) => log::warn!("documentSymbol failed: {}", e),
+                    },
+                }
+            }
+            if symbols.is_empty() {
+                symbols = self.tag_symbols();
+            }
+            if let Some(outline) = self.outline.as_mut() {
+                *outline = Outline { source, symbols };
+            }
+        }
+    }
+
+    /// the outline in `area`: a separator column, then one row per symbol with the
+    /// one around the cursor highlighted and kept in view
+    pub(super) fn render_outline_cells(&mut self, area: Rect) {
+        self.refresh_outline();
+        let Some(outline) = &self.outline else { return; };
+        let (bg, fg) = (self.theme.bg, self.theme.fg);
+        for row in area.y..area.y + area.height {
+            self.screen.put_char(row, area.x, '\u{2502}', self.theme.gutter_separator, bg);
+        }
+        let width = area.width.saturating_sub(2);
+        if outline.symbols.is_empty() {
+            self.screen.put_str(area.y, area.x + 2, "No symbols", self.theme.comment, bg);
+            return;
+        }
+        let current = symbol::symbol_at(&outline.symbols, self.current_window().cursor.line);
+        let offset = current.map_or(0, |i| i.saturating_sub(area.height / 2))
+            .min(outline.symbols.len().saturating_sub(area.height));
+        for (row, (i, sym)) in outline.symbols.iter().enumerate().skip(offset).take(area.height).enumerate() {
+            let row_bg = if Some(i) == current { self.theme.finder_selected_bg } else { bg };
+            let screen_row = area.y + row;
+            for col in area.x + 1..area.x + area.width {
+                self.screen.put_char(screen_row, col, ' ', fg, row_bg);
+            }
+            let indent = (sym.depth * 2).min(width.saturating_sub(3));
+            let icon_style = self.theme.capture_style(sym.kind.highlight());
+            self.screen.put_char_styled(screen_row, area.x + 2 + indent, sym.kind.icon(), &icon_style, fg, row_bg);
+            let name: String = sym.name.chars().take(width.saturating_sub(indent + 2)).collect();
+            self.screen.put_str(screen_row, area.x + 4 + indent, &name, fg, row_bg);
+        }
+    }
+
+    /// the symbol finder: the nested list on the left, the code around the
+    /// selected symbol on the right
+    pub(super) fn render_symbol_finder_cells(&mut self) {
+        let width = self.screen.width;
+        let height = self.screen.height.saturating_sub(2);
+        let (bg, fg) = (self.theme.bg, self.theme.fg);
+        let Some(finder) = &self.fuzzy_finder else { return; };
+        let prompt = finder.prompt();
+        self.screen.put_str(0, 0, prompt, self.theme.finder_prompt_fg, bg);
+        self.screen.put_str(0, prompt.len(), &finder.query, fg, bg);
+        let count = format!("{}/{}", finder.matches.len(), finder.symbols.len());
+        self.screen.put_str(0, width.saturating_sub(count.len() + 1), &count, self.theme.comment, bg);
+
+        let list_width = if width >= 60 { width / 2 } else { width };
+        let rows = height.saturating_sub(1);
+        let offset = finder.selected_index.saturating_sub(rows.saturating_sub(1));
+        for (row, (i, m)) in finder.matches.iter().enumerate().skip(offset).take(rows).enumerate() {
+            let Some(sym) = finder.symbols.get(m.index) else { continue; };
+            let screen_row = row + 1;
+            let row_bg = if i == finder.selected_index { self.theme.finder_selected_bg } else { bg };
+            for col in 0..list_width {
+                self.screen.put_char(screen_row, col, ' ', fg, row_bg);
+            }
+            let indent = (sym.depth * 2).min(list_width / 2);
+            let icon_style = self.theme.capture_style(sym.kind.highlight());
+            self.screen.put_char_styled(screen_row, indent, sym.kind.icon(), &icon_style, fg, row_bg);
+            let location = format!(":{}", sym.line + 1);
+            let room = list_width.saturating_sub(indent + 2 + location.len() + 2);
+            let name: String = sym.name.chars().take(room).collect();
+            self.screen.put_str(screen_row, indent + 2, &name, fg, row_bg);
+            let location_col = indent + 2 + name.chars().count() + 2;
+            self.screen.put_str(screen_row, location_col, &location, self.theme.comment, row_bg);
+        }
+
+        if list_width == width {
+            return;
+        }
+        let Some(sym) = finder.selected_symbol() else { return; };
+        let target = sym.line;
+        let preview = Rect { x: list_width, y: 1, width: width - list_width, height: rows };
+        self.render_preview_cells(preview, target);
+    }
+
+    /// lines of the current buffer around `target`, which is highlighted
+    fn render_preview_cells(&mut self, area: Rect, target: usize) {
+        let bg = self.theme.bg;
+        for row in area.y..area.y + area.height {
+            self.screen.put_char(row, area.x, '\u{2502}', self.theme.gutter_separator, bg);
+        }
+        let buffer = &self.buffers[self.windows[self.active_window].buffer_index];
+        let first = target.saturating_sub(area.height / 3);
+        let number_width = (first + area.height).to_string().len();
+        let tab = " ".repeat(self.config.tab_width);
+        for row in 0..area.height {
+            let line = first + row;
+            let Some(text) = buffer.get_line(line) else { break; };
+            let row_bg = if line == target { self.theme.current_line_bg } else { bg };
+            let screen_row = area.y + row;
+            for col in area.x + 1..area.x + area.width {
+                self.screen.put_char(screen_row, col, ' ', self.theme.fg, row_bg);
+            }
+            let number = format!("{:>w$} ", line + 1, w = number_width);
+            let number_fg: Color = if line == target { self.theme.gutter_current } else { self.theme.gutter_fg };
+            self.screen.put_str(screen_row, area.x + 2, &number, number_fg, row_bg);
+            let room = area.width.saturating_sub(number.len() + 3);
+            let text: String = text.replace('\t', &tab).chars().take(room).collect();
+            self.screen.put_str(screen_row, area.x + 2 + number.len(), &text, self.theme.fg, row_bg);
+        }
+    }
+}
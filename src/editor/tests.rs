@@ -0,0 +1,250 @@
+// key-driven tests of the editor on a headless terminal
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use super::Editor;
+use crate::test_dir::TestDir;
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|n| format!("line {}\n", n)).collect()
+}
+
+impl Editor {
+    /// type `keys` as if at the keyboard, one character at a time
+    fn type_keys(&mut self, keys: &str) {
+        for ch in keys.chars() {
+            let code = match ch {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                _ => KeyCode::Char(ch),
+            };
+            self.handle_typed_key(KeyEvent::new(code, KeyModifiers::NONE)).unwrap();
+        }
+    }
+
+    fn text(&self) -> String {
+        self.current_buffer().rope().to_string()
+    }
+
+    fn go_to_line(&mut self, line: usize) {
+        self.current_window_mut().cursor.line = line - 1;
+        self.current_window_mut().cursor.col = 0;
+    }
+}
+
+#[test]
+fn test_delete_to_counted_file_start() {
+    let mut editor = Editor::for_test(&numbered_lines(10));
+    editor.go_to_line(8);
+    editor.type_keys("d5gg");
+    assert_eq!(editor.text(), "line 1\nline 2\nline 3\nline 4\nline 9\nline 10\n");
+    assert_eq!(editor.current_window().cursor.line, 4);
+}
+
+#[test]
+fn test_delete_to_percent() {
+    let mut editor = Editor::for_test(&numbered_lines(10));
+    editor.type_keys("d50%");
+    assert_eq!(editor.text(), numbered_lines(10).replacen(&numbered_lines(5), "", 1));
+}
+
+#[test]
+fn test_counted_delete_down() {
+    let mut editor = Editor::for_test(&numbered_lines(10));
+    editor.go_to_line(2);
+    editor.type_keys("3dj");
+    assert_eq!(editor.text(), "line 1\nline 6\nline 7\nline 8\nline 9\nline 10\n");
+}
+
+#[test]
+fn test_visual_delete_includes_last_character() {
+    let mut editor = Editor::for_test("abcdef\nghijkl\n");
+    editor.type_keys("lvlld");
+    assert_eq!(editor.text(), "aef\nghijkl\n");
+    assert_eq!(editor.registers.get(None).unwrap().as_string(), "bcd");
+    assert_eq!(editor.current_window().cursor.col, 1);
+    editor.type_keys("vjd");
+    assert_eq!(editor.text(), "aijkl\n");
+}
+
+#[test]
+fn test_visual_yank_includes_last_character() {
+    let mut editor = Editor::for_test("abcdef\n");
+    editor.type_keys("lvlly");
+    assert_eq!(editor.registers.get(None).unwrap().as_string(), "bcd");
+    assert_eq!(editor.text(), "abcdef\n");
+    assert_eq!(editor.current_window().cursor.col, 1);
+}
+
+#[test]
+fn test_global_follows_marks_across_edits() {
+    let mut editor = Editor::for_test("a1\nb\na2\na3\nc\n");
+    editor.type_keys(":g/^a/m0\n");
+    assert_eq!(editor.text(), "a3\na2\na1\nb\nc\n");
+    editor.type_keys(":g/^a/j\n");
+    assert_eq!(editor.text(), "a3 a2\na1 b\nc\n");
+    editor.type_keys(":g/a/d\n");
+    assert_eq!(editor.text(), "c\n");
+}
+
+#[test]
+fn test_ex_ranges_from_marks_and_searches() {
+    let mut editor = Editor::for_test(&numbered_lines(10));
+    editor.go_to_line(2);
+    editor.type_keys("ma");
+    editor.go_to_line(5);
+    editor.type_keys(":'a,/line 7/d\n");
+    assert_eq!(editor.text(), "line 1\nline 8\nline 9\nline 10\n");
+    editor.go_to_line(4);
+    editor.type_keys(":?line 1$?,.-1d\n");
+    assert_eq!(editor.text(), "line 10\n");
+}
+
+#[test]
+fn test_normal_under_global_runs_on_marked_lines() {
+    let mut editor = Editor::for_test("a1\nb\na2\n");
+    editor.type_keys(":g/a/normal A;\n");
+    assert_eq!(editor.text(), "a1;\nb\na2;\n");
+}
+
+#[test]
+fn test_cdo_stops_at_unwritten_changes_unless_autowrite() {
+    use crate::quickfix::{Entry, ListKind};
+    let dir = TestDir::new("cdo");
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "x\n").unwrap();
+    std::fs::write(&b, "x\n").unwrap();
+    let mut editor = Editor::for_test("");
+    editor.open(&a).unwrap();
+    let entry = |path: &std::path::Path| Entry { path: Some(path.to_path_buf()), line: 1, col: 1, kind: None, text: String::new(), valid: true };
+    editor.set_list(ListKind::Quickfix, "t", vec![entry(&a), entry(&b)], false).unwrap();
+
+    editor.type_keys(":cdo s/$/!/\n");
+    assert_eq!(editor.message.as_deref(), Some("No write since last change"));
+    assert_eq!(editor.text(), "x!\n");
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "x\n");
+
+    editor.type_keys(":set autowrite\n:cdo s/$/?/\n");
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "x!?\n");
+    assert_eq!(editor.text(), "x?\n");
+}
+
+#[test]
+fn test_location_list_follows_published_diagnostics() {
+    use crate::lsp::{Diagnostic, DiagnosticSeverity, LspRange, Position};
+    let dir = TestDir::new("diagnostics");
+    let path = dir.join("a.txt");
+    std::fs::write(&path, "é🦀 x\n").unwrap();
+    let mut editor = Editor::for_test("");
+    editor.open(&path).unwrap();
+    let uri = format!("file://{}", path.display());
+    let at = Position { line: 0, character: 4 };
+    editor.lsp_client.diagnostics.insert(uri.clone(), vec![
+        Diagnostic { range: LspRange { start: at.clone(), end: at }, severity: DiagnosticSeverity::Error, message: "bad".to_string() },
+    ]);
+
+    editor.diagnostics_arrived(&uri);
+    let list = editor.current_window().location_list.as_ref().unwrap();
+    assert_eq!((list.entries.len(), list.entries[0].col), (1, 4));
+
+    editor.lsp_client.diagnostics.insert(uri.clone(), Vec::new());
+    editor.diagnostics_arrived(&uri);
+    assert!(editor.current_window().location_list.as_ref().unwrap().entries.is_empty());
+}
+
+#[test]
+fn test_foldmethod_change_recomputes_folds() {
+    let mut editor = Editor::for_test("a\n    b\n    c\nd\n");
+    editor.go_to_line(2);
+    editor.type_keys(":set foldmethod=indent\nzc");
+    assert_eq!(editor.current_window().folds.closed_at(1), Some((1, 2)));
+    editor.type_keys("ggOx\x1b");
+    assert_eq!(editor.current_window().folds.closed_at(2), Some((2, 3)));
+    // manual keeps the folds it finds
+    editor.type_keys(":set foldmethod=manual\n");
+    editor.sync_folds(editor.active_window);
+    assert_eq!(editor.current_window().folds.closed_at(2), Some((2, 3)));
+}
+
+#[test]
+fn test_open_detects_language_from_shebang() {
+    let dir = TestDir::new("language");
+    let path = dir.join("script");
+    std::fs::write(&path, "#!/usr/bin/env python3\nprint(1)\n").unwrap();
+    let mut editor = Editor::for_test("");
+    editor.open(&path).unwrap();
+    assert_eq!(editor.current_buffer().language(), Some("python"));
+}
+
+#[test]
+fn test_cached_search_follows_case_options() {
+    let mut editor = Editor::for_test("Foo\n");
+    let rope = ropey::Rope::from_str("Foo\n");
+    assert_eq!(editor.cached_search("foo").unwrap().find_in_lines(&rope, 0..1).len(), 0);
+    editor.type_keys(":set ignorecase\n");
+    assert_eq!(editor.cached_search("foo").unwrap().find_in_lines(&rope, 0..1).len(), 1);
+    assert!(editor.cached_search("\\(").is_none());
+}
+
+#[test]
+fn test_star_searches_whole_snake_case_word() {
+    let mut editor = Editor::for_test("my_var = 1\nmy = var\nx = my_var\n");
+    editor.type_keys("l*");
+    assert_eq!((editor.current_window().cursor.line, editor.current_window().cursor.col), (2, 4));
+    editor.type_keys("#");
+    assert_eq!((editor.current_window().cursor.line, editor.current_window().cursor.col), (0, 0));
+}
+
+#[test]
+fn test_zero_tab_width_is_clamped() {
+    let mut editor = Editor::for_test("\tif x {\n");
+    editor.config.tab_width = 0;
+    editor.config.expand_tab = false;
+    editor.type_keys("A\ny\x1b");
+    assert_eq!(editor.text().lines().count(), 2);
+}
+
+#[test]
+fn test_global_stops_when_edits_outrun_the_marks() {
+    let line = format!("x{}\n", "a".repeat(1100));
+    let mut editor = Editor::for_test(&line.repeat(2));
+    editor.type_keys(":g/^x/s/a/\\r/g\n");
+    assert_eq!(editor.message.as_deref(), Some("Too many changes to follow the remaining lines"));
+    // the second line was never reached
+    assert!(editor.text().ends_with(&line));
+}
+
+#[test]
+fn test_cdo_stops_when_edits_outrun_later_entries() {
+    use crate::quickfix::{Entry, ListKind};
+    let line = format!("x{}\n", "a".repeat(1100));
+    let mut editor = Editor::for_test(&line.repeat(2));
+    let entry = |line| Entry { path: None, line, col: 1, kind: None, text: String::new(), valid: true };
+    editor.set_list(ListKind::Quickfix, "t", vec![entry(1), entry(2)], false).unwrap();
+    editor.type_keys(":cdo s/a/\\r/g\n");
+    assert_eq!(editor.message.as_deref(), Some("Too many changes to follow the remaining lines"));
+    assert!(editor.text().ends_with(&line));
+}
+
+#[test]
+fn test_leader_binding_waits_for_a_longer_one() {
+    use crate::config::LeaderBinding;
+    let mut editor = Editor::for_test("a\na\na\na\n");
+    editor.config.leader_key = Some(' ');
+    editor.config.leader_bindings.insert("x".to_string(), LeaderBinding::Action("s/a/b/".to_string()));
+    editor.config.leader_bindings.insert("xx".to_string(), LeaderBinding::Action("s/a/c/".to_string()));
+    editor.type_keys(" xx");
+    assert_eq!(editor.text(), "c\na\na\na\n");
+
+    // the shorter binding runs once the timeout passes
+    editor.type_keys("j x");
+    assert_eq!(editor.text(), "c\na\na\na\n");
+    editor.config.timeout_len_ms = 0;
+    assert!(editor.leader_timeout_due());
+    editor.resolve_pending_leader().unwrap();
+    assert_eq!(editor.text(), "c\nb\na\na\n");
+
+    // or when the next key breaks the longer one, which then runs as usual
+    editor.type_keys("j xj");
+    assert_eq!(editor.text(), "c\nb\nb\na\n");
+    assert_eq!(editor.current_window().cursor.line, 3);
+}
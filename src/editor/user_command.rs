@@ -0,0 +1,170 @@
+// user commands (:command) and abbreviation expansion
+
+use std::collections::HashMap;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::user_command::{self, AbbrevMode, Abbreviations, RangeSpec, UserCommand};
+use super::Editor;
+
+const MAX_USER_COMMAND_DEPTH: usize = 20;
+
+/// user commands and abbreviations declared in the config file
+pub(super) fn from_config(config: &Config) -> (HashMap<String, UserCommand>, Abbreviations) {
+    let mut commands = HashMap::new();
+    for (name, spec) in &config.commands {
+        match UserCommand::from_config(name, spec) {
+            Ok(cmd) => { commands.insert(cmd.name.clone(), cmd); }
+            Err(e) => log::warn!("Invalid command {} in config: {}", name, e),
+        }
+    }
+    let mut abbreviations = Abbreviations::new();
+    let tables = [(AbbrevMode::Insert, &config.iabbrev), (AbbrevMode::Command, &config.cabbrev)];
+    for (mode, table) in tables {
+        for (lhs, rhs) in table {
+            if let Err(e) = abbreviations.add(mode, lhs, rhs) {
+                log::warn!("Invalid abbreviation in config: {}", e);
+            }
+        }
+    }
+    (commands, abbreviations)
+}
+
+impl Editor {
+    pub(super) fn define_user_command(&mut self, bang: bool, definition: &str) -> Result<()> {
+        if definition.is_empty() {
+            if self.user_commands.is_empty() {
+                self.message = Some("No user-defined commands found".to_string());
+            } else {
+                let mut names: Vec<&String> = self.user_commands.keys().collect();
+                names.sort();
+                let lines: Vec<String> = names.iter().map(|n| self.user_commands[*n].describe()).collect();
+                self.message = Some(lines.join(" | "));
+            }
+            return Ok(());
+        }
+        let cmd = UserCommand::parse(definition)?;
+        if !bang && self.user_commands.contains_key(&cmd.name) {
+            return Err(Error::ParseError(format!("Command already exists: add ! to replace it: {}", cmd.name)));
+        }
+        self.user_commands.insert(cmd.name.clone(), cmd);
+        Ok(())
+    }
+
+    pub(super) fn delete_user_command(&mut self, name: &str) {
+        if self.user_commands.remove(name).is_none() {
+            self.message = Some(format!("No such user-defined command: {}", name));
+        }
+    }
+
+    /// run `input` as a user command; returns false if no user command matches
+    pub(super) fn run_user_command(&mut self, input: &str) -> Result<bool> {
+        let input = input.trim();
+        let input = input.strip_prefix(':').unwrap_or(input);
+        let line_count = self.current_buffer().line_count();
+        let cursor_line = self.current_window().cursor.line + 1;
+        let (range, rest) = self.with_line_context(|lines| crate::command::parse_range(input, lines));
+        let (name, bang, args) = user_command::split_invocation(rest);
+        let Some(cmd) = self.user_commands.get(name).cloned() else { return Ok(false); };
+
+        let visual = self.visual_cmd_range.take();
+        if cmd.range == RangeSpec::None && (range.is_some() || visual.is_some()) {
+            return Err(Error::ParseError(format!("No range allowed: {}", name)));
+        }
+        let (line1, line2) = if let Some(r) = range {
+            (r.start, r.end.min(line_count))
+        } else if let Some((vs, ve)) = visual {
+            (vs + 1, ve + 1)
+        } else if cmd.range == RangeSpec::WholeFile {
+            (1, line_count)
+        } else {
+            (cursor_line, cursor_line)
+        };
+        let expanded = cmd.expand(args, bang, line1, line2)?;
+
+        if self.user_command_depth >= MAX_USER_COMMAND_DEPTH {
+            return Err(Error::ParseError(format!("Command too recursive: {}", name)));
+        }
+        self.user_command_depth += 1;
+        let result = self.run_ex_command(&expanded);
+        self.user_command_depth -= 1;
+        result.map(|_| true)
+    }
+
+    pub(super) fn abbreviate(&mut self, mode: Option<AbbrevMode>, args: &str) -> Result<()> {
+        let Some((lhs, rhs)) = args.split_once(char::is_whitespace) else {
+            let entries: Vec<String> = self.abbreviations.list().into_iter()
+                .filter(|(m, lhs, _)| {
+                    let mode_ok = match mode {
+                        Some(AbbrevMode::Insert) => *m == 'i',
+                        Some(AbbrevMode::Command) => *m == 'c',
+                        None => true,
+                    };
+                    mode_ok && (args.is_empty() || lhs.starts_with(args))
+                })
+                .map(|(m, lhs, rhs)| format!("{}  {}  {}", m, lhs, rhs))
+                .collect();
+            self.message = Some(if entries.is_empty() {
+                "No abbreviation found".to_string()
+            } else { entries.join(" | ") });
+            return Ok(());
+        };
+        let rhs = rhs.trim();
+        let modes = match mode {
+            Some(m) => vec![m],
+            None => vec![AbbrevMode::Insert, AbbrevMode::Command],
+        };
+        for m in modes {
+            self.abbreviations.add(m, lhs, rhs)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn unabbreviate(&mut self, mode: Option<AbbrevMode>, lhs: &str) {
+        let removed = match mode {
+            Some(m) => self.abbreviations.remove(m, lhs),
+            None => {
+                let i = self.abbreviations.remove(AbbrevMode::Insert, lhs);
+                self.abbreviations.remove(AbbrevMode::Command, lhs) || i
+            }
+        };
+        if !removed {
+            self.message = Some(format!("No such abbreviation: {}", lhs));
+        }
+    }
+
+    /// expand an insert-mode abbreviation ending at the cursor
+    pub(super) fn expand_insert_abbreviation(&mut self) {
+        let line = self.current_window().cursor.line;
+        let col = self.current_window().cursor.col;
+        let before: String = self.current_buffer().get_line(line)
+            .unwrap_or_default()
+            .chars()
+            .take(col)
+            .collect();
+        let Some((len, rhs)) = self.abbreviations.expand(AbbrevMode::Insert, &before) else { return; };
+        let rhs = rhs.to_string();
+        let start = col - len;
+        self.rec_delete_range(line, start, line, col);
+        let (mut cur_line, mut cur_col) = (line, start);
+        for ch in rhs.chars() {
+            if ch == '\n' {
+                self.rec_insert_newline(cur_line, cur_col);
+                cur_line += 1;
+                cur_col = 0;
+            } else {
+                self.rec_insert_char(cur_line, cur_col, ch);
+                cur_col += 1;
+            }
+        }
+        self.current_window_mut().cursor.line = cur_line;
+        self.current_window_mut().cursor.col = cur_col;
+    }
+
+    /// expand a command-line abbreviation at the end of the command buffer
+    pub(super) fn expand_command_abbreviation(&mut self) {
+        let Some((len, rhs)) = self.abbreviations.expand(AbbrevMode::Command, &self.command_buffer) else { return; };
+        let rhs = rhs.to_string();
+        let keep = self.command_buffer.chars().count() - len;
+        self.command_buffer = self.command_buffer.chars().take(keep).collect::<String>() + &rhs;
+    }
+}
@@ -1,6 +1,7 @@
 // editor event system for plugin pub-sub
 
 use std::collections::HashMap;
+use crate::language::LanguageRegistry;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum EditorEvent {
@@ -23,12 +24,15 @@ pub struct EditorApi {
     commands: Vec<String>,
     inserts: Vec<(usize, usize, String)>, // (line, col, text)
     cursor_moves: Vec<(usize, usize)>,
+    filetype: Option<String>, // language of the current buffer
 }
 
 impl EditorApi {
     pub fn new() -> Self {
-        Self { messages: Vec::new(), commands: Vec::new(), inserts: Vec::new(), cursor_moves: Vec::new() }
+        Self { messages: Vec::new(), commands: Vec::new(), inserts: Vec::new(), cursor_moves: Vec::new(), filetype: None }
     }
+    pub fn filetype(&self) -> Option<&str> { self.filetype.as_deref() }
+    pub fn set_filetype(&mut self, filetype: Option<&str>) { self.filetype = filetype.map(str::to_string); }
     pub fn show_message(&mut self, msg: &str) { self.messages.push(msg.to_string()); }
     pub fn execute_command(&mut self, cmd: &str) { self.commands.push(cmd.to_string()); }
     pub fn insert_text(&mut self, line: usize, col: usize, text: &str) {
@@ -98,13 +102,46 @@ impl Plugin for AutoPairsPlugin {
     }
 }
 
-/// comment toggle plugin example
+/// comment toggle plugin example; the comment string follows the language the
+/// editor detected for the last opened buffer, by name or `#!` line
 pub struct CommentTogglePlugin {
     pub comment_str: String, // e.g. "// " for Rust
+    languages: LanguageRegistry,
+}
+
+impl CommentTogglePlugin {
+    pub fn new(languages: LanguageRegistry) -> Self {
+        Self { comment_str: String::new(), languages }
+    }
 }
 
 impl Plugin for CommentTogglePlugin {
     fn name(&self) -> &str { "comment-toggle" }
-    fn on_event(&mut self, _event: &EditorEvent, _api: &mut EditorApi) {}
+    fn on_event(&mut self, event: &EditorEvent, api: &mut EditorApi) {
+        if let EditorEvent::BufferOpen { .. } = event {
+            self.comment_str = api.filetype()
+                .and_then(|name| self.languages.get(name))
+                .and_then(|l| l.comment.as_deref())
+                .map(|comment| format!("{} ", comment))
+                .unwrap_or_default();
+        }
+    }
     fn commands(&self) -> Vec<String> { vec!["ToggleComment".to_string()] }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment_toggle_follows_filetype() {
+        let mut plugin = CommentTogglePlugin::new(LanguageRegistry::builtin());
+        let mut api = EditorApi::new();
+        api.set_filetype(Some("python"));
+        plugin.on_event(&EditorEvent::BufferOpen { path: "script".to_string() }, &mut api);
+        assert_eq!(plugin.comment_str, "# ");
+        api.set_filetype(None);
+        plugin.on_event(&EditorEvent::BufferOpen { path: "notes".to_string() }, &mut api);
+        assert_eq!(plugin.comment_str, "");
+    }
+}
@@ -1,4 +1,7 @@
-// File type detection
+// File type detection, for the fixed set of types; the language registry
+// behind it knows more languages and what the editor does with each
+
+use crate::language::LanguageRegistry;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
@@ -29,59 +32,42 @@ impl FileType {
             FileType::Unknown => "unknown",
         }
     }
-}
 
-pub fn detect_file_type(path: &std::path::Path, content: &str) -> FileType {
-    // 1. Check file extension
-    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-        match ext {
-            "rs" => return FileType::Rust,
-            "py" => return FileType::Python,
-            "js" => return FileType::JavaScript,
-            "ts" => return FileType::TypeScript,
-            "go" => return FileType::Go,
-            "c" | "h" => return FileType::C,
-            "cpp" | "hpp" | "cxx" | "hxx" => return FileType::Cpp,
-            "md" | "markdown" => return FileType::Markdown,
-            "txt" => return FileType::Text,
-            _ => {}
+    /// the type of a registry language, Unknown for the ones without a variant
+    pub fn from_language(name: &str) -> Self {
+        match name {
+            "rust" => FileType::Rust,
+            "python" => FileType::Python,
+            "javascript" => FileType::JavaScript,
+            "typescript" => FileType::TypeScript,
+            "go" => FileType::Go,
+            "c" => FileType::C,
+            "cpp" => FileType::Cpp,
+            "markdown" => FileType::Markdown,
+            "text" => FileType::Text,
+            _ => FileType::Unknown,
         }
     }
+}
 
-    // 2. Check shebang
-    if let Some(first_line) = content.lines().next() {
-        if first_line.starts_with("#!") {
-            if first_line.contains("python") {
-                return FileType::Python;
-            }
-            if first_line.contains("node") {
-                return FileType::JavaScript;
-            }
-        }
-    }
+/// file type by extension, shebang, then modeline, as the built-in languages
+/// detect them
+pub fn detect_file_type(path: &std::path::Path, content: &str) -> FileType {
+    LanguageRegistry::shared().detect(Some(path), content)
+        .map_or(FileType::Unknown, |l| FileType::from_language(&l.name))
+}
 
-    // 3. Check modeline (simple version)
-    // Look in the first and last 5 lines
-    for line in content.lines().take(5).chain(content.lines().rev().take(5)) {
-        if let Some(ft_pos) = line.find("ft=") {
-            let modeline = &line[ft_pos + 3..];
-            let filetype = modeline
-                .split(|c: char| c.is_whitespace() || c == ':')
-                .next()
-                .unwrap_or("");
-            match filetype {
-                "rust" => return FileType::Rust,
-                "python" => return FileType::Python,
-                "javascript" => return FileType::JavaScript,
-                "typescript" => return FileType::TypeScript,
-                "go" => return FileType::Go,
-                "c" => return FileType::C,
-                "cpp" => return FileType::Cpp,
-                "markdown" => return FileType::Markdown,
-                _ => {}
-            }
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
 
-    FileType::Unknown
+    #[test]
+    fn test_detect_file_type() {
+        assert_eq!(detect_file_type(Path::new("a.rs"), ""), FileType::Rust);
+        assert_eq!(detect_file_type(Path::new("notes.txt"), ""), FileType::Text);
+        assert_eq!(detect_file_type(Path::new("run"), "#!/usr/bin/env python3\n"), FileType::Python);
+        assert_eq!(detect_file_type(Path::new("x"), "// vim: ft=go\n"), FileType::Go);
+        assert_eq!(detect_file_type(Path::new("Makefile"), ""), FileType::Unknown);
+    }
 }
@@ -0,0 +1,471 @@
+// code folding: per-window fold ranges from manual zf, indentation, or
+// tree-sitter `folds.scm` queries, and the visible-line arithmetic around them
+
+use ropey::Rope;
+use serde::Deserialize;
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+use crate::buffer::LineEdit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldMethod {
+    #[default]
+    Manual,
+    Indent,
+    Syntax,
+}
+
+impl FoldMethod {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "manual" => Some(FoldMethod::Manual),
+            "indent" => Some(FoldMethod::Indent),
+            "syntax" => Some(FoldMethod::Syntax),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FoldMethod::Manual => "manual",
+            FoldMethod::Indent => "indent",
+            FoldMethod::Syntax => "syntax",
+        }
+    }
+}
+
+/// a fold over lines `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+/// a line's indent level as foldmethod=indent last measured it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indent {
+    Blank,
+    Level(usize),
+    Stale, // changed since, to be measured again
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FoldState {
+    pub method: Option<FoldMethod>, // None follows the global `foldmethod`
+    folds: Vec<Fold>,               // sorted by start, outer folds before inner ones
+    buffer_id: u64,
+    version: u64,                   // buffer version the ranges match
+    computed: FoldMethod,           // method the ranges came from
+    indents: Vec<Indent>,           // per line, for the indent method
+    indent_width: usize,            // shift width the indents were measured with
+}
+
+impl FoldState {
+    pub fn folds(&self) -> &[Fold] {
+        &self.folds
+    }
+
+    /// buffer, version and method the folds were last brought up to date with
+    pub fn synced(&self) -> (u64, u64, FoldMethod) {
+        (self.buffer_id, self.version, self.computed)
+    }
+
+    /// drop every fold, e.g. when the window shows another buffer
+    pub fn reset(&mut self, buffer_id: u64, version: u64) {
+        self.folds.clear();
+        self.indents.clear();
+        self.buffer_id = buffer_id;
+        self.version = version;
+    }
+
+    /// record that the folds match `version`, e.g. manual folds after following edits
+    pub fn mark_synced(&mut self, version: u64, method: FoldMethod) {
+        self.version = version;
+        self.computed = method;
+    }
+
+    /// follow one buffer edit: move folds with the lines, and mark the lines it
+    /// changed for measuring again
+    pub fn follow_edit(&mut self, edit: &LineEdit) {
+        if edit.delta != 0 {
+            self.shift_lines(edit.at, edit.delta, edit.version);
+            let at = edit.at.min(self.indents.len());
+            if edit.delta > 0 {
+                self.indents.splice(at..at, std::iter::repeat(Indent::Stale).take(edit.delta as usize));
+            } else {
+                let end = (at + edit.delta.unsigned_abs()).min(self.indents.len());
+                self.indents.drain(at..end);
+            }
+        }
+        let end = (edit.last + 1).min(self.indents.len());
+        let start = edit.first.min(end);
+        self.indents[start..end].fill(Indent::Stale);
+        self.version = edit.version;
+    }
+
+    /// the edits since the last sync are no longer known: measure every line again
+    pub fn remeasure(&mut self) {
+        self.indents.clear();
+    }
+
+    /// indent folds for a buffer of `line_count` lines, measuring only the lines
+    /// changed since the last call with `measure` (see `indent_level`)
+    pub fn indent_folds(&mut self, line_count: usize, shift_width: usize, measure: impl Fn(usize) -> Option<usize>) -> Vec<(usize, usize)> {
+        if self.indents.len() != line_count || self.indent_width != shift_width {
+            self.indents = vec![Indent::Stale; line_count];
+            self.indent_width = shift_width;
+        }
+        for (line, indent) in self.indents.iter_mut().enumerate() {
+            if *indent == Indent::Stale {
+                *indent = measure(line).map_or(Indent::Blank, Indent::Level);
+            }
+        }
+        let levels: Vec<Option<usize>> = self.indents.iter()
+            .map(|indent| match indent {
+                Indent::Level(level) => Some(*level),
+                _ => None,
+            })
+            .collect();
+        indent_folds(&levels)
+    }
+
+    fn sort(&mut self) {
+        self.folds.sort_by_key(|f| (f.start, std::cmp::Reverse(f.end)));
+        self.folds.dedup_by_key(|f| (f.start, f.end));
+    }
+
+    /// follow a change in line count: `delta` lines inserted before line `at`, or
+    /// removed starting at `at`; folds whose lines all went away are dropped
+    pub fn shift_lines(&mut self, at: usize, delta: isize, version: u64) {
+        self.version = version;
+        if delta >= 0 {
+            let d = delta as usize;
+            for f in &mut self.folds {
+                if f.start >= at { f.start += d; }
+                if f.end >= at { f.end += d; }
+            }
+        } else {
+            let n = delta.unsigned_abs();
+            let removed = at..at + n;
+            self.folds.retain_mut(|f| {
+                if removed.contains(&f.start) && removed.contains(&f.end) {
+                    return false;
+                }
+                f.start = if f.start >= at + n { f.start - n } else { f.start.min(at) };
+                f.end = if f.end >= at + n { f.end - n } else if removed.contains(&f.end) { at.saturating_sub(1) } else { f.end };
+                f.end > f.start
+            });
+        }
+        self.sort();
+    }
+
+    /// replace computed folds (indent or syntax method); a fold stays closed when
+    /// a closed fold started on the same line before
+    pub fn replace(&mut self, ranges: Vec<(usize, usize)>, version: u64, method: FoldMethod) {
+        let closed: Vec<usize> = self.folds.iter().filter(|f| f.closed).map(|f| f.start).collect();
+        self.folds = ranges.into_iter()
+            .filter(|(s, e)| e > s)
+            .map(|(start, end)| Fold { start, end, closed: closed.contains(&start) })
+            .collect();
+        self.version = version;
+        self.computed = method;
+        self.sort();
+    }
+
+    /// zf: a new closed fold
+    pub fn create(&mut self, start: usize, end: usize) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.folds.retain(|f| (f.start, f.end) != (start, end));
+        self.folds.push(Fold { start, end, closed: true });
+        self.sort();
+    }
+
+    /// zd: delete the innermost fold at `line`
+    pub fn delete(&mut self, line: usize) -> bool {
+        match self.innermost(line, |_| true) {
+            Some(i) => { self.folds.remove(i); true }
+            None => false,
+        }
+    }
+
+    /// zE
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    fn innermost(&self, line: usize, pred: impl Fn(&Fold) -> bool) -> Option<usize> {
+        self.folds.iter().enumerate()
+            .filter(|(_, f)| f.start <= line && line <= f.end && pred(f))
+            .min_by_key(|(_, f)| f.end - f.start)
+            .map(|(i, _)| i)
+    }
+
+    /// the closed fold hiding `line`: the outermost closed one around it
+    pub fn closed_at(&self, line: usize) -> Option<(usize, usize)> {
+        self.folds.iter()
+            .filter(|f| f.closed && f.start <= line && line <= f.end)
+            .max_by_key(|f| f.end - f.start)
+            .map(|f| (f.start, f.end))
+    }
+
+    /// zo: open the closed fold shown at `line`
+    pub fn open(&mut self, line: usize) -> bool {
+        let Some(range) = self.closed_at(line) else { return false; };
+        for f in self.folds.iter_mut().filter(|f| (f.start, f.end) == range) {
+            f.closed = false;
+        }
+        true
+    }
+
+    /// open every fold around `line`, so the line itself shows
+    pub fn reveal(&mut self, line: usize) {
+        for f in self.folds.iter_mut().filter(|f| f.start <= line && line <= f.end) {
+            f.closed = false;
+        }
+    }
+
+    /// zc: close the innermost open fold around `line`, or around the closed fold
+    /// already shown there
+    pub fn close(&mut self, line: usize) -> bool {
+        let shown = self.closed_at(line).unwrap_or((line, line));
+        let target = self.innermost(line, |f| {
+            !f.closed && f.start <= shown.0 && shown.1 <= f.end && (f.start, f.end) != shown
+        });
+        match target {
+            Some(i) => { self.folds[i].closed = true; true }
+            None => false,
+        }
+    }
+
+    /// za
+    pub fn toggle(&mut self, line: usize) -> bool {
+        if self.closed_at(line).is_some() { self.open(line) } else { self.close(line) }
+    }
+
+    /// zR / zM
+    pub fn set_all(&mut self, closed: bool) {
+        for f in &mut self.folds {
+            f.closed = closed;
+        }
+    }
+
+    /// zj: first line of the next fold below `line`
+    pub fn next_start(&self, line: usize) -> Option<usize> {
+        self.folds.iter().map(|f| f.start).filter(|&s| s > line).min()
+    }
+
+    /// zk: last line of the previous fold above `line`
+    pub fn prev_end(&self, line: usize) -> Option<usize> {
+        self.folds.iter().map(|f| f.end).filter(|&e| e < line).max()
+    }
+
+    /// first and last buffer line of the screen line showing `line`
+    pub fn display_range(&self, line: usize) -> (usize, usize) {
+        self.closed_at(line).unwrap_or((line, line))
+    }
+
+    /// the screen line `count` screen lines below (positive) or above the one
+    /// showing `line`, as its first buffer line
+    pub fn move_visible(&self, line: usize, count: isize, line_count: usize) -> usize {
+        let mut line = self.display_range(line).0;
+        for _ in 0..count.unsigned_abs() {
+            let next = if count > 0 {
+                let end = self.display_range(line).1;
+                if end + 1 >= line_count { break; }
+                end + 1
+            } else {
+                if line == 0 { break; }
+                line - 1
+            };
+            line = self.display_range(next).0;
+        }
+        line
+    }
+
+    /// buffer lines `first..=last` covered by `count` screen lines from `line`
+    pub fn span(&self, line: usize, count: usize, line_count: usize) -> (usize, usize) {
+        let first = self.display_range(line).0;
+        let last_shown = self.move_visible(first, count.saturating_sub(1) as isize, line_count);
+        (first, self.display_range(last_shown).1)
+    }
+
+    /// screen lines from the one showing `from` down to the one showing `to`
+    pub fn rows_between(&self, from: usize, to: usize) -> usize {
+        let (mut line, to) = (self.display_range(from).0, self.display_range(to).0);
+        let mut rows = 0;
+        while line < to {
+            line = self.display_range(line).1 + 1;
+            rows += 1;
+        }
+        rows
+    }
+}
+
+/// the indent level of a line for foldmethod=indent, None when it is blank
+pub fn indent_level(line: impl IntoIterator<Item = char>, shift_width: usize) -> Option<usize> {
+    let shift_width = shift_width.max(1);
+    let mut width = 0;
+    for c in line {
+        match c {
+            '\t' => width += shift_width,
+            c if c.is_whitespace() => width += 1,
+            _ => return Some(width / shift_width),
+        }
+    }
+    None
+}
+
+/// indent folds from each line's `indent_level`: each run of lines indented
+/// deeper than the line before it is a fold, nested by level; blank lines join
+/// the shallower of their neighbours
+pub fn indent_folds(raw: &[Option<usize>]) -> Vec<(usize, usize)> {
+    let levels: Vec<usize> = (0..raw.len()).map(|i| {
+        raw[i].unwrap_or_else(|| {
+            let prev = raw[..i].iter().rev().find_map(|l| *l).unwrap_or(0);
+            let next = raw[i + 1..].iter().find_map(|l| *l).unwrap_or(0);
+            prev.min(next)
+        })
+    }).collect();
+
+    let mut folds = Vec::new();
+    let mut open: Vec<usize> = Vec::new(); // start line per open level
+    for (i, &lvl) in levels.iter().enumerate() {
+        while open.len() > lvl {
+            let start = open.pop().unwrap_or(0);
+            folds.push((start, i - 1));
+        }
+        while open.len() < lvl {
+            open.push(i);
+        }
+    }
+    while let Some(start) = open.pop() {
+        folds.push((start, levels.len().saturating_sub(1)));
+    }
+    // trailing blank lines do not belong to the fold above them
+    folds.into_iter()
+        .map(|(s, mut e)| {
+            while e > s && raw[e].is_none() { e -= 1; }
+            (s, e)
+        })
+        .filter(|(s, e)| e > s)
+        .collect()
+}
+
+/// line ranges of the `@fold` captures of a `folds.scm` query
+pub fn syntax_folds(query: &Query, root: Node, rope: &Rope) -> Vec<(usize, usize)> {
+    let len = rope.len_bytes();
+    let text = |node: Node| {
+        let r = node.start_byte().min(len)..node.end_byte().min(len);
+        rope.byte_slice(r).chunks().map(str::as_bytes)
+    };
+    let Some(fold_idx) = query.capture_index_for_name("fold") else { return Vec::new(); };
+    let mut ranges = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, root, text);
+    while let Some(m) = matches.next() {
+        for capture in m.captures.iter().filter(|c| c.index == fold_idx) {
+            let (start, end) = (capture.node.start_position(), capture.node.end_position());
+            // a node ending at column 0 ends on the line before
+            let last = if end.column == 0 { end.row.saturating_sub(1) } else { end.row };
+            if last > start.row {
+                ranges.push((start.row, last));
+            }
+        }
+    }
+    ranges.sort_unstable();
+    ranges.dedup();
+    ranges
+}
+
+/// the text shown for a closed fold, like vim's `+--  12 lines: fn main() {`
+pub fn summary(first_line: &str, lines: usize, depth: usize) -> String {
+    format!("+-{}{:>3} lines: {}", "-".repeat(depth.max(1)), lines, first_line.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(folds: &[(usize, usize, bool)]) -> FoldState {
+        let mut s = FoldState::default();
+        s.folds = folds.iter().map(|&(start, end, closed)| Fold { start, end, closed }).collect();
+        s.sort();
+        s
+    }
+
+    #[test]
+    fn test_open_close_nested() {
+        let mut s = state(&[(2, 10, false), (4, 6, false)]);
+        assert!(s.close(5));
+        assert_eq!(s.closed_at(5), Some((4, 6)));
+        // closing again on the closed fold closes its parent
+        assert!(s.close(5));
+        assert_eq!(s.closed_at(5), Some((2, 10)));
+        assert!(s.open(5));
+        assert_eq!(s.closed_at(5), Some((4, 6)));
+        assert!(s.toggle(5));
+        assert_eq!(s.closed_at(5), None);
+        s.set_all(true);
+        assert_eq!(s.display_range(8), (2, 10));
+        assert_eq!(s.next_start(0), Some(2));
+        assert_eq!(s.prev_end(12), Some(10));
+    }
+
+    #[test]
+    fn test_visible_lines() {
+        let s = state(&[(2, 5, true), (8, 9, false)]);
+        assert_eq!(s.move_visible(1, 1, 20), 2);
+        assert_eq!(s.move_visible(2, 1, 20), 6);
+        assert_eq!(s.move_visible(6, -1, 20), 2);
+        assert_eq!(s.move_visible(4, -3, 20), 0);
+        assert_eq!(s.move_visible(18, 5, 20), 19);
+        assert_eq!(s.rows_between(0, 7), 4);
+        assert_eq!(s.span(3, 2, 20), (2, 6));
+    }
+
+    #[test]
+    fn test_shift_lines() {
+        let mut s = state(&[(2, 5, true), (8, 9, true)]);
+        s.shift_lines(3, 2, 1);
+        assert_eq!(s.folds()[0], Fold { start: 2, end: 7, closed: true });
+        // removing all of a fold's lines removes the fold
+        s.shift_lines(10, -2, 2);
+        assert_eq!(s.folds().len(), 1);
+        s.shift_lines(0, -3, 3);
+        assert_eq!((s.folds()[0].start, s.folds()[0].end), (0, 4));
+    }
+
+    #[test]
+    fn test_indent_folds() {
+        let text = "fn a() {\n    let x = 1;\n\n    if x {\n        y();\n    }\n}\n\nfn b() {}";
+        let levels: Vec<Option<usize>> = text.lines().map(|l| indent_level(l.chars(), 4)).collect();
+        // the one-line `y();` level makes no fold
+        assert_eq!(indent_folds(&levels), vec![(1, 5)]);
+        let mut s = FoldState::default();
+        s.replace(indent_folds(&levels), 1, FoldMethod::Indent);
+        assert_eq!(s.folds().len(), 1);
+        assert_eq!(summary("    let x = 1;", 5, 1), "+--  5 lines: let x = 1;");
+    }
+
+    #[test]
+    fn test_indent_folds_measure_changed_lines() {
+        let mut lines = vec!["fn a() {", "    x();", "    y();", "}"];
+        let measured = std::cell::Cell::new(0);
+        let mut s = FoldState::default();
+        let mut sync = |s: &mut FoldState, lines: &[&str]| {
+            let ranges = s.indent_folds(lines.len(), 4, |l| {
+                measured.set(measured.get() + 1);
+                indent_level(lines[l].chars(), 4)
+            });
+            s.replace(ranges, 0, FoldMethod::Indent);
+        };
+        sync(&mut s, &lines);
+        assert_eq!((s.folds()[0].start, s.folds()[0].end, measured.get()), (1, 2, 4));
+        // a line opened below `x();` is measured on its own
+        lines.insert(2, "    z();");
+        s.follow_edit(&LineEdit { version: 1, at: 2, delta: 1, first: 1, last: 2 });
+        sync(&mut s, &lines);
+        assert_eq!((s.folds()[0].start, s.folds()[0].end, measured.get()), (1, 3, 6));
+    }
+}
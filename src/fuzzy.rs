@@ -9,6 +9,8 @@ pub struct FuzzyMatch {
     pub score: i32,
     /// Indices of matched characters in the item
     pub matched_indices: Vec<usize>,
+    /// Position of the item in the candidate list
+    pub index: usize,
 }
 
 /// Fuzzy matcher with fzf-style scoring
@@ -30,6 +32,7 @@ impl FuzzyMatcher {
                 item: candidate.to_string(),
                 score: 0,
                 matched_indices: vec![],
+                index: 0,
             });
         }
 
@@ -69,6 +72,7 @@ impl FuzzyMatcher {
             item: candidate.to_string(),
             score,
             matched_indices,
+            index: 0,
         })
     }
 
@@ -138,7 +142,8 @@ impl FuzzyMatcher {
     pub fn fuzzy_match_all(&self, pattern: &str, candidates: &[String]) -> Vec<FuzzyMatch> {
         let mut matches: Vec<FuzzyMatch> = candidates
             .iter()
-            .filter_map(|c| self.fuzzy_match(pattern, c))
+            .enumerate()
+            .filter_map(|(i, c)| self.fuzzy_match(pattern, c).map(|m| FuzzyMatch { index: i, ..m }))
             .collect();
 
         // Sort by score (highest first)
@@ -1,10 +1,16 @@
 // Fuzzy finder UI component
 
 use crate::fuzzy::{FuzzyMatch, FuzzyMatcher};
+use crate::symbol::Symbol;
 use regex::Regex;
-use std::fs;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Mutex;
+use std::thread;
 
 /// Type of fuzzy finder
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,6 +18,7 @@ pub enum FinderType {
     Files,
     Buffers,
     Grep,
+    Symbols,
 }
 
 /// State for the fuzzy finder UI
@@ -31,6 +38,12 @@ pub struct FuzzyFinder {
     pub max_results: usize,
     /// Fuzzy matcher
     matcher: FuzzyMatcher,
+    /// Symbols behind the candidates of a symbol finder, one per candidate
+    pub symbols: Vec<Symbol>,
+    /// Lines behind the candidates of a grep finder, one per candidate
+    pub grep_matches: Vec<GrepMatch>,
+    /// Lines of a grep still searching, arriving as they are found
+    grep_results: Option<Receiver<GrepMatch>>,
 }
 
 impl FuzzyFinder {
@@ -43,6 +56,9 @@ impl FuzzyFinder {
             selected_index: 0,
             max_results: 20,
             matcher: FuzzyMatcher::default(),
+            symbols: Vec::new(),
+            grep_matches: Vec::new(),
+            grep_results: None,
         }
     }
 
@@ -62,10 +78,52 @@ impl FuzzyFinder {
         finder
     }
 
-    /// Create a new grep finder searching for pattern in files
-    pub fn grep(base_path: &PathBuf, pattern: &str) -> Self {
+    /// Create a new grep finder searching for pattern in files; the lines
+    /// stream in through poll_grep
+    pub fn grep(base_path: &Path, pattern: &str, max_results: usize) -> Result<Self, String> {
         let mut finder = Self::new(FinderType::Grep);
-        finder.candidates = grep_files(base_path, pattern);
+        finder.grep_results = Some(spawn_grep(base_path, pattern, max_results)?);
+        Ok(finder)
+    }
+
+    /// Take in the lines a grep has found since the last call; returns true if
+    /// there were any, or the search finished
+    pub fn poll_grep(&mut self) -> bool {
+        let Some(results) = &self.grep_results else { return false; };
+        let mut changed = false;
+        loop {
+            match results.try_recv() {
+                Ok(found) => {
+                    self.candidates.push(found.candidate());
+                    self.grep_matches.push(found);
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.grep_results = None;
+                    changed = true;
+                    break;
+                }
+            }
+        }
+        if changed {
+            self.update_matches();
+        }
+        changed
+    }
+
+    /// Whether a grep finder is still searching
+    pub fn is_searching(&self) -> bool {
+        self.grep_results.is_some()
+    }
+
+    /// Create a symbol finder matching on symbol names; with an empty query the
+    /// symbols stay in document order
+    pub fn symbols(symbols: Vec<Symbol>) -> Self {
+        let mut finder = Self::new(FinderType::Symbols);
+        finder.candidates = symbols.iter().map(|s| s.name.clone()).collect();
+        finder.symbols = symbols;
+        finder.max_results = usize::MAX;
         finder.update_matches();
         finder
     }
@@ -119,6 +177,21 @@ impl FuzzyFinder {
             .map(|m| m.item.as_str())
     }
 
+    /// Get the symbol behind the selected item of a symbol finder
+    pub fn selected_symbol(&self) -> Option<&Symbol> {
+        self.matches
+            .get(self.selected_index)
+            .and_then(|m| self.symbols.get(m.index))
+    }
+
+    /// Get the lines behind the matches of a grep finder, best first
+    pub fn matched_grep_lines(&self) -> Vec<&GrepMatch> {
+        self.matches
+            .iter()
+            .filter_map(|m| self.grep_matches.get(m.index))
+            .collect()
+    }
+
     /// Update matches based on current query
     fn update_matches(&mut self) {
         self.matches = self.matcher.fuzzy_match_all(&self.query, &self.candidates);
@@ -136,156 +209,179 @@ impl FuzzyFinder {
             FinderType::Files => "Files> ",
             FinderType::Buffers => "Buffers> ",
             FinderType::Grep => "Grep> ",
+            FinderType::Symbols => "Symbols> ",
         }
     }
 }
 
-/// Search for pattern in files and return results as "file:line:content"
-fn grep_files(base_path: &PathBuf, pattern: &str) -> Vec<String> {
-    let mut results = Vec::new();
-    let max_results = 100;
-
-    let regex = match Regex::new(pattern) {
-        Ok(r) => r,
-        Err(_) => return results, // Invalid regex, return empty
-    };
-
-    for entry in WalkDir::new(base_path)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|e| !is_hidden(e) && !is_ignored(e))
-    {
-        if results.len() >= max_results {
-            break;
-        }
+/// A line that matched a grep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrepMatch {
+    /// Path relative to the directory searched
+    pub path: PathBuf,
+    /// 1-indexed line
+    pub line: usize,
+    /// 1-indexed character column where the match starts
+    pub col: usize,
+    pub text: String,
+}
 
-        if let Ok(entry) = entry {
-            if entry.file_type().is_file() {
-                // Skip binary files (simple heuristic: check extension)
-                let path = entry.path();
-                if is_likely_binary(path) {
-                    continue;
-                }
+impl GrepMatch {
+    /// The match as a finder candidate, "file:line:content"
+    fn candidate(&self) -> String {
+        let truncated_line = if self.text.len() > 80 {
+            let end = (0..=77).rev().find(|&i| self.text.is_char_boundary(i)).unwrap_or(0);
+            format!("{}...", &self.text[..end])
+        } else {
+            self.text.clone()
+        };
+        format!("{}:{}:{}", self.path.display(), self.line, truncated_line.trim())
+    }
+}
+
+/// Compile a grep pattern, with the reason when it is not a valid regex
+fn grep_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| {
+        let reason = e.to_string();
+        let reason = reason.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+        format!("Invalid pattern: {}", reason)
+    })
+}
 
-                if let Ok(content) = fs::read_to_string(path) {
-                    for (line_num, line) in content.lines().enumerate() {
-                        if regex.is_match(line) {
-                            if let Ok(relative) = path.strip_prefix(base_path) {
-                                let truncated_line = if line.len() > 80 {
-                                    format!("{}...", &line[..77])
-                                } else {
-                                    line.to_string()
-                                };
-                                results.push(format!(
-                                    "{}:{}:{}",
-                                    relative.display(),
-                                    line_num + 1,
-                                    truncated_line.trim()
-                                ));
-                                if results.len() >= max_results {
-                                    break;
-                                }
-                            }
+/// Search for pattern in the files under base_path on worker threads, sending
+/// the matching lines in file order as they are found, the first max_results
+/// of them. The search stops early when the receiver is dropped
+pub fn spawn_grep(base_path: &Path, pattern: &str, max_results: usize) -> Result<Receiver<GrepMatch>, String> {
+    let regex = grep_regex(pattern)?;
+    let base_path = base_path.to_path_buf();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let files = project_files(&base_path);
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let (done_tx, done_rx) = mpsc::channel();
+        let workers = thread::available_parallelism().map_or(4, |n| n.get()).min(8);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let done_tx = done_tx.clone();
+                let (files, next, stop, base_path, regex) = (&files, &next, &stop, &base_path, &regex);
+                scope.spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(i) else { break; };
+                        if done_tx.send((i, grep_file(base_path, path, regex, max_results))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(done_tx);
+            // workers finish files out of order; lines go on in file order so
+            // that the cap always keeps the same ones
+            let mut finished: HashMap<usize, Vec<GrepMatch>> = HashMap::new();
+            let (mut next_file, mut sent) = (0, 0);
+            for (i, matches) in &done_rx {
+                finished.insert(i, matches);
+                while let Some(matches) = finished.remove(&next_file) {
+                    next_file += 1;
+                    for found in matches {
+                        if sent == max_results || tx.send(found).is_err() {
+                            stop.store(true, Ordering::Relaxed);
+                            return;
                         }
+                        sent += 1;
                     }
                 }
+                if sent == max_results {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
             }
-        }
-    }
-
-    results
+        });
+    });
+    Ok(rx)
 }
 
-/// Check if file is likely binary based on extension
-fn is_likely_binary(path: &std::path::Path) -> bool {
-    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        matches!(
-            ext.to_lowercase().as_str(),
-            "png"
-                | "jpg"
-                | "jpeg"
-                | "gif"
-                | "ico"
-                | "bmp"
-                | "webp"
-                | "pdf"
-                | "zip"
-                | "tar"
-                | "gz"
-                | "rar"
-                | "7z"
-                | "exe"
-                | "dll"
-                | "so"
-                | "dylib"
-                | "a"
-                | "o"
-                | "obj"
-                | "class"
-                | "pyc"
-                | "pyo"
-                | "wasm"
-                | "ttf"
-                | "otf"
-                | "woff"
-                | "woff2"
-                | "mp3"
-                | "mp4"
-                | "avi"
-                | "mov"
-                | "mkv"
-                | "webm"
-                | "sqlite"
-                | "db"
-                | "swp"
-        )
-    } else {
-        false
-    }
+/// Search for pattern in files, returning at most max_results matching lines
+/// in file order
+pub fn grep_files(base_path: &Path, pattern: &str, max_results: usize) -> Result<Vec<GrepMatch>, String> {
+    Ok(spawn_grep(base_path, pattern, max_results)?.iter().collect())
 }
 
-/// Collect all files in a directory tree (excluding hidden and common ignore patterns)
-fn collect_files(base_path: &PathBuf) -> Vec<String> {
-    let mut files = Vec::new();
-
-    for entry in WalkDir::new(base_path)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|e| !is_hidden(e) && !is_ignored(e))
-    {
-        if let Ok(entry) = entry {
-            if entry.file_type().is_file() {
-                if let Ok(relative) = entry.path().strip_prefix(base_path) {
-                    files.push(relative.to_string_lossy().to_string());
-                }
-            }
+/// The lines of one file that match, at most max_results of them; none for a
+/// file that cannot be read or looks binary
+fn grep_file(base_path: &Path, path: &Path, regex: &Regex, max_results: usize) -> Vec<GrepMatch> {
+    let mut found = Vec::new();
+    let Ok(file) = File::open(path) else { return found; };
+    let mut reader = BufReader::new(file);
+    match reader.fill_buf() {
+        Ok(start) if !looks_binary(start) => {}
+        _ => return found,
+    }
+    let relative = path.strip_prefix(base_path).unwrap_or(path);
+    let mut bytes = Vec::new();
+    let mut line_num = 0;
+    while found.len() < max_results {
+        bytes.clear();
+        match reader.read_until(b'\n', &mut bytes) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => line_num += 1,
         }
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches(['\n', '\r']);
+        let Some(start) = regex.find(line).map(|m| m.start()) else { continue; };
+        found.push(GrepMatch {
+            path: relative.to_path_buf(),
+            line: line_num,
+            col: line[..start].chars().count() + 1,
+            text: line.to_string(),
+        });
     }
+    found
+}
 
-    files
+/// Check if bytes from the start of a file look binary: a NUL byte in them,
+/// as git and grep decide
+pub fn looks_binary(start: &[u8]) -> bool {
+    start.contains(&0)
+}
+
+/// Check if a file is likely binary by sniffing its first few kilobytes
+pub fn is_likely_binary(path: &Path) -> bool {
+    let mut start = Vec::new();
+    match File::open(path) {
+        Ok(file) => file.take(8192).read_to_end(&mut start).is_ok() && looks_binary(&start),
+        Err(_) => false,
+    }
 }
 
-/// Check if entry is hidden (starts with .)
-fn is_hidden(entry: &walkdir::DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
+/// Collect all files in a directory tree (excluding hidden and ignored ones)
+fn collect_files(base_path: &PathBuf) -> Vec<String> {
+    project_files(base_path)
+        .iter()
+        .filter_map(|path| path.strip_prefix(base_path).ok())
+        .map(|relative| relative.to_string_lossy().to_string())
+        .collect()
 }
 
-/// Check if entry should be ignored (common patterns)
-fn is_ignored(entry: &walkdir::DirEntry) -> bool {
-    let name = entry.file_name().to_string_lossy();
-    matches!(
-        name.as_ref(),
-        "node_modules" | "target" | "dist" | "build" | "__pycache__" | ".git" | "vendor"
-    )
+/// Paths of the files under a directory, sorted, leaving out hidden ones,
+/// those .gitignore and .ignore files exclude, and build and dependency
+/// directories such as node_modules and target
+pub fn project_files(base_path: &Path) -> Vec<PathBuf> {
+    let files = Mutex::new(Vec::new());
+    crate::ignore::walk(base_path, |path| {
+        files.lock().unwrap().push(path.to_path_buf());
+        true
+    });
+    let mut files = files.into_inner().unwrap();
+    files.sort();
+    files
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_dir::TestDir;
 
     #[test]
     fn test_fuzzy_finder_new() {
@@ -342,4 +438,25 @@ mod tests {
         finder.clear_query();
         assert!(finder.query.is_empty());
     }
+
+    #[test]
+    fn test_grep_files() {
+        let dir = TestDir::new("grep");
+        std::fs::write(dir.join("a.txt"), "one\ntwo needle\nneedle three\n").unwrap();
+        std::fs::write(dir.join("data.bin"), b"needle\0\x01").unwrap();
+        let found = grep_files(&dir, "needle", 100).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].line, found[0].col), (2, 5));
+        assert_eq!(found[0].path, PathBuf::from("a.txt"));
+        // the cap keeps the first lines in file order, whichever thread finds them
+        for name in ["b.txt", "c.txt", "d.txt"] {
+            std::fs::write(dir.join(name), "needle\n").unwrap();
+        }
+        let capped = grep_files(&dir, "needle", 3).unwrap();
+        assert_eq!(capped.iter().map(|m| (m.path.to_string_lossy().to_string(), m.line)).collect::<Vec<_>>(),
+            vec![("a.txt".to_string(), 2), ("a.txt".to_string(), 3), ("b.txt".to_string(), 1)]);
+        assert!(grep_files(&dir, "needle(", 100).unwrap_err().starts_with("Invalid pattern"));
+        assert!(is_likely_binary(&dir.join("data.bin")));
+        assert!(!is_likely_binary(&dir.join("a.txt")));
+    }
 }
@@ -0,0 +1,123 @@
+// :global -- the lines a pattern marks, and keeping those marks on their lines
+// while the commands run on each one add and remove lines around them
+
+use ropey::Rope;
+use std::collections::VecDeque;
+use crate::search::SearchPattern;
+
+/// lines `first..=last` on which a match of `pattern` starts, or with `invert`
+/// the lines on which none does
+pub fn matching_lines(rope: &Rope, pattern: &SearchPattern, first: usize, last: usize, invert: bool) -> Vec<usize> {
+    let mut hits: Vec<usize> = pattern.find_in_lines(rope, first..last + 1).iter()
+        .map(|m| rope.byte_to_line(m.start.min(rope.len_bytes())))
+        .filter(|&line| line <= last)
+        .collect();
+    hits.dedup();
+    if !invert {
+        return hits;
+    }
+    let last = last.min(rope.len_lines().saturating_sub(1));
+    (first..=last).filter(|line| hits.binary_search(line).is_err()).collect()
+}
+
+/// where a line ends up after a buffer line edit (first line moved, lines
+/// added or removed), None when it was one of the lines removed
+pub fn follow_line(line: usize, at: usize, delta: isize) -> Option<usize> {
+    if line < at {
+        return Some(line);
+    }
+    if delta >= 0 {
+        return Some(line + delta as usize);
+    }
+    let removed = delta.unsigned_abs();
+    line.checked_sub(removed).filter(|_| line >= at + removed)
+}
+
+/// the marked lines still to visit, in order. an edit above all of them only
+/// moves a running offset; one further down shifts the marks below it
+pub struct Marks {
+    lines: VecDeque<isize>, // each plus `shift` is where the line is now
+    shift: isize,
+}
+
+impl Marks {
+    pub fn new(lines: Vec<usize>) -> Self {
+        Self { lines: lines.into_iter().map(|l| l as isize).collect(), shift: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn pop_front(&mut self) -> Option<usize> {
+        self.lines.pop_front().map(|l| (l + self.shift) as usize)
+    }
+
+    /// follow a buffer line edit as `follow_line` does, dropping the marks on
+    /// lines it removed
+    pub fn follow(&mut self, at: usize, delta: isize) {
+        let at = at as isize - self.shift;
+        let start = self.lines.partition_point(|&l| l < at);
+        let removed = if delta < 0 {
+            self.lines.range(start..).take_while(|&&l| l < at - delta).count()
+        } else { 0 };
+        self.lines.drain(start..start + removed);
+        if start == 0 {
+            self.shift += delta;
+        } else {
+            for line in self.lines.range_mut(start..) {
+                *line += delta;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_lines() {
+        let rope = Rope::from_str("error: a\nok\nerror: b\nwarn\nerror: c error\n");
+        let pattern = SearchPattern::new("error", false, false).unwrap();
+        assert_eq!(matching_lines(&rope, &pattern, 0, 4, false), vec![0, 2, 4]);
+        assert_eq!(matching_lines(&rope, &pattern, 1, 3, false), vec![2]);
+        assert_eq!(matching_lines(&rope, &pattern, 0, 4, true), vec![1, 3]);
+        let across = SearchPattern::new(r"ok\nerror", false, false).unwrap();
+        assert_eq!(matching_lines(&rope, &across, 0, 4, false), vec![1]);
+    }
+
+    #[test]
+    fn test_follow_line() {
+        // lines 2 and 3 deleted
+        assert_eq!(follow_line(1, 2, -2), Some(1));
+        assert_eq!(follow_line(2, 2, -2), None);
+        assert_eq!(follow_line(3, 2, -2), None);
+        assert_eq!(follow_line(4, 2, -2), Some(2));
+        // a line inserted above line 5
+        assert_eq!(follow_line(4, 5, 1), Some(4));
+        assert_eq!(follow_line(5, 5, 1), Some(6));
+    }
+
+    #[test]
+    fn test_marks_follow_edits_like_follow_line() {
+        let start = vec![1, 3, 4, 6, 9, 12];
+        let edits = [(0, -1), (5, 2), (2, -2), (0, 3), (9, -3), (20, 1)];
+        let mut marks = Marks::new(start.clone());
+        let mut expected = start;
+        for (at, delta) in edits {
+            marks.follow(at, delta);
+            expected = expected.into_iter().filter_map(|l| follow_line(l, at, delta)).collect();
+        }
+        assert_eq!(marks.len(), expected.len());
+        let mut lines = Vec::new();
+        while let Some(line) = marks.pop_front() {
+            lines.push(line);
+        }
+        assert_eq!(lines, expected);
+    }
+}
@@ -0,0 +1,130 @@
+// runtime tree-sitter grammars and query files
+//
+// layout under the config dir, matching nvim-treesitter:
+//   runtime/parser/<lang>.so               compiled grammar exporting tree_sitter_<lang>
+//   runtime/queries/<lang>/highlights.scm  (also injections, locals, textobjects, folds, indents, tags)
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_sitter::Language;
+
+pub const QUERY_KINDS: [&str; 7] = ["highlights", "injections", "locals", "textobjects", "folds", "indents", "tags"];
+
+/// runtime directory inside the config dir
+pub fn runtime_dir() -> Option<PathBuf> {
+    crate::config::config_dir().map(|d| d.join("runtime"))
+}
+
+/// where a query in use came from
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum QuerySource {
+    #[default]
+    None,
+    Bundled,
+    User(PathBuf),
+}
+
+impl QuerySource {
+    pub fn describe(&self) -> String {
+        match self {
+            QuerySource::None => "none".to_string(),
+            QuerySource::Bundled => "bundled".to_string(),
+            QuerySource::User(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// the user's copy of a query, which replaces the bundled one
+pub fn user_query(runtime: &Path, lang: &str, kind: &str) -> Option<(PathBuf, String)> {
+    let path = runtime.join("queries").join(lang).join(format!("{}.scm", kind));
+    fs::read_to_string(&path).ok().map(|text| (path, text))
+}
+
+/// grammar libraries in runtime/parser as (language name, path), sorted by name
+pub fn runtime_parsers(runtime: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(runtime.join("parser")) else { return Vec::new(); };
+    let mut parsers: Vec<(String, PathBuf)> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("so" | "dylib")))
+        .filter_map(|p| Some((p.file_stem()?.to_str()?.to_string(), p)))
+        .collect();
+    parsers.sort();
+    parsers
+}
+
+/// name of the function a grammar library exports
+fn symbol_name(lang: &str) -> String {
+    format!("tree_sitter_{}", lang.replace('-', "_"))
+}
+
+/// load a grammar from a shared library; the library stays loaded for the rest
+/// of the process since the Language points into it
+#[cfg(unix)]
+pub fn load_grammar(path: &Path, lang: &str) -> Result<Language, String> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    let c_symbol = CString::new(symbol_name(lang)).map_err(|e| e.to_string())?;
+    let dl_error = || {
+        let err = unsafe { libc::dlerror() };
+        if err.is_null() {
+            "unknown error".to_string()
+        } else {
+            unsafe { CStr::from_ptr(err) }.to_string_lossy().into_owned()
+        }
+    };
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(format!("Can't load {}: {}", path.display(), dl_error()));
+    }
+    let symbol = unsafe { libc::dlsym(handle, c_symbol.as_ptr()) };
+    if symbol.is_null() {
+        unsafe { libc::dlclose(handle) };
+        return Err(format!("{} has no {}", path.display(), symbol_name(lang)));
+    }
+    let language_fn = unsafe {
+        let f: unsafe extern "C" fn() -> *const () = std::mem::transmute(symbol);
+        tree_sitter_language::LanguageFn::from_raw(f)
+    };
+    let language = Language::new(language_fn);
+    let abi = language.abi_version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION).contains(&abi) {
+        return Err(format!(
+            "{}: grammar ABI {} is not supported (need {}-{})",
+            path.display(), abi, tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION, tree_sitter::LANGUAGE_VERSION
+        ));
+    }
+    Ok(language)
+}
+
+#[cfg(not(unix))]
+pub fn load_grammar(path: &Path, _lang: &str) -> Result<Language, String> {
+    Err(format!("Can't load {}: runtime grammars need a unix system", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_runtime_layout() {
+        let dir = TestDir::new("grammar");
+        fs::create_dir_all(dir.join("parser")).unwrap();
+        fs::create_dir_all(dir.join("queries/zig")).unwrap();
+        fs::write(dir.join("parser/zig.so"), b"").unwrap();
+        fs::write(dir.join("parser/README"), b"").unwrap();
+        fs::write(dir.join("queries/zig/highlights.scm"), "(comment) @comment").unwrap();
+
+        let parsers = runtime_parsers(&dir);
+        assert_eq!(parsers.len(), 1);
+        assert_eq!(parsers[0].0, "zig");
+        assert_eq!(user_query(&dir, "zig", "highlights").map(|(_, t)| t), Some("(comment) @comment".to_string()));
+        assert!(user_query(&dir, "zig", "locals").is_none());
+        // an empty file is not a loadable library
+        assert!(load_grammar(&parsers[0].1, "zig").is_err());
+        assert_eq!(symbol_name("c-sharp"), "tree_sitter_c_sharp");
+    }
+}
@@ -0,0 +1,335 @@
+// .gitignore and .ignore rules, and a walk of the files under a directory
+// that honors them, spread over worker threads
+
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// ignore files read in each directory; later ones win
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// directories left out when no ignore file says otherwise
+const DEFAULT_IGNORES: &[&str] = &["node_modules", "target", "dist", "build", "__pycache__", "vendor"];
+
+/// a rule of an ignore file
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,   // against the path relative to the file's directory
+    negate: bool,   // !pattern: re-include
+    dir_only: bool, // pattern/: directories only
+}
+
+/// the rules of one ignore file, for the paths under its directory
+#[derive(Debug)]
+pub struct Gitignore {
+    root: PathBuf,
+    prefix: PathBuf, // `root` relative to the file's own directory, when it is read from above
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    /// rules from the text of an ignore file in `root`; lines that are not
+    /// valid patterns are skipped, as git does
+    pub fn new(root: &Path, text: &str) -> Self {
+        let rules = text.lines().filter_map(parse_rule).collect();
+        Self { root: root.to_path_buf(), prefix: PathBuf::new(), rules }
+    }
+
+    /// the rules of `file`, if it can be read and has any
+    pub fn from_file(root: &Path, file: &Path) -> Option<Self> {
+        let text = fs::read_to_string(file).ok()?;
+        let ignore = Self::new(root, &text);
+        (!ignore.rules.is_empty()).then_some(ignore)
+    }
+
+    /// Some(true) if the last rule matching `path` ignores it, Some(false) if
+    /// it re-includes it, None if no rule matches
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = self.prefix.join(path.strip_prefix(&self.root).ok()?);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        self.rules.iter().rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(&relative))
+            .map(|rule| !rule.negate)
+    }
+}
+
+/// a line of an ignore file as a rule; None for blanks and comments
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negate, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    // a slash anywhere but the end ties the pattern to the file's directory
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    if pattern.is_empty() {
+        return None;
+    }
+    let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+    let regex = Regex::new(&format!("{}{}$", prefix, glob_to_regex(pattern))).ok()?;
+    Some(Rule { regex, negate, dir_only })
+}
+
+/// the regex for a gitignore glob: * and ? stop at slashes, ** crosses them
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    Some('/') if at_start => {
+                        regex.push_str("(?:.*/)?");
+                        i += 3;
+                        continue;
+                    }
+                    None if at_start => regex.push_str(".*"),
+                    _ => regex.push_str("[^/]*"),
+                }
+                i += 2;
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(rest) => format!("^{}", rest),
+                        None => class,
+                    };
+                    regex.push('[');
+                    regex.push_str(&class.replace('\\', "\\\\"));
+                    regex.push(']');
+                    i += len + 2;
+                    continue;
+                }
+                _ => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+/// whether the ignore files `stack`, outermost first, leave `path` out; a
+/// directory none of them mentions is left out by a default name
+fn is_ignored(stack: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    stack.iter().rev()
+        .find_map(|ignore| ignore.matched(path, is_dir))
+        .unwrap_or_else(|| {
+            is_dir && path.file_name().and_then(|n| n.to_str()).is_some_and(|n| DEFAULT_IGNORES.contains(&n))
+        })
+}
+
+/// the ignore files above `base` that apply to it: those of the directories
+/// between the enclosing git repository's root and `base`, and the
+/// repository's info/exclude
+fn ancestor_ignores(base: &Path) -> Vec<Arc<Gitignore>> {
+    let Ok(canonical) = base.canonicalize() else { return Vec::new(); };
+    let Some(repo) = canonical.ancestors().find(|dir| dir.join(".git").exists()) else { return Vec::new(); };
+    let mut files = vec![(repo, repo.join(".git/info/exclude"))];
+    let mut dirs: Vec<&Path> = canonical.ancestors().skip(1).take_while(|dir| dir.starts_with(repo)).collect();
+    dirs.reverse();
+    for dir in dirs {
+        files.extend(IGNORE_FILES.iter().map(|name| (dir, dir.join(name))));
+    }
+    // the walk names paths from `base`, so rules from above it are rebased there
+    files.into_iter()
+        .filter_map(|(dir, file)| {
+            let mut ignore = Gitignore::from_file(base, &file)?;
+            ignore.prefix = canonical.strip_prefix(dir).ok()?.to_path_buf();
+            Some(Arc::new(ignore))
+        })
+        .collect()
+}
+
+/// the ignore files in `dir`
+fn directory_ignores(dir: &Path) -> Vec<Arc<Gitignore>> {
+    IGNORE_FILES.iter()
+        .filter_map(|name| Gitignore::from_file(dir, &dir.join(name)))
+        .map(Arc::new)
+        .collect()
+}
+
+/// directories waiting to be read, and how many workers are reading one
+struct Queue {
+    dirs: Vec<(PathBuf, Vec<Arc<Gitignore>>)>,
+    busy: usize,
+}
+
+/// call `visit` with every file under `base` that no ignore file leaves out,
+/// hidden ones skipped, from several threads at once and in no set order;
+/// the walk stops early once `visit` returns false
+pub fn walk<F>(base: &Path, visit: F)
+where
+    F: Fn(&Path) -> bool + Sync,
+{
+    let queue = Mutex::new(Queue { dirs: vec![(base.to_path_buf(), ancestor_ignores(base))], busy: 0 });
+    let ready = Condvar::new();
+    let stop = AtomicBool::new(false);
+    let workers = thread::available_parallelism().map_or(4, |n| n.get()).min(8);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some((dir, parents)) = next_dir(&queue, &ready, &stop) {
+                    let found = read_dir(&dir, parents, &visit, &stop);
+                    let mut queue = queue.lock().unwrap();
+                    queue.dirs.extend(found);
+                    queue.busy -= 1;
+                    ready.notify_all();
+                }
+            });
+        }
+    });
+}
+
+/// the next directory to read, waiting while other workers may still find
+/// some; None once the walk is over
+fn next_dir(
+    queue: &Mutex<Queue>,
+    ready: &Condvar,
+    stop: &AtomicBool,
+) -> Option<(PathBuf, Vec<Arc<Gitignore>>)> {
+    let mut queue = queue.lock().unwrap();
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(dir) = queue.dirs.pop() {
+            queue.busy += 1;
+            return Some(dir);
+        }
+        if queue.busy == 0 {
+            return None;
+        }
+        queue = ready.wait(queue).unwrap();
+    }
+}
+
+/// visit the files in `dir`, returning its subdirectories to walk
+fn read_dir<F>(
+    dir: &Path,
+    mut stack: Vec<Arc<Gitignore>>,
+    visit: &F,
+    stop: &AtomicBool,
+) -> Vec<(PathBuf, Vec<Arc<Gitignore>>)>
+where
+    F: Fn(&Path) -> bool + Sync,
+{
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new(); };
+    stack.extend(directory_ignores(dir));
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // follow links to files, but not to directories, which could loop
+        let Ok(file_type) = entry.file_type() else { continue; };
+        let is_dir = file_type.is_dir();
+        let is_file = file_type.is_file() || (file_type.is_symlink() && path.is_file());
+        if !(is_dir || is_file) || is_ignored(&stack, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            subdirs.push((path, stack.clone()));
+        } else if !visit(&path) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    subdirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn ignored(text: &str, path: &str, is_dir: bool) -> Option<bool> {
+        Gitignore::new(Path::new("/repo"), text).matched(&Path::new("/repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn test_unanchored_patterns_match_at_any_depth() {
+        assert_eq!(ignored("*.log", "debug.log", false), Some(true));
+        assert_eq!(ignored("*.log", "a/b/debug.log", false), Some(true));
+        assert_eq!(ignored("*.log", "debug.txt", false), None);
+        assert_eq!(ignored("target", "crates/x/target", true), Some(true));
+    }
+
+    #[test]
+    fn test_anchored_patterns() {
+        assert_eq!(ignored("/build", "build", true), Some(true));
+        assert_eq!(ignored("/build", "src/build", true), None);
+        assert_eq!(ignored("doc/*.html", "doc/index.html", false), Some(true));
+        assert_eq!(ignored("doc/*.html", "doc/api/index.html", false), None);
+        assert_eq!(ignored("a/**/z", "a/z", false), Some(true));
+        assert_eq!(ignored("a/**/z", "a/b/c/z", false), Some(true));
+        assert_eq!(ignored("**/logs", "x/y/logs", true), Some(true));
+        assert_eq!(ignored("out/**", "out/a/b", false), Some(true));
+    }
+
+    #[test]
+    fn test_directory_only_and_negation() {
+        assert_eq!(ignored("cache/", "cache", true), Some(true));
+        assert_eq!(ignored("cache/", "cache", false), None);
+        let rules = "*.log\n!keep.log\n# comment\n\n";
+        assert_eq!(ignored(rules, "x.log", false), Some(true));
+        assert_eq!(ignored(rules, "keep.log", false), Some(false));
+    }
+
+    #[test]
+    fn test_glob_classes_and_escapes() {
+        assert_eq!(ignored("file[0-9].txt", "file3.txt", false), Some(true));
+        assert_eq!(ignored("file[!0-9].txt", "file3.txt", false), None);
+        assert_eq!(ignored("\\#notes", "#notes", false), Some(true));
+        assert_eq!(ignored("?.rs", "a.rs", false), Some(true));
+        assert_eq!(ignored("?.rs", "ab.rs", false), None);
+    }
+
+    #[test]
+    fn test_walk_honors_ignore_files() {
+        let dir = TestDir::new("walk");
+        fs::create_dir_all(dir.join("src/gen")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::create_dir_all(dir.join("node_modules/x")).unwrap();
+        fs::create_dir_all(dir.join("src/build")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.tmp\n!/src/build\n").unwrap();
+        fs::write(dir.join("src/.ignore"), "gen\n").unwrap();
+        for file in ["main.rs", "x.tmp", "src/lib.rs", "src/gen/out.rs", "target/bin", ".hidden/a", "node_modules/x/y.js", "src/build/b.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let found = Mutex::new(Vec::new());
+        walk(&dir, |path| {
+            found.lock().unwrap().push(path.strip_prefix(&dir).unwrap().to_path_buf());
+            true
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort();
+        // node_modules is left out by default, src/build re-included
+        assert_eq!(found, vec![PathBuf::from("main.rs"), PathBuf::from("src/build/b.rs"), PathBuf::from("src/lib.rs")]);
+    }
+}
@@ -0,0 +1,360 @@
+// indentation engine: levels from tree-sitter `indents.scm` queries, with a
+// brace/keyword heuristic for languages without one or for code that does not
+// parse yet
+//
+// captures understood in indents.scm:
+//   @indent   lines after the node's first line are one level deeper; nodes
+//             starting on the same line add a single level
+//   @outdent  a line starting with the node goes back one level; a node that is
+//             also @indent (else, case) does not indent its body past its parent
+//   @open     a new line right after the node still belongs to it; without this
+//             a node ending where the line above ends is finished (Python blocks
+//             end with their last statement, brace blocks with the brace)
+//   @dedent   a new line after this node goes back one level (return, pass)
+//   @keep     lines inside the node keep their indentation (strings, comments)
+
+use ropey::Rope;
+use serde::Deserialize;
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+
+/// width of one indentation level and whether it is written with tabs
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndentStyle {
+    pub width: usize,
+    pub tabs: bool,
+}
+
+impl IndentStyle {
+    /// display width of a line's leading whitespace
+    pub fn columns(&self, text: &str) -> usize {
+        let mut cols = 0;
+        for ch in text.chars() {
+            match ch {
+                ' ' => cols += 1,
+                '\t' => cols += self.width - cols % self.width,
+                _ => break,
+            }
+        }
+        cols
+    }
+
+    /// leading whitespace `columns` wide
+    pub fn string(&self, columns: usize) -> String {
+        if self.tabs {
+            format!("{}{}", "\t".repeat(columns / self.width), " ".repeat(columns % self.width))
+        } else {
+            " ".repeat(columns)
+        }
+    }
+}
+
+/// words that start a line which belongs one level out from the block above it
+const BRANCH_WORDS: &[&str] = &["else", "elif", "elsif", "except", "finally", "end"];
+/// words that end a block when they start a line in Python-like code
+const EXIT_WORDS: &[&str] = &["return", "break", "continue", "pass", "raise"];
+/// words that open a block when a line starting with them ends in ':'
+const COLON_WORDS: &[&str] = &[
+    "def", "class", "if", "elif", "else", "for", "while", "try", "except", "finally",
+    "with", "match", "case", "default", "async",
+];
+
+/// whether typing the last character of `before_cursor` (the line up to the
+/// cursor) should re-indent the line: a closing bracket or a branch keyword
+/// starting it, or the colon ending a Python `else:`-style line
+pub fn is_reindent_trigger(before_cursor: &str) -> bool {
+    let text = before_cursor.trim_start();
+    match text {
+        "}" | ")" | "]" => true,
+        _ if BRANCH_WORDS.contains(&text) => true,
+        _ => text.ends_with(':') && BRANCH_WORDS.contains(&first_word(text)),
+    }
+}
+
+/// the indentation, in columns, `line` should have; `None` leaves the line as it
+/// is (it continues a multi-line string or comment). A blank line gets the indent
+/// for text typed on it.
+pub fn indent_for_line(tree: Option<(&Query, Node)>, rope: &Rope, line: usize, style: &IndentStyle) -> Option<usize> {
+    let level = tree.map_or(Level::Unknown, |(query, root)| query_level(query, root, rope, line));
+    match level {
+        Level::At(level) => Some(level * style.width),
+        Level::Keep if line_text(rope, line).trim().is_empty() => {
+            Some(prev_non_blank(rope, line).map_or(0, |p| style.columns(&line_text(rope, p))))
+        }
+        Level::Keep => None,
+        Level::Unknown => Some(heuristic_indent(rope, line, style)),
+    }
+}
+
+enum Level {
+    At(usize),
+    Keep,
+    Unknown, // no query, or the tree has an error around the line
+}
+
+fn line_text(rope: &Rope, line: usize) -> String {
+    if line >= rope.len_lines() { return String::new(); }
+    rope.line(line).to_string().trim_end_matches(['\n', '\r']).to_string()
+}
+
+fn first_word(text: &str) -> &str {
+    let text = text.trim_start();
+    let end = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+    &text[..end]
+}
+
+fn prev_non_blank(rope: &Rope, line: usize) -> Option<usize> {
+    (0..line).rev().find(|&l| !line_text(rope, l).trim().is_empty())
+}
+
+/// byte offset of the first (or last) non-blank character of a line
+fn non_blank_byte(rope: &Rope, line: usize, last: bool) -> Option<usize> {
+    let text = line_text(rope, line);
+    let pos = if last {
+        text.char_indices().rev().find(|(_, c)| !c.is_whitespace())
+    } else {
+        text.char_indices().find(|(_, c)| !c.is_whitespace())
+    };
+    pos.map(|(i, _)| rope.line_to_byte(line) + i)
+}
+
+struct Captures {
+    indent: Option<u32>,
+    outdent: Option<u32>,
+    open: Option<u32>,
+    dedent: Option<u32>,
+    keep: Option<u32>,
+}
+
+/// captured nodes intersecting the byte at `at`, with their capture index
+fn captures_at<'t>(query: &Query, root: Node<'t>, rope: &Rope, at: usize) -> Vec<(Node<'t>, u32)> {
+    let len = rope.len_bytes();
+    let text = |node: Node| {
+        let r = node.start_byte().min(len)..node.end_byte().min(len);
+        rope.byte_slice(r).chunks().map(str::as_bytes)
+    };
+    let mut found = Vec::new();
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(at..at + 1);
+    let mut matches = cursor.matches(query, root, text);
+    while let Some(m) = matches.next() {
+        found.extend(m.captures.iter().map(|c| (c.node, c.index)));
+    }
+    // a node can match several patterns for the same capture
+    found.sort_by_key(|&(n, i)| (n.id(), i));
+    found.dedup_by_key(|&mut (n, i)| (n.id(), i));
+    found
+}
+
+fn query_level(query: &Query, root: Node, rope: &Rope, line: usize) -> Level {
+    let names = Captures {
+        indent: query.capture_index_for_name("indent"),
+        outdent: query.capture_index_for_name("outdent"),
+        open: query.capture_index_for_name("open"),
+        dedent: query.capture_index_for_name("dedent"),
+        keep: query.capture_index_for_name("keep"),
+    };
+    // a line with text is placed by its first character; a blank line by the last
+    // character of the line above, as if continuing it
+    let first = non_blank_byte(rope, line, false);
+    let (anchor, prev) = match first {
+        Some(byte) => (byte, None),
+        None => match prev_non_blank(rope, line) {
+            Some(p) => (non_blank_byte(rope, p, true).unwrap_or(0), Some(p)),
+            None => return Level::At(0),
+        },
+    };
+    let Some(leaf) = root.descendant_for_byte_range(anchor, anchor + 1) else { return Level::Unknown; };
+    let mut node = Some(leaf);
+    while let Some(n) = node {
+        if n.is_error() || n.is_missing() { return Level::Unknown; }
+        node = n.parent();
+    }
+    let caps = captures_at(query, root, rope, anchor);
+    let has = |node: Node, index: Option<u32>| {
+        caps.iter().any(|&(n, i)| n.id() == node.id() && Some(i) == index)
+    };
+    // for a blank line, a node the line above finishes does not count
+    let closed = |node: Node| {
+        prev.is_some() && node.end_byte() == leaf.end_byte() && !has(node, names.open)
+    };
+
+    let mut rows = Vec::new();
+    let mut level: isize = 0;
+    let mut outdent_here = false;
+    for &(node, index) in &caps {
+        if node.start_byte() > anchor || node.end_byte() <= anchor { continue; }
+        let row = node.start_position().row;
+        let index = Some(index);
+        if row >= line {
+            // the node starts this line: only an outdent applies
+            outdent_here |= index == names.outdent && node.start_byte() == anchor;
+            continue;
+        }
+        if index == names.keep && prev.map_or(true, |p| node.end_position().row > p) {
+            return Level::Keep;
+        }
+        if index == names.dedent && prev.is_some() {
+            level -= 1;
+        }
+        if closed(node) { continue; }
+        if index == names.indent {
+            rows.push(row);
+        } else if index == names.outdent && has(node, names.indent) {
+            level -= 1;
+        }
+    }
+    if outdent_here { level -= 1; }
+    rows.sort_unstable();
+    rows.dedup();
+    Level::At((level + rows.len() as isize).max(0) as usize)
+}
+
+/// indentation from the lines above: keep the previous line's, one level deeper
+/// after a line opening a block, one level out for a closing bracket or branch
+/// keyword and after `return`-like lines ending an indented Python-style block
+fn heuristic_indent(rope: &Rope, line: usize, style: &IndentStyle) -> usize {
+    let Some(prev) = prev_non_blank(rope, line) else { return 0; };
+    let prev_text = line_text(rope, prev);
+    let prev_cols = style.columns(&prev_text);
+    let p = prev_text.trim();
+    let mut cols = prev_cols;
+    if opens_block(p) {
+        cols += style.width;
+    } else if EXIT_WORDS.contains(&first_word(p)) && !p.ends_with(';') && in_colon_block(rope, prev, prev_cols, style) {
+        cols = cols.saturating_sub(style.width);
+    }
+    let current = line_text(rope, line);
+    let c = current.trim();
+    if c.starts_with(['}', ')', ']']) {
+        cols = cols.saturating_sub(style.width);
+    } else if BRANCH_WORDS.contains(&first_word(c)) {
+        let after_bracket = p.starts_with(['}', ')', ']']) && !opens_block(p);
+        cols = if after_bracket { prev_cols } else { cols.saturating_sub(style.width) };
+    }
+    cols
+}
+
+fn opens_block(line: &str) -> bool {
+    let last_word = line.rsplit(|c: char| !(c.is_alphanumeric() || c == '_')).next().unwrap_or("");
+    line.ends_with(['{', '(', '['])
+        || (line.ends_with(':') && COLON_WORDS.contains(&first_word(line)))
+        || matches!(last_word, "then" | "do" | "else" | "begin" | "repeat")
+}
+
+/// whether the nearest less indented line above `line` opens a block with ':'
+fn in_colon_block(rope: &Rope, line: usize, cols: usize, style: &IndentStyle) -> bool {
+    (0..line).rev()
+        .map(|l| line_text(rope, l))
+        .find(|t| !t.trim().is_empty() && style.columns(t) < cols)
+        .is_some_and(|t| t.trim_end().ends_with(':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::syntax::SyntaxHighlighter;
+
+    const STYLE: IndentStyle = IndentStyle { width: 4, tabs: false };
+
+    /// every line of `text` reindented, as `=` over the whole buffer does; with
+    /// `flatten` the lines lose their indentation first
+    fn reindent(sh: &mut SyntaxHighlighter, text: &str, ext: &str, flatten: bool) -> String {
+        let input: String = if flatten {
+            text.lines().map(|l| format!("{}\n", l.trim_start())).collect()
+        } else {
+            text.to_string()
+        };
+        let mut buffer = Buffer::from_string(&input);
+        sh.update(&mut buffer, ext);
+        let rope = buffer.rope().clone();
+        (0..rope.len_lines() - 1).map(|l| {
+            let line = line_text(&rope, l);
+            match indent_for_line(sh.indents(&buffer), &rope, l, &STYLE) {
+                _ if line.trim().is_empty() => "\n".to_string(),
+                Some(cols) => format!("{}{}\n", STYLE.string(cols), line.trim_start()),
+                None => format!("{}\n", line),
+            }
+        }).collect()
+    }
+
+    /// indent for a new blank line opened below the line containing `after`
+    fn new_line(sh: &mut SyntaxHighlighter, text: &str, ext: &str, after: &str) -> usize {
+        let at = text.find(after).unwrap();
+        let eol = at + text[at..].find('\n').unwrap();
+        let opened = format!("{}\n{}", &text[..eol], &text[eol..]);
+        let mut buffer = Buffer::from_string(&opened);
+        sh.update(&mut buffer, ext);
+        let line = opened[..eol].matches('\n').count() + 1;
+        indent_for_line(sh.indents(&buffer), buffer.rope(), line, &STYLE).unwrap()
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_rust() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "impl Foo {\n    fn run(&self, x: i32) -> i32 {\n        if x > 0 {\n            call(\n                x,\n            )\n        } else {\n            match x {\n                0 => 1,\n                _ => {\n                    2\n                }\n            }\n        }\n    }\n}\n";
+        assert_eq!(reindent(&mut sh, text, "rs", true), text);
+        assert_eq!(new_line(&mut sh, text, "rs", "fn run"), 8);
+        assert_eq!(new_line(&mut sh, text, "rs", "0 => 1"), 16);
+        // after a closing brace the enclosing block decides
+        assert_eq!(new_line(&mut sh, text, "rs", "                }"), 16);
+        assert_eq!(new_line(&mut sh, text, "rs", "    }\n}"), 4);
+    }
+
+    #[cfg(feature = "lang-python")]
+    #[test]
+    fn test_python() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "def f(x):\n    if x:\n        y = g(\n            x,\n        )\n    elif x > 1:\n        pass\n    else:\n        return 2\n    try:\n        h()\n    except E:\n        h()\n";
+        assert_eq!(reindent(&mut sh, text, "py", false), text);
+        assert_eq!(new_line(&mut sh, text, "py", "    else:"), 8);
+        assert_eq!(new_line(&mut sh, text, "py", "return 2"), 4);
+        assert_eq!(new_line(&mut sh, text, "py", "        )"), 8);
+    }
+
+    #[cfg(feature = "lang-go")]
+    #[test]
+    fn test_go() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "func f(x int) int {\n\tswitch x {\n\tcase 1:\n\t\treturn 1\n\tdefault:\n\t\treturn 2\n\t}\n}\n";
+        let tabs = IndentStyle { width: 4, tabs: true };
+        let spaced = text.replace('\t', "    ");
+        assert_eq!(reindent(&mut sh, &spaced, "go", true), spaced);
+        assert_eq!(tabs.string(8), "\t\t");
+    }
+
+    #[cfg(feature = "lang-c")]
+    #[test]
+    fn test_c() {
+        let mut sh = SyntaxHighlighter::new();
+        // case labels sit one level inside the switch, as with vim's default
+        // cinoptions; the comment's second line keeps its (lack of) indentation
+        let text = "int f(int x) {\n    if (x)\n        x++;\n    else\n        x--;\n    switch (x) {\n        case 1:\n            x = 2;\n            break;\n    }\n    /* a\nb */\n    return x;\n}\n";
+        assert_eq!(reindent(&mut sh, text, "c", true), text);
+    }
+
+    #[cfg(feature = "lang-js")]
+    #[test]
+    fn test_javascript() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "function f(a) {\n    return a\n        .map((x) => {\n            return x;\n        })\n        .filter(Boolean);\n}\nconst o = {\n    k: [\n        1,\n    ],\n};\n";
+        assert_eq!(reindent(&mut sh, text, "js", true), text);
+    }
+
+    #[test]
+    fn test_heuristic() {
+        let rope = Rope::from_str("if x then\n  y\nend\nfoo {\n    bar\n}\n");
+        let style = IndentStyle { width: 2, tabs: false };
+        assert_eq!(heuristic_indent(&rope, 1, &style), 2);
+        assert_eq!(heuristic_indent(&rope, 2, &style), 0);
+        let rope = Rope::from_str("def f():\n    return 1\n\n");
+        assert_eq!(heuristic_indent(&rope, 2, &STYLE), 0);
+        let rope = Rope::from_str("    }\n    else {\n");
+        assert_eq!(heuristic_indent(&rope, 1, &STYLE), 4);
+        assert!(is_reindent_trigger("    }"));
+        assert!(is_reindent_trigger("    else:"));
+        assert!(!is_reindent_trigger("    elsewhere"));
+    }
+}
@@ -1,6 +1,8 @@
 // Key mapping and input handling
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use crate::config::LeaderBinding;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
@@ -47,6 +49,13 @@ pub enum Action {
     ScrollMiddleToScreen,  // zz
     ScrollBottomToScreen,  // zb
     MoveToPercent,         // count% (e.g., 50% goes to 50% of file)
+    MoveFunctionNext,      // ]m (next function or method start)
+    MoveFunctionPrev,      // [m
+    MoveSectionNext,       // ]] (next function not nested in another)
+    MoveSectionPrev,       // [[
+    MoveContextStart,      // [c (start of the innermost scope pinned at the window top)
+    MoveFoldNext,          // zj (start of the next fold)
+    MoveFoldPrev,          // zk (end of the previous fold)
 
     // Mode switching
     EnterInsertMode,
@@ -136,12 +145,24 @@ pub enum Action {
     WindowCycle,       // Ctrl-w w
     WindowEqualize,    // Ctrl-w =
 
+    // Folding
+    CreateFold,     // zf (operator; creates a fold over a visual selection)
+    OpenFold,       // zo
+    CloseFold,      // zc
+    ToggleFold,     // za
+    OpenAllFolds,   // zR
+    CloseAllFolds,  // zM
+    DeleteFold,     // zd
+    EliminateFolds, // zE
+
     // LSP
     LspCompletion, // Ctrl-n (trigger completion)
 
     // Other
-    RepeatLastChange, // . (dot command)
-    OpenFileFinder,   // Ctrl-p (file fuzzy finder)
+    RepeatLastChange,        // . (dot command)
+    RepeatSubstitute,        // & (last :s on the cursor line)
+    RepeatSubstituteAll,     // g& (last :s on every line, with its flags)
+    OpenFileFinder,          // Ctrl-p (file fuzzy finder)
     None,
 }
 
@@ -220,6 +241,7 @@ fn map_normal_mode_key(key: KeyEvent) -> Action {
         KeyCode::Char('x') => Action::DeleteChar,
         KeyCode::Char('u') => Action::Undo,
         KeyCode::Char('.') => Action::RepeatLastChange,
+        KeyCode::Char('&') => Action::RepeatSubstitute,
 
         // Operators
         KeyCode::Char('d') => Action::Delete,
@@ -289,9 +311,197 @@ fn map_visual_mode_key(key: KeyEvent) -> Action {
         KeyCode::Char('$') => Action::MoveLineEnd,
         KeyCode::Char('d') => Action::Delete,
         KeyCode::Char('y') => Action::Yank,
+        KeyCode::Char('=') => Action::AutoIndent,
         KeyCode::Char('c') => Action::VisualBlockChange,
         KeyCode::Char('I') => Action::VisualBlockInsert,
         KeyCode::Char('A') => Action::VisualBlockAppend,
         _ => Action::None,
     }
 }
+
+/// continuations of the `g` prefix: (key, action, which-key description)
+pub const G_PREFIX: &[(char, Action, &str)] = &[
+    ('g', Action::MoveFileStart, "first line"),
+    ('e', Action::MoveWordEndBack, "end of previous word"),
+    ('E', Action::MoveWordEndBackBig, "end of previous WORD"),
+    ('_', Action::MoveLineEndNonBlank, "last non-blank"),
+    ('0', Action::MoveLineStartDisplay, "screen line start"),
+    ('$', Action::MoveLineEndDisplay, "screen line end"),
+    ('J', Action::JoinNoSpace, "join without space"),
+    ('u', Action::MakeLowercase, "lowercase"),
+    ('U', Action::MakeUppercase, "uppercase"),
+    ('~', Action::ToggleCase, "toggle case"),
+    (';', Action::JumpToChangeNext, "next change"),
+    (',', Action::JumpToChangePrev, "previous change"),
+    ('&', Action::RepeatSubstituteAll, "repeat :s on all lines"),
+];
+
+/// continuations of the `z` prefix
+pub const Z_PREFIX: &[(char, Action, &str)] = &[
+    ('t', Action::ScrollTopToScreen, "cursor line to top"),
+    ('z', Action::ScrollMiddleToScreen, "cursor line to middle"),
+    ('b', Action::ScrollBottomToScreen, "cursor line to bottom"),
+    ('f', Action::CreateFold, "create fold"),
+    ('o', Action::OpenFold, "open fold"),
+    ('c', Action::CloseFold, "close fold"),
+    ('a', Action::ToggleFold, "toggle fold"),
+    ('R', Action::OpenAllFolds, "open all folds"),
+    ('M', Action::CloseAllFolds, "close all folds"),
+    ('j', Action::MoveFoldNext, "next fold start"),
+    ('k', Action::MoveFoldPrev, "previous fold end"),
+    ('d', Action::DeleteFold, "delete fold"),
+    ('E', Action::EliminateFolds, "delete all folds"),
+];
+
+/// continuations of the `]` prefix
+pub const NEXT_PREFIX: &[(char, Action, &str)] = &[
+    ('m', Action::MoveFunctionNext, "next function start"),
+    (']', Action::MoveSectionNext, "next top-level function"),
+];
+
+/// continuations of the `[` prefix
+pub const PREV_PREFIX: &[(char, Action, &str)] = &[
+    ('m', Action::MoveFunctionPrev, "previous function start"),
+    ('[', Action::MoveSectionPrev, "previous top-level function"),
+    ('c', Action::MoveContextStart, "start of pinned scope"),
+];
+
+/// continuations of the Ctrl-w prefix
+pub const CTRL_W_PREFIX: &[(char, Action, &str)] = &[
+    ('s', Action::WindowSplitH, "split horizontally"),
+    ('v', Action::WindowSplitV, "split vertically"),
+    ('c', Action::WindowClose, "close window"),
+    ('h', Action::WindowFocusLeft, "focus left"),
+    ('j', Action::WindowFocusDown, "focus down"),
+    ('k', Action::WindowFocusUp, "focus up"),
+    ('l', Action::WindowFocusRight, "focus right"),
+    ('w', Action::WindowCycle, "next window"),
+    ('=', Action::WindowEqualize, "equalize sizes"),
+];
+
+/// binding table for a built-in prefix key ('\x17' stands for Ctrl-w)
+pub fn prefix_table(prefix: char) -> Option<&'static [(char, Action, &'static str)]> {
+    match prefix {
+        'g' => Some(G_PREFIX),
+        'z' => Some(Z_PREFIX),
+        ']' => Some(NEXT_PREFIX),
+        '[' => Some(PREV_PREFIX),
+        '\x17' => Some(CTRL_W_PREFIX),
+        _ => None,
+    }
+}
+
+/// one entry of the which-key popup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyHint {
+    pub key: String,
+    pub desc: String,
+    pub is_group: bool,
+}
+
+/// result of feeding a key sequence to the leader tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaderMatch {
+    Action(String),         // complete sequence bound to an action
+    ActionOrPrefix(String), // complete, but a longer sequence also starts here
+    Prefix,                 // more keys needed
+    NoMatch,
+}
+
+pub fn match_leader_sequence(bindings: &HashMap<String, LeaderBinding>, seq: &str) -> LeaderMatch {
+    let longer = bindings.keys().any(|k| k.len() > seq.len() && k.starts_with(seq));
+    match (bindings.get(seq), longer) {
+        (Some(binding), false) => LeaderMatch::Action(binding.action().to_string()),
+        (Some(binding), true) => LeaderMatch::ActionOrPrefix(binding.action().to_string()),
+        (None, true) => LeaderMatch::Prefix,
+        (None, false) => LeaderMatch::NoMatch,
+    }
+}
+
+/// list the keys that can follow `prefix` in the leader tree
+pub fn leader_hints(
+    bindings: &HashMap<String, LeaderBinding>,
+    groups: &HashMap<String, String>,
+    prefix: &str,
+) -> Vec<KeyHint> {
+    let mut hints: Vec<KeyHint> = Vec::new();
+    for (seq, binding) in bindings {
+        let rest = match seq.strip_prefix(prefix) {
+            Some(r) if !r.is_empty() => r,
+            _ => continue,
+        };
+        let next = rest.chars().next().unwrap();
+        let key = next.to_string();
+        if rest.chars().count() == 1 {
+            hints.retain(|h| h.key != key);
+            hints.push(KeyHint { key, desc: binding.description().to_string(), is_group: false });
+        } else if !hints.iter().any(|h| h.key == key) {
+            let group = format!("{}{}", prefix, next);
+            let desc = groups.get(&group).cloned().unwrap_or_else(|| {
+                let n = bindings.keys().filter(|k| k.starts_with(&group)).count();
+                format!("{} binding{}", n, if n == 1 { "" } else { "s" })
+            });
+            hints.push(KeyHint { key, desc: format!("+{}", desc), is_group: true });
+        }
+    }
+    hints.sort_by(|a, b| a.key.cmp(&b.key));
+    hints
+}
+
+/// hints for a built-in prefix key
+pub fn prefix_hints(prefix: char) -> Vec<KeyHint> {
+    prefix_table(prefix)
+        .map(|table| table.iter().map(|(c, _, desc)| KeyHint {
+            key: c.to_string(),
+            desc: desc.to_string(),
+            is_group: false,
+        }).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings() -> HashMap<String, LeaderBinding> {
+        let mut b = HashMap::new();
+        b.insert("w".to_string(), LeaderBinding::Action("w".to_string()));
+        b.insert("fg".to_string(), LeaderBinding::Described {
+            action: "Rg".to_string(),
+            desc: Some("grep".to_string()),
+        });
+        b.insert("ff".to_string(), LeaderBinding::Action("Files".to_string()));
+        b
+    }
+
+    #[test]
+    fn test_match_leader_sequence() {
+        let b = bindings();
+        assert_eq!(match_leader_sequence(&b, "f"), LeaderMatch::Prefix);
+        assert_eq!(match_leader_sequence(&b, "fg"), LeaderMatch::Action("Rg".to_string()));
+        assert_eq!(match_leader_sequence(&b, "x"), LeaderMatch::NoMatch);
+        let mut b = b;
+        b.insert("f".to_string(), LeaderBinding::Action("Buffers".to_string()));
+        assert_eq!(match_leader_sequence(&b, "f"), LeaderMatch::ActionOrPrefix("Buffers".to_string()));
+    }
+
+    #[test]
+    fn test_leader_hints_groups() {
+        let b = bindings();
+        let mut groups = HashMap::new();
+        groups.insert("f".to_string(), "find".to_string());
+        let root = leader_hints(&b, &groups, "");
+        assert_eq!(root.len(), 2);
+        assert_eq!(root[0], KeyHint { key: "f".to_string(), desc: "+find".to_string(), is_group: true });
+        assert_eq!(root[1].desc, "w");
+        let sub = leader_hints(&b, &groups, "f");
+        assert_eq!(sub.iter().map(|h| h.key.as_str()).collect::<Vec<_>>(), vec!["f", "g"]);
+        assert_eq!(sub[1].desc, "grep");
+    }
+
+    #[test]
+    fn test_prefix_hints() {
+        assert!(prefix_hints('z').iter().any(|h| h.key == "z"));
+        assert!(prefix_hints('q').is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+// language registry: what the editor knows about each filetype, in one place.
+// Built-in definitions come from languages.toml at the crate root; a
+// languages.toml in the config dir replaces keys of those or adds languages.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use toml::{Table, Value};
+use crate::autocmd::pattern_matches;
+use crate::indent::IndentStyle;
+
+const BUILTIN: &str = include_str!("../languages.toml");
+
+/// an external program: a language server or formatter
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Program {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Language {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    pub globs: Vec<String>,
+    pub shebangs: Vec<String>,
+    pub comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+    pub indent: Option<IndentStyle>, // None follows tab_width / expand_tab
+    pub grammar: Option<String>,
+    pub language_servers: Vec<Program>,
+    pub formatter: Option<Program>,
+    #[serde(skip)]
+    user: bool, // defined or changed by the user's languages.toml
+}
+
+impl Language {
+    /// tree-sitter grammar for this language, its own name unless set
+    pub fn grammar(&self) -> &str {
+        self.grammar.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LanguageRegistry {
+    languages: Vec<Language>,
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl LanguageRegistry {
+    pub fn builtin() -> Self {
+        Self::with_overrides("").expect("bundled languages.toml is valid")
+    }
+
+    /// the built-in languages, parsed once for lookups that need no user config
+    pub fn shared() -> &'static Self {
+        static SHARED: OnceLock<LanguageRegistry> = OnceLock::new();
+        SHARED.get_or_init(Self::builtin)
+    }
+
+    /// built-in languages merged with `languages.toml` in `config_dir`; a file that
+    /// does not parse is reported and the built-in definitions used alone
+    pub fn load(config_dir: Option<&Path>) -> (Self, Vec<String>) {
+        let Some(path) = config_dir.map(|d| d.join("languages.toml")) else {
+            return (Self::builtin(), Vec::new());
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return (Self::builtin(), Vec::new());
+        };
+        match Self::with_overrides(&text) {
+            Ok(registry) => (registry, Vec::new()),
+            Err(e) => (Self::builtin(), vec![format!("{}: {}", path.display(), e)]),
+        }
+    }
+
+    /// built-in languages with the `[[language]]` entries of `text` merged over them
+    pub fn with_overrides(text: &str) -> Result<Self, String> {
+        let mut tables = language_tables(BUILTIN)?;
+        let builtin_count = tables.len();
+        let mut touched = vec![false; builtin_count];
+        for table in language_tables(text)? {
+            let Some(name) = table.get("name").and_then(Value::as_str) else {
+                return Err("[[language]] entry without a name".to_string());
+            };
+            match tables.iter().position(|t| t.get("name").and_then(Value::as_str) == Some(name)) {
+                Some(i) => {
+                    tables[i].extend(table);
+                    if i < builtin_count {
+                        touched[i] = true;
+                    }
+                }
+                None => {
+                    tables.push(table);
+                    touched.push(true);
+                }
+            }
+        }
+        let mut languages = Vec::new();
+        for (table, user) in tables.into_iter().zip(touched) {
+            let name = table.get("name").and_then(Value::as_str).unwrap_or("").to_string();
+            let mut language: Language = Value::Table(table).try_into()
+                .map_err(|e| format!("language {}: {}", name, e))?;
+            if language.indent.is_some_and(|i| i.width == 0) {
+                return Err(format!("language {}: indent width must be at least 1", name));
+            }
+            language.user = user;
+            languages.push(language);
+        }
+        Ok(Self { languages })
+    }
+
+    /// languages for `grammars` no language uses yet, such as runtime grammar
+    /// libraries, each matching files with the grammar's name as extension; then
+    /// `extensions` (editor.toml `[grammar_extensions]`) adds file extensions to
+    /// the languages of a grammar, ahead of other claims on them
+    pub fn add_grammars<'a>(&mut self, grammars: impl IntoIterator<Item = &'a str>, extensions: &HashMap<String, Vec<String>>) {
+        for grammar in grammars {
+            if !self.languages.iter().any(|l| l.grammar() == grammar) {
+                self.languages.push(Language { name: grammar.to_string(), extensions: vec![grammar.to_string()], ..Language::default() });
+            }
+        }
+        for (grammar, exts) in extensions {
+            if !self.languages.iter().any(|l| l.grammar() == grammar) {
+                self.languages.push(Language { name: grammar.clone(), ..Language::default() });
+            }
+            for language in self.languages.iter_mut().filter(|l| l.grammar() == grammar) {
+                for ext in exts {
+                    if !language.extensions.contains(ext) {
+                        language.extensions.push(ext.clone());
+                    }
+                }
+                language.user = true;
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Language> {
+        self.languages.iter().find(|l| l.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Language> {
+        self.languages.iter()
+    }
+
+    /// languages the user defined or changed come first, so their claims on an
+    /// extension or file name win over the built-in ones
+    fn by_priority(&self) -> impl Iterator<Item = &Language> {
+        self.languages.iter().filter(|l| l.user).chain(self.languages.iter().filter(|l| !l.user))
+    }
+
+    /// the language of a file: by file name, glob, extension, `#!` line, then a
+    /// `ft=` modeline in the first or last five lines of `content`
+    pub fn detect(&self, path: Option<&Path>, content: &str) -> Option<&Language> {
+        if let Some(path) = path {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let full = path.to_string_lossy();
+            if let Some(l) = self.by_priority().find(|l| l.filenames.iter().any(|f| f == file_name)) {
+                return Some(l);
+            }
+            if let Some(l) = self.by_priority().find(|l| l.globs.iter().any(|g| pattern_matches(g, &full))) {
+                return Some(l);
+            }
+            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+                if let Some(l) = self.by_priority().find(|l| l.extensions.iter().any(|e| e == ext)) {
+                    return Some(l);
+                }
+            }
+        }
+        if let Some(interpreter) = content.lines().next().and_then(shebang_interpreter) {
+            let known = |s: &String| interpreter == *s
+                || interpreter.strip_prefix(s.as_str()).is_some_and(|v| v.chars().all(|c| c.is_ascii_digit() || c == '.'));
+            if let Some(l) = self.by_priority().find(|l| l.shebangs.iter().any(known)) {
+                return Some(l);
+            }
+        }
+        let lines: Vec<&str> = content.lines().collect();
+        let head = lines.len().min(5);
+        let tail = lines.len().saturating_sub(5).max(head);
+        lines[..head].iter().chain(&lines[tail..])
+            .find_map(|line| modeline_filetype(line))
+            .and_then(|name| self.get(name))
+    }
+}
+
+/// the `[[language]]` tables of a languages.toml
+fn language_tables(text: &str) -> Result<Vec<Table>, String> {
+    let mut doc: Table = toml::from_str(text).map_err(|e| e.to_string())?;
+    match doc.remove("language") {
+        None => Ok(Vec::new()),
+        Some(Value::Array(entries)) => entries.into_iter().map(|entry| match entry {
+            Value::Table(t) => Ok(t),
+            _ => Err("[[language]] entries must be tables".to_string()),
+        }).collect(),
+        Some(_) => Err("`language` must be an array of tables".to_string()),
+    }
+}
+
+/// program named on a `#!` line, looking through `env` and its options
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    Some(program)
+}
+
+/// filetype set by a vim modeline: `vim: ft=python` or `vim: set filetype=rust:`
+fn modeline_filetype(line: &str) -> Option<&str> {
+    let at = ["filetype=", "ft="].iter().find_map(|key| {
+        line.match_indices(key)
+            .find(|(i, _)| *i == 0 || !line.as_bytes()[i - 1].is_ascii_alphanumeric())
+            .map(|(i, _)| i + key.len())
+    })?;
+    let name = line[at..].split(|c: char| c.is_whitespace() || c == ':').next()?;
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect<'a>(registry: &'a LanguageRegistry, path: &str, content: &str) -> Option<&'a str> {
+        let path = (!path.is_empty()).then(|| Path::new(path));
+        registry.detect(path, content).map(|l| l.name.as_str())
+    }
+
+    #[test]
+    fn test_detect() {
+        let r = LanguageRegistry::builtin();
+        assert_eq!(detect(&r, "src/main.rs", ""), Some("rust"));
+        assert_eq!(detect(&r, "Makefile", ""), Some("make"));
+        assert_eq!(detect(&r, "/home/me/.bashrc", ""), Some("bash"));
+        assert_eq!(detect(&r, "a.code-workspace", ""), Some("json"));
+        assert_eq!(detect(&r, "script", "#!/usr/bin/env python3.12\n"), Some("python"));
+        assert_eq!(detect(&r, "script", "#!/usr/bin/env -S node --harmony\n"), Some("javascript"));
+        assert_eq!(detect(&r, "script", "#!/bin/sh\n"), Some("bash"));
+        assert_eq!(detect(&r, "", "#!/usr/bin/pythonista\n"), None);
+        assert_eq!(detect(&r, "notes", "x\n# vim: set ft=markdown:\n"), Some("markdown"));
+        assert_eq!(detect(&r, "notes", "soft=go\n"), None);
+        assert_eq!(detect(&r, "notes.xyz", "plain\n"), None);
+    }
+
+    #[test]
+    fn test_builtin_definitions() {
+        let r = LanguageRegistry::builtin();
+        let rust = r.get("rust").unwrap();
+        assert_eq!(rust.grammar(), "rust");
+        assert_eq!(rust.comment.as_deref(), Some("//"));
+        assert_eq!(rust.language_servers[0].command, "rust-analyzer");
+        let go = r.get("go").unwrap();
+        assert!(go.indent.is_some_and(|i| i.tabs));
+        assert_eq!(go.formatter.as_ref().map(|f| f.command.as_str()), Some("gofmt"));
+        assert!(r.get("python").unwrap().indent.is_none());
+    }
+
+    #[test]
+    fn test_user_overrides() {
+        let text = r#"
+            [[language]]
+            name = "cpp"
+            extensions = ["cpp", "h"]
+            indent = { width = 2, tabs = false }
+
+            [[language]]
+            name = "zig"
+            extensions = ["zig"]
+            comment = "//"
+            language_servers = [{ command = "zls" }]
+        "#;
+        let r = LanguageRegistry::with_overrides(text).unwrap();
+        // a user claim on .h beats the built-in C definition
+        assert_eq!(detect(&r, "x.h", ""), Some("cpp"));
+        assert_eq!(detect(&r, "x.c", ""), Some("c"));
+        let cpp = r.get("cpp").unwrap();
+        assert_eq!(cpp.indent.map(|i| i.width), Some(2));
+        // keys the override leaves out keep their built-in values
+        assert_eq!(cpp.comment.as_deref(), Some("//"));
+        assert!(!cpp.extensions.contains(&"hpp".to_string()));
+        let zig = r.get("zig").unwrap();
+        assert_eq!((zig.grammar(), zig.language_servers[0].args.len()), ("zig", 0));
+
+        assert!(LanguageRegistry::with_overrides("[[language]]\nextensions = [\"q\"]\n").is_err());
+        let err = LanguageRegistry::with_overrides("[[language]]\nname = \"q\"\ncomments = \"#\"\n").unwrap_err();
+        assert!(err.contains("language q"), "{}", err);
+        let err = LanguageRegistry::with_overrides("[[language]]\nname = \"q\"\nindent = { width = 0, tabs = true }\n").unwrap_err();
+        assert!(err.contains("indent width"), "{}", err);
+    }
+
+    #[test]
+    fn test_add_grammars() {
+        let mut r = LanguageRegistry::builtin();
+        let extensions = HashMap::from([
+            ("zig".to_string(), vec!["zon".to_string()]),
+            ("cpp".to_string(), vec!["h".to_string()]),
+        ]);
+        r.add_grammars(["zig", "rust"], &extensions);
+        assert_eq!(detect(&r, "build.zig", ""), Some("zig"));
+        assert_eq!(detect(&r, "build.zig.zon", ""), Some("zig"));
+        // extensions given for a grammar win over the built-in claims
+        assert_eq!(detect(&r, "x.h", ""), Some("cpp"));
+        assert_eq!(r.iter().filter(|l| l.grammar() == "rust").count(), 1);
+        assert_eq!(LanguageRegistry::shared().get("rust").map(|l| l.name.as_str()), Some("rust"));
+    }
+}
@@ -1,6 +1,7 @@
 // Bitsy - A Vim-compatible text editor written in Rust
 // Module declarations
 
+pub mod autocmd;
 pub mod buffer;
 pub mod command;
 pub mod command_bar;
@@ -10,23 +11,40 @@ pub mod editor;
 pub mod event;
 pub mod error;
 pub mod filetype;
+pub mod fold;
 pub mod fuzzy;
 pub mod fuzzy_finder;
+pub mod global;
+pub mod grammar;
+pub mod ignore;
+pub mod indent;
 pub mod keymap;
+pub mod language;
 pub mod lsp;
+pub mod mapping;
 pub mod mode;
 pub mod motion;
 pub mod operator;
+pub mod quickfix;
 pub mod register;
+pub mod replace;
 pub mod screen;
+pub mod search;
 pub mod selection;
+pub mod symbol;
 pub mod syntax;
 pub mod statusline;
+pub mod substitute;
+pub mod term_color;
 pub mod term_pane;
+#[cfg(test)]
+mod test_dir;
+pub mod textobject;
 pub mod terminal;
 pub mod theme;
 pub mod viewport;
 pub mod undo;
+pub mod user_command;
 pub mod window;
 
 // Re-export commonly used types
@@ -5,8 +5,10 @@ mod transport;
 
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::OnceLock;
 use crate::config::Config;
-pub use protocol::{CompletionItem, Diagnostic, DiagnosticSeverity, Position, Range as LspRange};
+use crate::language::LanguageRegistry;
+pub use protocol::{CompletionItem, Diagnostic, DiagnosticSeverity, DocumentSymbol, Position, Range as LspRange};
 
 pub struct LspClient {
     transport: Option<transport::Transport>,
@@ -15,6 +17,28 @@ pub struct LspClient {
     request_id: u64,
     pending_completions: Vec<CompletionItem>,
     pub diagnostics: HashMap<String, Vec<Diagnostic>>, // uri -> diagnostics
+    pub document_symbols: HashMap<String, (u64, Vec<DocumentSymbol>)>, // uri -> buffer version, symbols
+    symbol_requests: HashMap<u64, (String, u64)>, // request id -> uri, buffer version
+    events: Vec<LspEvent>,
+}
+
+/// something the server sent that the editor may want to redraw for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LspEvent {
+    Diagnostics(String),
+    DocumentSymbols(String),
+}
+
+/// the char column of a UTF-16 `character` offset into `line`
+pub fn char_column(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (col, ch) in line.chars().enumerate() {
+        if units >= character as usize || ch == '\n' {
+            return col;
+        }
+        units += ch.len_utf16();
+    }
+    line.chars().count()
 }
 
 impl LspClient {
@@ -26,6 +50,9 @@ impl LspClient {
             request_id: 0,
             pending_completions: Vec::new(),
             diagnostics: HashMap::new(),
+            document_symbols: HashMap::new(),
+            symbol_requests: HashMap::new(),
+            events: Vec::new(),
         }
     }
 
@@ -51,6 +78,7 @@ impl LspClient {
                 "textDocument": {
                     "completion": { "completionItem": { "snippetSupport": false } },
                     "hover": { "contentFormat": ["plaintext"] },
+                    "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
                     "publishDiagnostics": { "relatedInformation": false }
                 }
             }
@@ -132,24 +160,67 @@ impl LspClient {
         Ok(None)
     }
 
+    pub fn document_symbols(&mut self, uri: &str) -> Result<Vec<DocumentSymbol>, String> {
+        if !self.is_running() { return Ok(Vec::new()); }
+        let id = self.next_id();
+        self.send_request("textDocument/documentSymbol", id, serde_json::json!({
+            "textDocument": { "uri": uri }
+        }))?;
+        if let Some(resp) = self.poll_response(id, 2000) {
+            if let Some(result) = resp.get("result") {
+                return Ok(protocol::parse_document_symbols(result));
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// ask for `uri`'s symbols as of buffer `version` without waiting; the answer
+    /// lands in `document_symbols` during a later `poll_notifications`
+    pub fn request_document_symbols(&mut self, uri: &str, version: u64) -> Result<(), String> {
+        if !self.is_running() { return Ok(()); }
+        if self.symbol_requests.values().any(|(u, v)| u == uri && *v == version) { return Ok(()); }
+        let id = self.next_id();
+        self.send_request("textDocument/documentSymbol", id, serde_json::json!({
+            "textDocument": { "uri": uri }
+        }))?;
+        self.symbol_requests.insert(id, (uri.to_string(), version));
+        Ok(())
+    }
+
     /// poll for notifications (diagnostics, etc) -- non-blocking
     pub fn poll_notifications(&mut self) {
-        if let Some(ref mut t) = self.transport {
-            while let Some(msg) = t.try_recv() {
-                if let Some(method) = msg.get("method").and_then(|m| m.as_str()) {
-                    if method == "textDocument/publishDiagnostics" {
-                        if let Some(params) = msg.get("params") {
-                            if let (Some(uri), Some(diags)) = (
-                                params.get("uri").and_then(|u| u.as_str()),
-                                params.get("diagnostics").and_then(|d| d.as_array()),
-                            ) {
-                                let parsed: Vec<Diagnostic> = diags.iter()
-                                    .filter_map(|d| protocol::parse_diagnostic(d))
-                                    .collect();
-                                self.diagnostics.insert(uri.to_string(), parsed);
-                            }
-                        }
-                    }
+        while let Some(msg) = self.transport.as_mut().and_then(|t| t.try_recv()) {
+            self.handle_message(msg);
+        }
+    }
+
+    /// what arrived since the last call
+    pub fn take_events(&mut self) -> Vec<LspEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// store a notification or a response to an async request
+    fn handle_message(&mut self, msg: serde_json::Value) {
+        let method = msg.get("method").and_then(|m| m.as_str());
+        if let (None, Some(id)) = (method, msg.get("id").and_then(|i| i.as_u64())) {
+            if let Some((uri, version)) = self.symbol_requests.remove(&id) {
+                let symbols = msg.get("result").map(protocol::parse_document_symbols).unwrap_or_default();
+                self.document_symbols.insert(uri.clone(), (version, symbols));
+                self.events.push(LspEvent::DocumentSymbols(uri));
+            }
+            return;
+        }
+        if method == Some("textDocument/publishDiagnostics") {
+            if let Some(params) = msg.get("params") {
+                if let (Some(uri), Some(diags)) = (
+                    params.get("uri").and_then(|u| u.as_str()),
+                    params.get("diagnostics").and_then(|d| d.as_array()),
+                ) {
+                    let parsed: Vec<Diagnostic> = diags.iter()
+                        .filter_map(|d| protocol::parse_diagnostic(d))
+                        .collect();
+                    self.diagnostics.insert(uri.to_string(), parsed);
+                    self.events.push(LspEvent::Diagnostics(uri.to_string()));
                 }
             }
         }
@@ -181,32 +252,16 @@ impl LspClient {
     }
 
     fn poll_response(&mut self, id: u64, timeout_ms: u64) -> Option<serde_json::Value> {
-        if let Some(ref mut t) = self.transport {
-            let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms);
-            while std::time::Instant::now() < deadline {
-                if let Some(msg) = t.try_recv() {
-                    if msg.get("id").and_then(|i| i.as_u64()) == Some(id) {
-                        return Some(msg);
-                    }
-                    // handle notifications while waiting
-                    if let Some(method) = msg.get("method").and_then(|m| m.as_str()) {
-                        if method == "textDocument/publishDiagnostics" {
-                            if let Some(params) = msg.get("params") {
-                                if let (Some(uri), Some(diags)) = (
-                                    params.get("uri").and_then(|u| u.as_str()),
-                                    params.get("diagnostics").and_then(|d| d.as_array()),
-                                ) {
-                                    let parsed: Vec<Diagnostic> = diags.iter()
-                                        .filter_map(|d| protocol::parse_diagnostic(d))
-                                        .collect();
-                                    self.diagnostics.insert(uri.to_string(), parsed);
-                                }
-                            }
-                        }
-                    }
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms);
+        while self.transport.is_some() && std::time::Instant::now() < deadline {
+            while let Some(msg) = self.transport.as_mut().and_then(|t| t.try_recv()) {
+                if msg.get("id").and_then(|i| i.as_u64()) == Some(id) && msg.get("method").is_none() {
+                    return Some(msg);
                 }
-                std::thread::sleep(std::time::Duration::from_millis(10));
+                // handle notifications while waiting
+                self.handle_message(msg);
             }
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
         None
     }
@@ -216,14 +271,58 @@ impl Drop for LspClient {
     fn drop(&mut self) { self.stop(); }
 }
 
-/// determine LSP server command for a file extension
+/// determine LSP server command for a file extension: the first server of the
+/// built-in language that claims it
 pub fn server_for_extension(ext: &str) -> Option<(&'static str, &'static [&'static str])> {
-    match ext {
-        "rs" => Some(("rust-analyzer", &[])),
-        "py" => Some(("pyright-langserver", &["--stdio"])),
-        "js" | "jsx" | "ts" | "tsx" => Some(("typescript-language-server", &["--stdio"])),
-        "go" => Some(("gopls", &[])),
-        "c" | "cpp" | "h" | "hpp" => Some(("clangd", &[])),
-        _ => None,
+    type Server = (&'static str, &'static str, Vec<&'static str>); // extension, command, args
+    static SERVERS: OnceLock<Vec<Server>> = OnceLock::new();
+    let servers = SERVERS.get_or_init(|| {
+        LanguageRegistry::shared().iter()
+            .filter_map(|l| Some((l, l.language_servers.first()?)))
+            .flat_map(|(l, server)| l.extensions.iter().map(move |ext| {
+                (ext.as_str(), server.command.as_str(), server.args.iter().map(String::as_str).collect())
+            }))
+            .collect()
+    });
+    servers.iter().find(|(e, _, _)| *e == ext).map(|(_, command, args)| (*command, args.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_column_counts_utf16_units() {
+        assert_eq!(char_column("abc", 2), 2);
+        assert_eq!(char_column("\u{1f600}x", 2), 1);
+        assert_eq!(char_column("\u{e9}t\u{e9}\n", 9), 3);
+    }
+
+    #[test]
+    fn test_server_for_extension() {
+        assert_eq!(server_for_extension("rs"), Some(("rust-analyzer", &[][..])));
+        assert_eq!(server_for_extension("py"), Some(("pyright-langserver", &["--stdio"][..])));
+        assert_eq!(server_for_extension("md"), None);
+    }
+
+    #[test]
+    fn test_symbol_response_is_cached_by_version() {
+        let mut client = LspClient::new();
+        client.symbol_requests.insert(7, ("file:///a.rs".to_string(), 3));
+        client.handle_message(serde_json::json!({ "jsonrpc": "2.0", "id": 7, "result": [{
+            "name": "main", "kind": 12,
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 2, "character": 1 } },
+            "selectionRange": { "start": { "line": 0, "character": 3 }, "end": { "line": 0, "character": 7 } }
+        }] }));
+        client.handle_message(serde_json::json!({ "jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+            "params": { "uri": "file:///a.rs", "diagnostics": [] } }));
+        let (version, symbols) = &client.document_symbols["file:///a.rs"];
+        assert_eq!((*version, symbols[0].name.as_str()), (3, "main"));
+        assert!(client.symbol_requests.is_empty());
+        assert_eq!(client.take_events(), vec![
+            LspEvent::DocumentSymbols("file:///a.rs".to_string()),
+            LspEvent::Diagnostics("file:///a.rs".to_string()),
+        ]);
+        assert!(client.take_events().is_empty());
     }
 }
@@ -13,6 +13,16 @@ pub struct CompletionItem {
     pub kind: Option<u32>,
 }
 
+/// a documentSymbol result; nested DocumentSymbols are flattened in document
+/// order, SymbolInformation has no children
+#[derive(Debug, Clone)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: u32,
+    pub range: Range,         // the whole definition
+    pub selection: Position,  // start of the name
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DiagnosticSeverity { Error, Warning, Information, Hint }
 
@@ -64,6 +74,40 @@ pub fn parse_definition(value: &serde_json::Value) -> Option<(String, u32, u32)>
     Some((uri, line, character))
 }
 
+fn parse_position(value: &serde_json::Value) -> Option<Position> {
+    Some(Position {
+        line: value.get("line")?.as_u64()? as u32,
+        character: value.get("character")?.as_u64()? as u32,
+    })
+}
+
+fn parse_range(value: &serde_json::Value) -> Option<Range> {
+    Some(Range { start: parse_position(value.get("start")?)?, end: parse_position(value.get("end")?)? })
+}
+
+pub fn parse_document_symbols(value: &serde_json::Value) -> Vec<DocumentSymbol> {
+    fn walk(items: &[serde_json::Value], out: &mut Vec<DocumentSymbol>) {
+        for item in items {
+            let name = item.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string();
+            let kind = item.get("kind").and_then(|k| k.as_u64()).unwrap_or(0) as u32;
+            // DocumentSymbol has range/selectionRange, SymbolInformation a location
+            let range = item.get("range").or_else(|| item.get("location").and_then(|l| l.get("range")));
+            let Some(range) = range.and_then(parse_range) else { continue; };
+            let selection = item.get("selectionRange").and_then(parse_range)
+                .map_or_else(|| range.start.clone(), |r| r.start);
+            out.push(DocumentSymbol { name, kind, range, selection });
+            if let Some(children) = item.get("children").and_then(|c| c.as_array()) {
+                walk(children, out);
+            }
+        }
+    }
+    let mut out = Vec::new();
+    if let Some(items) = value.as_array() {
+        walk(items, &mut out);
+    }
+    out
+}
+
 pub fn parse_diagnostic(value: &serde_json::Value) -> Option<Diagnostic> {
     let range_val = value.get("range")?;
     let start = range_val.get("start")?;
@@ -0,0 +1,217 @@
+// User key mappings (:map, :nnoremap, ...) and vim key notation
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    Insert,
+    Command,
+}
+
+impl MapMode {
+    pub fn letter(&self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::Insert => 'i',
+            MapMode::Command => 'c',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub lhs: String, // as written, for listings
+    pub rhs: Vec<KeyEvent>,
+    pub rhs_text: String,
+    pub noremap: bool,
+}
+
+/// result of looking up the keys typed so far
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapMatch {
+    None,
+    Prefix,                  // a longer mapping may still match
+    Exact(Mapping),          // complete, unambiguous match
+    ExactOrPrefix(Mapping),  // complete, but a longer mapping also starts here
+}
+
+/// drop modifiers that are already implied by the key itself so typed keys
+/// compare equal to parsed notation
+pub fn normalize_key(key: KeyEvent) -> KeyEvent {
+    let mut mods = key.modifiers;
+    if let KeyCode::Char(_) = key.code {
+        mods.remove(KeyModifiers::SHIFT);
+    }
+    KeyEvent::new(key.code, mods)
+}
+
+/// parse vim key notation ("<C-w>j", "<leader>ff", "jk") into key events
+pub fn parse_keys(notation: &str, leader: Option<char>) -> Result<Vec<KeyEvent>> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                if let Some(key) = parse_special(&rest[1..end], leader) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err(Error::ParseError("Empty key sequence".to_string()));
+    }
+    Ok(keys)
+}
+
+fn parse_special(name: &str, leader: Option<char>) -> Option<KeyEvent> {
+    let mut mods = KeyModifiers::NONE;
+    let mut base = name;
+    // modifier prefixes: C- A- M- S-
+    while base.len() > 2 && base.as_bytes()[1] == b'-' {
+        match base.as_bytes()[0].to_ascii_lowercase() {
+            b'c' => mods |= KeyModifiers::CONTROL,
+            b'a' | b'm' => mods |= KeyModifiers::ALT,
+            b's' => mods |= KeyModifiers::SHIFT,
+            _ => return None,
+        }
+        base = &base[2..];
+    }
+    let base_lower = base.to_ascii_lowercase();
+    let code = match base_lower.as_str() {
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "bslash" => KeyCode::Char('\\'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "leader" if mods.is_empty() => KeyCode::Char(leader.unwrap_or('\\')),
+        _ => {
+            if let Some(n) = base_lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                KeyCode::F(n)
+            } else if base.chars().count() == 1 && !mods.is_empty() {
+                let ch = base.chars().next().unwrap();
+                // <C-W> and <C-w> are the same key
+                KeyCode::Char(if mods.contains(KeyModifiers::CONTROL) { ch.to_ascii_lowercase() } else { ch })
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(normalize_key(KeyEvent::new(code, mods)))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyMappings {
+    maps: HashMap<MapMode, HashMap<Vec<KeyEvent>, Mapping>>,
+}
+
+impl KeyMappings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, mode: MapMode, lhs: &str, rhs: &str, noremap: bool, leader: Option<char>) -> Result<()> {
+        let keys = parse_keys(lhs, leader)?;
+        let rhs_keys = parse_keys(rhs, leader)?;
+        let mapping = Mapping { lhs: lhs.to_string(), rhs: rhs_keys, rhs_text: rhs.to_string(), noremap };
+        self.maps.entry(mode).or_default().insert(keys, mapping);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, mode: MapMode, lhs: &str, leader: Option<char>) -> bool {
+        let Ok(keys) = parse_keys(lhs, leader) else { return false; };
+        self.maps.get_mut(&mode).map(|m| m.remove(&keys).is_some()).unwrap_or(false)
+    }
+
+    pub fn is_empty(&self, mode: MapMode) -> bool {
+        self.maps.get(&mode).map(|m| m.is_empty()).unwrap_or(true)
+    }
+
+    pub fn lookup(&self, mode: MapMode, typed: &[KeyEvent]) -> MapMatch {
+        let Some(table) = self.maps.get(&mode) else { return MapMatch::None; };
+        let longer = table.keys().any(|k| k.len() > typed.len() && k.starts_with(typed));
+        match (table.get(typed), longer) {
+            (Some(m), false) => MapMatch::Exact(m.clone()),
+            (Some(m), true) => MapMatch::ExactOrPrefix(m.clone()),
+            (None, true) => MapMatch::Prefix,
+            (None, false) => MapMatch::None,
+        }
+    }
+
+    /// sorted (mode letter, lhs, rhs, noremap) entries for the `:map` listing
+    pub fn list(&self, modes: &[MapMode]) -> Vec<(char, &str, &str, bool)> {
+        let mut out: Vec<(char, &str, &str, bool)> = Vec::new();
+        for mode in modes {
+            if let Some(table) = self.maps.get(mode) {
+                for m in table.values() {
+                    out.push((mode.letter(), m.lhs.as_str(), m.rhs_text.as_str(), m.noremap));
+                }
+            }
+        }
+        out.sort();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ch(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(parse_keys("jk", None).unwrap(), vec![ch('j'), ch('k')]);
+        assert_eq!(
+            parse_keys("<C-W>j<CR>", None).unwrap(),
+            vec![KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL), ch('j'), KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)]
+        );
+        assert_eq!(parse_keys("<leader>f", Some(' ')).unwrap(), vec![ch(' '), ch('f')]);
+        assert_eq!(parse_keys("<lt>x", None).unwrap(), vec![ch('<'), ch('x')]);
+        // unknown notation is taken literally
+        assert_eq!(parse_keys("<foo>", None).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_normalize_shifted_char() {
+        let typed = KeyEvent::new(KeyCode::Char('J'), KeyModifiers::SHIFT);
+        assert_eq!(normalize_key(typed), ch('J'));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut maps = KeyMappings::new();
+        maps.add(MapMode::Insert, "jk", "<Esc>", true, None).unwrap();
+        maps.add(MapMode::Normal, "g", "gg", false, None).unwrap();
+        maps.add(MapMode::Normal, "gx", "x", false, None).unwrap();
+        assert_eq!(maps.lookup(MapMode::Insert, &[ch('j')]), MapMatch::Prefix);
+        assert!(matches!(maps.lookup(MapMode::Insert, &[ch('j'), ch('k')]), MapMatch::Exact(_)));
+        assert!(matches!(maps.lookup(MapMode::Normal, &[ch('g')]), MapMatch::ExactOrPrefix(_)));
+        assert_eq!(maps.lookup(MapMode::Normal, &[ch('j')]), MapMatch::None);
+        assert!(maps.remove(MapMode::Insert, "jk", None));
+        assert!(maps.is_empty(MapMode::Insert));
+    }
+}
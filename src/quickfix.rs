@@ -0,0 +1,833 @@
+// quickfix lists: locations picked out of compiler and tool output by vim
+// errorformat patterns, and the place reached in them.
+//
+// an errorformat is a comma separated list of scanf-like patterns tried in turn
+// on every line of output: %f file, %l line, %c column, %m message, %t type
+// letter and so on. %E %W %I %N %A start an entry that %C lines continue and %Z
+// ends, %G lines are general messages, and a - prefix (%-G) drops what matched
+// while + keeps the whole line as the message. Lines nothing matches are kept
+// as text-only entries.
+//
+// besides the one quickfix list each window can hold a location list of its
+// own, filled by the :l commands or by producers such as the language server's
+// diagnostics for the buffer in it
+
+use regex::{Captures, Regex};
+use ropey::Rope;
+use std::path::{Path, PathBuf};
+use crate::autocmd::glob_match;
+use crate::lsp::{self, Diagnostic, DiagnosticSeverity};
+use crate::search::{self, SearchPattern};
+use crate::substitute;
+
+/// which list a command works on: the global quickfix list or the current
+/// window's location list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Quickfix,
+    Location,
+}
+
+impl ListKind {
+    /// the name the list window shows
+    pub fn name(self) -> &'static str {
+        match self {
+            ListKind::Quickfix => "Quickfix List",
+            ListKind::Location => "Location List",
+        }
+    }
+}
+
+/// one line of a quickfix list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: Option<PathBuf>,
+    pub line: usize, // 1-indexed, 0 when not known
+    pub col: usize,  // 1-indexed character column, 0 when not known
+    pub kind: Option<char>, // E error, W warning, I info, N note
+    pub text: String,
+    pub valid: bool, // has a location to jump to
+}
+
+impl Entry {
+    /// a line of output kept as it is, with nowhere to jump
+    pub fn text(text: &str) -> Self {
+        Self { path: None, line: 0, col: 0, kind: None, text: text.to_string(), valid: false }
+    }
+
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            Some('E') => "error",
+            Some('W') => "warning",
+            Some('I') => "info",
+            Some('N') => "note",
+            _ => "",
+        }
+    }
+
+    /// `path|line col N kind| text`, as the list window shows it
+    pub fn display(&self) -> String {
+        if !self.valid {
+            return format!("|| {}", self.text);
+        }
+        let path = self.path.as_ref().map_or(String::new(), |p| p.display().to_string());
+        let mut location = String::new();
+        if self.line > 0 {
+            location.push_str(&self.line.to_string());
+            if self.col > 0 {
+                location.push_str(&format!(" col {}", self.col));
+            }
+        }
+        let kind = self.kind_name();
+        if !kind.is_empty() {
+            if !location.is_empty() {
+                location.push(' ');
+            }
+            location.push_str(kind);
+        }
+        format!("{}|{}| {}", path, location, self.text)
+    }
+}
+
+/// a list of entries and the current one
+#[derive(Debug, Clone, Default)]
+pub struct QuickfixList {
+    pub title: String, // the command that filled it
+    pub entries: Vec<Entry>,
+    pub index: usize,
+}
+
+impl QuickfixList {
+    /// a list whose current entry is the first valid one
+    pub fn new(title: &str, entries: Vec<Entry>) -> Self {
+        let index = entries.iter().position(|e| e.valid).unwrap_or(0);
+        Self { title: title.to_string(), entries, index }
+    }
+
+    pub fn valid_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.valid).count()
+    }
+
+    pub fn current(&self) -> Option<&Entry> {
+        self.entries.get(self.index).filter(|e| e.valid)
+    }
+
+    /// go `count` valid entries forward; None, staying put, past the last
+    pub fn next(&mut self, count: usize) -> Option<&Entry> {
+        let valid: Vec<usize> = (self.index + 1..self.entries.len()).filter(|&i| self.entries[i].valid).collect();
+        let &to = valid.get(count.max(1) - 1).or(if count > 1 { valid.last() } else { None })?;
+        self.index = to;
+        self.entries.get(to)
+    }
+
+    /// go `count` valid entries back; None, staying put, before the first
+    pub fn prev(&mut self, count: usize) -> Option<&Entry> {
+        let valid: Vec<usize> = (0..self.index.min(self.entries.len())).rev().filter(|&i| self.entries[i].valid).collect();
+        let &to = valid.get(count.max(1) - 1).or(if count > 1 { valid.last() } else { None })?;
+        self.index = to;
+        self.entries.get(to)
+    }
+
+    /// go to entry `n`, 1-indexed over all entries, or the last for 0; an
+    /// invalid one goes on to the next valid entry
+    pub fn select(&mut self, n: usize) -> Option<&Entry> {
+        let n = if n == 0 { self.entries.len() } else { n.min(self.entries.len()) };
+        let from = n.checked_sub(1)?;
+        let to = (from..self.entries.len()).find(|&i| self.entries[i].valid)
+            .or_else(|| (0..from).rev().find(|&i| self.entries[i].valid))?;
+        self.index = to;
+        self.entries.get(to)
+    }
+
+    /// the current entry as `(n of m)` over the valid entries
+    pub fn position(&self) -> (usize, usize) {
+        let n = self.entries[..self.index.min(self.entries.len())].iter().filter(|e| e.valid).count() + 1;
+        (n, self.valid_count())
+    }
+
+    /// the valid entries, in order, or with `per_file` only the first of each
+    /// file
+    pub fn targets(&self, per_file: bool) -> Vec<usize> {
+        let mut seen = Vec::new();
+        (0..self.entries.len())
+            .filter(|&i| self.entries[i].valid)
+            .filter(|&i| {
+                if !per_file {
+                    return true;
+                }
+                let path = &self.entries[i].path;
+                if seen.contains(&path) {
+                    return false;
+                }
+                seen.push(path);
+                true
+            })
+            .collect()
+    }
+}
+
+/// what a pattern of an errorformat does with the lines it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prefix {
+    Single,              // a whole entry on one line
+    Start(Option<char>), // %E %W %I %N %A: the first line of an entry, of that type
+    Continue,            // %C: more of the entry being read
+    End,                 // %Z: the last line of it
+    General,             // %G: a message with no location
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    File,
+    Line,
+    Col,
+    Message,
+    Type,
+    Pointer, // %p: the ^ under a column
+    Skip,    // %n %r %s, read but unused
+}
+
+#[derive(Debug, Clone)]
+struct Format {
+    prefix: Prefix,
+    drop: bool,  // %-: the matched line is left out
+    whole: bool, // %+: the whole line is the message
+    regex: Regex,
+    fields: Vec<Field>,
+}
+
+/// a compiled errorformat
+#[derive(Debug, Clone)]
+pub struct ErrorFormat {
+    formats: Vec<Format>,
+}
+
+const RUST: &str = "%-Gerror: aborting %.%#,\
+%-Gerror: could not compile %.%#,\
+%-Gwarning: %.%#generated %.%#,\
+%Eerror[E%n]: %m,\
+%Eerror: %m,\
+%Wwarning: %m,\
+%C %#--> %f:%l:%c,\
+%+Ethread '%.%#' panicked at %f:%l:%c:,\
+%-G%.%#";
+
+const GCC: &str = "%-Gmake: %.%#,\
+%-Gmake[%*\\d]: %.%#,\
+%f:%l:%c: %trror: %m,\
+%f:%l:%c: fatal %trror: %m,\
+%f:%l:%c: %tarning: %m,\
+%f:%l:%c: %tote: %m,\
+%f:%l: %trror: %m,\
+%f:%l: %tarning: %m,\
+%f:%l:%c: %m,\
+%f:%l: %m,\
+%-G%.%#";
+
+const GO: &str = "%-G#%.%#,\
+%\\s%#%f:%l:%c: %m,\
+%\\s%#%f:%l: %m,\
+%-G%.%#";
+
+const PYTEST: &str = "%f:%l: %m,\
+%-G%.%#";
+
+impl ErrorFormat {
+    /// parse a comma separated errorformat (`\,` for a comma in a pattern), or
+    /// the name of a built-in one
+    pub fn new(spec: &str) -> Result<Self, String> {
+        if let Some(builtin) = Self::builtin(spec) {
+            return Ok(builtin);
+        }
+        let formats = split_formats(spec).iter()
+            .filter(|f| !f.is_empty())
+            .map(|f| compile(f))
+            .collect::<Result<Vec<_>, _>>()?;
+        if formats.is_empty() {
+            return Err("Empty errorformat".to_string());
+        }
+        Ok(Self { formats })
+    }
+
+    /// the built-in errorformats: rust (also cargo, rustc), gcc (clang, make),
+    /// go and pytest
+    pub fn builtin(name: &str) -> Option<Self> {
+        let spec = match name {
+            "rust" | "cargo" | "rustc" => RUST,
+            "gcc" | "clang" | "make" => GCC,
+            "go" => GO,
+            "pytest" | "python" => PYTEST,
+            _ => return None,
+        };
+        let formats = split_formats(spec).iter().map(|f| compile(f).expect("built-in errorformat")).collect();
+        Some(Self { formats })
+    }
+
+    /// the built-in errorformat for the program a makeprg runs, gcc's when
+    /// there is none for it
+    pub fn for_program(makeprg: &str) -> Self {
+        let program = makeprg.split_whitespace().next().unwrap_or("");
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let name = match program {
+            "cargo" | "rustc" => "rust",
+            "go" => "go",
+            "pytest" | "py.test" | "python" | "python3" => "pytest",
+            _ => "gcc",
+        };
+        Self::builtin(name).expect("built-in errorformat")
+    }
+
+    /// the entries in a tool's output
+    pub fn parse(&self, output: &str) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut pending: Option<Entry> = None; // a multi-line entry still being read
+        for line in output.lines() {
+            let line = line.trim_end_matches('\r');
+            // %C and %Z only go on from a %E, %W, ... line
+            let found = self.formats.iter()
+                .filter(|f| pending.is_some() || !matches!(f.prefix, Prefix::Continue | Prefix::End))
+                .find_map(|f| f.regex.captures(line).map(|caps| (f, caps)));
+            let Some((format, caps)) = found else {
+                flush(&mut entries, &mut pending);
+                if !line.trim().is_empty() {
+                    entries.push(Entry::text(line));
+                }
+                continue;
+            };
+            let mut entry = read_fields(format, &caps);
+            if format.whole {
+                entry.text = line.to_string();
+            }
+            match format.prefix {
+                Prefix::General => {
+                    if !format.drop {
+                        flush(&mut entries, &mut pending);
+                        entries.push(Entry::text(&entry.text));
+                    }
+                }
+                Prefix::Single => {
+                    flush(&mut entries, &mut pending);
+                    if !format.drop {
+                        entries.push(finish(entry));
+                    }
+                }
+                Prefix::Start(kind) => {
+                    flush(&mut entries, &mut pending);
+                    entry.kind = entry.kind.or(kind);
+                    if !format.drop {
+                        pending = Some(entry);
+                    }
+                }
+                Prefix::Continue | Prefix::End => {
+                    if let Some(p) = pending.as_mut() {
+                        merge(p, entry, !format.drop);
+                    }
+                    if format.prefix == Prefix::End {
+                        flush(&mut entries, &mut pending);
+                    }
+                }
+            }
+        }
+        flush(&mut entries, &mut pending);
+        entries
+    }
+}
+
+/// an entry with its validity worked out
+fn finish(mut entry: Entry) -> Entry {
+    entry.valid = entry.path.as_ref().is_some_and(|p| !p.as_os_str().is_empty());
+    entry
+}
+
+fn flush(entries: &mut Vec<Entry>, pending: &mut Option<Entry>) {
+    if let Some(entry) = pending.take() {
+        entries.push(finish(entry));
+    }
+}
+
+/// add what a continuation line read to the entry it continues
+fn merge(entry: &mut Entry, more: Entry, with_text: bool) {
+    if entry.path.is_none() {
+        entry.path = more.path;
+    }
+    if entry.line == 0 {
+        entry.line = more.line;
+    }
+    if entry.col == 0 {
+        entry.col = more.col;
+    }
+    entry.kind = entry.kind.or(more.kind);
+    if with_text && !more.text.is_empty() {
+        if !entry.text.is_empty() {
+            entry.text.push(' ');
+        }
+        entry.text.push_str(more.text.trim());
+    }
+}
+
+fn read_fields(format: &Format, caps: &Captures) -> Entry {
+    let mut entry = Entry::text("");
+    for (i, field) in format.fields.iter().enumerate() {
+        let Some(value) = caps.get(i + 1).map(|m| m.as_str()) else { continue; };
+        match field {
+            Field::File => entry.path = Some(PathBuf::from(value.trim())),
+            Field::Line => entry.line = value.parse().unwrap_or(0),
+            Field::Col => entry.col = value.parse().unwrap_or(0),
+            Field::Message => entry.text = value.to_string(),
+            Field::Type => {
+                entry.kind = value.chars().next().map(|c| c.to_ascii_uppercase()).filter(|c| matches!(c, 'E' | 'W' | 'I' | 'N'));
+            }
+            Field::Pointer => entry.col = value.chars().map(|c| if c == '\t' { 8 } else { 1 }).sum::<usize>() + 1,
+            Field::Skip => {}
+        }
+    }
+    entry
+}
+
+/// the patterns of an errorformat: split on commas, `\,` being a comma
+fn split_formats(spec: &str) -> Vec<String> {
+    let mut formats = vec![String::new()];
+    let mut chars = spec.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                formats.last_mut().unwrap().push(',');
+            }
+            ',' => formats.push(String::new()),
+            _ => formats.last_mut().unwrap().push(c),
+        }
+    }
+    formats
+}
+
+/// one pattern of an errorformat as an anchored regex and what its groups hold
+fn compile(pattern: &str) -> Result<Format, String> {
+    let invalid = |what: &str| format!("Invalid errorformat: {} in {}", what, pattern);
+    // %E, %G, ... or with a modifier %-G, %+E
+    let (drop, whole) = (pattern.starts_with("%-"), pattern.starts_with("%+"));
+    let letter_at = if drop || whole { 2 } else { 1 };
+    let letter = pattern.strip_prefix('%').and_then(|_| pattern[letter_at..].chars().next());
+    let prefix = match letter {
+        Some(c @ ('E' | 'W' | 'I' | 'N')) => Prefix::Start(Some(c)),
+        Some('A') => Prefix::Start(None),
+        Some('C') => Prefix::Continue,
+        Some('Z') => Prefix::End,
+        Some('G') => Prefix::General,
+        Some(c @ ('O' | 'P' | 'Q' | 'D' | 'X')) => return Err(invalid(&format!("unsupported %{}", c))),
+        _ if drop || whole => return Err(invalid("%- or %+ without a prefix")),
+        _ => Prefix::Single,
+    };
+    let rest = if prefix == Prefix::Single { pattern } else { &pattern[letter_at + 1..] };
+
+    let mut regex = String::from("^");
+    let mut fields = Vec::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            continue;
+        }
+        let Some(conv) = chars.next() else { return Err(invalid("trailing %")); };
+        let (group, field) = match conv {
+            'f' => ("(.+?)", Field::File),
+            'l' => (r"(\d+)", Field::Line),
+            'c' | 'v' => (r"(\d+)", Field::Col),
+            'n' => (r"(\d+)", Field::Skip),
+            'm' => ("(.*?)", Field::Message),
+            'r' | 's' => ("(.*?)", Field::Skip),
+            't' => ("(.)", Field::Type),
+            'p' => (r"([-. \t]*)", Field::Pointer),
+            '*' => {
+                // %*{conv}: matched and thrown away
+                match chars.next() {
+                    Some('[') => regex.push_str(&class(&mut chars).ok_or_else(|| invalid("unclosed %*["))?),
+                    Some('\\') => {
+                        regex.push('\\');
+                        regex.extend(chars.next());
+                    }
+                    Some(c) => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+                    None => return Err(invalid("trailing %*")),
+                }
+                regex.push('*');
+                continue;
+            }
+            '[' => {
+                regex.push_str(&class(&mut chars).ok_or_else(|| invalid("unclosed %["))?);
+                continue;
+            }
+            '.' => { regex.push('.'); continue; }
+            '#' => { regex.push('*'); continue; }
+            '^' => { regex.push_str(r"\^"); continue; }
+            '$' => { regex.push_str(r"\$"); continue; }
+            '%' => { regex.push('%'); continue; }
+            '\\' => {
+                // %\s and the like pass a regex escape through
+                regex.push('\\');
+                regex.extend(chars.next());
+                continue;
+            }
+            c => return Err(invalid(&format!("unknown %{}", c))),
+        };
+        regex.push_str(group);
+        fields.push(field);
+    }
+    regex.push('$');
+    let regex = Regex::new(&regex).map_err(|e| invalid(&e.to_string()))?;
+    Ok(Format { prefix, drop, whole, regex, fields })
+}
+
+/// a `[...]` character class, the `[` already read
+fn class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut out = String::from("[");
+    // a ] first in the class is one of its characters
+    if let Some(&c) = chars.peek().filter(|&&c| c == ']' || c == '^') {
+        out.push(c);
+        chars.next();
+        if c == '^' && chars.peek() == Some(&']') {
+            out.push_str(r"\]");
+            chars.next();
+        }
+    }
+    for c in chars.by_ref() {
+        if c == ']' {
+            out.push(']');
+            return Some(out);
+        }
+        if c == '[' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    None
+}
+
+/// the arguments of :vimgrep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VimgrepArgs {
+    pub pattern: String,
+    pub every: bool, // g: every match on a line, not just the first
+    pub jump: bool,  // without j: go to the first match
+    pub files: Vec<String>,
+}
+
+/// `/pattern/[g][j] file...`, any delimiter, or `pattern file...`
+pub fn parse_vimgrep(args: &str) -> Result<VimgrepArgs, String> {
+    let args = args.trim_start();
+    let (pattern, flags, files) = match args.chars().next() {
+        Some(delim) if substitute::is_delimiter(delim) && !delim.is_alphanumeric() => {
+            let (pattern, rest) = search::split_at_delimiter(&args[delim.len_utf8()..], delim);
+            let rest = rest.ok_or_else(|| format!("Missing closing {}", delim))?;
+            let (flags, files) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+            (pattern, flags, files)
+        }
+        _ => match args.split_once(char::is_whitespace) {
+            Some((pattern, files)) => (pattern.to_string(), "", files),
+            None => (args.to_string(), "", ""),
+        },
+    };
+    let (mut every, mut jump) = (false, true);
+    for c in flags.chars() {
+        match c {
+            'g' => every = true,
+            'j' => jump = false,
+            _ => return Err(format!("Unknown :vimgrep flag: {}", c)),
+        }
+    }
+    let files: Vec<String> = files.split_whitespace().map(str::to_string).collect();
+    if pattern.is_empty() || files.is_empty() {
+        return Err("Usage: :vimgrep /pattern/[g][j] file...".to_string());
+    }
+    Ok(VimgrepArgs { pattern, every, jump, files })
+}
+
+/// whether a :vimgrep file pattern names the file at `relative`: `*` and `?`
+/// stay within a directory unless the pattern starts with `**/`, which looks
+/// in every directory
+pub fn file_matches(pattern: &str, relative: &str) -> bool {
+    match pattern.strip_prefix("**/") {
+        Some(rest) => {
+            // the last as many path components as the rest of the pattern has
+            let depth = rest.matches('/').count() + 1;
+            let mut parts: Vec<&str> = relative.rsplit('/').take(depth).collect();
+            parts.reverse();
+            parts.len() == depth && glob_match(rest, &parts.join("/"))
+        }
+        None => glob_match(pattern, relative) && relative.matches('/').count() == pattern.matches('/').count(),
+    }
+}
+
+/// an entry for each line of `text` that `pattern` matches, or with `every`
+/// for each match
+pub fn vimgrep_entries(path: &Path, text: &str, pattern: &SearchPattern, every: bool) -> Vec<Entry> {
+    let rope = Rope::from_str(text);
+    let mut entries: Vec<Entry> = Vec::new();
+    for found in pattern.find_in_lines(&rope, 0..rope.len_lines()) {
+        let (line, col) = search::position(&rope, found.start);
+        if !every && entries.last().is_some_and(|e| e.line == line + 1) {
+            continue;
+        }
+        let text = rope.line(line).to_string();
+        entries.push(Entry {
+            path: Some(path.to_path_buf()),
+            line: line + 1,
+            col: col + 1,
+            kind: None,
+            text: text.trim_end_matches(['\n', '\r']).trim().to_string(),
+            valid: true,
+        });
+    }
+    entries
+}
+
+/// a language server's diagnostics for the file at `path`, in line order;
+/// `rope` is its text, for turning UTF-16 offsets into character columns
+pub fn diagnostic_entries(path: &Path, diagnostics: &[Diagnostic], rope: &Rope) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = diagnostics.iter()
+        .map(|d| Entry {
+            path: Some(path.to_path_buf()),
+            line: d.range.start.line as usize + 1,
+            col: match d.range.start.line as usize {
+                line if line < rope.len_lines() => {
+                    lsp::char_column(&rope.line(line).to_string(), d.range.start.character) + 1
+                }
+                _ => d.range.start.character as usize + 1,
+            },
+            kind: Some(match d.severity {
+                DiagnosticSeverity::Error => 'E',
+                DiagnosticSeverity::Warning => 'W',
+                DiagnosticSeverity::Information => 'I',
+                DiagnosticSeverity::Hint => 'N',
+            }),
+            text: d.message.lines().next().unwrap_or("").to_string(),
+            valid: true,
+        })
+        .collect();
+    entries.sort_by_key(|e| (e.line, e.col));
+    entries
+}
+
+/// what :cexpr reads entries from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Register(char),
+    System(String), // a shell command's output
+    Text(String),
+}
+
+/// `@r`, `system('cmd')`, a quoted string, or bare text
+pub fn parse_expr(expr: &str) -> Result<Expr, String> {
+    let expr = expr.trim();
+    if let Some(reg) = expr.strip_prefix('@') {
+        let mut chars = reg.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(r), None) => Ok(Expr::Register(r)),
+            _ => Err(format!("Invalid register: {}", expr)),
+        };
+    }
+    if let Some(inner) = expr.strip_prefix("system(").and_then(|e| e.strip_suffix(')')) {
+        return unquote(inner.trim()).map(Expr::System).ok_or_else(|| format!("Invalid expression: {}", expr));
+    }
+    if expr.is_empty() {
+        return Err("Argument required".to_string());
+    }
+    Ok(Expr::Text(unquote(expr).unwrap_or_else(|| expr.to_string())))
+}
+
+/// the text of a 'single' or "double" quoted string; \n and \t are read in
+/// double quotes and '' is a quote in single ones
+fn unquote(s: &str) -> Option<String> {
+    if s.len() >= 2 && s.starts_with('\'') && s.ends_with('\'') {
+        return Some(s[1..s.len() - 1].replace("''", "'"));
+    }
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        let mut out = String::new();
+        let mut chars = s[1..s.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(c) => out.push(c),
+                None => out.push('\\'),
+            }
+        }
+        return Some(out);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locations(entries: &[Entry]) -> Vec<(String, usize, usize, Option<char>, &str)> {
+        entries.iter()
+            .filter(|e| e.valid)
+            .map(|e| (e.path.as_ref().unwrap().display().to_string(), e.line, e.col, e.kind, e.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_errorformat() {
+        let output = "   Compiling demo v0.1.0 (/tmp/demo)
+error[E0425]: cannot find value `x` in this scope
+ --> src/main.rs:2:13
+  |
+2 |     let y = x + 1;
+  |             ^ not found in this scope
+
+warning: unused variable: `y`
+ --> src/lib.rs:10:9
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+warning: `demo` (bin \"demo\") generated 1 warning
+error: could not compile `demo` (bin \"demo\") due to 1 previous error
+thread 'tests::it_works' panicked at src/lib.rs:20:5:
+";
+        let entries = ErrorFormat::for_program("cargo build").parse(output);
+        assert_eq!(locations(&entries), vec![
+            ("src/main.rs".to_string(), 2, 13, Some('E'), "cannot find value `x` in this scope"),
+            ("src/lib.rs".to_string(), 10, 9, Some('W'), "unused variable: `y`"),
+            ("src/lib.rs".to_string(), 20, 5, Some('E'), "thread 'tests::it_works' panicked at src/lib.rs:20:5:"),
+        ]);
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    fn test_gcc_errorformat() {
+        let output = "main.c: In function 'main':
+main.c:3:5: error: 'x' undeclared (first use in this function)
+main.c:4:10: fatal error: foo.h: No such file or directory
+main.c:7:1: warning: control reaches end of non-void function
+util.c:12: undefined reference to `f'
+make: *** [Makefile:2: all] Error 1
+";
+        let entries = ErrorFormat::for_program("make").parse(output);
+        assert_eq!(locations(&entries), vec![
+            ("main.c".to_string(), 3, 5, Some('E'), "'x' undeclared (first use in this function)"),
+            ("main.c".to_string(), 4, 10, Some('E'), "foo.h: No such file or directory"),
+            ("main.c".to_string(), 7, 1, Some('W'), "control reaches end of non-void function"),
+            ("util.c".to_string(), 12, 0, None, "undefined reference to `f'"),
+        ]);
+    }
+
+    #[test]
+    fn test_go_and_pytest_errorformats() {
+        let go = "# example.com/demo\n./main.go:5:2: undefined: x\n--- FAIL: TestX (0.00s)\n    x_test.go:12: want 1, got 2\nFAIL\n";
+        assert_eq!(locations(&ErrorFormat::for_program("go build ./...").parse(go)), vec![
+            ("./main.go".to_string(), 5, 2, None, "undefined: x"),
+            ("x_test.go".to_string(), 12, 0, None, "want 1, got 2"),
+        ]);
+        let pytest = "    def test_a():\n>       assert 1 == 2\nE       assert 1 == 2\n\ntests/test_a.py:5: AssertionError\n";
+        assert_eq!(locations(&ErrorFormat::for_program("pytest -q").parse(pytest)), vec![
+            ("tests/test_a.py".to_string(), 5, 0, None, "AssertionError"),
+        ]);
+    }
+
+    #[test]
+    fn test_custom_errorformat() {
+        let efm = ErrorFormat::new(r"%A%f\, line %l:,%C%p^,%Z%m,%-G%.%#").unwrap();
+        let entries = efm.parse("a.py, line 3:\n    ^\nSyntaxError: bad\nother\n");
+        assert_eq!(locations(&entries), vec![("a.py".to_string(), 3, 5, None, "SyntaxError: bad")]);
+        // unmatched lines are kept as text
+        let entries = ErrorFormat::new("%f:%l: %m").unwrap().parse("a.rs:1: x\nnot a location\n");
+        assert_eq!(entries.len(), 2);
+        assert!(!entries[1].valid);
+        assert_eq!(entries[1].display(), "|| not a location");
+        assert_eq!(entries[0].display(), "a.rs|1| x");
+        assert!(ErrorFormat::new("%f:%q").is_err());
+        assert!(ErrorFormat::new("%Ofoo").is_err());
+        assert!(ErrorFormat::new("gcc").is_ok());
+    }
+
+    #[test]
+    fn test_quickfix_list_navigation() {
+        let mut entries = ErrorFormat::new("%f:%l: %m").unwrap().parse("a:1: x\nnoise\nb:2: y\nb:3: z\n");
+        entries[3].kind = Some('E');
+        let mut list = QuickfixList::new(":make", entries);
+        assert_eq!(list.position(), (1, 3));
+        assert_eq!(list.next(1).map(|e| e.line), Some(2));
+        assert_eq!(list.position(), (2, 3));
+        assert_eq!(list.next(5).map(|e| e.line), Some(3));
+        assert!(list.next(1).is_none());
+        assert_eq!(list.prev(2).map(|e| e.line), Some(1));
+        assert!(list.prev(1).is_none());
+        assert_eq!(list.select(2).map(|e| e.line), Some(2));
+        assert_eq!(list.select(0).map(|e| e.line), Some(3));
+        assert_eq!(list.current().unwrap().display(), "b|3 error| z");
+        assert_eq!(list.targets(false), vec![0, 2, 3]);
+        assert_eq!(list.targets(true), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_parse_vimgrep() {
+        let args = parse_vimgrep("/fn \\w\\+/gj src/*.rs %").unwrap();
+        assert_eq!(args.pattern, "fn \\w\\+");
+        assert!(args.every && !args.jump);
+        assert_eq!(args.files, vec!["src/*.rs", "%"]);
+        let args = parse_vimgrep("#a/b# **/*.rs").unwrap();
+        assert_eq!((args.pattern.as_str(), args.every, args.jump), ("a/b", false, true));
+        let args = parse_vimgrep("TODO *.md").unwrap();
+        assert_eq!((args.pattern.as_str(), args.files.len()), ("TODO", 1));
+        assert!(parse_vimgrep("/x/").is_err());
+        assert!(parse_vimgrep("/x/q a.rs").is_err());
+        assert!(parse_vimgrep("/x a.rs").is_err());
+    }
+
+    #[test]
+    fn test_file_matches() {
+        assert!(file_matches("*.rs", "main.rs"));
+        assert!(!file_matches("*.rs", "src/main.rs"));
+        assert!(file_matches("src/*.rs", "src/main.rs"));
+        assert!(!file_matches("src/*.rs", "src/editor/mod.rs"));
+        assert!(file_matches("**/*.rs", "main.rs"));
+        assert!(file_matches("**/*.rs", "src/editor/mod.rs"));
+        assert!(file_matches("**/editor/*.rs", "src/editor/mod.rs"));
+        assert!(!file_matches("**/editor/*.rs", "src/main.rs"));
+    }
+
+    #[test]
+    fn test_vimgrep_entries() {
+        let pattern = SearchPattern::new("foo", false, false).unwrap();
+        let text = "foo foo\n  bar\n\tfoo\n";
+        let entries = vimgrep_entries(Path::new("a.rs"), text, &pattern, false);
+        assert_eq!(locations(&entries), vec![
+            ("a.rs".to_string(), 1, 1, None, "foo foo"),
+            ("a.rs".to_string(), 3, 2, None, "foo"),
+        ]);
+        assert_eq!(vimgrep_entries(Path::new("a.rs"), text, &pattern, true).len(), 3);
+    }
+
+    #[test]
+    fn test_diagnostic_entries() {
+        use crate::lsp::{LspRange, Position};
+        let at = |line, character| LspRange { start: Position { line, character }, end: Position { line, character } };
+        let diagnostics = vec![
+            Diagnostic { range: at(9, 4), severity: DiagnosticSeverity::Warning, message: "unused".to_string() },
+            Diagnostic { range: at(1, 0), severity: DiagnosticSeverity::Error, message: "mismatched types\nexpected u8".to_string() },
+        ];
+        assert_eq!(locations(&diagnostic_entries(Path::new("a.rs"), &diagnostics, &Rope::new())), vec![
+            ("a.rs".to_string(), 2, 1, Some('E'), "mismatched types"),
+            ("a.rs".to_string(), 10, 5, Some('W'), "unused"),
+        ]);
+        // the emoji is two UTF-16 units but one character
+        let rope = Rope::from_str("let s = \"🦀\"; x\n");
+        let wide = vec![Diagnostic { range: at(0, 14), severity: DiagnosticSeverity::Hint, message: "x".to_string() }];
+        assert_eq!(diagnostic_entries(Path::new("a.rs"), &wide, &rope)[0].col, 14);
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        assert_eq!(parse_expr("@a"), Ok(Expr::Register('a')));
+        assert_eq!(parse_expr("system('cargo build')"), Ok(Expr::System("cargo build".to_string())));
+        assert_eq!(parse_expr("\"a.rs:1: x\\nb.rs:2: y\""), Ok(Expr::Text("a.rs:1: x\nb.rs:2: y".to_string())));
+        assert_eq!(parse_expr("a.rs:1: x"), Ok(Expr::Text("a.rs:1: x".to_string())));
+        assert!(parse_expr("@ab").is_err());
+        assert!(parse_expr("").is_err());
+    }
+}
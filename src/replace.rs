@@ -0,0 +1,302 @@
+// :Replace -- a substitute across the files of the project, planned up front so
+// every match can be looked over, and switched off, before anything changes.
+//
+// the preview lists each file and under it each match as the line before and
+// after; the plan keeps a checksum of the text it was made from so a file that
+// changed since is left alone rather than edited at stale offsets
+
+use ropey::Rope;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::search::{self, SearchPattern};
+use crate::substitute::{self, Edit};
+
+/// `/pattern/replacement/[flags]` after :Replace, any delimiter; the flags are
+/// i and I, over ignorecase and smartcase
+pub fn parse(args: &str) -> Result<(String, String, Option<bool>), String> {
+    let args = args.trim_start();
+    let delim = match args.chars().next() {
+        Some(c) if substitute::is_delimiter(c) => c,
+        _ => return Err("Usage: :Replace /pattern/replacement/[flags]".to_string()),
+    };
+    let (pattern, rest) = search::split_at_delimiter(&args[delim.len_utf8()..], delim);
+    let (replacement, rest) = match rest {
+        Some(rest) => search::split_at_delimiter(rest, delim),
+        None => (String::new(), None),
+    };
+    let mut ignore_case = None;
+    for c in rest.unwrap_or("").trim().chars() {
+        match c {
+            'i' => ignore_case = Some(true),
+            'I' => ignore_case = Some(false),
+            'g' => {} // every match is replaced anyway
+            _ => return Err(format!("Unknown :Replace flag: {}", c)),
+        }
+    }
+    Ok((pattern, replacement, ignore_case))
+}
+
+fn checksum(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// one match and what it turns into
+#[derive(Debug, Clone)]
+pub struct ReplaceMatch {
+    pub line: usize,    // 0-indexed line the match starts on
+    pub edit: Edit,     // bytes of the file's text
+    pub before: String, // the lines the match is on
+    pub after: String,  // the same lines once replaced
+    pub enabled: bool,
+}
+
+/// the matches in one file
+#[derive(Debug, Clone)]
+pub struct FileReplace {
+    pub path: PathBuf,
+    pub matches: Vec<ReplaceMatch>,
+    checksum: u64, // of the text the matches were found in
+}
+
+impl FileReplace {
+    /// every match of `pattern` in `text`, None when there is none
+    pub fn new(path: PathBuf, text: &str, pattern: &SearchPattern, replacement: &str) -> Option<Self> {
+        let rope = Rope::from_str(text);
+        let last = rope.len_lines().saturating_sub(1);
+        let matches: Vec<ReplaceMatch> = substitute::plan(&rope, pattern, replacement, 0, last, true)
+            .into_iter()
+            .map(|edit| {
+                let line = rope.byte_to_line(edit.start);
+                let end_line = rope.byte_to_line(edit.end.saturating_sub(1).max(edit.start));
+                let from = rope.line_to_byte(line);
+                let to = if end_line + 1 < rope.len_lines() { rope.line_to_byte(end_line + 1) } else { rope.len_bytes() };
+                let before = text[from..to].trim_end_matches(['\n', '\r']).to_string();
+                let after = format!("{}{}{}", &text[from..edit.start], edit.text, &text[edit.end..to]);
+                let after = after.trim_end_matches(['\n', '\r']).to_string();
+                ReplaceMatch { line, edit, before, after, enabled: true }
+            })
+            .collect();
+        if matches.is_empty() {
+            return None;
+        }
+        Some(Self { path, matches, checksum: checksum(text) })
+    }
+
+    /// whether `text` is still what the matches were found in
+    pub fn is_unchanged(&self, text: &str) -> bool {
+        checksum(text) == self.checksum
+    }
+
+    /// the replacements still switched on, in document order
+    pub fn enabled(&self) -> impl DoubleEndedIterator<Item = &Edit> {
+        self.matches.iter().filter(|m| m.enabled).map(|m| &m.edit)
+    }
+
+    /// `text` with the enabled replacements made
+    pub fn apply(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        for edit in self.enabled() {
+            out.push_str(&text[pos..edit.start]);
+            out.push_str(&edit.text);
+            pos = edit.end;
+        }
+        out.push_str(&text[pos..]);
+        out
+    }
+}
+
+/// what a row of the preview shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    Summary,
+    File(usize),
+    Match(usize, usize),
+    Other,
+}
+
+/// a planned :Replace and the layout of its preview
+#[derive(Debug, Clone)]
+pub struct ProjectReplace {
+    pub pattern: String,
+    pub replacement: String,
+    pub files: Vec<FileReplace>,
+    rows: Vec<Row>,
+}
+
+/// a line of the preview on one row, line breaks in a match shown as ⏎
+fn one_row(text: &str) -> String {
+    text.trim().replace('\n', "\u{23ce}")
+}
+
+impl ProjectReplace {
+    pub fn new(pattern: String, replacement: String, files: Vec<FileReplace>) -> Self {
+        let mut rows = vec![Row::Summary, Row::Other];
+        for (f, file) in files.iter().enumerate() {
+            rows.push(Row::File(f));
+            for m in 0..file.matches.len() {
+                rows.extend([Row::Match(f, m), Row::Match(f, m)]);
+            }
+            rows.push(Row::Other);
+        }
+        Self { pattern, replacement, files, rows }
+    }
+
+    /// (switched on, all) matches
+    pub fn counts(&self) -> (usize, usize) {
+        self.files.iter().flat_map(|f| &f.matches)
+            .fold((0, 0), |(on, all), m| (on + usize::from(m.enabled), all + 1))
+    }
+
+    /// the preview buffer's text, one row per entry of `rows`: a summary, then
+    /// each file's path and its matches as `[x] line: before` over `after`
+    pub fn preview(&self) -> String {
+        let (on, all) = self.counts();
+        let mut lines = vec![
+            format!(
+                "Replace /{}/{}/ -- {} of {} matches in {} files; Tab toggles, :w applies, :q cancels",
+                self.pattern, self.replacement, on, all, self.files.len()
+            ),
+            String::new(),
+        ];
+        for file in &self.files {
+            lines.push(file.path.display().to_string());
+            let width = file.matches.last().map_or(1, |m| (m.line + 1).to_string().len());
+            for m in &file.matches {
+                let mark = if m.enabled { 'x' } else { ' ' };
+                lines.push(format!("  [{}] {:>width$}: {}", mark, m.line + 1, one_row(&m.before)));
+                lines.push(format!("      {:>width$}  {}", "", one_row(&m.after)));
+            }
+            lines.push(String::new());
+        }
+        lines.join("\n")
+    }
+
+    /// switch the match shown on preview row `row`, every match of a file on its
+    /// path row, or every match on the summary row; when some are off they all
+    /// go on, else all off. False when the row shows no match
+    pub fn toggle(&mut self, row: usize) -> bool {
+        let targets: Vec<(usize, usize)> = match self.rows.get(row) {
+            Some(&Row::Match(f, m)) => vec![(f, m)],
+            Some(&Row::File(f)) => (0..self.files[f].matches.len()).map(|m| (f, m)).collect(),
+            Some(Row::Summary) => self.files.iter().enumerate()
+                .flat_map(|(f, file)| (0..file.matches.len()).map(move |m| (f, m)))
+                .collect(),
+            _ => return false,
+        };
+        let enable = targets.iter().any(|&(f, m)| !self.files[f].matches[m].enabled);
+        for (f, m) in targets {
+            self.files[f].matches[m].enabled = enable;
+        }
+        true
+    }
+}
+
+/// write `text` over `path` through a temporary file next to it that is
+/// renamed into place, so the file is never left half written; the file's
+/// permissions are kept, and a symlink is written through rather than replaced
+pub fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let path = &fs::canonicalize(path)?;
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let temp = path.with_file_name(format!(".{}.bitsy-replace", name.to_string_lossy()));
+    let permissions = fs::metadata(path)?.permissions();
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::set_permissions(&temp, permissions)?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn file(text: &str, pattern: &str, replacement: &str) -> FileReplace {
+        let pattern = SearchPattern::new(pattern, false, false).unwrap();
+        FileReplace::new(PathBuf::from("a.rs"), text, &pattern, replacement).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" /foo/bar/"), Ok(("foo".to_string(), "bar".to_string(), None)));
+        assert_eq!(parse(r"#a/b#c\#d#I"), Ok(("a/b".to_string(), "c#d".to_string(), Some(false))));
+        assert_eq!(parse("/gone"), Ok(("gone".to_string(), String::new(), None)));
+        assert!(parse("").is_err());
+        assert!(parse("/a/b/x").is_err());
+    }
+
+    #[test]
+    fn test_file_replace() {
+        let text = "let foo = foo();\nbar\n  foo\n";
+        let mut replace = file(text, r"\<foo\>", "baz");
+        assert_eq!(replace.matches.len(), 3);
+        assert_eq!(replace.matches[1].line, 0);
+        assert_eq!(replace.matches[2].before, "  foo");
+        assert_eq!(replace.matches[2].after, "  baz");
+        assert_eq!(replace.apply(text), "let baz = baz();\nbar\n  baz\n");
+        replace.matches[1].enabled = false;
+        assert_eq!(replace.apply(text), "let baz = foo();\nbar\n  baz\n");
+        assert!(replace.is_unchanged(text));
+        assert!(!replace.is_unchanged("let foo = 1;\n"));
+        let across = file("a\nb\n", r"a\nb", "ab");
+        assert_eq!((across.matches[0].before.as_str(), across.matches[0].after.as_str()), ("a\nb", "ab"));
+        assert!(FileReplace::new(PathBuf::from("a.rs"), "x\n", &SearchPattern::literal("y"), "z").is_none());
+    }
+
+    #[test]
+    fn test_preview_toggle() {
+        let mut plan = ProjectReplace::new("foo".to_string(), "bar".to_string(), vec![file("foo\nfoo\n", "foo", "bar")]);
+        let preview = plan.preview();
+        let rows: Vec<&str> = preview.lines().collect();
+        assert_eq!(&rows[2..5], ["a.rs", "  [x] 1: foo", "         bar"]);
+        assert!(plan.toggle(4));
+        assert_eq!(plan.counts(), (1, 2));
+        assert!(plan.preview().contains("  [ ] 1: foo"));
+        // a file row turns them all back on, then all off
+        assert!(plan.toggle(2));
+        assert_eq!(plan.counts(), (2, 2));
+        assert!(plan.toggle(0));
+        assert_eq!(plan.counts(), (0, 2));
+        assert!(!plan.toggle(1));
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = TestDir::new("replace");
+        let path = dir.join("f.txt");
+        fs::write(&path, "old\n").unwrap();
+        write_atomically(&path, "new\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomically_keeps_symlinks_and_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new("replace-link");
+        fs::create_dir_all(dir.join("real")).unwrap();
+        let target = dir.join("real/f.sh");
+        fs::write(&target, "old\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = dir.join("f.sh");
+        std::os::unix::fs::symlink("real/f.sh", &link).unwrap();
+        write_atomically(&link, "new\n").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(fs::read_dir(dir.join("real")).unwrap().count(), 1);
+    }
+}
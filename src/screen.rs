@@ -2,6 +2,8 @@
 
 use crossterm::style::Color;
 use std::io::{self, Write};
+use crate::term_color::{downsample, ColorSupport};
+use crate::theme::Style;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Cell {
@@ -9,14 +11,32 @@ pub struct Cell {
     pub fg: Color,
     pub bg: Color,
     pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub undercurl: bool,
 }
 
 impl Cell {
     pub fn new(ch: char, fg: Color, bg: Color) -> Self {
-        Self { ch, fg, bg, bold: false }
+        Self { ch, fg, bg, bold: false, italic: false, underline: false, undercurl: false }
     }
     pub fn blank(bg: Color) -> Self {
-        Self { ch: ' ', fg: Color::Reset, bg, bold: false }
+        Self::new(' ', Color::Reset, bg)
+    }
+    /// a cell drawn with `style`, using `fg`/`bg` where the style leaves colors unset
+    pub fn styled(ch: char, style: &Style, fg: Color, bg: Color) -> Self {
+        Self {
+            ch,
+            fg: style.fg.unwrap_or(fg),
+            bg: style.bg.unwrap_or(bg),
+            bold: style.bold,
+            italic: style.italic,
+            underline: style.underline,
+            undercurl: style.undercurl,
+        }
+    }
+    fn attrs(&self) -> (bool, bool, bool, bool) {
+        (self.bold, self.italic, self.underline, self.undercurl)
     }
 }
 
@@ -30,6 +50,7 @@ pub struct Screen {
     pub cells: Vec<Vec<Cell>>,
     prev: Vec<Vec<Cell>>,
     force_redraw: bool,
+    color_support: ColorSupport, // colors are downsampled to this on output
 }
 
 impl Screen {
@@ -37,7 +58,7 @@ impl Screen {
         let blank_row = || vec![Cell::default(); width];
         let cells: Vec<Vec<Cell>> = (0..height).map(|_| blank_row()).collect();
         let prev: Vec<Vec<Cell>> = (0..height).map(|_| blank_row()).collect();
-        Self { width, height, cells, prev, force_redraw: true }
+        Self { width, height, cells, prev, force_redraw: true, color_support: ColorSupport::TrueColor }
     }
 
     pub fn resize(&mut self, width: usize, height: usize) {
@@ -69,10 +90,14 @@ impl Screen {
 
     pub fn put_char_bold(&mut self, row: usize, col: usize, ch: char, fg: Color, bg: Color) {
         if row < self.height && col < self.width {
-            self.cells[row][col] = Cell { ch, fg, bg, bold: true };
+            self.cells[row][col] = Cell { bold: true, ..Cell::new(ch, fg, bg) };
         }
     }
 
+    pub fn put_char_styled(&mut self, row: usize, col: usize, ch: char, style: &Style, fg: Color, bg: Color) {
+        self.set(row, col, Cell::styled(ch, style, fg, bg));
+    }
+
     pub fn put_str(&mut self, row: usize, start_col: usize, text: &str, fg: Color, bg: Color) {
         for (i, ch) in text.chars().enumerate() {
             let col = start_col + i;
@@ -93,9 +118,10 @@ impl Screen {
     pub fn flush(&mut self) -> io::Result<()> {
         use crossterm::{cursor, queue, style};
         let stdout = &mut io::stdout();
-        let mut last_fg = Color::Reset;
-        let mut last_bg = Color::Reset;
-        let mut last_bold = false;
+        // None forces the next cell to re-emit its color
+        let mut last_fg: Option<Color> = None;
+        let mut last_bg: Option<Color> = None;
+        let mut last_attrs = (false, false, false, false);
         let mut need_move = true;
         let mut prev_row: usize = 0;
         let mut prev_col: usize = 0;
@@ -112,21 +138,28 @@ impl Screen {
                     queue!(stdout, cursor::MoveTo(col as u16, row as u16))?;
                     need_move = false;
                 }
-                if cell.fg != last_fg {
-                    queue!(stdout, style::SetForegroundColor(cell.fg))?;
-                    last_fg = cell.fg;
+                if cell.attrs() != last_attrs {
+                    // SGR reset clears colors too, so re-emit them afterwards
+                    queue!(stdout, style::SetAttribute(style::Attribute::Reset))?;
+                    last_fg = None;
+                    last_bg = None;
+                    let (bold, italic, underline, undercurl) = cell.attrs();
+                    if bold { queue!(stdout, style::SetAttribute(style::Attribute::Bold))?; }
+                    if italic { queue!(stdout, style::SetAttribute(style::Attribute::Italic))?; }
+                    if undercurl {
+                        queue!(stdout, style::SetAttribute(style::Attribute::Undercurled))?;
+                    } else if underline {
+                        queue!(stdout, style::SetAttribute(style::Attribute::Underlined))?;
+                    }
+                    last_attrs = cell.attrs();
                 }
-                if cell.bg != last_bg {
-                    queue!(stdout, style::SetBackgroundColor(cell.bg))?;
-                    last_bg = cell.bg;
+                if last_fg != Some(cell.fg) {
+                    queue!(stdout, style::SetForegroundColor(downsample(cell.fg, self.color_support)))?;
+                    last_fg = Some(cell.fg);
                 }
-                if cell.bold != last_bold {
-                    if cell.bold {
-                        queue!(stdout, style::SetAttribute(style::Attribute::Bold))?;
-                    } else {
-                        queue!(stdout, style::SetAttribute(style::Attribute::NoBold))?;
-                    }
-                    last_bold = cell.bold;
+                if last_bg != Some(cell.bg) {
+                    queue!(stdout, style::SetBackgroundColor(downsample(cell.bg, self.color_support)))?;
+                    last_bg = Some(cell.bg);
                 }
                 queue!(stdout, style::Print(cell.ch))?;
                 prev_row = row;
@@ -134,8 +167,8 @@ impl Screen {
             }
         }
         queue!(stdout, style::ResetColor)?;
-        if last_bold {
-            queue!(stdout, style::SetAttribute(style::Attribute::NoBold))?;
+        if last_attrs != (false, false, false, false) {
+            queue!(stdout, style::SetAttribute(style::Attribute::Reset))?;
         }
         stdout.flush()?;
 
@@ -150,4 +183,13 @@ impl Screen {
     }
 
     pub fn force_redraw(&mut self) { self.force_redraw = true; }
+
+    pub fn color_support(&self) -> ColorSupport { self.color_support }
+
+    pub fn set_color_support(&mut self, support: ColorSupport) {
+        if support != self.color_support {
+            self.color_support = support;
+            self.force_redraw = true;
+        }
+    }
 }
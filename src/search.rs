@@ -0,0 +1,689 @@
+// vim search patterns on the regex crate: magic syntax is translated, the case
+// options applied, and matches are found on a rope as byte ranges.
+//
+// `\v` very magic, `\m` magic (the default), `\M` nomagic and `\V` very nomagic
+// switch what needs a backslash; `\c`/`\C` anywhere force the case. Items that
+// reach past the end of a line -- `\n`, `\_s`, `\_.`, `\_[...]` -- make the
+// pattern search the whole text, otherwise it is matched line by line.
+
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+use std::ops::Range;
+use crate::user_command::is_keyword_char;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Magic {
+    Very,
+    On,
+    Off,
+    VeryOff,
+}
+
+/// a compiled search pattern
+#[derive(Debug, Clone)]
+pub struct SearchPattern {
+    regex: Regex,
+    multiline: bool, // some item matches a line break
+}
+
+/// a vim pattern as a regex crate pattern, whether it spans lines, and the case
+/// sensitivity `\c` (false) or `\C` (true) asked for
+fn translate(pattern: &str) -> Result<(String, bool, Option<bool>), String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = String::new();
+    let mut magic = Magic::On;
+    let mut multiline = false;
+    let mut case = None;
+    let mut i = 0;
+    // `^` anchors only at the start of a branch and `$` only at its end
+    let mut branch_start = true;
+    while i < chars.len() {
+        let c = chars[i];
+        let at_start = std::mem::replace(&mut branch_start, false);
+        let escaped = c == '\\' && i + 1 < chars.len();
+        let (item, special) = if escaped { (chars[i + 1], true) } else { (c, false) };
+        i += if escaped { 2 } else { 1 };
+        // characters whose meaning flips with the backslash under each magic level
+        let operator = |set: &str| set.contains(item);
+        let is = |very: &str, on: &str, off: &str| match magic {
+            Magic::Very => operator(very) != special,
+            Magic::On => operator(on) != special,
+            Magic::Off => operator(off) != special,
+            Magic::VeryOff => special && (operator(very) || operator(on) || operator(off)),
+        };
+        let ends_branch = |at: usize| {
+            let next = chars.get(at).copied();
+            let after = chars.get(at + 1).copied();
+            match magic {
+                Magic::Very => next.is_none() || matches!(next, Some('|' | ')')),
+                _ => next.is_none() || (next == Some('\\') && matches!(after, Some('|' | ')'))),
+            }
+        };
+        if special && matches!(item, 'v' | 'm' | 'M' | 'V') {
+            magic = match item { 'v' => Magic::Very, 'm' => Magic::On, 'M' => Magic::Off, _ => Magic::VeryOff };
+            branch_start = at_start;
+            continue;
+        }
+        if special && matches!(item, 'c' | 'C') {
+            case = Some(item == 'C');
+            branch_start = at_start;
+            continue;
+        }
+        if item == '^' && (special == (magic == Magic::VeryOff)) && at_start {
+            out.push('^');
+            branch_start = true;
+            continue;
+        }
+        if item == '$' && (special == (magic == Magic::VeryOff)) && ends_branch(i) {
+            out.push('$');
+            continue;
+        }
+        if is("(", "", "") && item == '(' {
+            out.push('(');
+            branch_start = true;
+        } else if is(")", "", "") && item == ')' {
+            out.push(')');
+        } else if is("|", "", "") && item == '|' {
+            out.push('|');
+            branch_start = true;
+        } else if is("+?=", "", "") && matches!(item, '+' | '?' | '=') {
+            out.push(if item == '=' { '?' } else { item });
+        } else if is("<>", "", "") && matches!(item, '<' | '>') {
+            out.push_str(if item == '<' { r"\b{start}" } else { r"\b{end}" });
+        } else if is("{", "", "") && item == '{' {
+            // \{n,m} counts, \{-n,m} the fewest
+            let close = chars[i..].iter().position(|&c| c == '}').ok_or("unmatched \\{")?;
+            let body: String = chars[i..i + close].iter().collect();
+            i += close + 1;
+            let body = body.strip_suffix('\\').unwrap_or(&body);
+            let (lazy, counts) = match body.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, body),
+            };
+            if !counts.chars().all(|c| c.is_ascii_digit() || c == ',') {
+                return Err(format!("invalid count \\{{{}}}", body));
+            }
+            match counts {
+                "" | "," => out.push('*'),
+                _ if counts.starts_with(',') => out.push_str(&format!("{{0{}}}", counts)),
+                _ => out.push_str(&format!("{{{}}}", counts)),
+            }
+            if lazy {
+                out.push('?');
+            }
+        } else if is(".*[~", ".*[~", "") && item == '.' {
+            out.push('.');
+        } else if is(".*[~", ".*[~", "") && item == '*' {
+            out.push('*');
+        } else if is(".*[~", ".*[~", "") && item == '[' {
+            match bracket(&chars, i) {
+                Some((class, end)) => {
+                    out.push_str(&class);
+                    i = end;
+                }
+                None => out.push_str(r"\["),
+            }
+        } else if special {
+            match item {
+                'n' => {
+                    out.push('\n');
+                    multiline = true;
+                }
+                't' => out.push('\t'),
+                'e' => out.push_str(r"\x1b"),
+                'r' => out.push('\r'),
+                's' => out.push_str(r"[ \t]"),
+                'S' => out.push_str(r"[^ \t\n]"),
+                'd' | 'D' | 'w' | 'W' => out.push_str(&format!("\\{}", item)),
+                'a' => out.push_str("[A-Za-z]"),
+                'A' => out.push_str("[^A-Za-z]"),
+                'l' => out.push_str("[a-z]"),
+                'L' => out.push_str("[^a-z]"),
+                'u' => out.push_str("[A-Z]"),
+                'U' => out.push_str("[^A-Z]"),
+                'x' => out.push_str("[0-9A-Fa-f]"),
+                'X' => out.push_str("[^0-9A-Fa-f]"),
+                'h' => out.push_str("[A-Za-z_]"),
+                'k' | 'i' => out.push_str(r"\w"),
+                'f' => out.push_str(r"\S"),
+                'p' => out.push_str(r"[^\x00-\x1f]"),
+                '_' => {
+                    let Some(&next) = chars.get(i) else { return Err("trailing \\_".to_string()); };
+                    i += 1;
+                    multiline = true;
+                    match next {
+                        '.' => out.push_str("(?s:.)"),
+                        '[' => match bracket(&chars, i) {
+                            Some((class, end)) => {
+                                out.push_str(&format!(r"(?:\n|{})", class));
+                                i = end;
+                            }
+                            None => return Err("unmatched \\_[".to_string()),
+                        },
+                        's' => out.push_str(r"\s"),
+                        _ => return Err(format!("unsupported item \\_{}", next)),
+                    }
+                }
+                '1'..='9' => return Err("back-references are not supported in search patterns".to_string()),
+                'z' | '%' | '@' | '&' => return Err(format!("unsupported item \\{}", item)),
+                _ => out.push_str(&regex::escape(&item.to_string())),
+            }
+        } else {
+            out.push_str(&regex::escape(&item.to_string()));
+        }
+    }
+    Ok((out, multiline, case))
+}
+
+/// a `[...]` collection starting after the `[` at `chars[start - 1]`, as a regex
+/// class and the index after its `]`; None if it is not closed
+fn bracket(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut out = String::from("[");
+    let mut i = start;
+    if chars.get(i) == Some(&'^') {
+        out.push('^');
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        out.push_str(r"\]");
+        i += 1;
+    }
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ']' => return Some((out + "]", i + 1)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let close = chars[i..].windows(2).position(|w| w == [':', ']'])?;
+                out.extend(&chars[i..i + close + 2]);
+                i += close + 2;
+                continue;
+            }
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some(&e @ ('\\' | ']' | '^' | '-')) => out.push_str(&format!("\\{}", e)),
+                    Some('n') => out.push_str(r"\n"),
+                    Some('t') => out.push_str(r"\t"),
+                    Some('e') => out.push_str(r"\x1b"),
+                    _ => {
+                        out.push_str(r"\\");
+                        i += 1;
+                        continue;
+                    }
+                }
+                i += 2;
+                continue;
+            }
+            '[' | '&' | '~' => out.push_str(&format!("\\{}", c)),
+            _ => out.push(c),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// whether a pattern has an upper case letter outside of a backslash item
+fn has_upper(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+impl SearchPattern {
+    /// compile a vim pattern; `ignore_case` applies unless `smart_case` and the
+    /// pattern has capitals, and `\c`/`\C` override both
+    pub fn new(pattern: &str, ignore_case: bool, smart_case: bool) -> Result<Self, String> {
+        let (source, multiline, case) = translate(pattern)?;
+        let sensitive = case.unwrap_or(!ignore_case || (smart_case && has_upper(pattern)));
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(!sensitive)
+            .multi_line(true)
+            .build()
+            .map_err(|e| match e {
+                regex::Error::Syntax(msg) => msg.lines().last().unwrap_or("invalid pattern").trim_start_matches("error: ").to_string(),
+                other => other.to_string(),
+            })?;
+        Ok(Self { regex, multiline })
+    }
+
+    /// a pattern matching `text` literally
+    pub fn literal(text: &str) -> Self {
+        Self {
+            regex: Regex::new(&regex::escape(text)).expect("escaped text is a valid regex"),
+            multiline: text.contains('\n'),
+        }
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// whether matches can run past the end of a line
+    pub fn is_multiline(&self) -> bool {
+        self.multiline
+    }
+
+    /// matches in the text of `lines`, as byte ranges of the rope
+    pub fn find_in_lines(&self, rope: &Rope, lines: Range<usize>) -> Vec<Range<usize>> {
+        let end = lines.end.min(rope.len_lines());
+        let start = lines.start.min(end);
+        if self.multiline {
+            let offset = rope.line_to_byte(start);
+            let text = rope.byte_slice(offset..rope.line_to_byte(end)).to_string();
+            return self.regex.find_iter(&text).map(|m| offset + m.start()..offset + m.end()).collect();
+        }
+        (start..end).flat_map(|line| self.line_matches(rope, line)).collect()
+    }
+
+    /// matches on one line, the line break left out
+    fn line_matches(&self, rope: &Rope, line: usize) -> Vec<Range<usize>> {
+        let offset = rope.line_to_byte(line);
+        let text = rope.line(line).to_string();
+        let text = text.trim_end_matches(['\n', '\r']);
+        self.regex.find_iter(text).map(|m| offset + m.start()..offset + m.end()).collect()
+    }
+
+    /// the first match starting at or after byte `from`, then from the top; the
+    /// flag tells whether the search wrapped around
+    pub fn find_forward(&self, rope: &Rope, from: usize) -> Option<(Range<usize>, bool)> {
+        if self.multiline {
+            let all = self.find_in_lines(rope, 0..rope.len_lines());
+            return all.iter().find(|m| m.start >= from).map(|m| (m.clone(), false))
+                .or_else(|| all.first().map(|m| (m.clone(), true)));
+        }
+        let first_line = rope.byte_to_line(from.min(rope.len_bytes()));
+        for line in first_line..rope.len_lines() {
+            if let Some(m) = self.line_matches(rope, line).into_iter().find(|m| m.start >= from) {
+                return Some((m, false));
+            }
+        }
+        (0..=first_line).find_map(|line| self.line_matches(rope, line).into_iter().next()).map(|m| (m, true))
+    }
+
+    /// the last match starting before byte `before`, then from the bottom; the
+    /// flag tells whether the search wrapped around
+    pub fn find_backward(&self, rope: &Rope, before: usize) -> Option<(Range<usize>, bool)> {
+        if self.multiline {
+            let all = self.find_in_lines(rope, 0..rope.len_lines());
+            return all.iter().rev().find(|m| m.start < before).map(|m| (m.clone(), false))
+                .or_else(|| all.last().map(|m| (m.clone(), true)));
+        }
+        let first_line = rope.byte_to_line(before.min(rope.len_bytes()));
+        for line in (0..=first_line).rev() {
+            if let Some(m) = self.line_matches(rope, line).into_iter().rev().find(|m| m.start < before) {
+                return Some((m, false));
+            }
+        }
+        (first_line..rope.len_lines()).rev()
+            .find_map(|line| self.line_matches(rope, line).into_iter().last())
+            .map(|m| (m, true))
+    }
+
+    /// which match, counting from 1, is the last one starting at or before byte
+    /// `at`, and how many matches there are in all
+    pub fn match_index(&self, rope: &Rope, at: usize) -> (usize, usize) {
+        let all = self.find_in_lines(rope, 0..rope.len_lines());
+        (all.partition_point(|m| m.start <= at), all.len())
+    }
+}
+
+/// where every match of the last pattern counted starts, kept until the pattern
+/// or the buffer changes so that n and N do not search the whole buffer again
+#[derive(Debug, Clone, Default)]
+pub struct MatchCache {
+    key: Option<(String, (u64, u64))>, // regex, buffer id and version
+    starts: Vec<usize>,
+}
+
+impl MatchCache {
+    /// `SearchPattern::match_index` for the buffer `source` (id and version)
+    pub fn match_index(&mut self, pattern: &SearchPattern, rope: &Rope, source: (u64, u64), at: usize) -> (usize, usize) {
+        let fresh = self.key.as_ref().is_some_and(|(regex, key)| *key == source && regex == pattern.regex.as_str());
+        if !fresh {
+            self.starts = pattern.find_in_lines(rope, 0..rope.len_lines()).into_iter().map(|m| m.start).collect();
+            self.key = Some((pattern.regex.as_str().to_string(), source));
+        }
+        (self.starts.partition_point(|&start| start <= at), self.starts.len())
+    }
+}
+
+/// (line, char column) of a byte offset
+pub fn position(rope: &Rope, byte: usize) -> (usize, usize) {
+    let byte = byte.min(rope.len_bytes());
+    let line = rope.byte_to_line(byte);
+    (line, rope.byte_to_char(byte) - rope.line_to_char(line))
+}
+
+/// byte offset of a (line, char column) position, clamped to the line
+pub fn byte_at(rope: &Rope, line: usize, col: usize) -> usize {
+    if line >= rope.len_lines() {
+        return rope.len_bytes();
+    }
+    let line_start = rope.line_to_char(line);
+    let line_end = rope.line_to_char(line + 1);
+    rope.char_to_byte((line_start + col).min(line_end))
+}
+
+/// char column ranges on `line` covered by byte ranges `matches`, clipped to
+/// the line; empty matches cover nothing
+pub fn line_spans(rope: &Rope, matches: &[Range<usize>], line: usize) -> Vec<(usize, usize)> {
+    if line >= rope.len_lines() {
+        return Vec::new();
+    }
+    let (start, end) = (rope.line_to_byte(line), rope.line_to_byte(line + 1));
+    let first = matches.partition_point(|m| m.end <= start);
+    matches[first..].iter()
+        .take_while(|m| m.start < end)
+        .filter(|m| !m.is_empty())
+        .map(|m| (position(rope, m.start.max(start)).1, rope.byte_to_char(m.end.min(end)) - rope.line_to_char(line)))
+        .collect()
+}
+
+/// the text up to an unescaped `delim` and what follows it, None when the text
+/// runs out first; `\delim` stands for the delimiter itself
+pub fn split_at_delimiter(text: &str, delim: char) -> (String, Option<&str>) {
+    let mut out = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delim {
+            return (out, Some(&text[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delim => out.push(next),
+                Some((_, next)) => {
+                    out.push('\\');
+                    out.push(next);
+                }
+                None => out.push('\\'),
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    (out, None)
+}
+
+/// where a search puts the cursor relative to the match it found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchOffset {
+    #[default]
+    None,
+    Lines(isize), // /pat/+N: N lines down, first column
+    End(isize),   // /pat/e+N: N chars from the last char of the match
+    Start(isize), // /pat/s+N or b+N: N chars from its first char
+}
+
+impl SearchOffset {
+    /// the (line, char column) to land on for match `found`
+    pub fn land(self, rope: &Rope, found: &Range<usize>) -> (usize, usize) {
+        let last_char = rope.len_chars().saturating_sub(1);
+        let move_chars = |byte: usize, n: isize| {
+            let at = rope.byte_to_char(byte.min(rope.len_bytes())).saturating_add_signed(n).min(last_char);
+            position(rope, rope.char_to_byte(at))
+        };
+        match self {
+            SearchOffset::None => position(rope, found.start),
+            SearchOffset::Lines(n) => {
+                let line = rope.byte_to_line(found.start.min(rope.len_bytes()));
+                (line.saturating_add_signed(n).min(rope.len_lines().saturating_sub(1)), 0)
+            }
+            SearchOffset::Start(n) => move_chars(found.start, n),
+            SearchOffset::End(n) => {
+                let end = if found.is_empty() { found.start } else { rope.char_to_byte(rope.byte_to_char(found.end) - 1) };
+                move_chars(end, n)
+            }
+        }
+    }
+}
+
+/// `[+-N]`, `e[+-N]`, `s[+-N]` or `b[+-N]`; a sign alone counts one
+fn parse_offset(text: &str) -> Result<SearchOffset, String> {
+    let invalid = || format!("Invalid search offset: {}", text);
+    let (kind, number) = match text.chars().next() {
+        None => return Ok(SearchOffset::None),
+        Some(c @ ('e' | 's' | 'b')) => (Some(c), &text[1..]),
+        Some(_) => (None, text),
+    };
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, number.strip_prefix('+').unwrap_or(number)),
+    };
+    let n = match digits {
+        "" if number.is_empty() => 0,
+        "" => 1,
+        _ if digits.chars().all(|c| c.is_ascii_digit()) => digits.parse::<isize>().map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    Ok(match kind {
+        Some('e') => SearchOffset::End(sign * n),
+        Some(_) => SearchOffset::Start(sign * n),
+        None => SearchOffset::Lines(sign * n),
+    })
+}
+
+/// what was typed at a `/` or `?` prompt: the pattern, up to an unescaped
+/// `delim`, and the offset after it
+pub fn parse_search(input: &str, delim: char) -> Result<(String, SearchOffset), String> {
+    let (pattern, rest) = split_at_delimiter(input, delim);
+    Ok((pattern, parse_offset(rest.unwrap_or(""))?))
+}
+
+/// searches typed at the prompt, oldest first, and the place Up/Down have
+/// reached in them; only entries starting with what was typed before the first
+/// Up are visited
+#[derive(Debug, Clone, Default)]
+pub struct SearchHistory {
+    entries: Vec<String>,
+    index: Option<usize>,
+    prefix: String,
+}
+
+impl SearchHistory {
+    /// remember a search, moving an earlier copy of it to the end
+    pub fn add(&mut self, entry: &str) {
+        if !entry.is_empty() {
+            self.entries.retain(|e| e != entry);
+            self.entries.push(entry.to_string());
+        }
+        self.reset();
+    }
+
+    /// stop browsing, so the next Up starts from the newest entry
+    pub fn reset(&mut self) {
+        self.index = None;
+        self.prefix.clear();
+    }
+
+    /// the next older entry, `typed` being the prompt text when browsing starts
+    pub fn older(&mut self, typed: &str) -> Option<String> {
+        if self.index.is_none() {
+            self.prefix = typed.to_string();
+        }
+        let end = self.index.unwrap_or(self.entries.len());
+        let i = self.entries[..end].iter().rposition(|e| e.starts_with(&self.prefix))?;
+        self.index = Some(i);
+        Some(self.entries[i].clone())
+    }
+
+    /// the next newer entry, and past the newest the text browsing started from
+    pub fn newer(&mut self) -> Option<String> {
+        let start = self.index? + 1;
+        match self.entries[start..].iter().position(|e| e.starts_with(&self.prefix)) {
+            Some(i) => {
+                self.index = Some(start + i);
+                Some(self.entries[start + i].clone())
+            }
+            None => {
+                self.index = None;
+                Some(std::mem::take(&mut self.prefix))
+            }
+        }
+    }
+}
+
+/// the keyword under char column `col` of `line`, or the next one after it
+pub fn word_at(line: &str, col: usize) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let start = (col..chars.len()).find(|&i| is_keyword_char(chars[i]))?;
+    let start = (0..start).rev().take_while(|&i| is_keyword_char(chars[i])).last().unwrap_or(start);
+    Some(chars[start..].iter().take_while(|&&c| is_keyword_char(c)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(pattern: &str) -> String {
+        translate(pattern).unwrap().0
+    }
+
+    fn found(pattern: &str, text: &str) -> Vec<String> {
+        let rope = Rope::from_str(text);
+        let p = SearchPattern::new(pattern, false, false).unwrap();
+        p.find_in_lines(&rope, 0..rope.len_lines()).into_iter().map(|m| text[m].to_string()).collect()
+    }
+
+    #[test]
+    fn test_translate_magic() {
+        assert_eq!(regex(r"foo\d+"), r"foo\d\+");
+        assert_eq!(regex(r"\<is\>"), r"\b{start}is\b{end}");
+        assert_eq!(regex(r"\(a\|b\)\+"), "(a|b)+");
+        assert_eq!(regex("a(b)+?"), r"a\(b\)\+\?");
+        assert_eq!(regex(r"\v(a|b)+<x>"), r"(a|b)+\b{start}x\b{end}");
+        assert_eq!(regex(r"x\{2,3}y\{-}z\{,4}"), "x{2,3}y*?z{0,4}");
+        assert_eq!(regex(r"\V.*\.\*"), r"\.\*.*");
+        assert_eq!(regex(r"\M.*\."), r"\.\*.");
+        assert_eq!(regex("^a^b$c$"), r"^a\^b\$c$");
+        assert_eq!(regex(r"[^a-z\]]"), r"[^a-z\]]");
+        assert_eq!(regex("[x"), r"\[x");
+        assert!(translate(r"\(a\)\1").is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(found(r"foo\d\+", "foo12 foo foo3\n"), vec!["foo12", "foo3"]);
+        assert_eq!(found(r"\<in\>", "in inner pin in\n"), vec!["in", "in"]);
+        assert_eq!(found(r"^\s*fn", "fn a\n  fn b\nx fn\n"), vec!["fn", "  fn"]);
+        assert_eq!(found("end$", "end end\nend\n"), vec!["end", "end"]);
+        // across line breaks
+        assert_eq!(found(r"a\nb", "xa\nb\na\nc\n"), vec!["a\nb"]);
+        assert_eq!(found(r"{\_s*}", "f() {\n\n}\ng() {}\n"), vec!["{\n\n}", "{}"]);
+    }
+
+    #[test]
+    fn test_case() {
+        let matches = |pattern: &str, ic, scs| {
+            let rope = Rope::from_str("Foo foo FOO\n");
+            SearchPattern::new(pattern, ic, scs).unwrap().find_in_lines(&rope, 0..1).len()
+        };
+        assert_eq!(matches("foo", false, false), 1);
+        assert_eq!(matches("foo", true, false), 3);
+        assert_eq!(matches("Foo", true, true), 1);
+        assert_eq!(matches("foo", true, true), 3);
+        assert_eq!(matches(r"foo\C", true, false), 1);
+        assert_eq!(matches(r"\cFOO", false, false), 3);
+        // a backslash item is not a capital
+        assert_eq!(matches(r"\Vfoo", true, true), 3);
+    }
+
+    #[test]
+    fn test_forward_backward_chars() {
+        let text = "héllo wörld\nwörld\n";
+        let rope = Rope::from_str(text);
+        let p = SearchPattern::new("wörld", false, false).unwrap();
+        let (m, wrapped) = p.find_forward(&rope, byte_at(&rope, 0, 1)).unwrap();
+        assert_eq!((position(&rope, m.start), wrapped), ((0, 6), false));
+        let (m, wrapped) = p.find_forward(&rope, byte_at(&rope, 1, 1)).unwrap();
+        assert_eq!((position(&rope, m.start), wrapped), ((0, 6), true));
+        let (m, wrapped) = p.find_backward(&rope, byte_at(&rope, 0, 6)).unwrap();
+        assert_eq!((position(&rope, m.start), wrapped), ((1, 0), true));
+        let all = p.find_in_lines(&rope, 0..2);
+        assert_eq!(line_spans(&rope, &all, 0), vec![(6, 11)]);
+        let multi = SearchPattern::new(r"d\nw", false, false).unwrap();
+        let all = multi.find_in_lines(&rope, 0..2);
+        assert_eq!((line_spans(&rope, &all, 0), line_spans(&rope, &all, 1)), (vec![(10, 12)], vec![(0, 1)]));
+    }
+
+    #[test]
+    fn test_match_index() {
+        let rope = Rope::from_str("a foo\nfoo foo\nbar\n");
+        let p = SearchPattern::new("foo", false, false).unwrap();
+        assert_eq!(p.match_index(&rope, byte_at(&rope, 0, 2)), (1, 3));
+        assert_eq!(p.match_index(&rope, byte_at(&rope, 1, 4)), (3, 3));
+        assert_eq!(p.match_index(&rope, 0), (0, 3));
+
+        let mut cache = MatchCache::default();
+        assert_eq!(cache.match_index(&p, &rope, (1, 0), byte_at(&rope, 1, 4)), (3, 3));
+        // the same buffer version answers from the cache, even for other text
+        assert_eq!(cache.match_index(&p, &Rope::new(), (1, 0), 0), (0, 3));
+        assert_eq!(cache.match_index(&p, &Rope::new(), (1, 1), 0), (0, 0));
+        let bar = SearchPattern::new("bar", false, false).unwrap();
+        assert_eq!(cache.match_index(&bar, &rope, (1, 1), rope.len_bytes()), (1, 1));
+    }
+
+    #[test]
+    fn test_parse_search() {
+        let parsed = |input: &str| parse_search(input, '/');
+        assert_eq!(parsed("foo"), Ok(("foo".to_string(), SearchOffset::None)));
+        assert_eq!(parsed("foo/"), Ok(("foo".to_string(), SearchOffset::None)));
+        assert_eq!(parsed("foo/e"), Ok(("foo".to_string(), SearchOffset::End(0))));
+        assert_eq!(parsed("foo/e+1"), Ok(("foo".to_string(), SearchOffset::End(1))));
+        assert_eq!(parsed("foo/s-2"), Ok(("foo".to_string(), SearchOffset::Start(-2))));
+        assert_eq!(parsed("foo/b"), Ok(("foo".to_string(), SearchOffset::Start(0))));
+        assert_eq!(parsed("foo/+3"), Ok(("foo".to_string(), SearchOffset::Lines(3))));
+        assert_eq!(parsed("foo/-"), Ok(("foo".to_string(), SearchOffset::Lines(-1))));
+        assert_eq!(parsed("foo/2"), Ok(("foo".to_string(), SearchOffset::Lines(2))));
+        assert_eq!(parsed(r"a\/b/e"), Ok(("a/b".to_string(), SearchOffset::End(0))));
+        assert_eq!(parsed("/e"), Ok((String::new(), SearchOffset::End(0))));
+        assert_eq!(parse_search("bar?b", '?'), Ok(("bar".to_string(), SearchOffset::Start(0))));
+        assert!(parsed("foo/x").is_err());
+        assert!(parsed("foo/e+x").is_err());
+    }
+
+    #[test]
+    fn test_offset_land() {
+        let rope = Rope::from_str("one\n  foo bar\nthree\n");
+        let found = byte_at(&rope, 1, 2)..byte_at(&rope, 1, 5);
+        assert_eq!(SearchOffset::None.land(&rope, &found), (1, 2));
+        assert_eq!(SearchOffset::End(0).land(&rope, &found), (1, 4));
+        assert_eq!(SearchOffset::End(2).land(&rope, &found), (1, 6));
+        assert_eq!(SearchOffset::Start(-2).land(&rope, &found), (1, 0));
+        assert_eq!(SearchOffset::Start(-3).land(&rope, &found), (0, 3));
+        assert_eq!(SearchOffset::Lines(1).land(&rope, &found), (2, 0));
+        assert_eq!(SearchOffset::Lines(-5).land(&rope, &found), (0, 0));
+        assert_eq!(SearchOffset::Lines(9).land(&rope, &found), (3, 0));
+    }
+
+    #[test]
+    fn test_search_history() {
+        let mut history = SearchHistory::default();
+        for entry in ["foo", "bar", "fob", "baz", "foo"] {
+            history.add(entry);
+        }
+        // foo moved to the end
+        assert_eq!(history.older(""), Some("foo".to_string()));
+        assert_eq!(history.older(""), Some("baz".to_string()));
+        assert_eq!(history.newer(), Some("foo".to_string()));
+        assert_eq!(history.newer(), Some(String::new()));
+        assert_eq!(history.newer(), None);
+        // only what starts with the typed text
+        assert_eq!(history.older("fo"), Some("foo".to_string()));
+        assert_eq!(history.older("foo"), Some("fob".to_string()));
+        assert_eq!(history.older("fob"), None);
+        assert_eq!(history.newer(), Some("foo".to_string()));
+        assert_eq!(history.newer(), Some("fo".to_string()));
+    }
+
+    #[test]
+    fn test_word_at() {
+        assert_eq!(word_at("let my_var = 1;", 5), Some("my_var".to_string()));
+        assert_eq!(word_at("let my_var = 1;", 10), Some("1".to_string()));
+        assert_eq!(word_at("let my_var = 1;", 14), None);
+    }
+}
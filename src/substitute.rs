@@ -0,0 +1,354 @@
+// :substitute -- parsing `:s{delim}pattern{delim}replacement{delim}[flags] [count]`
+// and its repeat forms, expanding vim replacement strings against regex
+// captures, and working out the edits a substitute makes.
+//
+// replacement items: & and \0 (the match), \1..\9 (groups), ~ (the previous
+// replacement), \u \l (next character upper/lower case), \U \L ... \E (until
+// \E), \r \n and a literal CR (line break), \t, and \ before anything else
+// for the character itself
+
+use regex::Captures;
+use ropey::Rope;
+use crate::search::{self, SearchPattern};
+
+/// options a substitute runs with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub global: bool,              // g: every match on a line, not the first
+    pub ignore_case: Option<bool>, // i / I: over ignorecase and smartcase
+    pub count_only: bool,          // n: report the number of matches, change nothing
+    pub quiet: bool,               // e: no error when nothing matches
+    pub confirm: bool,             // c: ask before each replacement
+}
+
+impl Flags {
+    /// `base` with the flag letters applied; `&` (keep the previous flags) is
+    /// handled by passing those as `base`
+    pub fn apply(mut self, letters: &str) -> Flags {
+        for c in letters.chars() {
+            match c {
+                'g' => self.global = !self.global,
+                'i' => self.ignore_case = Some(true),
+                'I' => self.ignore_case = Some(false),
+                'n' => self.count_only = true,
+                'e' => self.quiet = true,
+                'c' => self.confirm = true,
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+/// a parsed :s, :&, :&& or :~
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstituteSpec {
+    pub pattern: Option<String>,     // None: the previous substitute's; empty: the last search pattern
+    pub replacement: Option<String>, // None: the previous substitute's
+    pub flags: String,               // flag letters, a leading & keeps the previous flags
+    pub count: Option<usize>,        // lines from the last line of the range
+}
+
+impl SubstituteSpec {
+    pub fn keeps_flags(&self) -> bool {
+        self.flags.starts_with('&')
+    }
+}
+
+/// the last substitute, for the repeat forms and `~`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: Flags,
+}
+
+/// characters that can delimit a pattern: anything but letters, digits,
+/// whitespace, `\`, `"` and `|`
+pub fn is_delimiter(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|')
+}
+
+/// `[&][flags] [count]` after the pattern and replacement
+fn parse_flags(text: &str) -> Result<(String, Option<usize>), String> {
+    let letters: String = text.chars().take_while(|c| !c.is_whitespace() && !c.is_ascii_digit()).collect();
+    for (i, c) in letters.chars().enumerate() {
+        match c {
+            '&' if i == 0 => {}
+            'g' | 'c' | 'i' | 'I' | 'n' | 'e' => {}
+            '&' => return Err("& must be the first substitute flag".to_string()),
+            _ => return Err(format!("Unknown substitute flag: {}", c)),
+        }
+    }
+    let rest = text[letters.len()..].trim();
+    let count = match rest {
+        "" => None,
+        _ => match rest.parse::<usize>() {
+            Ok(0) => return Err("Count must be positive".to_string()),
+            Ok(n) => Some(n),
+            Err(_) => return Err(format!("Trailing characters: {}", rest)),
+        },
+    };
+    Ok((letters, count))
+}
+
+/// a substitute command after its range: `s/a/b/g`, `s#a#b#`, `s`, `substitute`,
+/// `&`, `&&` or `~`; None when `command` is some other command
+pub fn parse(command: &str) -> Option<Result<SubstituteSpec, String>> {
+    let repeat = |pattern: Option<String>, rest: &str| {
+        parse_flags(rest).map(|(flags, count)| SubstituteSpec { pattern, replacement: None, flags, count })
+    };
+    if let Some(rest) = command.strip_prefix('&') {
+        return Some(repeat(None, rest));
+    }
+    if let Some(rest) = command.strip_prefix('~') {
+        return Some(repeat(Some(String::new()), rest));
+    }
+    let name_end = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    if !matches!(&command[..name_end], "s" | "substitute") {
+        return None;
+    }
+    let rest = &command[name_end..];
+    let delim = match rest.chars().next() {
+        None => return Some(repeat(None, "")),
+        Some(c) if c.is_whitespace() || c == '&' => return Some(repeat(None, rest.trim_start())),
+        Some(c) if is_delimiter(c) => c,
+        Some(_) => return None,
+    };
+    let (pattern, rest) = search::split_at_delimiter(&rest[delim.len_utf8()..], delim);
+    let (replacement, rest) = match rest {
+        Some(rest) => search::split_at_delimiter(rest, delim),
+        None => (String::new(), None),
+    };
+    Some(parse_flags(rest.unwrap_or("")).map(|(flags, count)| SubstituteSpec {
+        pattern: Some(pattern),
+        replacement: Some(replacement),
+        flags,
+        count,
+    }))
+}
+
+/// a replacement with each unescaped `~` replaced by the previous replacement
+pub fn expand_tilde(replacement: &str, previous: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push('\\');
+                if let Some(next) = chars.next() {
+                    out.push(next);
+                }
+            }
+            '~' => out.push_str(previous),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+fn push_cased(out: &mut String, text: &str, one: &mut Option<Case>, all: Option<Case>) {
+    for c in text.chars() {
+        match one.take().or(all) {
+            Some(Case::Upper) => out.extend(c.to_uppercase()),
+            Some(Case::Lower) => out.extend(c.to_lowercase()),
+            None => out.push(c),
+        }
+    }
+}
+
+/// the text a match is replaced with
+pub fn expand(replacement: &str, caps: &Captures) -> String {
+    let mut out = String::new();
+    let (mut one, mut all) = (None, None);
+    let group = |i: usize| caps.get(i).map_or("", |m| m.as_str());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => push_cased(&mut out, group(0), &mut one, all),
+            '\r' => out.push('\n'),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => push_cased(&mut out, group(d as usize - '0' as usize), &mut one, all),
+                Some('u') => one = Some(Case::Upper),
+                Some('l') => one = Some(Case::Lower),
+                Some('U') => all = Some(Case::Upper),
+                Some('L') => all = Some(Case::Lower),
+                Some('E' | 'e') => all = None,
+                Some('n' | 'r') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => push_cased(&mut out, &other.to_string(), &mut one, all),
+                None => out.push('\\'),
+            },
+            _ => push_cased(&mut out, &c.to_string(), &mut one, all),
+        }
+    }
+    out
+}
+
+/// one replacement: rope bytes `start..end` become `text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+fn char_len_at(rope: &Rope, byte: usize) -> usize {
+    if byte >= rope.len_bytes() {
+        1
+    } else {
+        rope.char(rope.byte_to_char(byte)).len_utf8()
+    }
+}
+
+/// the edits substituting `replacement` for the matches of `pattern` that start
+/// on lines `first..=last`, in document order and without overlaps: the first
+/// match on each line, or all of them when `global`. A match may run past the
+/// end of its line; the search goes on after it.
+pub fn plan(rope: &Rope, pattern: &SearchPattern, replacement: &str, first: usize, last: usize, global: bool) -> Vec<Edit> {
+    let last = last.min(rope.len_lines().saturating_sub(1));
+    if first > last {
+        return Vec::new();
+    }
+    let limit = if last + 1 < rope.len_lines() { rope.line_to_byte(last + 1) } else { rope.len_bytes() + 1 };
+    let regex = pattern.regex();
+    let base = rope.line_to_byte(first);
+    let whole = pattern.is_multiline().then(|| rope.byte_slice(base..).to_string());
+    // next match starting at or after `pos`, as (start, end, replacement text)
+    let next = |pos: usize| -> Option<(usize, usize, String)> {
+        if let Some(text) = &whole {
+            let caps = regex.captures_at(text, pos.checked_sub(base).filter(|&p| p <= text.len())?)?;
+            let m = caps.get(0)?;
+            return Some((base + m.start(), base + m.end(), expand(replacement, &caps)));
+        }
+        let first_line = rope.byte_to_line(pos.min(rope.len_bytes()));
+        for line in first_line..=last {
+            let line_start = rope.line_to_byte(line);
+            let text = rope.line(line).to_string();
+            let text = text.trim_end_matches(['\n', '\r']);
+            let from = pos.saturating_sub(line_start);
+            if from > text.len() {
+                continue;
+            }
+            if let Some(caps) = regex.captures_at(text, from) {
+                let m = caps.get(0)?;
+                return Some((line_start + m.start(), line_start + m.end(), expand(replacement, &caps)));
+            }
+        }
+        None
+    };
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut pos = base;
+    while pos < limit {
+        let Some((start, end, text)) = next(pos) else { break; };
+        if start >= limit {
+            break;
+        }
+        // an empty match right where the previous one ended is not another match
+        if start == end && edits.last().is_some_and(|e| e.end == start && e.start < e.end) {
+            pos = start + char_len_at(rope, start);
+            continue;
+        }
+        edits.push(Edit { start, end, text });
+        pos = if !global {
+            rope.line_to_byte((rope.byte_to_line(end.saturating_sub(1).max(start)) + 1).min(rope.len_lines()))
+                .max(end)
+        } else if end > start {
+            end
+        } else {
+            end + char_len_at(rope, end)
+        };
+        if !global && rope.byte_to_line(start) == last {
+            break;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(pattern: &str, replacement: &str, flags: &str, count: Option<usize>) -> SubstituteSpec {
+        SubstituteSpec { pattern: Some(pattern.to_string()), replacement: Some(replacement.to_string()), flags: flags.to_string(), count }
+    }
+
+    fn run(text: &str, pattern: &str, replacement: &str, lines: (usize, usize), global: bool) -> String {
+        let mut rope = Rope::from_str(text);
+        let pattern = SearchPattern::new(pattern, false, false).unwrap();
+        for edit in plan(&rope, &pattern, replacement, lines.0, lines.1, global).into_iter().rev() {
+            let (start, end) = (rope.byte_to_char(edit.start), rope.byte_to_char(edit.end));
+            rope.remove(start..end);
+            rope.insert(start, &edit.text);
+        }
+        rope.to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("s/a/b/g"), Some(Ok(spec("a", "b", "g", None))));
+        assert_eq!(parse(r"s/a\/b/c\/d/"), Some(Ok(spec("a/b", "c/d", "", None))));
+        assert_eq!(parse(r"s#/usr/bin#/opt\#x#gI 3"), Some(Ok(spec("/usr/bin", "/opt#x", "gI", Some(3)))));
+        assert_eq!(parse(r"s/\d\+/[&]"), Some(Ok(spec(r"\d\+", "[&]", "", None))));
+        assert_eq!(parse("s/gone"), Some(Ok(spec("gone", "", "", None))));
+        assert_eq!(parse("substitute|a|b|"), None);
+        let repeat = |pattern: Option<&str>, flags: &str, count| SubstituteSpec {
+            pattern: pattern.map(str::to_string), replacement: None, flags: flags.to_string(), count,
+        };
+        assert_eq!(parse("s"), Some(Ok(repeat(None, "", None))));
+        assert_eq!(parse("&&"), Some(Ok(repeat(None, "&", None))));
+        assert_eq!(parse("& 4"), Some(Ok(repeat(None, "", Some(4)))));
+        assert_eq!(parse("~g"), Some(Ok(repeat(Some(""), "g", None))));
+        assert_eq!(parse("set"), None);
+        assert_eq!(parse("sp"), None);
+        assert!(parse("s/a/b/x").unwrap().is_err());
+        assert!(parse("s/a/b/g&").unwrap().is_err());
+        assert!(parse("s/a/b/ 2x").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let last = Flags { global: true, ..Flags::default() };
+        assert_eq!(Flags::default().apply("gi"), Flags { global: true, ignore_case: Some(true), ..Flags::default() });
+        assert_eq!(last.apply("&n"), Flags { global: true, count_only: true, ..Flags::default() });
+        assert_eq!(last.apply("&g"), Flags::default());
+        assert_eq!(Flags::default().apply("gc"), Flags { global: true, confirm: true, ..Flags::default() });
+    }
+
+    #[test]
+    fn test_expand() {
+        let sub = |text: &str, pattern: &str, replacement: &str| run(text, pattern, replacement, (0, 0), true);
+        assert_eq!(sub("john smith", r"\(\w\+\) \(\w\+\)", r"\2, \1"), "smith, john");
+        assert_eq!(sub("a1 b22", r"\d\+", "<&>"), "a<1> b<22>");
+        assert_eq!(sub("a&b", "&", r"\&\&"), "a&&b");
+        assert_eq!(sub("hello world", r"\w\+", r"\u&"), "Hello World");
+        assert_eq!(sub("make it loud", r"it \w\+", r"\U&\E!"), "make IT LOUD!");
+        assert_eq!(sub("SHOUT quiet", r"\(\w\+\) \(\w\+\)", r"\L\u\1\E \U\2"), "Shout QUIET");
+        assert_eq!(sub("a,b,c", ",", r"\r"), "a\nb\nc");
+        assert_eq!(sub("a,b", ",", r"\n\t"), "a\n\tb");
+        assert_eq!(expand_tilde(r"x~\~", "prev"), r"xprev\~");
+    }
+
+    #[test]
+    fn test_plan() {
+        let text = "foo foo\nbar\nfoo\n";
+        assert_eq!(run(text, "foo", "x", (0, 2), false), "x foo\nbar\nx\n");
+        assert_eq!(run(text, "foo", "x", (0, 1), true), "x x\nbar\nfoo\n");
+        // matches running across lines, and joining every line of the range
+        assert_eq!(run("a\nb\nc\nd\n", r"\n", "", (0, 1), true), "abc\nd\n");
+        assert_eq!(run("fn(\n  a,\n)\nx\n", r"(\_s*a,\_s*)", "(a)", (0, 3), false), "fn(a)\nx\n");
+        // empty matches
+        assert_eq!(run("abc\n", "x*", "-", (0, 0), true), "-a-b-c-\n");
+        assert_eq!(run("abc\n", "b*", "-", (0, 0), true), "-a-c-\n");
+        assert_eq!(run("a\nb\n", "^", "# ", (0, 1), false), "# a\n# b\n");
+        assert_eq!(run("a\nb", "$", ";", (0, 1), false), "a;\nb;");
+        // char columns, not bytes
+        assert_eq!(run("héllo wörld\n", "ö", "o", (0, 0), true), "héllo world\n");
+    }
+}
@@ -0,0 +1,283 @@
+// document symbols for :Symbols and the outline sidebar: definitions captured by
+// a tree-sitter `tags.scm` query, or the language server's documentSymbol reply.
+//
+// captures understood in tags.scm: @name on the identifier, and on the whole
+// definition one of @definition.{module,section,class,struct,interface,enum,
+// type,function,method,constant,variable,field,macro}
+
+use ropey::Rope;
+use std::collections::HashMap;
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+use crate::lsp::{self, DocumentSymbol, Position};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Module,
+    Section,
+    Class,
+    Struct,
+    Interface,
+    Enum,
+    Type,
+    Function,
+    Method,
+    Constant,
+    Variable,
+    Field,
+    Macro,
+    Other,
+}
+
+impl SymbolKind {
+    fn from_capture(name: &str) -> Option<Self> {
+        Some(match name.strip_prefix("definition.")? {
+            "module" | "namespace" => SymbolKind::Module,
+            "section" | "heading" => SymbolKind::Section,
+            "class" | "impl" => SymbolKind::Class,
+            "struct" | "union" => SymbolKind::Struct,
+            "interface" | "trait" => SymbolKind::Interface,
+            "enum" => SymbolKind::Enum,
+            "type" => SymbolKind::Type,
+            "function" => SymbolKind::Function,
+            "method" => SymbolKind::Method,
+            "constant" => SymbolKind::Constant,
+            "variable" => SymbolKind::Variable,
+            "field" => SymbolKind::Field,
+            "macro" => SymbolKind::Macro,
+            _ => SymbolKind::Other,
+        })
+    }
+
+    /// LSP SymbolKind numbers
+    pub fn from_lsp(kind: u32) -> Self {
+        match kind {
+            2..=4 => SymbolKind::Module,
+            5 => SymbolKind::Class,
+            23 => SymbolKind::Struct,
+            11 => SymbolKind::Interface,
+            10 => SymbolKind::Enum,
+            26 => SymbolKind::Type,
+            12 => SymbolKind::Function,
+            6 | 9 => SymbolKind::Method,
+            14 | 22 => SymbolKind::Constant,
+            13 => SymbolKind::Variable,
+            7 | 8 => SymbolKind::Field,
+            _ => SymbolKind::Other,
+        }
+    }
+
+    pub fn icon(self) -> char {
+        match self {
+            SymbolKind::Module => 'M',
+            SymbolKind::Section => '#',
+            SymbolKind::Class => 'C',
+            SymbolKind::Struct => 'S',
+            SymbolKind::Interface => 'I',
+            SymbolKind::Enum => 'E',
+            SymbolKind::Type => 'T',
+            SymbolKind::Function => 'f',
+            SymbolKind::Method => 'm',
+            SymbolKind::Constant => 'c',
+            SymbolKind::Variable => 'v',
+            SymbolKind::Field => '.',
+            SymbolKind::Macro => '!',
+            SymbolKind::Other => '*',
+        }
+    }
+
+    /// highlight group the icon is drawn with
+    pub fn highlight(self) -> &'static str {
+        match self {
+            SymbolKind::Module | SymbolKind::Section => "module",
+            SymbolKind::Class | SymbolKind::Struct | SymbolKind::Interface
+            | SymbolKind::Enum | SymbolKind::Type => "type",
+            SymbolKind::Function | SymbolKind::Method => "function",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Variable | SymbolKind::Other => "variable",
+            SymbolKind::Field => "property",
+            SymbolKind::Macro => "function.macro",
+        }
+    }
+}
+
+/// a definition; positions are (line, char column)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub line: usize, // where the name is, the jump target
+    pub col: usize,
+    pub start: (usize, usize), // the whole definition
+    pub end: (usize, usize),
+    pub depth: usize, // definitions it sits inside
+}
+
+fn char_position(rope: &Rope, byte: usize) -> (usize, usize) {
+    let byte = byte.min(rope.len_bytes());
+    let line = rope.byte_to_line(byte);
+    (line, rope.byte_to_char(byte) - rope.line_to_char(line))
+}
+
+/// definitions captured by a tags query, nested
+pub fn tag_symbols(query: &Query, root: Node, rope: &Rope) -> Vec<Symbol> {
+    let len = rope.len_bytes();
+    let text = |node: Node| {
+        let r = node.start_byte().min(len)..node.end_byte().min(len);
+        rope.byte_slice(r).chunks().map(str::as_bytes)
+    };
+    let names = query.capture_names();
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, root, text);
+    // definition node id -> (pattern, symbol); a later pattern replaces an earlier one
+    let mut found: HashMap<usize, (usize, Symbol)> = HashMap::new();
+    while let Some(m) = matches.next() {
+        let mut name = None;
+        let mut definition = None;
+        for capture in m.captures {
+            let capture_name = names[capture.index as usize];
+            if capture_name == "name" {
+                name = Some(capture.node);
+            } else if let Some(kind) = SymbolKind::from_capture(capture_name) {
+                definition = Some((capture.node, kind));
+            }
+        }
+        let (Some(name), Some((node, kind))) = (name, definition) else { continue; };
+        if found.get(&node.id()).is_some_and(|(pattern, _)| *pattern > m.pattern_index) {
+            continue;
+        }
+        let text = rope.byte_slice(name.start_byte()..name.end_byte()).to_string();
+        let (line, col) = char_position(rope, name.start_byte());
+        let symbol = Symbol {
+            name: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            kind,
+            line,
+            col,
+            start: char_position(rope, node.start_byte()),
+            end: char_position(rope, node.end_byte()),
+            depth: 0,
+        };
+        found.insert(node.id(), (m.pattern_index, symbol));
+    }
+    let mut symbols: Vec<Symbol> = found.into_values().map(|(_, s)| s).collect();
+    nest(&mut symbols);
+    symbols
+}
+
+/// symbols from a documentSymbol reply, nested
+pub fn lsp_symbols(items: &[DocumentSymbol], rope: &Rope) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = items.iter().map(|item| {
+        let (line, col) = lsp_position(rope, &item.selection);
+        Symbol {
+            name: item.name.clone(),
+            kind: SymbolKind::from_lsp(item.kind),
+            line,
+            col,
+            start: lsp_position(rope, &item.range.start),
+            end: lsp_position(rope, &item.range.end),
+            depth: 0,
+        }
+    }).collect();
+    nest(&mut symbols);
+    symbols
+}
+
+/// line and char column of a server position, whose column counts UTF-16 units
+fn lsp_position(rope: &Rope, position: &Position) -> (usize, usize) {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return (line, position.character as usize);
+    }
+    (line, lsp::char_column(&rope.line(line).to_string(), position.character))
+}
+
+/// put symbols in document order, outer before inner, and set each one's depth
+/// to the number of symbols whose range holds it
+pub fn nest(symbols: &mut [Symbol]) {
+    symbols.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    for symbol in symbols.iter_mut() {
+        while open.last().is_some_and(|end| *end < symbol.end) {
+            open.pop();
+        }
+        symbol.depth = open.len();
+        open.push(symbol.end);
+    }
+}
+
+/// index of the innermost symbol whose definition covers `line`
+pub fn symbol_at(symbols: &[Symbol], line: usize) -> Option<usize> {
+    symbols.iter().rposition(|s| s.start.0 <= line && line <= s.end.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::lsp::LspRange;
+    use crate::syntax::SyntaxHighlighter;
+
+    fn outline(text: &str, lang: &str) -> Vec<String> {
+        let mut sh = SyntaxHighlighter::new();
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, lang);
+        let Some((query, root)) = sh.tags(&buffer) else { return Vec::new(); };
+        tag_symbols(query, root, buffer.rope()).iter()
+            .map(|s| format!("{}{} {} {}", "  ".repeat(s.depth), s.kind.icon(), s.name, s.line))
+            .collect()
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_rust_symbols() {
+        let text = "const MAX: usize = 3;\nstruct Foo;\nimpl Foo {\n    fn new() -> Self { Foo }\n}\nfn main() {\n    fn helper() {}\n}\n";
+        assert_eq!(outline(text, "rust"), vec![
+            "c MAX 0", "S Foo 1", "C Foo 2", "  m new 3", "f main 5", "  f helper 6",
+        ]);
+    }
+
+    #[cfg(feature = "lang-python")]
+    #[test]
+    fn test_python_symbols() {
+        let text = "LIMIT = 10\nvalue = 2\nclass A:\n    @staticmethod\n    def make():\n        pass\n    def run(self):\n        def inner():\n            pass\n";
+        assert_eq!(outline(text, "python"), vec![
+            "c LIMIT 0", "C A 2", "  m make 4", "  m run 6", "    f inner 7",
+        ]);
+    }
+
+    #[cfg(feature = "lang-md")]
+    #[test]
+    fn test_markdown_headings_nest() {
+        let text = "# Top\n\ntext\n\n## Sub\n\nmore\n\n# Next\n";
+        assert_eq!(outline(text, "markdown"), vec!["# Top 0", "  # Sub 4", "# Next 8"]);
+    }
+
+    #[test]
+    fn test_lsp_symbols_and_symbol_at() {
+        let item = |name: &str, kind, (l1, l2): (u32, u32)| DocumentSymbol {
+            name: name.to_string(),
+            kind,
+            range: LspRange { start: Position { line: l1, character: 0 }, end: Position { line: l2, character: 1 } },
+            selection: Position { line: l1, character: 4 },
+        };
+        // flat SymbolInformation-style results still nest by range
+        let rope = Rope::from_str(&"fn  x\n".repeat(13));
+        let symbols = lsp_symbols(&[item("run", 6, (2, 4)), item("Foo", 5, (1, 9)), item("main", 12, (11, 12))], &rope);
+        let names: Vec<(&str, usize, SymbolKind)> = symbols.iter().map(|s| (s.name.as_str(), s.depth, s.kind)).collect();
+        assert_eq!(names, vec![("Foo", 0, SymbolKind::Class), ("run", 1, SymbolKind::Method), ("main", 0, SymbolKind::Function)]);
+        assert_eq!((symbols[1].line, symbols[1].col), (2, 4));
+        assert_eq!(symbol_at(&symbols, 3), Some(1));
+        assert_eq!(symbol_at(&symbols, 7), Some(0));
+        assert_eq!(symbol_at(&symbols, 10), None);
+
+        // the server counts UTF-16 units: the emoji is two of them but one char
+        let rope = Rope::from_str("// \u{1f600} caf\u{e9}\nfn f() {}\n");
+        let symbols = lsp_symbols(&[DocumentSymbol {
+            name: "f".to_string(),
+            kind: 12,
+            range: LspRange { start: Position { line: 0, character: 5 }, end: Position { line: 1, character: 9 } },
+            selection: Position { line: 0, character: 9 },
+        }], &rope);
+        assert_eq!((symbols[0].line, symbols[0].col), (0, 8));
+        assert_eq!((symbols[0].start, symbols[0].end), ((0, 4), (1, 9)));
+    }
+}
@@ -1,131 +1,980 @@
-// tree-sitter based syntax highlighting
+// tree-sitter based syntax highlighting: one persistent tree per buffer, kept in
+// step with edits and reparsed incrementally; highlights are queried per viewport
 
-use crossterm::style::Color;
-use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent, Highlighter};
-use crate::theme::Theme;
+use ropey::Rope;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+use crate::buffer::Buffer;
+use crate::grammar::{self, QuerySource};
+use crate::language::LanguageRegistry;
+use crate::theme::{Style, Theme};
 
-// highlight names recognized by our theme
-const HIGHLIGHT_NAMES: &[&str] = &[
-    "attribute", "comment", "constant", "constant.builtin", "constructor",
-    "embedded", "escape", "function", "function.builtin", "function.macro",
-    "keyword", "label", "namespace", "number", "operator", "property",
+// capture names themes can style; a theme may style any of these, and
+// unstyled dotted names fall back to their parent ("function.macro" -> "function")
+pub const HIGHLIGHT_NAMES: &[&str] = &[
+    "attribute", "boolean", "character", "comment", "comment.documentation",
+    "constant", "constant.builtin", "constructor", "embedded", "escape",
+    "function", "function.builtin", "function.macro", "function.method",
+    "keyword", "keyword.function", "keyword.operator", "keyword.return",
+    "label", "module", "namespace", "number", "operator", "property",
     "punctuation", "punctuation.bracket", "punctuation.delimiter",
-    "punctuation.special", "string", "string.special", "tag", "type",
-    "type.builtin", "variable", "variable.builtin", "variable.parameter",
+    "punctuation.special", "string", "string.escape", "string.regex",
+    "string.special", "tag", "tag.attribute", "text.emphasis", "text.literal",
+    "text.reference", "text.strong", "text.title", "text.uri", "type", "type.builtin",
+    "variable", "variable.builtin", "variable.member", "variable.parameter",
 ];
 
-pub struct SyntaxHighlighter {
-    highlighter: Highlighter,
-    configs: Vec<(&'static str, HighlightConfiguration)>, // (extension, config)
+// buffers larger than this are parsed on a worker thread instead of during render
+const BACKGROUND_PARSE_BYTES: usize = 256 * 1024;
+// injected languages may inject others (markdown -> markdown_inline -> html)
+const MAX_INJECTION_DEPTH: usize = 3;
+
+/// a buffer's parse tree and the buffer version it was parsed from; edits made
+/// since are applied with `Tree::edit` until the next parse
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    pub tree: Tree,
+    pub lang: String, // name of the grammar that produced the tree
+    pub version: u64,
+    injections: Vec<InjectionTree>,
 }
 
-impl SyntaxHighlighter {
-    pub fn new() -> Self {
-        let highlighter = Highlighter::new();
-        let mut sh = Self { highlighter, configs: Vec::new() };
-        sh.register_languages();
-        sh
+/// the tree of a region injected into a buffer's tree, kept between renders;
+/// edits move its included ranges with the text and mark it for an incremental
+/// reparse when they touch them
+#[derive(Debug, Clone)]
+struct InjectionTree {
+    lang: usize,
+    tree: Tree,
+    edited: bool,
+    used: bool, // painted in the current highlight pass
+}
+
+impl SyntaxTree {
+    fn new(tree: Tree, lang: String, version: u64) -> Self {
+        Self { tree, lang, version, injections: Vec::new() }
     }
 
-    fn register_languages(&mut self) {
+    /// apply a text edit to the tree and the injected trees
+    pub fn edit(&mut self, edit: &InputEdit) {
+        self.tree.edit(edit);
+        for injection in &mut self.injections {
+            let ranges = injection.tree.included_ranges();
+            let touched = ranges.first().is_some_and(|first| first.start_byte <= edit.old_end_byte)
+                && ranges.last().is_some_and(|last| last.end_byte >= edit.start_byte);
+            injection.tree.edit(edit);
+            injection.edited |= touched;
+        }
+    }
+
+    /// keep the injected trees of the tree this one replaces, which are already
+    /// up to date with the text
+    pub fn adopt_injections(&mut self, old: SyntaxTree) {
+        if old.lang == self.lang {
+            self.injections = old.injections;
+        }
+    }
+}
+
+/// a compiled-in grammar with the queries its crate bundles
+struct BuiltinGrammar {
+    name: &'static str,
+    language: Language,
+    queries: [&'static str; 7], // highlights, injections, locals, textobjects, folds, indents, tags
+}
+
+struct LanguageConfig {
+    name: String,
+    aliases: Vec<String>, // names and extensions of the registry languages using this grammar
+    language: Language,
+    grammar: Option<PathBuf>, // shared library, None when compiled in
+    query: Query,
+    capture_highlights: Vec<Option<usize>>, // capture index -> HIGHLIGHT_NAMES index
+    injections: Option<Query>, // without the injection.combined patterns
+    combined_injections: Option<Query>, // only those, run over the whole tree
+    locals: Option<Query>,
+    textobjects: Option<Query>,
+    folds: Option<Query>,
+    indents: Option<Query>,
+    tags: Option<Query>,
+    query_sources: [QuerySource; 7],
+}
+
+/// a scope of the locals query while resolving references
+struct LocalScope<'tree> {
+    end: usize,
+    inherits: bool,
+    definitions: Vec<(String, Node<'tree>)>,
+}
+
+struct ParseResult {
+    buffer_id: u64,
+    tree: Option<SyntaxTree>,
+}
+
+pub struct SyntaxHighlighter {
+    parser: Parser,
+    languages: Vec<LanguageConfig>,
+    parsing: HashSet<u64>, // buffers with a background parse in flight
+    injection_trees: Vec<InjectionTree>, // lent by the tree being highlighted
+    results_tx: Sender<ParseResult>,
+    results_rx: Receiver<ParseResult>,
+}
+
+/// HIGHLIGHT_NAMES index for a capture: the longest name equal to it or a dotted
+/// prefix of it ("function.method.call" -> "function.method")
+fn highlight_index(capture: &str) -> Option<usize> {
+    HIGHLIGHT_NAMES.iter()
+        .enumerate()
+        .filter(|(_, name)| {
+            capture == **name || (capture.starts_with(**name) && capture.as_bytes().get(name.len()) == Some(&b'.'))
+        })
+        .max_by_key(|(_, name)| name.len())
+        .map(|(i, _)| i)
+}
+
+/// parse a rope chunk by chunk, reusing `old` (already edited) for unchanged regions
+fn parse_rope(parser: &mut Parser, rope: &Rope, old: Option<&Tree>) -> Option<Tree> {
+    let len = rope.len_bytes();
+    parser.parse_with_options(&mut |byte: usize, _: Point| -> &[u8] {
+        if byte >= len {
+            return &[];
+        }
+        let (chunk, chunk_start, _, _) = rope.chunk_at_byte(byte);
+        &chunk.as_bytes()[byte - chunk_start..]
+    }, old, None)
+}
+
+fn builtin_grammars() -> Vec<BuiltinGrammar> {
+    vec![
         #[cfg(feature = "lang-rust")]
-        self.register_lang("rs", tree_sitter_rust::LANGUAGE.into(),
-            tree_sitter_rust::HIGHLIGHTS_QUERY, "", "");
+        BuiltinGrammar { name: "rust", language: tree_sitter_rust::LANGUAGE.into(),
+            queries: [tree_sitter_rust::HIGHLIGHTS_QUERY, tree_sitter_rust::INJECTIONS_QUERY, "",
+                include_str!("../queries/rust/textobjects.scm"), include_str!("../queries/rust/folds.scm"),
+                include_str!("../queries/rust/indents.scm"), include_str!("../queries/rust/tags.scm")] },
         #[cfg(feature = "lang-python")]
-        self.register_lang("py", tree_sitter_python::LANGUAGE.into(),
-            tree_sitter_python::HIGHLIGHTS_QUERY, "", "");
+        BuiltinGrammar { name: "python", language: tree_sitter_python::LANGUAGE.into(),
+            queries: [tree_sitter_python::HIGHLIGHTS_QUERY, "", "", include_str!("../queries/python/textobjects.scm"),
+                include_str!("../queries/python/folds.scm"),
+                include_str!("../queries/python/indents.scm"), include_str!("../queries/python/tags.scm")] },
         #[cfg(feature = "lang-js")]
-        {
-            self.register_lang("js", tree_sitter_javascript::LANGUAGE.into(),
-                tree_sitter_javascript::HIGHLIGHT_QUERY,
-                tree_sitter_javascript::INJECTIONS_QUERY,
-                tree_sitter_javascript::LOCALS_QUERY);
-            self.register_lang("jsx", tree_sitter_javascript::LANGUAGE.into(),
-                tree_sitter_javascript::HIGHLIGHT_QUERY,
-                tree_sitter_javascript::INJECTIONS_QUERY,
-                tree_sitter_javascript::LOCALS_QUERY);
-        }
+        BuiltinGrammar { name: "javascript", language: tree_sitter_javascript::LANGUAGE.into(),
+            queries: [tree_sitter_javascript::HIGHLIGHT_QUERY, tree_sitter_javascript::INJECTIONS_QUERY, tree_sitter_javascript::LOCALS_QUERY,
+                include_str!("../queries/javascript/textobjects.scm"), include_str!("../queries/javascript/folds.scm"),
+                include_str!("../queries/javascript/indents.scm"), include_str!("../queries/javascript/tags.scm")] },
         #[cfg(feature = "lang-c")]
-        self.register_lang("c", tree_sitter_c::LANGUAGE.into(),
-            tree_sitter_c::HIGHLIGHT_QUERY, "", "");
-        #[cfg(feature = "lang-c")]
-        self.register_lang("h", tree_sitter_c::LANGUAGE.into(),
-            tree_sitter_c::HIGHLIGHT_QUERY, "", "");
+        BuiltinGrammar { name: "c", language: tree_sitter_c::LANGUAGE.into(),
+            queries: [tree_sitter_c::HIGHLIGHT_QUERY, "", "", include_str!("../queries/c/textobjects.scm"),
+                include_str!("../queries/c/folds.scm"),
+                include_str!("../queries/c/indents.scm"), include_str!("../queries/c/tags.scm")] },
         #[cfg(feature = "lang-go")]
-        self.register_lang("go", tree_sitter_go::LANGUAGE.into(),
-            tree_sitter_go::HIGHLIGHTS_QUERY, "", "");
+        BuiltinGrammar { name: "go", language: tree_sitter_go::LANGUAGE.into(),
+            queries: [tree_sitter_go::HIGHLIGHTS_QUERY, "", "", include_str!("../queries/go/textobjects.scm"),
+                include_str!("../queries/go/folds.scm"),
+                include_str!("../queries/go/indents.scm"), include_str!("../queries/go/tags.scm")] },
         #[cfg(feature = "lang-toml")]
-        self.register_lang("toml", tree_sitter_toml_ng::LANGUAGE.into(),
-            tree_sitter_toml_ng::HIGHLIGHTS_QUERY, "", "");
+        BuiltinGrammar { name: "toml", language: tree_sitter_toml_ng::LANGUAGE.into(),
+            queries: [tree_sitter_toml_ng::HIGHLIGHTS_QUERY, "", "", "", include_str!("../queries/toml/folds.scm"), "", ""] },
         #[cfg(feature = "lang-json")]
-        self.register_lang("json", tree_sitter_json::LANGUAGE.into(),
-            tree_sitter_json::HIGHLIGHTS_QUERY, "", "");
+        BuiltinGrammar { name: "json", language: tree_sitter_json::LANGUAGE.into(),
+            queries: [tree_sitter_json::HIGHLIGHTS_QUERY, "", "", "", include_str!("../queries/json/folds.scm"), "", ""] },
         #[cfg(feature = "lang-md")]
-        self.register_lang("md", tree_sitter_md::LANGUAGE.into(),
-            tree_sitter_md::HIGHLIGHT_QUERY_BLOCK, tree_sitter_md::INJECTION_QUERY_BLOCK, "");
-    }
-
-    fn register_lang(&mut self, ext: &'static str, lang: tree_sitter::Language,
-        highlights: &str, injections: &str, locals: &str)
-    {
-        if let Ok(mut config) = HighlightConfiguration::new(lang, ext, highlights, injections, locals) {
-            config.configure(HIGHLIGHT_NAMES);
-            self.configs.push((ext, config));
-        }
-    }
-
-    /// get highlight spans for a source string by file extension
-    /// returns vec of (byte_start, byte_end, highlight_index) for each token
-    pub fn highlight(&mut self, ext: &str, source: &[u8]) -> Vec<(usize, usize, usize)> {
-        let config = self.configs.iter().find(|(e, _)| *e == ext);
-        let config = match config { Some((_, c)) => c, None => return Vec::new() };
-        let events = self.highlighter.highlight(config, source, None, |_| None);
-        let events = match events { Ok(e) => e, Err(_) => return Vec::new() };
-        let mut spans = Vec::new();
-        let mut current_highlight: Option<usize> = None;
-        for event in events {
-            match event {
-                Ok(HighlightEvent::Source { start, end }) => {
-                    if let Some(hi) = current_highlight {
-                        spans.push((start, end, hi));
-                    }
+        BuiltinGrammar { name: "markdown", language: tree_sitter_md::LANGUAGE.into(),
+            queries: [tree_sitter_md::HIGHLIGHT_QUERY_BLOCK, tree_sitter_md::INJECTION_QUERY_BLOCK, "",
+                "", include_str!("../queries/markdown/folds.scm"), "", include_str!("../queries/markdown/tags.scm")] },
+        // only reached through injections from the block grammar
+        #[cfg(feature = "lang-md")]
+        BuiltinGrammar { name: "markdown_inline", language: tree_sitter_md::INLINE_LANGUAGE.into(),
+            queries: [tree_sitter_md::HIGHLIGHT_QUERY_INLINE, tree_sitter_md::INJECTION_QUERY_INLINE, "", "", "", "", ""] },
+    ]
+}
+
+/// compile a language's queries: a user file in the runtime dir wins over the
+/// bundled text; a broken user file is reported and the bundled query kept
+fn build_language(
+    name: &str, aliases: Vec<String>, language: Language, grammar: Option<PathBuf>,
+    bundled: [&str; 7], runtime: Option<&Path>, errors: &mut Vec<String>,
+) -> LanguageConfig {
+    let mut compiled: Vec<Option<Query>> = Vec::new();
+    let mut query_sources: [QuerySource; 7] = Default::default();
+    let mut sources: Vec<String> = Vec::new();
+    for (i, kind) in grammar::QUERY_KINDS.iter().enumerate() {
+        let user = runtime.and_then(|dir| grammar::user_query(dir, name, kind));
+        let mut query = None;
+        let mut source = String::new();
+        if let Some((path, text)) = user {
+            match Query::new(&language, &text) {
+                Ok(q) => {
+                    query = Some(q);
+                    query_sources[i] = QuerySource::User(path);
+                    source = text;
                 }
-                Ok(HighlightEvent::HighlightStart(Highlight(idx))) => {
-                    current_highlight = Some(idx);
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if query.is_none() && !bundled[i].is_empty() {
+            match Query::new(&language, bundled[i]) {
+                Ok(q) => {
+                    query = Some(q);
+                    query_sources[i] = QuerySource::Bundled;
+                    source = bundled[i].to_string();
                 }
-                Ok(HighlightEvent::HighlightEnd) => {
-                    current_highlight = None;
+                Err(e) => errors.push(format!("bundled {} query for {}: {}", kind, name, e)),
+            }
+        }
+        compiled.push(query);
+        sources.push(source);
+    }
+    let mut compiled = compiled.into_iter();
+    let query = compiled.next().flatten()
+        .or_else(|| Query::new(&language, "").ok())
+        .expect("an empty query always compiles");
+    let capture_highlights = query.capture_names().iter().map(|n| highlight_index(n)).collect();
+    let mut injections = compiled.next().flatten();
+    let combined_injections = injections.as_mut().and_then(|query| split_combined(&language, query, &sources[1]));
+    LanguageConfig {
+        name: name.to_string(),
+        aliases,
+        language,
+        grammar,
+        query,
+        capture_highlights,
+        injections,
+        combined_injections,
+        locals: compiled.next().flatten(),
+        textobjects: compiled.next().flatten(),
+        folds: compiled.next().flatten(),
+        indents: compiled.next().flatten(),
+        tags: compiled.next().flatten(),
+        query_sources,
+    }
+}
+
+/// move the injection.combined patterns of `query` to a query of their own
+fn split_combined(language: &Language, query: &mut Query, source: &str) -> Option<Query> {
+    let combined = |query: &Query, i: usize| query.property_settings(i).iter().any(|p| &*p.key == "injection.combined");
+    if !(0..query.pattern_count()).any(|i| combined(query, i)) {
+        return None;
+    }
+    let mut only_combined = Query::new(language, source).ok()?;
+    for i in 0..query.pattern_count() {
+        if combined(query, i) {
+            query.disable_pattern(i);
+        } else {
+            only_combined.disable_pattern(i);
+        }
+    }
+    Some(only_combined)
+}
+
+/// the ranges an injection parses: the content node, less its named children
+/// unless the pattern sets injection.include-children (anonymous children are
+/// tokens of the content itself, like markdown's emphasis delimiters)
+fn content_ranges(node: Node, include_children: bool) -> Vec<tree_sitter::Range> {
+    if include_children || node.named_child_count() == 0 {
+        return vec![node.range()];
+    }
+    let mut ranges = Vec::new();
+    let (mut start_byte, mut start_point) = (node.start_byte(), node.start_position());
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.start_byte() > start_byte {
+            ranges.push(tree_sitter::Range { start_byte, end_byte: child.start_byte(), start_point, end_point: child.start_position() });
+        }
+        (start_byte, start_point) = (child.end_byte(), child.end_position());
+    }
+    if node.end_byte() > start_byte {
+        ranges.push(tree_sitter::Range { start_byte, end_byte: node.end_byte(), start_point, end_point: node.end_position() });
+    }
+    ranges
+}
+
+fn byte_ranges(ranges: &[tree_sitter::Range]) -> Vec<(usize, usize)> {
+    ranges.iter().map(|r| (r.start_byte, r.end_byte)).collect()
+}
+
+impl SyntaxHighlighter {
+    /// compiled-in grammars with their bundled queries
+    pub fn new() -> Self {
+        Self::with_runtime(None, &LanguageRegistry::builtin()).0
+    }
+
+    /// compiled-in grammars plus the grammar libraries and query overrides found in
+    /// `runtime`; the registry's languages name the grammar each one uses, and
+    /// their names and extensions resolve to it too. Returns load errors as well.
+    pub fn with_runtime(runtime: Option<&Path>, registry: &LanguageRegistry) -> (Self, Vec<String>) {
+        let (results_tx, results_rx) = channel();
+        let mut errors = Vec::new();
+        let runtime_parsers = runtime.map(grammar::runtime_parsers).unwrap_or_default();
+        let mut languages = Vec::new();
+        let aliases = |grammar: &str| -> Vec<String> {
+            registry.iter()
+                .filter(|l| l.grammar() == grammar)
+                .flat_map(|l| std::iter::once(&l.name).chain(&l.extensions).cloned())
+                .collect()
+        };
+
+        for builtin in builtin_grammars() {
+            // a runtime library with the same name replaces the compiled-in grammar
+            let replacement = runtime_parsers.iter().find(|(n, _)| n == builtin.name)
+                .and_then(|(n, path)| match grammar::load_grammar(path, n) {
+                    Ok(language) => Some((language, path.clone())),
+                    Err(e) => { errors.push(e); None }
+                });
+            let (language, grammar) = match replacement {
+                Some((language, path)) => (language, Some(path)),
+                None => (builtin.language, None),
+            };
+            languages.push(build_language(builtin.name, aliases(builtin.name), language, grammar, builtin.queries, runtime, &mut errors));
+        }
+        for (name, path) in &runtime_parsers {
+            if languages.iter().any(|l: &LanguageConfig| &l.name == name) {
+                continue;
+            }
+            match grammar::load_grammar(path, name) {
+                Ok(language) => {
+                    languages.push(build_language(name, aliases(name), language, Some(path.clone()), ["", "", "", "", "", "", ""], runtime, &mut errors));
                 }
-                Err(_) => break,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let sh = Self { parser: Parser::new(), languages, parsing: HashSet::new(), injection_trees: Vec::new(), results_tx, results_rx };
+        (sh, errors)
+    }
+
+    /// a grammar by its name, or by the name or extension of a language using it
+    fn language_index(&self, name: &str) -> Option<usize> {
+        self.languages.iter().position(|l| l.name == name)
+            .or_else(|| self.languages.iter().position(|l| l.aliases.iter().any(|a| a == name)))
+    }
+
+    fn language(&self, name: &str) -> Option<&LanguageConfig> {
+        self.language_index(name).map(|i| &self.languages[i])
+    }
+
+    /// check if we have a grammar for this language
+    pub fn supports(&self, name: &str) -> bool {
+        self.language(name).is_some()
+    }
+
+    /// `:TSInfo` report for a buffer using grammar `name`
+    pub fn info(&self, name: &str, buffer: &Buffer) -> String {
+        let Some(config) = self.language(name) else {
+            let names: Vec<&str> = self.languages.iter().map(|l| l.name.as_str()).collect();
+            return format!("No grammar '{}'; available: {}", name, names.join(", "));
+        };
+        let grammar = match &config.grammar {
+            Some(path) => path.display().to_string(),
+            None => "built in".to_string(),
+        };
+        let patterns = [Some(&config.query), config.injections.as_ref(), config.locals.as_ref(),
+            config.textobjects.as_ref(), config.folds.as_ref(), config.indents.as_ref(), config.tags.as_ref()];
+        let queries: Vec<String> = grammar::QUERY_KINDS.iter().enumerate().map(|(i, kind)| {
+            match patterns[i] {
+                Some(q) => format!("{}: {} ({} patterns)", kind, config.query_sources[i].describe(), q.pattern_count()),
+                None => format!("{}: none", kind),
+            }
+        }).collect();
+        let tree = match buffer.syntax_tree() {
+            Some(t) if t.lang == config.name => format!(
+                "tree: {} nodes, parsed at v{} (buffer v{})",
+                t.tree.root_node().descendant_count(), t.version, buffer.version()
+            ),
+            _ => "tree: not parsed yet".to_string(),
+        };
+        format!("{} [{}] ABI {} | {} | {}", config.name, grammar, config.language.abi_version(), queries.join(" | "), tree)
+    }
+
+    /// bring the buffer's tree up to date: small buffers are reparsed right away,
+    /// large ones on a worker thread while the edited old tree stays in use
+    pub fn update(&mut self, buffer: &mut Buffer, name: &str) {
+        let Some(config) = self.language_index(name).map(|i| &self.languages[i]) else {
+            if buffer.syntax_tree().is_some() {
+                buffer.set_syntax_tree(None);
+            }
+            return;
+        };
+        let old = buffer.syntax_tree().filter(|t| t.lang == config.name);
+        if old.is_some_and(|t| t.version == buffer.version()) {
+            return;
+        }
+        let old_tree = old.map(|t| t.tree.clone());
+        let (lang, version) = (config.name.clone(), buffer.version());
+
+        if buffer.rope().len_bytes() > BACKGROUND_PARSE_BYTES {
+            if !self.parsing.insert(buffer.id()) {
+                return; // the result will trigger another update
+            }
+            buffer.set_syntax_pending(true);
+            let (language, rope, buffer_id, tx) = (config.language.clone(), buffer.rope().clone(), buffer.id(), self.results_tx.clone());
+            thread::spawn(move || {
+                let mut parser = Parser::new();
+                let tree = parser.set_language(&language).ok()
+                    .and_then(|_| parse_rope(&mut parser, &rope, old_tree.as_ref()))
+                    .map(|tree| SyntaxTree::new(tree, lang, version));
+                let _ = tx.send(ParseResult { buffer_id, tree });
+            });
+            return;
+        }
+
+        if self.parser.set_language(&config.language).is_err() {
+            return;
+        }
+        if let Some(tree) = parse_rope(&mut self.parser, buffer.rope(), old_tree.as_ref()) {
+            buffer.set_syntax_tree(Some(SyntaxTree::new(tree, lang, version)));
+        }
+    }
+
+    /// the textobjects query for the grammar of the buffer's tree, with the tree's root
+    pub fn textobjects<'a>(&'a self, buffer: &'a Buffer) -> Option<(&'a Query, Node<'a>)> {
+        self.tree_query(buffer, |config| config.textobjects.as_ref())
+    }
+
+    /// the folds query for the grammar of the buffer's tree, with the tree's root
+    pub fn folds<'a>(&'a self, buffer: &'a Buffer) -> Option<(&'a Query, Node<'a>)> {
+        self.tree_query(buffer, |config| config.folds.as_ref())
+    }
+
+    /// the indents query for the grammar of the buffer's tree, with the tree's root
+    pub fn indents<'a>(&'a self, buffer: &'a Buffer) -> Option<(&'a Query, Node<'a>)> {
+        self.tree_query(buffer, |config| config.indents.as_ref())
+    }
+
+    /// the tags query (definitions for :Symbols) for the grammar of the buffer's tree, with the tree's root
+    pub fn tags<'a>(&'a self, buffer: &'a Buffer) -> Option<(&'a Query, Node<'a>)> {
+        self.tree_query(buffer, |config| config.tags.as_ref())
+    }
+
+    fn tree_query<'a>(&'a self, buffer: &'a Buffer, pick: fn(&LanguageConfig) -> Option<&Query>) -> Option<(&'a Query, Node<'a>)> {
+        let syntax = buffer.syntax_tree()?;
+        let config = self.languages.iter().find(|l| l.name == syntax.lang)?;
+        Some((pick(config)?, syntax.tree.root_node()))
+    }
+
+    /// install finished background parses; returns true if any buffer got a new tree
+    pub fn poll_parses(&mut self, buffers: &mut [Buffer]) -> bool {
+        let mut updated = false;
+        while let Ok(result) = self.results_rx.try_recv() {
+            self.parsing.remove(&result.buffer_id);
+            let Some(tree) = result.tree else {
+                for buffer in buffers.iter_mut().filter(|b| b.id() == result.buffer_id) {
+                    buffer.set_syntax_pending(false);
+                }
+                continue;
+            };
+            for buffer in buffers.iter_mut().filter(|b| b.id() == result.buffer_id) {
+                let newer = buffer.syntax_tree().map_or(true, |t| t.lang != tree.lang || t.version < tree.version);
+                if newer {
+                    buffer.set_syntax_tree(Some(tree.clone()));
+                    updated = true;
+                } else {
+                    buffer.set_syntax_pending(false);
+                }
+            }
+        }
+        updated
+    }
+
+    /// highlight spans for the bytes in `range` of the buffer, from its current tree
+    /// and any languages injected into it; returns sorted, non-overlapping
+    /// (byte_start, byte_end, highlight_index)
+    pub fn highlight(&mut self, buffer: &mut Buffer, range: Range<usize>) -> Vec<(usize, usize, usize)> {
+        let rope = buffer.rope().clone();
+        let Some(syntax) = buffer.syntax_tree_mut() else { return Vec::new(); };
+        let Some(lang) = self.languages.iter().position(|l| l.name == syntax.lang) else { return Vec::new(); };
+        if range.is_empty() {
+            return Vec::new();
+        }
+        let mut painted: Vec<Option<usize>> = vec![None; range.len()];
+        self.injection_trees = std::mem::take(&mut syntax.injections);
+        for injection in &mut self.injection_trees {
+            injection.used = false;
+        }
+        self.paint(lang, syntax.tree.root_node(), &rope, &range, &mut painted, 0);
+        // regions in view that no longer hold an injection, and emptied ones
+        syntax.injections = std::mem::take(&mut self.injection_trees);
+        syntax.injections.retain(|injection| {
+            let ranges = injection.tree.included_ranges();
+            let in_view = ranges.iter().any(|r| r.start_byte < range.end && r.end_byte > range.start);
+            let empty = ranges.iter().all(|r| r.start_byte >= r.end_byte);
+            (injection.used || !in_view) && !empty
+        });
+
+        let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+        for (i, hi) in painted.into_iter().enumerate() {
+            let Some(hi) = hi else { continue; };
+            let byte = range.start + i;
+            match spans.last_mut() {
+                Some(last) if last.1 == byte && last.2 == hi => last.1 += 1,
+                _ => spans.push((byte, byte + 1, hi)),
             }
         }
         spans
     }
 
-    /// check if we have a grammar for this extension
-    pub fn supports(&self, ext: &str) -> bool {
-        self.configs.iter().any(|(e, _)| *e == ext)
-    }
-}
-
-/// map highlight index to theme color
-pub fn highlight_color(index: usize, theme: &Theme) -> Color {
-    let name = HIGHLIGHT_NAMES.get(index).copied().unwrap_or("");
-    match name {
-        "comment" => theme.comment,
-        "string" | "string.special" => theme.string,
-        "number" | "constant" | "constant.builtin" => theme.warning, // orange
-        "keyword" => theme.accent,                                    // purple
-        "function" | "function.builtin" | "function.macro" => Color::Rgb { r: 0x7a, g: 0xa2, b: 0xf7 }, // blue
-        "type" | "type.builtin" | "constructor" => Color::Rgb { r: 0x2a, g: 0xc3, b: 0xde }, // cyan
-        "variable.builtin" => Color::Rgb { r: 0xf7, g: 0x76, b: 0x8e }, // red
-        "operator" => theme.fg,
-        "punctuation" | "punctuation.bracket" | "punctuation.delimiter" | "punctuation.special" => theme.comment,
-        "attribute" | "label" => theme.warning,
-        "namespace" => theme.accent,
-        "property" | "variable.parameter" => Color::Rgb { r: 0x7d, g: 0xcf, b: 0xff },
-        "escape" | "embedded" => theme.error,
-        "tag" => theme.error,
-        _ => theme.fg,
+    /// paint the captures of `root` (a tree of language `lang`) over `range`, then
+    /// each injected region on top
+    fn paint(&mut self, lang: usize, root: Node, rope: &Rope, range: &Range<usize>, painted: &mut [Option<usize>], depth: usize) {
+        let config = &self.languages[lang];
+        let len = rope.len_bytes();
+        let text = |node: Node| {
+            let r = node.start_byte().min(len)..node.end_byte().min(len);
+            rope.byte_slice(r).chunks().map(str::as_bytes)
+        };
+
+        // outer nodes first so nested ones win, and for the same node the earliest
+        // pattern in the query wins
+        let mut captures: Vec<(usize, usize, usize, usize)> = Vec::new(); // start, end, pattern, highlight
+        let references = self.local_references(lang, root, rope, range);
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range.clone());
+        let mut matches = cursor.captures(&config.query, root, text);
+        while let Some((m, index)) = matches.next() {
+            let capture = m.captures[*index];
+            let node = capture.node;
+            if references.iter().any(|r| r.0 == node.id()) {
+                continue;
+            }
+            if let Some(hi) = config.capture_highlights[capture.index as usize] {
+                captures.push((node.start_byte(), node.end_byte(), m.pattern_index, hi));
+            }
+        }
+        captures.extend(references.into_iter().map(|(_, start, end, hi)| (start, end, 0, hi)));
+        captures.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(b.2.cmp(&a.2)));
+        for (start, end, _, hi) in captures {
+            let (s, e) = (start.max(range.start), end.min(range.end));
+            if s < e {
+                painted[s - range.start..e - range.start].fill(Some(hi));
+            }
+        }
+
+        if depth >= MAX_INJECTION_DEPTH {
+            return;
+        }
+        for (inj_lang, ranges) in self.injections(lang, root, rope, range) {
+            if let Some(tree) = self.injection_tree(inj_lang, &ranges, rope) {
+                self.paint(inj_lang, tree.root_node(), rope, range, painted, depth + 1);
+            }
+        }
+    }
+
+    /// references in `range` to locals defined in an enclosing scope, resolved
+    /// by the locals query, with the highlight of their definition:
+    /// (node id, start, end, highlight)
+    fn local_references(&self, lang: usize, root: Node, rope: &Rope, range: &Range<usize>) -> Vec<(usize, usize, usize, usize)> {
+        let config = &self.languages[lang];
+        let Some(query) = &config.locals else { return Vec::new(); };
+        let len = rope.len_bytes();
+        let text = |node: Node| {
+            let r = node.start_byte().min(len)..node.end_byte().min(len);
+            rope.byte_slice(r).chunks().map(str::as_bytes)
+        };
+        let name = |node: Node| rope.byte_slice(node.start_byte().min(len)..node.end_byte().min(len)).to_string();
+        let kind = |i: u32| query.capture_names()[i as usize];
+
+        // definitions visible in the range can come from anywhere before it
+        let mut scopes: Vec<LocalScope> = vec![LocalScope { end: usize::MAX, inherits: false, definitions: Vec::new() }];
+        let mut defined: HashSet<usize> = HashSet::new();
+        let mut references = Vec::new();
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(0..range.end);
+        let mut matches = cursor.captures(query, root, text);
+        while let Some((m, index)) = matches.next() {
+            let node = m.captures[*index].node;
+            while scopes.len() > 1 && scopes.last().is_some_and(|s| s.end <= node.start_byte()) {
+                scopes.pop();
+            }
+            match kind(m.captures[*index].index) {
+                "local.scope" => {
+                    let inherits = !query.property_settings(m.pattern_index).iter()
+                        .any(|p| &*p.key == "local.scope-inherits" && p.value.as_deref() == Some("false"));
+                    scopes.push(LocalScope { end: node.end_byte(), inherits, definitions: Vec::new() });
+                }
+                "local.definition" => {
+                    defined.insert(node.id());
+                    if let Some(scope) = scopes.last_mut() {
+                        scope.definitions.push((name(node), node));
+                    }
+                }
+                "local.reference" if node.end_byte() > range.start && !defined.contains(&node.id()) => {
+                    let wanted = name(node);
+                    let mut found = None;
+                    for scope in scopes.iter().rev() {
+                        found = scope.definitions.iter().rev().find(|d| d.0 == wanted);
+                        if found.is_some() || !scope.inherits {
+                            break;
+                        }
+                    }
+                    if let Some(hi) = found.and_then(|d| self.node_highlight(config, root, d.1, rope)) {
+                        references.push((node.id(), node.start_byte(), node.end_byte(), hi));
+                    }
+                }
+                _ => {}
+            }
+        }
+        references
+    }
+
+    /// the highlight the earliest matching pattern gives exactly `node` of the tree at `root`
+    fn node_highlight(&self, config: &LanguageConfig, root: Node, node: Node, rope: &Rope) -> Option<usize> {
+        let len = rope.len_bytes();
+        let text = |node: Node| {
+            let r = node.start_byte().min(len)..node.end_byte().min(len);
+            rope.byte_slice(r).chunks().map(str::as_bytes)
+        };
+        let mut best: Option<(usize, usize)> = None; // pattern, highlight
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(node.byte_range());
+        let mut matches = cursor.captures(&config.query, root, text);
+        while let Some((m, index)) = matches.next() {
+            let capture = m.captures[*index];
+            if capture.node.id() != node.id() {
+                continue;
+            }
+            if let Some(hi) = config.capture_highlights[capture.index as usize] {
+                if best.map_or(true, |(pattern, _)| m.pattern_index < pattern) {
+                    best = Some((m.pattern_index, hi));
+                }
+            }
+        }
+        best.map(|(_, hi)| hi)
+    }
+
+    /// the tree of `ranges` injected as `lang`: the cached one when no edit
+    /// touched it, else reparsed incrementally from the cached one for the same
+    /// ranges (or ones overlapping them), else parsed fresh
+    fn injection_tree(&mut self, lang: usize, ranges: &[tree_sitter::Range], rope: &Rope) -> Option<Tree> {
+        let cache = &mut self.injection_trees;
+        let wanted = byte_ranges(ranges);
+        let (first, last) = (wanted.first()?.0, wanted.last()?.1);
+        let candidate = |c: &InjectionTree| c.lang == lang && !c.used;
+        let exact = cache.iter().position(|c| candidate(c) && byte_ranges(&c.tree.included_ranges()) == wanted);
+        if let Some(i) = exact.filter(|&i| !cache[i].edited) {
+            cache[i].used = true;
+            return Some(cache[i].tree.clone());
+        }
+        let old = exact.or_else(|| cache.iter().position(|c| {
+            let had = c.tree.included_ranges();
+            candidate(c) && had.first().is_some_and(|r| r.start_byte < last) && had.last().is_some_and(|r| r.end_byte > first)
+        }));
+        if self.parser.set_language(&self.languages[lang].language).is_err()
+            || self.parser.set_included_ranges(ranges).is_err()
+        {
+            return None;
+        }
+        let tree = parse_rope(&mut self.parser, rope, old.map(|i| &self.injection_trees[i].tree));
+        let _ = self.parser.set_included_ranges(&[]);
+        let tree = tree?;
+        let entry = InjectionTree { lang, tree: tree.clone(), edited: false, used: true };
+        match old {
+            Some(i) => self.injection_trees[i] = entry,
+            None => self.injection_trees.push(entry),
+        }
+        Some(tree)
+    }
+
+    /// regions of `root` within `range` that hold another registered language,
+    /// each as the ranges to parse it from; injection.combined patterns gather
+    /// all their matches in the tree into one region per language
+    fn injections(&self, lang: usize, root: Node, rope: &Rope, range: &Range<usize>) -> Vec<(usize, Vec<tree_sitter::Range>)> {
+        let config = &self.languages[lang];
+        let mut found = Vec::new();
+        if let Some(query) = &config.injections {
+            found = self.injection_matches(query, root, rope, Some(range));
+        }
+        if let Some(query) = &config.combined_injections {
+            let mut combined: Vec<(usize, Vec<tree_sitter::Range>)> = Vec::new();
+            for (inj_lang, ranges) in self.injection_matches(query, root, rope, None) {
+                match combined.iter_mut().find(|(l, _)| *l == inj_lang) {
+                    Some((_, all)) => all.extend(ranges),
+                    None => combined.push((inj_lang, ranges)),
+                }
+            }
+            for (inj_lang, mut ranges) in combined {
+                ranges.sort_by_key(|r| r.start_byte);
+                ranges.dedup_by_key(|r| r.start_byte);
+                if ranges.iter().any(|r| r.start_byte < range.end && r.end_byte > range.start) {
+                    found.push((inj_lang, ranges));
+                }
+            }
+        }
+        found.sort_by_key(|(inj_lang, ranges)| (*inj_lang, byte_ranges(ranges)));
+        found.dedup_by(|a, b| a.0 == b.0 && byte_ranges(&a.1) == byte_ranges(&b.1));
+        found
+    }
+
+    /// the language and ranges of each match of an injections query, in `range`
+    /// or the whole tree
+    fn injection_matches(&self, query: &Query, root: Node, rope: &Rope, range: Option<&Range<usize>>) -> Vec<(usize, Vec<tree_sitter::Range>)> {
+        let len = rope.len_bytes();
+        let text = |node: Node| {
+            let r = node.start_byte().min(len)..node.end_byte().min(len);
+            rope.byte_slice(r).chunks().map(str::as_bytes)
+        };
+        let content_idx = query.capture_index_for_name("injection.content");
+        let language_idx = query.capture_index_for_name("injection.language");
+        let mut found = Vec::new();
+        let mut cursor = QueryCursor::new();
+        if let Some(range) = range {
+            cursor.set_byte_range(range.clone());
+        }
+        let mut matches = cursor.matches(query, root, text);
+        while let Some(m) = matches.next() {
+            let settings = query.property_settings(m.pattern_index);
+            let mut name: Option<String> = settings.iter()
+                .find(|p| &*p.key == "injection.language")
+                .and_then(|p| p.value.as_deref().map(str::to_string));
+            let include_children = settings.iter().any(|p| &*p.key == "injection.include-children");
+            let mut content: Option<Node> = None;
+            for capture in m.captures {
+                if Some(capture.index) == language_idx {
+                    let r = capture.node.start_byte().min(len)..capture.node.end_byte().min(len);
+                    name = Some(rope.byte_slice(r).to_string());
+                } else if Some(capture.index) == content_idx {
+                    content = Some(capture.node);
+                }
+            }
+            let (Some(name), Some(content)) = (name, content) else { continue; };
+            let ranges = content_ranges(content, include_children);
+            if let (Some(inj_lang), false) = (self.injected_language(&name), ranges.is_empty()) {
+                found.push((inj_lang, ranges));
+            }
+        }
+        found
+    }
+
+    /// a language named by an injection: grammar name, file extension, or a
+    /// fence info string like "rust,ignore" or "{.python}"
+    fn injected_language(&self, name: &str) -> Option<usize> {
+        let name = name.trim().trim_start_matches(['{', '.']).to_ascii_lowercase();
+        let name = name.split([',', ' ', '}']).next().unwrap_or("");
+        if name.is_empty() {
+            return None;
+        }
+        self.language_index(name)
+    }
+}
+
+/// style for a highlight index, resolved through the theme
+pub fn highlight_style(index: usize, theme: &Theme) -> Style {
+    theme.capture_style(HIGHLIGHT_NAMES.get(index).copied().unwrap_or(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_highlight_index() {
+        let idx = |name: &str| highlight_index(name).map(|i| HIGHLIGHT_NAMES[i]);
+        assert_eq!(idx("function.method.call"), Some("function.method"));
+        assert_eq!(idx("function.macro"), Some("function.macro"));
+        assert_eq!(idx("keyword.coroutine"), Some("keyword"));
+        assert_eq!(idx("functional"), None);
+        assert_eq!(idx("local.scope"), None);
+    }
+
+    #[test]
+    fn test_user_query_overrides_bundled() {
+        let dir = TestDir::new("query");
+        std::fs::create_dir_all(dir.join("queries/json")).unwrap();
+        std::fs::write(dir.join("queries/json/highlights.scm"), "(string) @keyword").unwrap();
+        std::fs::create_dir_all(dir.join("queries/go")).unwrap();
+        std::fs::write(dir.join("queries/go/highlights.scm"), "(no_such_node) @keyword").unwrap();
+        let registry = LanguageRegistry::with_overrides("[[language]]\nname = \"json\"\nextensions = [\"json\", \"jsonc\"]\n").unwrap();
+        let (sh, errors) = SyntaxHighlighter::with_runtime(Some(&dir), &registry);
+
+        #[cfg(feature = "lang-json")]
+        {
+            let json = sh.language("jsonc").unwrap();
+            assert_eq!(json.name, "json");
+            assert!(matches!(json.query_sources[0], QuerySource::User(_)));
+            assert_eq!(json.query.pattern_count(), 1);
+        }
+        #[cfg(feature = "lang-go")]
+        {
+            // a broken override is reported and the bundled query stays in use
+            assert!(errors.iter().any(|e| e.contains("go")));
+            assert_eq!(sh.language("go").unwrap().query_sources[0], QuerySource::Bundled);
+        }
+        let _ = (sh, errors);
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_comment_above_viewport() {
+        let mut sh = SyntaxHighlighter::new();
+        let mut buffer = Buffer::from_string("fn a() {}\n/* one\ntwo\n*/\nfn b() {}\n");
+        sh.update(&mut buffer, "rs");
+        let line2 = buffer.line_to_byte(2)..buffer.line_to_byte(3);
+        let spans = sh.highlight(&mut buffer, line2.clone());
+        let comment = HIGHLIGHT_NAMES.iter().position(|n| *n == "comment").unwrap();
+        assert_eq!(spans.first().map(|s| s.2), Some(comment));
+
+        // closing the comment early un-comments line 2 after an incremental reparse
+        buffer.insert_char(1, 6, '*');
+        buffer.insert_char(1, 7, '/');
+        assert!(buffer.syntax_tree().unwrap().version < buffer.version());
+        sh.update(&mut buffer, "rs");
+        assert_eq!(buffer.syntax_tree().unwrap().version, buffer.version());
+        let line2 = buffer.line_to_byte(2)..buffer.line_to_byte(3);
+        assert!(sh.highlight(&mut buffer, line2).iter().all(|s| s.2 != comment));
+    }
+
+    #[cfg(all(feature = "lang-md", feature = "lang-rust"))]
+    #[test]
+    fn test_markdown_injections() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "# Title\n\n```rust\nfn main() {}\n```\n\nsome **bold** text\n";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "md");
+        let spans = sh.highlight(&mut buffer, 0..text.len());
+        let name_at = |needle: &str| {
+            let byte = text.find(needle).unwrap();
+            spans.iter().find(|s| s.0 <= byte && byte < s.1).map(|s| HIGHLIGHT_NAMES[s.2])
+        };
+        assert_eq!(name_at("fn main"), Some("keyword"));
+        assert_eq!(name_at("bold"), Some("text.strong"));
+        assert_eq!(name_at("Title"), Some("text.title"));
+        assert!(sh.injected_language("{.rs}").is_some());
+        assert!(sh.injected_language("cobol").is_none());
+    }
+
+    #[cfg(all(feature = "lang-md", feature = "lang-rust"))]
+    #[test]
+    fn test_injection_trees_are_kept_and_edited() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "```rust\nfn main() {}\n```\n\ntext\n";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "md");
+        sh.highlight(&mut buffer, 0..text.len());
+        let rust = sh.language_index("rust").unwrap();
+        let cached = |buffer: &Buffer| -> Vec<(Vec<(usize, usize)>, bool)> {
+            buffer.syntax_tree().unwrap().injections.iter()
+                .filter(|i| i.lang == rust)
+                .map(|i| (byte_ranges(&i.tree.included_ranges()), i.edited))
+                .collect()
+        };
+        assert_eq!(cached(&buffer), vec![(vec![(8, 21)], false)]);
+
+        // an edit after the block leaves its tree alone, one inside marks it
+        buffer.insert_char(4, 0, 'x');
+        assert_eq!(cached(&buffer), vec![(vec![(8, 21)], false)]);
+        buffer.insert_char(1, 0, ' ');
+        assert_eq!(cached(&buffer), vec![(vec![(9, 22)], true)]);
+        sh.update(&mut buffer, "md");
+        let len = buffer.rope().len_bytes();
+        let spans = sh.highlight(&mut buffer, 0..len);
+        assert_eq!(cached(&buffer), vec![(vec![(8, 22)], false)]);
+        assert!(spans.iter().any(|s| s.0 == 9 && HIGHLIGHT_NAMES[s.2] == "keyword"));
+
+        // removing the block drops its tree
+        buffer.delete_range(0, 0, 3, 0);
+        sh.update(&mut buffer, "md");
+        let len = buffer.rope().len_bytes();
+        sh.highlight(&mut buffer, 0..len);
+        assert!(cached(&buffer).is_empty());
+    }
+
+    #[cfg(all(feature = "lang-js", feature = "lang-json"))]
+    #[test]
+    fn test_combined_injections() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "json`[1, ${x}, 2]`;\nfoo(json`{}`);\n";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "js");
+        let (js, json) = (sh.language_index("javascript").unwrap(), sh.language_index("json").unwrap());
+        assert!(sh.languages[js].combined_injections.is_some());
+        let root = buffer.syntax_tree().unwrap().tree.root_node();
+        let found = sh.injections(js, root, buffer.rope(), &(0..text.len()));
+        // every json`...` fragment in the file is one document
+        let ranges: Vec<(usize, Vec<(usize, usize)>)> = found.iter().map(|(l, r)| (*l, byte_ranges(r))).collect();
+        assert_eq!(ranges, vec![(json, vec![(5, 9), (13, 17), (29, 31)])]);
+    }
+
+    #[cfg(feature = "lang-js")]
+    #[test]
+    fn test_references_take_the_highlight_of_their_local() {
+        let mut sh = SyntaxHighlighter::new();
+        let highlights = "(formal_parameters (identifier) @variable.parameter)\n(identifier) @variable";
+        let mut errors = Vec::new();
+        let config = build_language("javascript", vec!["js".to_string()], tree_sitter_javascript::LANGUAGE.into(), None,
+            [highlights, "", tree_sitter_javascript::LOCALS_QUERY, "", "", "", ""], None, &mut errors);
+        let js = sh.language_index("javascript").unwrap();
+        sh.languages[js] = config;
+        assert!(errors.is_empty());
+
+        let text = "function f(p) { return p; }\nlet q = p;\n";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "js");
+        let name_at = |spans: &[(usize, usize, usize)], byte: usize| {
+            spans.iter().find(|s| s.0 <= byte && byte < s.1).map(|s| HIGHLIGHT_NAMES[s.2])
+        };
+        let spans = sh.highlight(&mut buffer, 0..text.len());
+        assert_eq!(name_at(&spans, text.find("p)").unwrap()), Some("variable.parameter"));
+        assert_eq!(name_at(&spans, text.find("p;").unwrap()), Some("variable.parameter"));
+        // out of the function's scope the name is a plain variable again
+        assert_eq!(name_at(&spans, text.rfind('p').unwrap()), Some("variable"));
+        // a range starting after the definition still resolves it
+        let body = text.find("return").unwrap()..text.len();
+        let spans = sh.highlight(&mut buffer, body);
+        assert_eq!(name_at(&spans, text.find("p;").unwrap()), Some("variable.parameter"));
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_stale_tree_catches_up_with_edits() {
+        let mut sh = SyntaxHighlighter::new();
+        let mut buffer = Buffer::from_string("fn a() {}\n");
+        sh.update(&mut buffer, "rs");
+        let old = buffer.syntax_tree().cloned().unwrap();
+        buffer.insert_char(0, 0, ' ');
+        buffer.delete_range(0, 0, 0, 3);
+        // a tree parsed before those edits gets them replayed when installed
+        buffer.set_syntax_tree(Some(old));
+        let root = buffer.syntax_tree().unwrap().tree.root_node();
+        assert_eq!(root.end_byte(), buffer.rope().len_bytes());
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_edits_during_first_background_parse() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "fn a() {}\n".repeat(BACKGROUND_PARSE_BYTES / 10 + 1);
+        let mut buffer = Buffer::from_string(&text);
+        sh.update(&mut buffer, "rs");
+        assert!(buffer.syntax_tree().is_none());
+        // typed while the first tree is still being parsed on the worker
+        buffer.insert_char(0, 0, '\n');
+        buffer.insert_char(0, 0, '}');
+        buffer.insert_char(0, 0, '{');
+        let mut waited = 0;
+        while !sh.poll_parses(std::slice::from_mut(&mut buffer)) {
+            assert!(waited < 500, "background parse did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            waited += 1;
+        }
+        let tree = buffer.syntax_tree().unwrap();
+        assert!(tree.version < buffer.version());
+        assert_eq!(tree.tree.root_node().end_byte(), buffer.rope().len_bytes());
+        // an incremental reparse from the caught-up tree gives what a fresh parse does
+        let mut fresh = Buffer::from_string(&buffer.rope().to_string());
+        for buffer in [&mut buffer, &mut fresh] {
+            sh.update(buffer, "rs");
+            while !sh.poll_parses(std::slice::from_mut(buffer)) {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            assert_eq!(buffer.syntax_tree().unwrap().version, buffer.version());
+        }
+        let root = |b: &Buffer| b.syntax_tree().unwrap().tree.root_node().child_count();
+        assert_eq!(root(&buffer), root(&fresh));
+        let first = |b: &Buffer| b.syntax_tree().unwrap().tree.root_node().child(0).unwrap().kind();
+        assert_eq!(first(&buffer), first(&fresh));
     }
 }
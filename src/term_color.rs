@@ -0,0 +1,246 @@
+// terminal color capability detection and RGB downsampling
+
+use crossterm::style::Color;
+use std::path::PathBuf;
+
+/// how many colors the terminal can display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// detect from the environment: COLORTERM, then TERM, then the terminfo `colors` number
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").ok();
+        let term = std::env::var("TERM").ok();
+        let terminfo = term.as_deref().and_then(terminfo_colors);
+        Self::from_env(colorterm.as_deref(), term.as_deref(), terminfo)
+    }
+
+    /// `termguicolors` applied to support detected earlier: Some(true) forces 24-bit
+    /// color, Some(false) limits output to the 256-color palette, None trusts detection
+    pub fn with_termguicolors(self, termguicolors: Option<bool>) -> Self {
+        match termguicolors {
+            Some(true) => ColorSupport::TrueColor,
+            Some(false) => self.min(ColorSupport::Ansi256),
+            None => self,
+        }
+    }
+
+    pub fn from_env(colorterm: Option<&str>, term: Option<&str>, terminfo_colors: Option<u32>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            return ColorSupport::TrueColor;
+        }
+        let term = term.unwrap_or("");
+        if term.ends_with("-direct") || term.contains("truecolor") {
+            return ColorSupport::TrueColor;
+        }
+        match terminfo_colors {
+            Some(n) if n >= 1 << 24 => ColorSupport::TrueColor,
+            Some(n) if n >= 256 => ColorSupport::Ansi256,
+            Some(_) => ColorSupport::Ansi16,
+            None if term.contains("256color") => ColorSupport::Ansi256,
+            None => ColorSupport::Ansi16,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSupport::Ansi16 => "16 colors",
+            ColorSupport::Ansi256 => "256 colors",
+            ColorSupport::TrueColor => "truecolor",
+        }
+    }
+}
+
+/// the `colors` number from the compiled terminfo entry for `term`
+fn terminfo_colors(term: &str) -> Option<u32> {
+    let first = term.chars().next()?;
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+    for dir in dirs {
+        // Linux uses the first letter as subdirectory, macOS its hex code
+        for sub in [first.to_string(), format!("{:x}", first as u32)] {
+            if let Ok(data) = std::fs::read(dir.join(sub).join(term)) {
+                return parse_terminfo_colors(&data);
+            }
+        }
+    }
+    None
+}
+
+/// read the `colors` numeric capability from a compiled terminfo file
+fn parse_terminfo_colors(data: &[u8]) -> Option<u32> {
+    const COLORS: usize = 13; // index of `colors` among the numeric capabilities
+    let short = |i: usize| -> Option<usize> {
+        data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+    let num_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4, // ncurses 6 extended number format
+        _ => return None,
+    };
+    let (names_size, bools_count, nums_count) = (short(2)?, short(4)?, short(6)?);
+    if COLORS >= nums_count {
+        return None;
+    }
+    let mut offset = 12 + names_size + bools_count;
+    offset += offset % 2; // numbers start on an even byte
+    let at = offset + COLORS * num_size;
+    let bytes = data.get(at..at + num_size)?;
+    let value = if num_size == 2 {
+        i16::from_le_bytes([bytes[0], bytes[1]]) as i32
+    } else {
+        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    u32::try_from(value).ok()
+}
+
+// xterm's values for the 16 ANSI colors, in crossterm's order
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+// channel levels of the xterm 6x6x6 color cube (indices 16-231)
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    // weight green most and blue least, roughly following perceived brightness
+    3 * d(a.0, b.0) + 4 * d(a.1, b.1) + 2 * d(a.2, b.2)
+}
+
+fn nearest_cube_level(v: u8) -> usize {
+    (0..6).min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs()).unwrap_or(0)
+}
+
+/// rgb value of an xterm-256 palette index
+fn ansi256_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI16[index as usize].1,
+        16..=231 => {
+            let i = index - 16;
+            (CUBE_LEVELS[(i / 36) as usize], CUBE_LEVELS[(i / 6 % 6) as usize], CUBE_LEVELS[(i % 6) as usize])
+        }
+        _ => {
+            let v = 8 + (index - 232) * 10;
+            (v, v, v)
+        }
+    }
+}
+
+/// nearest xterm-256 index, choosing between the color cube and the grey ramp
+pub fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let (ri, gi, bi) = (nearest_cube_level(r), nearest_cube_level(g), nearest_cube_level(b));
+    let cube = (16 + 36 * ri + 6 * gi + bi) as u8;
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let grey = if avg < 8 { 232 } else { (232 + ((avg - 8) / 10).min(23)) as u8 };
+    if distance((r, g, b), ansi256_rgb(grey)) < distance((r, g, b), ansi256_rgb(cube)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+/// nearest of the 16 ANSI colors
+pub fn rgb_to_ansi16(r: u8, g: u8, b: u8) -> Color {
+    ANSI16.iter()
+        .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+        .map(|(c, _)| *c)
+        .unwrap_or(Color::Reset)
+}
+
+/// map a color to one the terminal can show; named colors pass through untouched
+pub fn downsample(color: Color, support: ColorSupport) -> Color {
+    match (color, support) {
+        (_, ColorSupport::TrueColor) => color,
+        (Color::Rgb { r, g, b }, ColorSupport::Ansi256) => Color::AnsiValue(rgb_to_ansi256(r, g, b)),
+        (Color::Rgb { r, g, b }, ColorSupport::Ansi16) => rgb_to_ansi16(r, g, b),
+        (Color::AnsiValue(n), ColorSupport::Ansi16) if n >= 16 => {
+            let (r, g, b) = ansi256_rgb(n);
+            rgb_to_ansi16(r, g, b)
+        }
+        _ => color,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_termguicolors() {
+        assert_eq!(ColorSupport::Ansi16.with_termguicolors(Some(true)), ColorSupport::TrueColor);
+        assert_eq!(ColorSupport::TrueColor.with_termguicolors(Some(false)), ColorSupport::Ansi256);
+        assert_eq!(ColorSupport::Ansi16.with_termguicolors(Some(false)), ColorSupport::Ansi16);
+        assert_eq!(ColorSupport::Ansi256.with_termguicolors(None), ColorSupport::Ansi256);
+    }
+
+    #[test]
+    fn test_detect_from_env() {
+        assert_eq!(ColorSupport::from_env(Some("truecolor"), Some("xterm"), None), ColorSupport::TrueColor);
+        assert_eq!(ColorSupport::from_env(None, Some("xterm-direct"), None), ColorSupport::TrueColor);
+        assert_eq!(ColorSupport::from_env(None, Some("tmux-256color"), None), ColorSupport::Ansi256);
+        assert_eq!(ColorSupport::from_env(None, Some("screen"), Some(8)), ColorSupport::Ansi16);
+        assert_eq!(ColorSupport::from_env(None, Some("xterm"), Some(256)), ColorSupport::Ansi256);
+        assert_eq!(ColorSupport::from_env(None, None, None), ColorSupport::Ansi16);
+    }
+
+    #[test]
+    fn test_parse_terminfo_colors() {
+        // header: magic, names size, bool count, number count, string count, table size
+        let mut data: Vec<u8> = [0o432u16, 3, 2, 14, 0, 0].iter().flat_map(|n| n.to_le_bytes()).collect();
+        data.extend(b"xt\0"); // names
+        data.extend([1, 0]); // two booleans
+        data.push(0); // pad byte: numbers start at an even offset
+        for i in 0..14u16 {
+            data.extend((if i == 13 { 256u16 } else { 0xffff }).to_le_bytes());
+        }
+        assert_eq!(parse_terminfo_colors(&data), Some(256));
+        assert_eq!(parse_terminfo_colors(b"garbage"), None);
+    }
+
+    #[test]
+    fn test_rgb_to_ansi256() {
+        assert_eq!(rgb_to_ansi256(255, 0, 0), 196);
+        assert_eq!(rgb_to_ansi256(0, 0, 0), 16);
+        assert_eq!(rgb_to_ansi256(128, 128, 128), 244);
+        assert_eq!(rgb_to_ansi256(0x1a, 0x1b, 0x26), 234);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let c = Color::Rgb { r: 250, g: 10, b: 10 };
+        assert_eq!(downsample(c, ColorSupport::TrueColor), c);
+        assert_eq!(downsample(c, ColorSupport::Ansi16), Color::Red);
+        assert_eq!(downsample(Color::Reset, ColorSupport::Ansi16), Color::Reset);
+        assert_eq!(downsample(Color::AnsiValue(196), ColorSupport::Ansi16), Color::Red);
+    }
+}
@@ -14,6 +14,7 @@ use crate::error::Result;
 pub struct Terminal {
     width: u16,
     height: u16,
+    raw: bool, // in raw mode on the alternate screen, to be restored on drop
 }
 
 impl Terminal {
@@ -24,7 +25,13 @@ impl Terminal {
 
         let (width, height) = terminal::size()?;
 
-        Ok(Self { width, height })
+        Ok(Self { width, height, raw: true })
+    }
+
+    /// a terminal of a fixed size that is never drawn to, for tests
+    #[cfg(test)]
+    pub(crate) fn headless(width: u16, height: u16) -> Self {
+        Self { width, height, raw: false }
     }
 
     pub fn size(&self) -> (u16, u16) {
@@ -129,6 +136,9 @@ impl Terminal {
 
 impl Drop for Terminal {
     fn drop(&mut self) {
+        if !self.raw {
+            return;
+        }
         let _ = execute!(io::stdout(), cursor::Show);
         let _ = execute!(io::stdout(), terminal::LeaveAlternateScreen);
         let _ = terminal::disable_raw_mode();
@@ -0,0 +1,356 @@
+// syntax-aware text objects and function motions from `textobjects.scm` queries
+//
+// captures follow nvim-treesitter-textobjects: @function.outer/inner,
+// @class.outer/inner, @parameter.inner, @comment.outer, @loop.outer/inner.
+// Outer arguments and inner comments are derived here instead of in the query.
+
+use ropey::Rope;
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Function,
+    Class,
+    Parameter,
+    Comment,
+    Loop,
+}
+
+impl ObjectKind {
+    /// the object bound to the key after `a`/`i`
+    pub fn from_key(c: char) -> Option<Self> {
+        match c {
+            'f' => Some(ObjectKind::Function),
+            'c' => Some(ObjectKind::Class),
+            'a' => Some(ObjectKind::Parameter),
+            '/' => Some(ObjectKind::Comment),
+            'l' => Some(ObjectKind::Loop),
+            _ => None,
+        }
+    }
+
+    fn capture(&self) -> &'static str {
+        match self {
+            ObjectKind::Function => "function",
+            ObjectKind::Class => "class",
+            ObjectKind::Parameter => "parameter",
+            ObjectKind::Comment => "comment",
+            ObjectKind::Loop => "loop",
+        }
+    }
+}
+
+/// byte range of a text object; linewise ranges cover whole lines including the
+/// final newline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectRange {
+    pub start: usize,
+    pub end: usize,
+    pub linewise: bool,
+}
+
+fn node_range(node: Node) -> Range<usize> {
+    node.start_byte()..node.end_byte()
+}
+
+/// nodes captured as one of `names` over the whole tree, in document order, each
+/// paired with the node its match captured as `names[1]`
+fn captured_nodes<'t>(query: &Query, root: Node<'t>, rope: &Rope, names: &[&str]) -> Vec<(usize, Node<'t>, Option<Node<'t>>)> {
+    let len = rope.len_bytes();
+    let text = |node: Node| {
+        let r = node.start_byte().min(len)..node.end_byte().min(len);
+        rope.byte_slice(r).chunks().map(str::as_bytes)
+    };
+    let indices: Vec<Option<u32>> = names.iter().map(|n| query.capture_index_for_name(n)).collect();
+    let mut found = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, root, text);
+    while let Some(m) = matches.next() {
+        let node_for = |i: usize| m.captures.iter().find(|c| Some(c.index) == indices[i]).map(|c| c.node);
+        if let Some(node) = node_for(0) {
+            found.push((0, node, indices.get(1).and_then(|_| node_for(1))));
+        } else if let Some(node) = indices.get(1).and_then(|_| node_for(1)) {
+            found.push((1, node, None));
+        }
+    }
+    found.sort_by_key(|(_, n, _)| (n.start_byte(), std::cmp::Reverse(n.end_byte())));
+    found
+}
+
+fn is_blank(rope: &Rope, range: Range<usize>) -> bool {
+    rope.byte_slice(range).chars().all(|c| c.is_whitespace())
+}
+
+/// shrink a body to what is between its brackets, without surrounding whitespace
+fn body_contents(rope: &Rope, range: Range<usize>) -> Range<usize> {
+    let text = rope.byte_slice(range.clone()).to_string();
+    let (mut from, mut to) = (0, text.len());
+    for (open, close) in [('{', '}'), ('(', ')'), ('[', ']')] {
+        if text.len() >= 2 && text.starts_with(open) && text.ends_with(close) {
+            (from, to) = (1, text.len() - 1);
+            break;
+        }
+    }
+    let inner = &text[from..to];
+    let start = range.start + from + (inner.len() - inner.trim_start().len());
+    let end = range.start + to - (inner.len() - inner.trim_end().len());
+    start..end.max(start)
+}
+
+/// an argument with the comma after it, or the comma before it if it is the last
+fn parameter_outer(rope: &Rope, node: Node) -> Range<usize> {
+    let range = node_range(node);
+    if let Some(comma) = node.next_sibling().filter(|n| n.kind() == ",") {
+        let mut end = comma.end_byte();
+        let line_end = rope.line_to_byte((rope.byte_to_line(end) + 1).min(rope.len_lines()));
+        while end < line_end && matches!(rope.byte(end), b' ' | b'\t') {
+            end += 1;
+        }
+        return range.start..end;
+    }
+    match node.prev_sibling().filter(|n| n.kind() == ",") {
+        Some(comma) => comma.start_byte()..range.end,
+        None => range,
+    }
+}
+
+/// runs of comments separated by nothing but a line break count as one
+fn comment_groups(rope: &Rope, nodes: &[Node]) -> Vec<Range<usize>> {
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    for node in nodes {
+        let range = node_range(*node);
+        match groups.last_mut() {
+            Some(last) if last.end <= range.start
+                && is_blank(rope, last.end..range.start)
+                && rope.byte_slice(last.end..range.start).chars().filter(|&c| c == '\n').count() <= 1 => {
+                last.end = range.end;
+            }
+            _ => groups.push(range),
+        }
+    }
+    groups
+}
+
+/// a comment without its markers and the whitespace around the text
+fn comment_contents(rope: &Rope, range: Range<usize>) -> Range<usize> {
+    let text = rope.byte_slice(range.clone()).to_string();
+    let body = text.trim_end();
+    let body = body.strip_suffix("*/").unwrap_or(body).trim_end();
+    let lead = ["///", "//!", "//", "/**", "/*", "#"].iter()
+        .find_map(|m| body.strip_prefix(m))
+        .unwrap_or(body);
+    let start = range.start + (body.len() - lead.len()) + (lead.len() - lead.trim_start().len());
+    let end = (range.start + body.len()).max(start);
+    start..end
+}
+
+/// whole lines when only whitespace shares the lines with the range
+fn widen_to_lines(rope: &Rope, range: Range<usize>) -> ObjectRange {
+    let first = rope.byte_to_line(range.start);
+    let last = rope.byte_to_line(range.end.saturating_sub(1).max(range.start));
+    let line_start = rope.line_to_byte(first);
+    let next_line = rope.line_to_byte((last + 1).min(rope.len_lines()));
+    let line_end = if next_line > 0 && rope.byte(next_line - 1) == b'\n' { next_line - 1 } else { next_line };
+    if !range.is_empty() && is_blank(rope, line_start..range.start) && is_blank(rope, range.end.min(line_end)..line_end) {
+        ObjectRange { start: line_start, end: next_line, linewise: true }
+    } else {
+        ObjectRange { start: range.start, end: range.end, linewise: false }
+    }
+}
+
+/// the object of `kind` around byte `cursor`, the innermost one if they nest;
+/// when the cursor is in none, the next one after it
+pub fn select(query: &Query, root: Node, rope: &Rope, kind: ObjectKind, inner: bool, cursor: usize) -> Option<ObjectRange> {
+    let outer_name = format!("{}.outer", kind.capture());
+    let inner_name = format!("{}.inner", kind.capture());
+    let nodes = captured_nodes(query, root, rope, &[&outer_name, &inner_name]);
+
+    // (outer range, inner range) per candidate
+    let candidates: Vec<(Range<usize>, Range<usize>)> = match kind {
+        ObjectKind::Parameter => nodes.iter()
+            .map(|(_, node, _)| (parameter_outer(rope, *node), node_range(*node)))
+            .collect(),
+        ObjectKind::Comment => {
+            let comments: Vec<Node> = nodes.iter().filter(|(i, _, _)| *i == 0).map(|(_, n, _)| *n).collect();
+            comment_groups(rope, &comments).into_iter()
+                .map(|group| (group.clone(), comment_contents(rope, group)))
+                .collect()
+        }
+        _ => nodes.iter()
+            .filter(|(i, _, _)| *i == 0)
+            .map(|(_, outer, body)| {
+                let body = body.unwrap_or(*outer);
+                let contents = if body == *outer { node_range(body) } else { body_contents(rope, node_range(body)) };
+                (node_range(*outer), contents)
+            })
+            .collect(),
+    };
+
+    let (outer, inner_range) = candidates.iter()
+        .filter(|(outer, _)| outer.start <= cursor && cursor < outer.end)
+        .min_by_key(|(outer, _)| outer.len())
+        .or_else(|| candidates.iter().filter(|(outer, _)| outer.start > cursor).min_by_key(|(outer, _)| outer.start))
+        .cloned()?;
+    let range = if inner { inner_range } else { outer };
+    if kind == ObjectKind::Parameter {
+        return Some(ObjectRange { start: range.start, end: range.end, linewise: false });
+    }
+    Some(widen_to_lines(rope, range))
+}
+
+/// start bytes of functions in document order; `outermost` skips functions
+/// nested inside another function
+pub fn function_starts(query: &Query, root: Node, rope: &Rope, outermost: bool) -> Vec<usize> {
+    let nodes: Vec<Node> = captured_nodes(query, root, rope, &["function.outer"]).into_iter().map(|(_, n, _)| n).collect();
+    let mut starts: Vec<usize> = nodes.iter()
+        // a wrapper like a decorated definition and the function it wraps are one function
+        .filter(|n| !n.parent().is_some_and(|p| nodes.iter().any(|o| o.id() == p.id())))
+        .filter(|n| !outermost || !nodes.iter().any(|o| {
+            o.id() != n.id() && o.start_byte() <= n.start_byte() && n.end_byte() <= o.end_byte()
+        }))
+        .map(|n| n.start_byte())
+        .collect();
+    starts.dedup();
+    starts
+}
+
+/// (first line, last line) of the functions, classes and loops that overlap
+/// `lines`, outer before inner; a wrapper such as a decorated definition stands
+/// for the definition it wraps
+pub fn scopes(query: &Query, root: Node, rope: &Rope, lines: Range<usize>) -> Vec<(usize, usize)> {
+    let len = rope.len_bytes();
+    let text = |node: Node| {
+        let r = node.start_byte().min(len)..node.end_byte().min(len);
+        rope.byte_slice(r).chunks().map(str::as_bytes)
+    };
+    let indices: Vec<u32> = ["function.outer", "class.outer", "loop.outer"].iter()
+        .filter_map(|n| query.capture_index_for_name(n))
+        .collect();
+    let last = lines.end.min(rope.len_lines());
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(rope.line_to_byte(lines.start.min(last))..rope.line_to_byte(last));
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut matches = cursor.matches(query, root, text);
+    while let Some(m) = matches.next() {
+        nodes.extend(m.captures.iter().filter(|c| indices.contains(&c.index)).map(|c| c.node));
+    }
+    let mut found: Vec<(usize, usize)> = nodes.iter()
+        .filter(|n| !n.parent().is_some_and(|p| nodes.iter().any(|o| o.id() == p.id())))
+        .map(|n| (n.start_position().row, n.end_position().row))
+        .collect();
+    found.sort_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+    found.dedup_by_key(|(start, _)| *start);
+    found
+}
+
+/// scope start lines to pin above a window whose rows show `line_at_row`, at
+/// most `max` of them and the innermost when there are more: the scopes around
+/// the first line the pinned rows leave in view that start above it
+pub fn context_lines(scopes: &[(usize, usize)], line_at_row: impl Fn(usize) -> Option<usize>, max: usize) -> Vec<usize> {
+    let mut pinned: Vec<usize> = Vec::new();
+    for _ in 0..=max {
+        let Some(line) = line_at_row(pinned.len()) else { break; };
+        let mut next: Vec<usize> = scopes.iter()
+            .filter(|&&(start, end)| start < line && line <= end)
+            .map(|&(start, _)| start)
+            .collect();
+        next.drain(..next.len().saturating_sub(max));
+        if next == pinned {
+            break;
+        }
+        pinned = next;
+    }
+    pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::syntax::SyntaxHighlighter;
+
+    fn object(sh: &mut SyntaxHighlighter, text: &str, ext: &str, kind: ObjectKind, inner: bool, at: &str) -> Option<String> {
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, ext);
+        let (query, root) = sh.textobjects(&buffer)?;
+        let r = select(query, root, buffer.rope(), kind, inner, text.find(at).unwrap())?;
+        Some(format!("{}{}", if r.linewise { "L:" } else { "" }, &text[r.start..r.end]))
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_rust_objects() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "// one\n// two\nfn add(a: i32, b: i32) -> i32 {\n    for i in 0..3 { a; }\n    a + b\n}\n";
+        let get = |sh: &mut SyntaxHighlighter, kind, inner, at| object(sh, text, "rs", kind, inner, at);
+        assert_eq!(get(&mut sh, ObjectKind::Function, false, "a + b").unwrap(), format!("L:{}", &text[14..]));
+        assert_eq!(get(&mut sh, ObjectKind::Function, true, "add").unwrap(), "L:    for i in 0..3 { a; }\n    a + b\n");
+        assert_eq!(get(&mut sh, ObjectKind::Loop, true, "in 0").unwrap(), "a;");
+        assert_eq!(get(&mut sh, ObjectKind::Parameter, false, "a: i32").unwrap(), "a: i32, ");
+        assert_eq!(get(&mut sh, ObjectKind::Parameter, false, "b: i32").unwrap(), ", b: i32");
+        assert_eq!(get(&mut sh, ObjectKind::Parameter, true, "b: i32").unwrap(), "b: i32");
+        assert_eq!(get(&mut sh, ObjectKind::Comment, false, "two").unwrap(), "L:// one\n// two\n");
+        assert_eq!(get(&mut sh, ObjectKind::Comment, true, "one").unwrap(), "one\n// two");
+        // no impl, struct or trait anywhere
+        assert_eq!(get(&mut sh, ObjectKind::Class, false, "add"), None);
+    }
+
+    #[test]
+    fn test_bundled_queries_compile() {
+        let (_, errors) = SyntaxHighlighter::with_runtime(None, &crate::language::LanguageRegistry::builtin());
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[cfg(feature = "lang-python")]
+    #[test]
+    fn test_python_function_starts() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "@cache\ndef a():\n    def b():\n        pass\n\nclass C:\n    def m(self, x):\n        return x\n";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "py");
+        let (query, root) = sh.textobjects(&buffer).unwrap();
+        let all = function_starts(query, root, buffer.rope(), false);
+        assert_eq!(all, vec![0, text.find("def b").unwrap(), text.find("def m").unwrap()]);
+        let outermost = function_starts(query, root, buffer.rope(), true);
+        assert_eq!(outermost, vec![0, text.find("def m").unwrap()]);
+        assert_eq!(object(&mut sh, text, "py", ObjectKind::Class, true, "self").unwrap(), "L:    def m(self, x):\n        return x\n");
+    }
+
+    #[test]
+    fn test_context_lines() {
+        // impl 0..20, fn 2..15, loop 5..10, fn 16..19
+        let scopes = [(0, 20), (2, 15), (5, 10), (16, 19)];
+        let from = |top: usize| move |row: usize| Some(top + row);
+        assert_eq!(context_lines(&scopes, from(0), 3), Vec::<usize>::new());
+        assert_eq!(context_lines(&scopes, from(1), 3), vec![0]);
+        // pinning the impl covers line 3, which is inside the fn as well
+        assert_eq!(context_lines(&scopes, from(3), 3), vec![0, 2]);
+        assert_eq!(context_lines(&scopes, from(7), 3), vec![0, 2, 5]);
+        assert_eq!(context_lines(&scopes, from(7), 2), vec![2, 5]);
+        assert_eq!(context_lines(&scopes, from(12), 3), vec![0, 2]);
+        assert_eq!(context_lines(&scopes, from(7), 0), Vec::<usize>::new());
+        assert_eq!(context_lines(&scopes, |row| (row == 0).then_some(30), 3), Vec::<usize>::new());
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_rust_scopes() {
+        let mut sh = SyntaxHighlighter::new();
+        let text = "struct S;
+impl S {
+    fn f(&self) {
+        for i in 0..3 {
+            g(i);
+        }
+    }
+}
+fn h() {}
+";
+        let mut buffer = Buffer::from_string(text);
+        sh.update(&mut buffer, "rs");
+        let (query, root) = sh.textobjects(&buffer).unwrap();
+        assert_eq!(scopes(query, root, buffer.rope(), 4..5), vec![(1, 7), (2, 6), (3, 5)]);
+        assert_eq!(scopes(query, root, buffer.rope(), 0..9), vec![(1, 7), (2, 6), (3, 5), (8, 8)]);
+    }
+}
@@ -1,10 +1,36 @@
 // theme configuration: webspinner default + custom palettes
 
 use crossterm::style::Color;
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// themes shipped with the editor (webspinner is built in as `Theme::webspinner`)
+const BUNDLED_THEMES: &[(&str, &str)] = &[
+    ("dawn", include_str!("../themes/dawn.toml")),
+    ("gruvbox", include_str!("../themes/gruvbox.toml")),
+    ("solarized-light", include_str!("../themes/solarized-light.toml")),
+];
+
+/// text attributes and optional colors for one highlight group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub undercurl: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Self {
+        Self { fg: Some(color), ..Self::default() }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Theme {
+    pub name: String,
     pub bg: Color,
     pub fg: Color,
     pub accent: Color,          // purple "web silk"
@@ -37,13 +63,119 @@ pub struct Theme {
     pub finder_selected_bg: Color,
     pub finder_prompt_fg: Color,
     pub tilde_fg: Color,
+    pub highlights: HashMap<String, Style>, // highlight group -> style
+}
+
+macro_rules! color_fields {
+    ($($field:ident),* $(,)?) => {
+        /// every color key a theme file may set
+        pub const COLOR_KEYS: &[&str] = &[$(stringify!($field)),*];
+
+        impl Theme {
+            fn color_field_mut(&mut self, key: &str) -> Option<&mut Color> {
+                match key {
+                    $(stringify!($field) => Some(&mut self.$field),)*
+                    _ => None,
+                }
+            }
+
+            pub fn color_field(&self, key: &str) -> Option<Color> {
+                match key {
+                    $(stringify!($field) => Some(self.$field),)*
+                    _ => None,
+                }
+            }
+        }
+    };
 }
 
+color_fields!(
+    bg, fg, accent, string, warning, error, comment, current_line_bg,
+    gutter_fg, gutter_current, gutter_separator, statusline_bg, statusline_fg,
+    mode_normal_bg, mode_normal_fg, mode_insert_bg, mode_insert_fg,
+    mode_visual_bg, mode_visual_fg, mode_command_bg, mode_command_fg,
+    mode_search_bg, mode_search_fg, selection_bg, selection_fg,
+    search_match_bg, search_match_fg, substitute_bg, substitute_fg,
+    finder_selected_bg, finder_prompt_fg, tilde_fg,
+);
+
 fn hex(r: u8, g: u8, b: u8) -> Color { Color::Rgb { r, g, b } }
 
+fn user_themes_dir() -> Option<PathBuf> {
+    crate::config::config_dir().map(|d| d.join("themes"))
+}
+
+/// parse "#rrggbb", "#rgb", an ANSI color name, "none", or a 0-255 palette index
+pub fn parse_color(s: &str) -> Option<Color> {
+    if let Some(h) = s.strip_prefix('#') {
+        let digits: Vec<u8> = h.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect::<Option<_>>()?;
+        return match digits.len() {
+            6 => Some(hex(digits[0] * 16 + digits[1], digits[2] * 16 + digits[3], digits[4] * 16 + digits[5])),
+            3 => Some(hex(digits[0] * 17, digits[1] * 17, digits[2] * 17)),
+            _ => None,
+        };
+    }
+    let color = match s.to_ascii_lowercase().replace(['_', '-', ' '], "").as_str() {
+        "none" | "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "darkred" => Color::DarkRed,
+        "darkgreen" => Color::DarkGreen,
+        "darkyellow" | "brown" => Color::DarkYellow,
+        "darkblue" => Color::DarkBlue,
+        "darkmagenta" => Color::DarkMagenta,
+        "darkcyan" => Color::DarkCyan,
+        "grey" | "gray" | "lightgrey" | "lightgray" => Color::Grey,
+        "darkgrey" | "darkgray" => Color::DarkGrey,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        other => return other.parse::<u8>().ok().map(Color::AnsiValue),
+    };
+    Some(color)
+}
+
+fn parse_color_value(val: &toml::Value, palette: &HashMap<String, Color>) -> Option<Color> {
+    match val {
+        toml::Value::String(s) => palette.get(s.as_str()).copied().or_else(|| parse_color(s)),
+        toml::Value::Integer(i) => u8::try_from(*i).ok().map(Color::AnsiValue),
+        _ => None,
+    }
+}
+
+/// a style is either a bare color (the foreground) or a table of
+/// fg, bg, bold, italic, underline, undercurl
+fn parse_style(val: &toml::Value, palette: &HashMap<String, Color>) -> Result<Style, String> {
+    let Some(table) = val.as_table() else {
+        return parse_color_value(val, palette).map(Style::fg).ok_or_else(|| "invalid color".to_string());
+    };
+    let mut style = Style::default();
+    for (key, v) in table {
+        match key.as_str() {
+            "fg" => style.fg = Some(parse_color_value(v, palette).ok_or("invalid fg color")?),
+            "bg" => style.bg = Some(parse_color_value(v, palette).ok_or("invalid bg color")?),
+            "bold" | "italic" | "underline" | "undercurl" => {
+                let on = v.as_bool().ok_or_else(|| format!("{} must be true or false", key))?;
+                match key.as_str() {
+                    "bold" => style.bold = on,
+                    "italic" => style.italic = on,
+                    "underline" => style.underline = on,
+                    _ => style.undercurl = on,
+                }
+            }
+            other => return Err(format!("unknown style key: {}", other)),
+        }
+    }
+    Ok(style)
+}
+
 impl Theme {
     pub fn webspinner() -> Self { // default theme
         Self {
+            name: "webspinner".to_string(),
             bg: hex(0x1a, 0x1b, 0x26),
             fg: hex(0xc0, 0xca, 0xf5),
             accent: hex(0xbb, 0x9a, 0xf7),       // purple web silk
@@ -76,38 +208,168 @@ impl Theme {
             finder_selected_bg: hex(0x28, 0x3b, 0x8a),
             finder_prompt_fg: hex(0x7a, 0xa2, 0xf7),
             tilde_fg: hex(0x3b, 0x40, 0x61),
+            highlights: HashMap::from([
+                ("comment".to_string(), Style { italic: true, ..Style::fg(hex(0x56, 0x5f, 0x89)) }),
+                ("function".to_string(), Style::fg(hex(0x7a, 0xa2, 0xf7))),   // blue
+                ("type".to_string(), Style::fg(hex(0x2a, 0xc3, 0xde))),       // cyan
+                ("constructor".to_string(), Style::fg(hex(0x2a, 0xc3, 0xde))),
+                ("property".to_string(), Style::fg(hex(0x7d, 0xcf, 0xff))),
+                ("variable.member".to_string(), Style::fg(hex(0x7d, 0xcf, 0xff))),
+                ("variable.parameter".to_string(), Style::fg(hex(0x7d, 0xcf, 0xff))),
+                ("variable.builtin".to_string(), Style::fg(hex(0xf7, 0x76, 0x8e))), // red
+                ("operator".to_string(), Style::fg(hex(0xc0, 0xca, 0xf5))),
+            ]),
         }
     }
 
-    pub fn from_toml(table: &toml::Table) -> Self {
+    /// parse a theme file: top-level color keys (any `Theme` field name), an optional
+    /// `[palette]` of named colors, and `[highlights]` styles per highlight group.
+    /// Keys that are missing keep their webspinner value.
+    pub fn from_toml(table: &toml::Table) -> Result<Self, String> {
         let mut theme = Self::webspinner();
-        fn parse_color(val: &toml::Value) -> Option<Color> {
-            val.as_str().and_then(|s| {
-                let s = s.trim_start_matches('#');
-                if s.len() == 6 {
-                    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
-                    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
-                    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
-                    Some(Color::Rgb { r, g, b })
-                } else { None }
-            })
+        theme.highlights.clear();
+        if let Some(name) = table.get("name").and_then(|v| v.as_str()) {
+            theme.name = name.to_string();
         }
-        macro_rules! set_color {
-            ($field:ident, $key:expr) => {
-                if let Some(v) = table.get($key) {
-                    if let Some(c) = parse_color(v) { theme.$field = c; }
-                }
+        let mut palette: HashMap<String, Color> = HashMap::new();
+        if let Some(p) = table.get("palette") {
+            let p = p.as_table().ok_or("palette must be a table")?;
+            for (name, val) in p {
+                let c = parse_color_value(val, &palette)
+                    .ok_or_else(|| format!("invalid color for palette.{}", name))?;
+                palette.insert(name.clone(), c);
+            }
+        }
+        for (key, val) in table {
+            if matches!(key.as_str(), "name" | "palette" | "highlights") { continue; }
+            let field = match key.as_str() {
+                "background" => "bg",
+                "foreground" => "fg",
+                k => k,
             };
+            let c = parse_color_value(val, &palette)
+                .ok_or_else(|| format!("invalid color for {}", key))?;
+            match theme.color_field_mut(field) {
+                Some(slot) => *slot = c,
+                None => return Err(format!("unknown theme key: {}", key)),
+            }
+        }
+        // highlight colors may also name a theme field, e.g. fg = "accent"
+        for key in COLOR_KEYS {
+            if let Some(c) = theme.color_field(key) {
+                palette.entry(key.to_string()).or_insert(c);
+            }
+        }
+        if let Some(h) = table.get("highlights") {
+            let h = h.as_table().ok_or("highlights must be a table")?;
+            for (group, val) in h {
+                let style = parse_style(val, &palette)
+                    .map_err(|e| format!("highlights.{}: {}", group, e))?;
+                theme.highlights.insert(group.clone(), style);
+            }
         }
-        set_color!(bg, "background");
-        set_color!(fg, "foreground");
-        set_color!(accent, "accent");
-        set_color!(string, "string");
-        set_color!(warning, "warning");
-        set_color!(error, "error");
-        set_color!(comment, "comment");
-        set_color!(current_line_bg, "current_line_bg");
-        theme
+        Ok(theme)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let table: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+        Self::from_toml(&table)
+    }
+
+    /// look up a colorscheme by name: the user's themes directory wins over bundled themes
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some(path) = user_themes_dir().map(|d| d.join(format!("{}.toml", name))) {
+            if path.is_file() {
+                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+                let mut theme = Self::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+                theme.name = name.to_string();
+                return Ok(theme);
+            }
+        }
+        if name == "webspinner" {
+            return Ok(Self::webspinner());
+        }
+        match BUNDLED_THEMES.iter().find(|(n, _)| *n == name) {
+            Some((_, content)) => {
+                let mut theme = Self::parse(content)?;
+                theme.name = name.to_string();
+                Ok(theme)
+            }
+            None => Err(format!("Cannot find color scheme '{}'", name)),
+        }
+    }
+
+    /// names of every colorscheme `load` can find, sorted
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = BUNDLED_THEMES.iter().map(|(n, _)| n.to_string()).collect();
+        names.push("webspinner".to_string());
+        if let Some(entries) = user_themes_dir().and_then(|d| std::fs::read_dir(d).ok()) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|e| e == "toml") {
+                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                        names.push(stem.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// style for a highlight group, if the theme defines one
+    pub fn highlight(&self, group: &str) -> Option<&Style> {
+        self.highlights.get(group)
+    }
+
+    /// style for the summary line of a closed fold: the `folded` group, else
+    /// comment-colored text on the current line background
+    pub fn folded_style(&self) -> Style {
+        self.highlights.get("folded").copied()
+            .unwrap_or(Style { bg: Some(self.current_line_bg), ..Style::fg(self.comment) })
+    }
+
+    /// style for the scope lines pinned at the top of a window: the `context`
+    /// group, else the current line background
+    pub fn context_style(&self) -> Style {
+        self.highlights.get("context").copied()
+            .unwrap_or(Style { bg: Some(self.current_line_bg), ..Style::default() })
+    }
+
+    /// style for a tree-sitter capture: the exact group, then each shorter dotted
+    /// prefix ("function.macro" -> "function"), then a color derived from the
+    /// theme's base fields
+    pub fn capture_style(&self, capture: &str) -> Style {
+        let mut name = capture;
+        loop {
+            if let Some(style) = self.highlights.get(name) {
+                return *style;
+            }
+            match name.rfind('.') {
+                Some(i) => name = &name[..i],
+                None => break,
+            }
+        }
+        // markup from Markdown and similar grammars reads better with attributes
+        match capture {
+            "text.title" => return Style { bold: true, ..Style::fg(self.accent) },
+            "text.strong" => return Style { bold: true, ..Style::fg(self.fg) },
+            "text.emphasis" => return Style { italic: true, ..Style::fg(self.fg) },
+            "text.uri" => return Style { underline: true, ..Style::fg(self.accent) },
+            "text.literal" => return Style::fg(self.string),
+            _ => {}
+        }
+        let color = match name {
+            "comment" => self.comment,
+            "string" | "character" => self.string,
+            "number" | "boolean" | "constant" | "attribute" | "label" => self.warning,
+            "keyword" | "namespace" | "module" => self.accent,
+            "punctuation" => self.comment,
+            "escape" | "embedded" | "tag" => self.error,
+            _ => self.fg,
+        };
+        Style::fg(color)
     }
 }
 
@@ -115,15 +377,73 @@ impl Default for Theme {
     fn default() -> Self { Self::webspinner() }
 }
 
-// deserialize helper for config loading
-#[derive(Debug, Clone, Deserialize, Default)]
-pub struct ThemeConfig {
-    pub background: Option<String>,
-    pub foreground: Option<String>,
-    pub accent: Option<String>,
-    pub string: Option<String>,
-    pub warning: Option<String>,
-    pub error: Option<String>,
-    pub comment: Option<String>,
-    pub current_line_bg: Option<String>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8000"), Some(hex(0xff, 0x80, 0x00)));
+        assert_eq!(parse_color("#f80"), Some(hex(0xff, 0x88, 0x00)));
+        assert_eq!(parse_color("dark_red"), Some(Color::DarkRed));
+        assert_eq!(parse_color("none"), Some(Color::Reset));
+        assert_eq!(parse_color("208"), Some(Color::AnsiValue(208)));
+        assert_eq!(parse_color("#12"), None);
+        assert_eq!(parse_color("chartreuse"), None);
+    }
+
+    #[test]
+    fn test_from_toml_palette_and_highlights() {
+        let theme = Theme::parse(r##"
+            name = "t"
+            background = "#000000"
+            accent = "purple"
+            [palette]
+            purple = "#800080"
+            [highlights]
+            comment = { fg = "accent", italic = true }
+            keyword = "purple"
+            "##).unwrap();
+        assert_eq!(theme.name, "t");
+        assert_eq!(theme.bg, hex(0, 0, 0));
+        assert_eq!(theme.accent, hex(0x80, 0, 0x80));
+        let comment = theme.highlight("comment").unwrap();
+        assert_eq!(comment.fg, Some(hex(0x80, 0, 0x80)));
+        assert!(comment.italic && !comment.bold);
+        assert_eq!(theme.highlight("keyword"), Some(&Style::fg(hex(0x80, 0, 0x80))));
+        assert!(Theme::parse("nonsense_key = \"#000000\"").is_err());
+        assert!(Theme::parse("[highlights]\nkeyword = { blink = true }").is_err());
+    }
+
+    #[test]
+    fn test_bundled_themes_cover_every_field() {
+        for (name, content) in BUNDLED_THEMES {
+            let table: toml::Table = toml::from_str(content).unwrap();
+            for key in COLOR_KEYS {
+                assert!(table.contains_key(*key), "{} is missing {}", name, key);
+            }
+            assert!(Theme::load(name).is_ok(), "{} failed to load", name);
+        }
+        assert!(Theme::available().contains(&"dawn".to_string()));
+        assert!(Theme::load("no-such-theme").is_err());
+    }
+
+    #[test]
+    fn test_capture_style_fallback() {
+        let theme = Theme::parse(r##"
+            accent = "#111111"
+            comment = "#222222"
+            [highlights]
+            function = { fg = "#333333", bold = true }
+            "function.macro" = { fg = "#444444", underline = true }
+            "##).unwrap();
+        assert_eq!(theme.capture_style("function.macro").fg, Some(hex(0x44, 0x44, 0x44)));
+        let method = theme.capture_style("function.method");
+        assert_eq!(method.fg, Some(hex(0x33, 0x33, 0x33)));
+        assert!(method.bold);
+        // nothing styled: derived from the base theme colors
+        assert_eq!(theme.capture_style("keyword.return").fg, Some(hex(0x11, 0x11, 0x11)));
+        assert_eq!(theme.capture_style("punctuation.bracket").fg, Some(hex(0x22, 0x22, 0x22)));
+        assert_eq!(theme.capture_style("variable").fg, Some(theme.fg));
+    }
 }
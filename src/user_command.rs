@@ -0,0 +1,278 @@
+// User-defined ex commands (:command) and abbreviations (:iabbrev, :cabbrev)
+
+use std::collections::HashMap;
+use crate::error::{Error, Result};
+
+/// number of arguments a user command accepts (-nargs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NArgs {
+    Zero,       // -nargs=0 (default)
+    One,        // -nargs=1
+    Any,        // -nargs=*
+    Optional,   // -nargs=?
+    AtLeastOne, // -nargs=+
+}
+
+/// whether a user command accepts a range, and its default (-range)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeSpec {
+    None,
+    CurrentLine, // -range
+    WholeFile,   // -range=%
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserCommand {
+    pub name: String,
+    pub nargs: NArgs,
+    pub range: RangeSpec,
+    pub replacement: String,
+}
+
+impl UserCommand {
+    /// parse the text after `:command`, e.g. "-nargs=1 -range Name ex-command"
+    pub fn parse(definition: &str) -> Result<Self> {
+        let mut nargs = NArgs::Zero;
+        let mut range = RangeSpec::None;
+        let mut rest = definition.trim_start();
+        while let Some(attr) = rest.strip_prefix('-') {
+            let end = attr.find(char::is_whitespace).unwrap_or(attr.len());
+            match &attr[..end] {
+                "nargs=0" => nargs = NArgs::Zero,
+                "nargs=1" => nargs = NArgs::One,
+                "nargs=*" => nargs = NArgs::Any,
+                "nargs=?" => nargs = NArgs::Optional,
+                "nargs=+" => nargs = NArgs::AtLeastOne,
+                "range" => range = RangeSpec::CurrentLine,
+                "range=%" => range = RangeSpec::WholeFile,
+                other => return Err(Error::ParseError(format!("Invalid attribute: -{}", other))),
+            }
+            rest = attr[end..].trim_start();
+        }
+        let name_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        let replacement = rest[name_end..].trim();
+        if name.is_empty() {
+            return Err(Error::ParseError("Usage: :command[!] [-nargs=N] [-range] Name ex-command".to_string()));
+        }
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::ParseError(format!(
+                "User defined commands must start with an uppercase letter: {}", name
+            )));
+        }
+        if replacement.is_empty() {
+            return Err(Error::ParseError(format!("Missing ex-command for {}", name)));
+        }
+        Ok(Self { name: name.to_string(), nargs, range, replacement: replacement.to_string() })
+    }
+
+    /// build a command from a config entry: `Name = "[-nargs=N] [-range] ex-command"`
+    pub fn from_config(name: &str, spec: &str) -> Result<Self> {
+        let spec = spec.trim_start();
+        let mut attrs_end = 0;
+        for token in spec.split_whitespace() {
+            if !token.starts_with('-') { break; }
+            attrs_end = spec[attrs_end..].find(token).unwrap() + attrs_end + token.len();
+        }
+        Self::parse(&format!("{} {} {}", &spec[..attrs_end], name, &spec[attrs_end..]))
+    }
+
+    /// substitute <args>, <q-args>, <line1>, <line2>, <bang> and <lt> into the replacement
+    pub fn expand(&self, args: &str, bang: bool, line1: usize, line2: usize) -> Result<String> {
+        let args = args.trim();
+        let count = args.split_whitespace().count();
+        let ok = match self.nargs {
+            NArgs::Zero => count == 0,
+            NArgs::One => !args.is_empty(), // the whole string, spaces included, is one argument
+            NArgs::Any => true,
+            NArgs::Optional => count <= 1,
+            NArgs::AtLeastOne => count >= 1,
+        };
+        if !ok {
+            let msg = match self.nargs {
+                NArgs::Zero => "No arguments allowed",
+                NArgs::AtLeastOne | NArgs::One => "Argument required",
+                _ => "Too many arguments",
+            };
+            return Err(Error::ParseError(format!("{}: {}", msg, self.name)));
+        }
+        let mut out = String::new();
+        let mut rest = self.replacement.as_str();
+        while let Some(start) = rest.find('<') {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            let end = match tail.find('>') {
+                Some(e) => e,
+                None => { out.push_str(tail); rest = ""; break; }
+            };
+            match &tail[1..end] {
+                "args" => out.push_str(args),
+                "q-args" => out.push_str(&format!("\"{}\"", args.replace('\\', "\\\\").replace('"', "\\\""))),
+                "line1" => out.push_str(&line1.to_string()),
+                "line2" => out.push_str(&line2.to_string()),
+                "bang" => if bang { out.push('!') },
+                "lt" => out.push('<'),
+                _ => out.push_str(&tail[..=end]),
+            }
+            rest = &tail[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// one line of the `:command` listing
+    pub fn describe(&self) -> String {
+        let nargs = match self.nargs {
+            NArgs::Zero => "0",
+            NArgs::One => "1",
+            NArgs::Any => "*",
+            NArgs::Optional => "?",
+            NArgs::AtLeastOne => "+",
+        };
+        let range = match self.range {
+            RangeSpec::None => "",
+            RangeSpec::CurrentLine => ".",
+            RangeSpec::WholeFile => "%",
+        };
+        format!("{:<12} {:<5} {:<5} {}", self.name, nargs, range, self.replacement)
+    }
+}
+
+/// split a user command invocation into (name, bang, args)
+pub fn split_invocation(input: &str) -> (&str, bool, &str) {
+    let input = input.trim_start();
+    let name_end = input.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(input.len());
+    let (name, rest) = input.split_at(name_end);
+    match rest.strip_prefix('!') {
+        Some(args) => (name, true, args.trim()),
+        None => (name, false, rest.trim()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbbrevMode {
+    Insert,
+    Command,
+}
+
+/// characters that make up a word for abbreviation purposes (vim's 'iskeyword' default)
+pub fn is_keyword_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Abbreviations {
+    insert: HashMap<String, String>,
+    command: HashMap<String, String>,
+}
+
+impl Abbreviations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn table(&self, mode: AbbrevMode) -> &HashMap<String, String> {
+        match mode {
+            AbbrevMode::Insert => &self.insert,
+            AbbrevMode::Command => &self.command,
+        }
+    }
+
+    pub fn add(&mut self, mode: AbbrevMode, lhs: &str, rhs: &str) -> Result<()> {
+        if lhs.is_empty() || !lhs.chars().all(is_keyword_char) {
+            return Err(Error::ParseError(format!("Invalid abbreviation: {}", lhs)));
+        }
+        let table = match mode {
+            AbbrevMode::Insert => &mut self.insert,
+            AbbrevMode::Command => &mut self.command,
+        };
+        table.insert(lhs.to_string(), rhs.to_string());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, mode: AbbrevMode, lhs: &str) -> bool {
+        match mode {
+            AbbrevMode::Insert => self.insert.remove(lhs).is_some(),
+            AbbrevMode::Command => self.command.remove(lhs).is_some(),
+        }
+    }
+
+    /// if `before` (the text left of the cursor) ends in an abbreviation, return the
+    /// number of chars to replace and the expansion
+    pub fn expand(&self, mode: AbbrevMode, before: &str) -> Option<(usize, &str)> {
+        let word_start = before
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_keyword_char(*c))
+            .last()
+            .map(|(i, _)| i)?;
+        let word = &before[word_start..];
+        self.table(mode).get(word).map(|rhs| (word.chars().count(), rhs.as_str()))
+    }
+
+    /// sorted (mode letter, lhs, rhs) entries for the `:abbreviate` listing
+    pub fn list(&self) -> Vec<(char, &str, &str)> {
+        let mut out: Vec<(char, &str, &str)> = self.insert.iter()
+            .map(|(l, r)| ('i', l.as_str(), r.as_str()))
+            .chain(self.command.iter().map(|(l, r)| ('c', l.as_str(), r.as_str())))
+            .collect();
+        out.sort();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_definition() {
+        let cmd = UserCommand::parse("-nargs=* -range Sort <line1>,<line2>!sort <args>").unwrap();
+        assert_eq!(cmd.name, "Sort");
+        assert_eq!(cmd.nargs, NArgs::Any);
+        assert_eq!(cmd.range, RangeSpec::CurrentLine);
+        assert_eq!(cmd.replacement, "<line1>,<line2>!sort <args>");
+        assert!(UserCommand::parse("lower w").is_err());
+        assert!(UserCommand::parse("-bogus W w").is_err());
+        assert!(UserCommand::parse("W").is_err());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let cmd = UserCommand::from_config("Todo", "-nargs=? Rg TODO <args>").unwrap();
+        assert_eq!(cmd.nargs, NArgs::Optional);
+        assert_eq!(cmd.replacement, "Rg TODO <args>");
+        let cmd = UserCommand::from_config("Fmt", "!cargo fmt").unwrap();
+        assert_eq!(cmd.replacement, "!cargo fmt");
+    }
+
+    #[test]
+    fn test_expand() {
+        let cmd = UserCommand::parse("-nargs=* -range=% Sort <line1>,<line2>!sort <args>").unwrap();
+        assert_eq!(cmd.expand("-r -u", false, 1, 40).unwrap(), "1,40!sort -r -u");
+        let cmd = UserCommand::parse("-nargs=1 Say echo <q-args><bang> <lt>x>").unwrap();
+        assert_eq!(cmd.expand("a \"b\"", true, 1, 1).unwrap(), "echo \"a \\\"b\\\"\"! <x>");
+        assert!(cmd.expand("", false, 1, 1).is_err());
+        let cmd = UserCommand::parse("W w").unwrap();
+        assert!(cmd.expand("extra", false, 1, 1).is_err());
+    }
+
+    #[test]
+    fn test_split_invocation() {
+        assert_eq!(split_invocation("Grep! foo bar"), ("Grep", true, "foo bar"));
+        assert_eq!(split_invocation("W"), ("W", false, ""));
+    }
+
+    #[test]
+    fn test_abbreviation_expand() {
+        let mut ab = Abbreviations::new();
+        ab.add(AbbrevMode::Insert, "teh", "the").unwrap();
+        ab.add(AbbrevMode::Command, "W", "w").unwrap();
+        assert_eq!(ab.expand(AbbrevMode::Insert, "fix teh"), Some((3, "the")));
+        assert_eq!(ab.expand(AbbrevMode::Insert, "fixteh"), None);
+        assert_eq!(ab.expand(AbbrevMode::Insert, "teh "), None);
+        assert_eq!(ab.expand(AbbrevMode::Command, "W"), Some((1, "w")));
+        assert_eq!(ab.expand(AbbrevMode::Insert, "W"), None);
+        assert!(ab.add(AbbrevMode::Insert, "a b", "x").is_err());
+    }
+}
@@ -1,5 +1,7 @@
 // Viewport for scrolling through buffer
 
+use crate::fold::FoldState;
+
 #[derive(Debug)]
 pub struct Viewport {
     pub offset_line: usize,
@@ -30,12 +32,15 @@ impl Viewport {
         self.offset_line = line;
     }
 
-    pub fn ensure_cursor_visible(&mut self, cursor_line: usize, cursor_col: usize) {
+    /// scroll so the cursor shows; a closed fold takes up a single screen line
+    pub fn ensure_cursor_visible(&mut self, cursor_line: usize, cursor_col: usize, folds: &FoldState) {
         // Vertical scrolling
+        let cursor_line = folds.display_range(cursor_line).0;
+        self.offset_line = folds.display_range(self.offset_line).0;
         if cursor_line < self.offset_line {
             self.offset_line = cursor_line;
-        } else if cursor_line >= self.offset_line + self.height {
-            self.offset_line = cursor_line.saturating_sub(self.height - 1);
+        } else if folds.rows_between(self.offset_line, cursor_line) >= self.height {
+            self.offset_line = folds.move_visible(cursor_line, -(self.height.saturating_sub(1) as isize), usize::MAX);
         }
 
         // Horizontal scrolling
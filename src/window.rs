@@ -1,6 +1,8 @@
 // window management for split panes
 
 use crate::cursor::Cursor;
+use crate::fold::FoldState;
+use crate::quickfix::QuickfixList;
 use crate::viewport::Viewport;
 
 #[derive(Debug, Clone, Copy)]
@@ -17,6 +19,8 @@ pub struct Window {
     pub cursor: Cursor,
     pub viewport: Viewport,
     pub rect: Rect, // screen region for this window
+    pub folds: FoldState,
+    pub location_list: Option<QuickfixList>, // the window's own quickfix-style list
 }
 
 impl Window {
@@ -26,6 +30,8 @@ impl Window {
             cursor: Cursor::default(),
             viewport: Viewport::new(width, height),
             rect: Rect { x: 0, y: 0, width, height },
+            folds: FoldState::default(),
+            location_list: None,
         }
     }
 